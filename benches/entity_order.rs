@@ -0,0 +1,94 @@
+//! Measures what keeping the entities of a system in order of index costs, by processing 10,000
+//! entities and by churning a tenth of them in and out of the system every frame.
+
+#![feature(test)]
+
+#[macro_use]
+extern crate ecs;
+extern crate test;
+
+use ecs::{BuildData, DataHelper, Entity, EntityIter, System, World};
+use ecs::system::{EntityProcess, EntitySystem};
+use test::Bencher;
+
+const ENTITIES: usize = 10_000;
+
+#[derive(Clone)]
+pub struct Position(f32);
+
+components! {
+    BenchComponents {
+        #[hot] position: Position
+    }
+}
+
+component_access! {
+    BenchComponents {
+        position: Position
+    }
+}
+
+pub struct Sum(f32);
+
+impl EntityProcess for Sum
+{
+    fn process(&mut self, entities: EntityIter<BenchComponents>, data: &mut DataHelper<BenchComponents>)
+    {
+        self.0 = 0.0;
+        for e in entities
+        {
+            self.0 += data.position[e].0;
+        }
+    }
+}
+
+impl System for Sum
+{
+    type Components = BenchComponents;
+}
+
+systems! {
+    BenchSystems<BenchComponents> {
+        sum: EntitySystem<Sum> = EntitySystem::new(Sum(0.0), aspect!(<BenchComponents> all: [position]))
+    }
+}
+
+fn world() -> (World<BenchComponents, BenchSystems>, Vec<Entity>)
+{
+    let mut world = World::<BenchComponents, BenchSystems>::new();
+    let entities = (0..ENTITIES).map(|i| world.create_entity(|e: BuildData, c: &mut BenchComponents| {
+        c.position.add(&e, Position(i as f32));
+    })).collect();
+    (world, entities)
+}
+
+#[bench]
+fn process_entities(b: &mut Bencher)
+{
+    let (mut world, _) = world();
+    b.iter(|| world.update());
+}
+
+// Every tenth entity leaves the system and comes back, so the others have to stay in order
+#[bench]
+fn churn_entities(b: &mut Bencher)
+{
+    let (mut world, entities) = world();
+    let mut add = false;
+    b.iter(|| {
+        for i in 0..ENTITIES / 10
+        {
+            let entity = entities[i * 10];
+            if add
+            {
+                world.data.add_component(entity, Position(0.0));
+            }
+            else
+            {
+                world.data.remove_component::<Position>(entity);
+            }
+        }
+        add = !add;
+        world.update();
+    });
+}
@@ -0,0 +1,117 @@
+//! Measures queued changes to 10,000 entities that ten systems are interested in, when the
+//! changes don't affect which systems the entities belong to.
+
+#![feature(test)]
+
+#[macro_use]
+extern crate ecs;
+extern crate test;
+
+use ecs::{BuildData, DataHelper, Entity, EntityIter, ModifyData, System, World};
+use ecs::system::{EntityProcess, EntitySystem};
+use test::Bencher;
+
+const ENTITIES: usize = 10_000;
+
+#[derive(Clone)]
+pub struct Position(f32);
+#[derive(Clone)]
+pub struct Health(i32);
+#[derive(Clone, Default)]
+pub struct Marker;
+
+components! {
+    BenchComponents {
+        #[hot] position: Position,
+        #[hot] health: Health,
+        #[null] marker: Marker
+    }
+}
+
+component_access! {
+    BenchComponents {
+        marker: Marker
+    }
+}
+
+pub struct Count(usize);
+
+impl EntityProcess for Count
+{
+    fn process(&mut self, entities: EntityIter<BenchComponents>, _: &mut DataHelper<BenchComponents>)
+    {
+        self.0 = entities.count();
+    }
+}
+
+impl System for Count
+{
+    type Components = BenchComponents;
+}
+
+macro_rules! count {
+    () => { EntitySystem::new(Count(0), aspect!(<BenchComponents> all: [position])) }
+}
+
+systems! {
+    BenchSystems<BenchComponents> {
+        a: EntitySystem<Count> = count!(),
+        b: EntitySystem<Count> = count!(),
+        c: EntitySystem<Count> = count!(),
+        d: EntitySystem<Count> = count!(),
+        e: EntitySystem<Count> = count!(),
+        f: EntitySystem<Count> = count!(),
+        g: EntitySystem<Count> = count!(),
+        h: EntitySystem<Count> = count!(),
+        i: EntitySystem<Count> = count!(),
+        j: EntitySystem<Count> = count!()
+    }
+}
+
+fn world() -> (World<BenchComponents, BenchSystems>, Vec<Entity>)
+{
+    let mut world = World::<BenchComponents, BenchSystems>::new();
+    let entities = (0..ENTITIES).map(|_| world.create_entity(|e: BuildData, c: &mut BenchComponents| {
+        c.position.add(&e, Position(0.0));
+        c.health.add(&e, Health(100));
+    })).collect();
+    (world, entities)
+}
+
+#[bench]
+fn modify_values(b: &mut Bencher)
+{
+    let (mut world, entities) = world();
+    b.iter(|| {
+        for &entity in entities.iter()
+        {
+            world.data.modify_entity(entity, |e: ModifyData, c: &mut BenchComponents| {
+                c.health.borrow(&e).unwrap().0 -= 1;
+            });
+        }
+        world.flush();
+    });
+}
+
+// Adding and removing a component outside the aspects reactivates every entity
+#[bench]
+fn toggle_unwatched_component(b: &mut Bencher)
+{
+    let (mut world, entities) = world();
+    let mut add = true;
+    b.iter(|| {
+        for &entity in entities.iter()
+        {
+            if add
+            {
+                world.data.add_component(entity, Marker);
+            }
+            else
+            {
+                world.data.remove_component::<Marker>(entity);
+            }
+        }
+        add = !add;
+        world.flush();
+    });
+}
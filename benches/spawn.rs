@@ -0,0 +1,53 @@
+//! Measures a bullet-hell frame: 2,000 entities queued with small builders, built by a flush and
+//! then removed again.
+
+#![feature(test)]
+
+#[macro_use]
+extern crate ecs;
+extern crate test;
+
+use ecs::{BuildData, Entity, World};
+use test::Bencher;
+
+const SPAWNS: usize = 2_000;
+
+#[derive(Clone)]
+pub struct Position(f32, f32);
+#[derive(Clone)]
+pub struct Velocity(f32, f32);
+
+components! {
+    BenchComponents {
+        #[hot] position: Position,
+        #[hot] velocity: Velocity
+    }
+}
+
+systems! {
+    BenchSystems<BenchComponents>;
+}
+
+#[bench]
+fn spawn_and_remove(b: &mut Bencher)
+{
+    let mut world = World::<BenchComponents, BenchSystems>::new();
+    let mut bullets: Vec<Entity> = Vec::with_capacity(SPAWNS);
+    b.iter(|| {
+        for i in 0..SPAWNS
+        {
+            let angle = i as f32;
+            bullets.push(world.data.create_entity(move |e: BuildData, c: &mut BenchComponents| {
+                c.position.add(&e, Position(0.0, 0.0));
+                c.velocity.add(&e, Velocity(angle.cos(), angle.sin()));
+            }));
+        }
+        world.flush();
+        for &bullet in bullets.iter()
+        {
+            world.data.remove_entity(bullet);
+        }
+        bullets.clear();
+        world.flush();
+    });
+}
@@ -0,0 +1,93 @@
+//! Compares integrating 100,000 bodies stored whole with streaming just the arrays of the fields
+//! the integrator uses.
+
+#![feature(test)]
+
+#[macro_use]
+extern crate ecs;
+extern crate test;
+
+use ecs::{ComponentStorage, Entity, SparseSetStorage};
+use test::Bencher;
+
+const ENTITIES: usize = 100_000;
+
+#[derive(Copy, Clone)]
+pub struct Vector
+{
+    x: f32,
+    y: f32,
+}
+
+split_component! {
+    Body -> (BodyStorage, BodyRef, BodyMut, BodyColumns) {
+        position: Vector,
+        velocity: Vector,
+        inertia: [f32; 9],
+        material: u32,
+        name: String,
+    }
+}
+
+fn body() -> Body
+{
+    Body {
+        position: Vector { x: 0.0, y: 0.0 },
+        velocity: Vector { x: 1.0, y: 1.0 },
+        inertia: [0.0; 9],
+        material: 0,
+        name: "body".to_string(),
+    }
+}
+
+#[bench]
+fn integrate_whole_bodies(b: &mut Bencher)
+{
+    let mut storage = SparseSetStorage::new();
+    for index in 0..ENTITIES
+    {
+        storage.insert(Entity::from_raw(index, 0), body());
+    }
+    b.iter(|| {
+        for (_, body) in storage.iter_mut()
+        {
+            body.position.x += body.velocity.x;
+            body.position.y += body.velocity.y;
+        }
+    });
+}
+
+#[bench]
+fn integrate_split_bodies(b: &mut Bencher)
+{
+    let mut storage = BodyStorage::new();
+    for index in 0..ENTITIES
+    {
+        storage.insert(Entity::from_raw(index, 0), body());
+    }
+    b.iter(|| {
+        let columns = storage.columns();
+        for (position, velocity) in columns.position.iter_mut().zip(columns.velocity.iter())
+        {
+            position.x += velocity.x;
+            position.y += velocity.y;
+        }
+    });
+}
+
+#[bench]
+fn iterate_split_proxies(b: &mut Bencher)
+{
+    let mut storage = BodyStorage::new();
+    for index in 0..ENTITIES
+    {
+        storage.insert(Entity::from_raw(index, 0), body());
+    }
+    b.iter(|| {
+        for (_, body) in storage.iter_mut()
+        {
+            body.position.x += body.velocity.x;
+            body.position.y += body.velocity.y;
+        }
+    });
+}
@@ -0,0 +1,90 @@
+//! Compares the storages over 100,000 entities, a tenth of which have their component removed
+//! and added back every frame.
+
+#![feature(test)]
+
+extern crate ecs;
+extern crate test;
+
+use ecs::{ComponentStorage, Entity, HashMapStorage, SparseSetStorage, VecStorage};
+use test::Bencher;
+
+const ENTITIES: usize = 100_000;
+const CHURN: usize = ENTITIES / 10;
+
+pub struct Motion
+{
+    x: f32,
+    dx: f32,
+}
+
+fn fill<S: ComponentStorage<Motion>>(storage: &mut S, step: usize)
+{
+    for index in 0..ENTITIES / step
+    {
+        storage.insert(Entity::from_raw(index * step, 0), Motion { x: 0.0, dx: 1.0 });
+    }
+}
+
+// Removes and adds back a different tenth of the components each frame
+fn churn<S: ComponentStorage<Motion>>(storage: &mut S, frame: usize)
+{
+    let start = (frame * CHURN) % ENTITIES;
+    for index in start..start + CHURN
+    {
+        storage.remove(index);
+    }
+    for index in start..start + CHURN
+    {
+        storage.insert(Entity::from_raw(index, 0), Motion { x: 0.0, dx: 1.0 });
+    }
+}
+
+macro_rules! storage_benches {
+    ($($churn:ident, $join:ident: $Storage:ident;)*) => {
+        $(
+            #[bench]
+            fn $churn(b: &mut Bencher)
+            {
+                let mut storage = $Storage::new();
+                fill(&mut storage, 1);
+                let mut frame = 0;
+                b.iter(|| {
+                    churn(&mut storage, frame);
+                    frame += 1;
+                    for (_, motion) in storage.iter_mut()
+                    {
+                        motion.x += motion.dx;
+                    }
+                });
+            }
+
+            // One in ten entities has the component, and drives a join over a dense list
+            #[bench]
+            fn $join(b: &mut Bencher)
+            {
+                let mut positions = VecStorage::new();
+                fill(&mut positions, 1);
+                let mut velocities = $Storage::new();
+                fill(&mut velocities, 10);
+                b.iter(|| {
+                    let mut total = 0.0;
+                    for (entity, velocity) in velocities.iter()
+                    {
+                        if let Some((_, position)) = positions.get(*entity)
+                        {
+                            total += position.x + velocity.dx;
+                        }
+                    }
+                    total
+                });
+            }
+        )*
+    }
+}
+
+storage_benches! {
+    churn_vec_storage, join_vec_storage: VecStorage;
+    churn_hash_map_storage, join_hash_map_storage: HashMapStorage;
+    churn_sparse_set_storage, join_sparse_set_storage: SparseSetStorage;
+}
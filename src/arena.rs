@@ -0,0 +1,140 @@
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::iter;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+
+/// Buffer that values of any type are moved into and back out of, so queueing a builder doesn't
+/// need an allocation of its own.
+///
+/// Values are expected to be taken out in roughly the order they were put in. Once every value
+/// has been taken out, the buffer starts again from the beginning, so it stops allocating once
+/// it has grown to fit the busiest flush.
+pub struct Arena
+{
+    words: Vec<u64>,
+    // Offset of each value from the oldest one still in the arena on, along with its destructor
+    // until it has been taken out
+    values: VecDeque<(usize, Option<unsafe fn(*const u64)>)>,
+    // Key of the value at the front of `values`
+    head: usize,
+    // The values can be of any type, so the arena can't be sent to another thread
+    marker: PhantomData<*mut u8>,
+}
+
+unsafe fn drop_value<V>(value: *const u64)
+{
+    ptr::read(value as *const V);
+}
+
+impl Arena
+{
+    pub fn new() -> Arena
+    {
+        Arena
+        {
+            words: Vec::new(),
+            values: VecDeque::new(),
+            head: 0,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns true if values of type `V` can be stored without boxing them first.
+    pub fn fits<V>() -> bool
+    {
+        mem::align_of::<V>() <= mem::align_of::<u64>()
+    }
+
+    /// Moves a value into the arena, returning the key to take it out with.
+    ///
+    /// Keys are handed out in order and never reused.
+    pub fn push<V>(&mut self, value: V) -> usize
+    {
+        assert!(Arena::fits::<V>(), "value is too strictly aligned for the arena");
+        let offset = self.words.len();
+        // Every value takes up at least a word, so even zero-sized ones point into the buffer
+        let words = cmp::max(1, (mem::size_of::<V>() + 7) / 8);
+        self.words.extend(iter::repeat(0).take(words));
+        unsafe { ptr::write(self.words.as_mut_ptr().offset(offset as isize) as *mut V, value); }
+        self.values.push_back((offset, Some(drop_value::<V>)));
+        self.head + self.values.len() - 1
+    }
+
+    /// Moves the value with `key` back out of the arena.
+    ///
+    /// The value must have been pushed as a `V`, and not taken out already.
+    pub unsafe fn take<V>(&mut self, key: usize) -> V
+    {
+        let offset = self.values[key - self.head].0;
+        let value = ptr::read(self.words.as_ptr().offset(offset as isize) as *const V);
+        self.forget(key);
+        value
+    }
+
+    /// Drops the value with `key` without taking it out, if it's still in the arena.
+    pub fn discard(&mut self, key: usize)
+    {
+        if key < self.head
+        {
+            return;
+        }
+        if let Some(&(offset, Some(destructor))) = self.values.get(key - self.head)
+        {
+            unsafe { destructor(self.words.as_ptr().offset(offset as isize)); }
+            self.forget(key);
+        }
+    }
+
+    /// Drops every value still in the arena.
+    pub fn clear(&mut self)
+    {
+        for &(offset, destructor) in self.values.iter()
+        {
+            if let Some(destructor) = destructor
+            {
+                unsafe { destructor(self.words.as_ptr().offset(offset as isize)); }
+            }
+        }
+        self.head += self.values.len();
+        self.values.clear();
+        self.words.clear();
+    }
+
+    /// Returns the number of bytes the buffer has allocated.
+    pub fn capacity(&self) -> usize
+    {
+        self.words.capacity() * mem::size_of::<u64>()
+    }
+
+    // Values are taken out roughly in order, so only a few taken ones are ever waiting behind
+    // the front to be popped
+    fn forget(&mut self, key: usize)
+    {
+        self.values[key - self.head].1 = None;
+        loop
+        {
+            match self.values.front()
+            {
+                Some(&(_, None)) => (),
+                _ => break,
+            }
+            self.values.pop_front();
+            self.head += 1;
+        }
+        if self.values.is_empty()
+        {
+            self.words.clear();
+        }
+    }
+}
+
+impl Drop for Arena
+{
+    fn drop(&mut self)
+    {
+        self.clear();
+    }
+}
@@ -1,27 +1,116 @@
 
-use {ComponentManager, EntityData};
+use {Component, ComponentManager, ComponentMask, Entity, EntityData, HasComponent};
 
-pub struct Aspect<T: ComponentManager>(Box<Fn(&EntityData, &T) -> bool + 'static>);
+pub struct Aspect<T: ComponentManager>
+{
+    check: Box<Fn(&EntityData, &T) -> bool + 'static>,
+    // The components the check looks at, if it only looks at which components an entity has
+    watched: Option<Box<Fn(&T) -> ComponentMask + 'static>>,
+}
 
 impl<T: ComponentManager> Aspect<T>
 {
     pub fn all() -> Aspect<T>
     {
-        Aspect(Box::new(|_, _| true))
+        Aspect { check: Box::new(|_, _| true), watched: Some(Box::new(|_| ComponentMask::empty())) }
     }
 
     pub fn none() -> Aspect<T>
     {
-        Aspect(Box::new(|_, _| false))
+        Aspect { check: Box::new(|_, _| false), watched: Some(Box::new(|_| ComponentMask::empty())) }
     }
 
     pub unsafe fn new(inner: Box<Fn(&EntityData, &T) -> bool + 'static>) -> Aspect<T>
     {
-        Aspect(inner)
+        Aspect { check: inner, watched: None }
+    }
+
+    /// Creates an aspect whose check only depends on the components in the mask returned by
+    /// `watched`, which lets systems skip entities whose other components change.
+    #[doc(hidden)]
+    pub unsafe fn watching(inner: Box<Fn(&EntityData, &T) -> bool + 'static>,
+                           watched: Box<Fn(&T) -> ComponentMask + 'static>) -> Aspect<T>
+    {
+        Aspect { check: inner, watched: Some(watched) }
+    }
+
+    /// Narrows the aspect to entities that have every component in the tuple `C`.
+    pub fn with<C>(self) -> Aspect<T> where C: ComponentSet<T>+'static
+    {
+        let inner = self.check;
+        Aspect { check: Box::new(move |en, co| (*inner)(en, co) && C::all_present(en, co)), watched: watch::<T, C>(self.watched) }
+    }
+
+    /// Narrows the aspect to entities that have at least one component in the tuple `C`.
+    pub fn with_any<C>(self) -> Aspect<T> where C: ComponentSet<T>+'static
+    {
+        let inner = self.check;
+        Aspect { check: Box::new(move |en, co| (*inner)(en, co) && C::any_present(en, co)), watched: watch::<T, C>(self.watched) }
+    }
+
+    /// Narrows the aspect to entities that have none of the components in the tuple `C`.
+    pub fn without<C>(self) -> Aspect<T> where C: ComponentSet<T>+'static
+    {
+        let inner = self.check;
+        Aspect { check: Box::new(move |en, co| (*inner)(en, co) && !C::any_present(en, co)), watched: watch::<T, C>(self.watched) }
     }
 
     pub fn check<'a>(&self, entity: &EntityData<'a>, components: &T) -> bool
     {
-        (self.0)(entity, components)
+        (self.check)(entity, components)
     }
+
+    /// Returns the components whose presence decides whether an entity matches the aspect.
+    ///
+    /// Returns `None` for aspects made with `Aspect::new`, as they can look at anything.
+    pub fn watched(&self, components: &T) -> Option<ComponentMask>
+    {
+        self.watched.as_ref().map(|watched| (**watched)(components))
+    }
+}
+
+fn watch<T, C>(watched: Option<Box<Fn(&T) -> ComponentMask + 'static>>) -> Option<Box<Fn(&T) -> ComponentMask + 'static>>
+    where T: ComponentManager, C: ComponentSet<T>+'static
+{
+    watched.map(|inner| Box::new(move |co: &T| (*inner)(co) | C::mask(co)) as Box<Fn(&T) -> ComponentMask>)
+}
+
+/// A tuple of component types that an `Aspect` can look for.
+pub trait ComponentSet<T: ComponentManager>
+{
+    fn all_present(entity: &Entity, components: &T) -> bool;
+    fn any_present(entity: &Entity, components: &T) -> bool;
+    fn mask(components: &T) -> ComponentMask;
 }
+
+macro_rules! impl_component_set {
+    ($($C:ident),+) => {
+        impl<T: ComponentManager, $($C: Component),+> ComponentSet<T> for ($($C,)+)
+            where $(T: HasComponent<$C>),+
+        {
+            fn all_present(entity: &Entity, components: &T) -> bool
+            {
+                components.mask_of(entity).contains(<Self as ComponentSet<T>>::mask(components))
+            }
+
+            fn any_present(entity: &Entity, components: &T) -> bool
+            {
+                components.mask_of(entity).intersects(<Self as ComponentSet<T>>::mask(components))
+            }
+
+            fn mask(components: &T) -> ComponentMask
+            {
+                ComponentMask::empty() $(| HasComponent::<$C>::list(components).mask())+
+            }
+        }
+    };
+}
+
+impl_component_set!(A);
+impl_component_set!(A, B);
+impl_component_set!(A, B, C);
+impl_component_set!(A, B, C, D);
+impl_component_set!(A, B, C, D, E);
+impl_component_set!(A, B, C, D, E, F);
+impl_component_set!(A, B, C, D, E, F, G);
+impl_component_set!(A, B, C, D, E, F, G, H);
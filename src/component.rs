@@ -1,107 +1,703 @@
 
-use std::collections::{HashMap, VecMap};
-use std::ops::{Index, IndexMut};
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::btree_set;
+use std::iter::Cloned;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut, Index, IndexMut};
 
-use self::InnerComponentList::{Hot, Cold};
+use self::InnerComponentList::{Hot, Cold, Null, Sparse, Buffered, Custom};
 
 use {BuildData, EditData, ModifyData};
 use Entity;
+use entity::Generations;
 use ComponentManager;
+use mask::{ComponentMask, ComponentMasks, ManagedMask};
+use storage::{ComponentStorage, DoubleBuffered, VecStorage, HashMapStorage, NullStorage, SparseSetStorage};
+use storage::{VecIter, VecIterMut, HashMapIter, HashMapIterMut, NullIter, NullIterMut};
+use storage::{SparseSetIter, SparseSetIterMut};
 
 pub trait Component: 'static {}
 
 impl<T:'static> Component for T {}
 
-pub struct ComponentList<T: Component>(InnerComponentList<T>);
+pub struct ComponentList<T: Component>
+{
+    inner: InnerComponentList<T>,
+    changes: Changes,
+    pool: Pool<T>,
+    mask: ManagedMask,
+}
+
+/// Components that a pooled `ComponentList` can reuse after their entity is removed.
+pub trait Poolable: Default
+{
+    /// Resets the component to a blank state, keeping any allocations it owns.
+    fn reset(&mut self);
+}
+
+/// Statistics about the pool of a `ComponentList`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PoolStats
+{
+    /// Number of components currently waiting in the pool.
+    pub available: usize,
+    /// Number of components taken from the pool by `add_pooled`.
+    pub reused: usize,
+    /// Number of components `add_pooled` had to create because the pool was empty.
+    pub created: usize,
+}
+
+/// Statistics about the components stored by a `ComponentList`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ComponentStats
+{
+    /// Name of the component list's field in the component manager.
+    pub name: &'static str,
+    pub type_name: &'static str,
+    /// Number of entities that have the component.
+    pub count: usize,
+    /// Number of components that can be stored without allocating.
+    pub capacity: usize,
+    /// Rough number of bytes allocated by the storage and pool.
+    pub bytes: usize,
+}
+
+// Components of removed entities, kept around to be reused by `add_pooled`.
+struct Pool<T>
+{
+    enabled: bool,
+    values: Vec<T>,
+    reused: usize,
+    created: usize,
+}
+
+/// Implemented by component managers that have a single list of components of type `T`.
+///
+/// This allows components to be accessed by type through `DataHelper::get` and
+/// `DataHelper::get_mut`. Declare the manager with `#[access]` in `components!`, or use the
+/// `component_access!` macro, to implement it.
+pub trait HasComponent<T: Component>
+{
+    fn list(&self) -> &ComponentList<T>;
+    fn list_mut(&mut self) -> &mut ComponentList<T>;
+    /// Returns the name of the field the list is kept in, for looking it up dynamically.
+    fn name(&self) -> &'static str;
+}
 
 enum InnerComponentList<T: Component>
 {
-    Hot(VecMap<T>),
-    Cold(HashMap<usize, T>),
+    Hot(VecStorage<T>),
+    Cold(HashMapStorage<T>),
+    Null(NullStorage<T>),
+    Sparse(SparseSetStorage<T>),
+    Buffered(DoubleBuffered<T>),
+    Custom(Box<ComponentStorage<T>+Send+Sync>),
+}
+
+// Entities whose components have been written since changes were last cleared, and the
+// version of each entity's latest change, which isn't affected by clearing.
+struct Changes
+{
+    tracking: bool,
+    inserted: BTreeSet<Entity>,
+    modified: BTreeSet<Entity>,
+    version: u64,
+    versions: BTreeMap<Entity, u64>,
+}
+
+impl Changes
+{
+    fn new() -> Changes
+    {
+        Changes {
+            tracking: false,
+            inserted: BTreeSet::new(),
+            modified: BTreeSet::new(),
+            version: 0,
+            versions: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, entity: Entity, replaced: bool)
+    {
+        if self.tracking
+        {
+            self.version += 1;
+            self.versions.insert(entity, self.version);
+            if replaced
+            {
+                self.modified.insert(entity);
+            }
+            else
+            {
+                self.inserted.insert(entity);
+            }
+        }
+    }
+
+    fn modify(&mut self, entity: Entity)
+    {
+        if self.tracking
+        {
+            self.version += 1;
+            self.versions.insert(entity, self.version);
+            self.modified.insert(entity);
+        }
+    }
+
+    fn forget(&mut self, entity: &Entity)
+    {
+        self.inserted.remove(entity);
+        self.modified.remove(entity);
+        self.versions.remove(entity);
+    }
+
+    fn clear(&mut self)
+    {
+        self.inserted.clear();
+        self.modified.clear();
+    }
 }
 
 impl<T: Component> ComponentList<T>
 {
+    /// Creates a list backed by a `VecStorage`.
     pub fn hot() -> ComponentList<T>
     {
-        ComponentList(Hot(VecMap::new()))
+        ComponentList {
+            inner: Hot(VecStorage::new()),
+            changes: Changes::new(),
+            pool: Pool { enabled: false, values: Vec::new(), reused: 0, created: 0 },
+            mask: ManagedMask::new(),
+        }
     }
 
+    /// Creates a list backed by a `HashMapStorage`.
     pub fn cold() -> ComponentList<T>
     {
-        ComponentList(Cold(HashMap::new()))
+        ComponentList {
+            inner: Cold(HashMapStorage::new()),
+            changes: Changes::new(),
+            pool: Pool { enabled: false, values: Vec::new(), reused: 0, created: 0 },
+            mask: ManagedMask::new(),
+        }
+    }
+
+    /// Creates a list backed by a `NullStorage`. Panics if `T` is not zero-sized.
+    pub fn null() -> ComponentList<T> where T: Default
+    {
+        ComponentList {
+            inner: Null(NullStorage::new()),
+            changes: Changes::new(),
+            pool: Pool { enabled: false, values: Vec::new(), reused: 0, created: 0 },
+            mask: ManagedMask::new(),
+        }
+    }
+
+    /// Creates a list backed by a `SparseSetStorage`.
+    pub fn sparse() -> ComponentList<T>
+    {
+        ComponentList {
+            inner: Sparse(SparseSetStorage::new()),
+            changes: Changes::new(),
+            pool: Pool { enabled: false, values: Vec::new(), reused: 0, created: 0 },
+            mask: ManagedMask::new(),
+        }
+    }
+
+    /// Creates a list backed by a `DoubleBuffered` storage.
+    pub fn double_buffered() -> ComponentList<T> where T: Clone
+    {
+        ComponentList {
+            inner: Buffered(DoubleBuffered::new()),
+            changes: Changes::new(),
+            pool: Pool { enabled: false, values: Vec::new(), reused: 0, created: 0 },
+            mask: ManagedMask::new(),
+        }
+    }
+
+    /// Creates a list backed by any other implementation of `ComponentStorage`.
+    ///
+    /// The storage has to be `Send` and `Sync`, so that worlds using it can still be shared.
+    pub fn with_storage<S: ComponentStorage<T>+Send+Sync+'static>(storage: S) -> ComponentList<T>
+    {
+        ComponentList {
+            inner: Custom(Box::new(storage)),
+            changes: Changes::new(),
+            pool: Pool { enabled: false, values: Vec::new(), reused: 0, created: 0 },
+            mask: ManagedMask::new(),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn assign_bit(mut self, bit: usize) -> ComponentList<T>
+    {
+        self.mask.assign_bit(bit);
+        self
+    }
+
+    /// Returns true if the list is a null list, which looks up generations in the world's entity
+    /// manager once they are shared with it.
+    #[doc(hidden)]
+    pub fn needs_generations(&self) -> bool
+    {
+        match self.inner
+        {
+            Null(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Lets a null list look up the generations of its entities in the world's entity manager.
+    #[doc(hidden)]
+    pub fn share_generations(&mut self, generations: &Generations)
+    {
+        if let Null(ref mut c) = self.inner
+        {
+            c.share_generations(generations);
+        }
+    }
+
+    /// Returns the mask with this list's bit set.
+    ///
+    /// The mask is empty if the list doesn't belong to a component manager.
+    pub fn mask(&self) -> ComponentMask
+    {
+        self.mask.mask()
+    }
+
+    /// Updates the component manager's masks with the entities that have gained or lost their
+    /// component since the last update.
+    #[doc(hidden)]
+    pub fn update_masks(&mut self, masks: &mut ComponentMasks)
+    {
+        let mut changes = mem::replace(&mut self.mask, ManagedMask::new());
+        changes.update_masks(masks, |entity| self.storage().contains(**entity));
+        self.mask = changes;
+    }
+
+    /// Returns `mask` with this list's bit set if the entity has the component, looking in the
+    /// storage only if the list has changed since the masks were last updated.
+    #[doc(hidden)]
+    pub fn update_mask(&self, entity: &Entity, mask: ComponentMask) -> ComponentMask
+    {
+        self.mask.update_mask(entity, mask, |entity| self.storage().contains(**entity))
+    }
+
+    /// Returns the storage backing this list.
+    pub fn storage(&self) -> &ComponentStorage<T>
+    {
+        match self.inner
+        {
+            Hot(ref c) => c,
+            Cold(ref c) => c,
+            Null(ref c) => c,
+            Sparse(ref c) => c,
+            Buffered(ref c) => c,
+            Custom(ref c) => &**c,
+        }
+    }
+
+    fn storage_mut(&mut self) -> &mut ComponentStorage<T>
+    {
+        match self.inner
+        {
+            Hot(ref mut c) => c,
+            Cold(ref mut c) => c,
+            Null(ref mut c) => c,
+            Sparse(ref mut c) => c,
+            Buffered(ref mut c) => c,
+            Custom(ref mut c) => &mut **c,
+        }
     }
 
     pub fn add(&mut self, entity: &BuildData, component: T) -> Option<T>
     {
-        match self.0
+        let previous = self.storage_mut().insert(*entity.0, component);
+        self.changes.insert(*entity.0, previous.is_some());
+        if previous.is_none()
         {
-            Hot(ref mut c) => c.insert(**entity.0, component),
-            Cold(ref mut c) => c.insert(**entity.0, component),
+            self.mask.toggled(*entity.0);
         }
+        previous
     }
 
-    pub fn insert(&mut self, entity: &ModifyData, component: T) -> Option<T>
+    /// Adds the default value of the component, returning it so that it can be adjusted.
+    pub fn add_default(&mut self, entity: &BuildData) -> &mut T where T: Default
     {
-        match self.0
+        self.add(entity, Default::default());
+        match self.storage_mut().get_mut(**entity.0)
         {
-            Hot(ref mut c) => c.insert(**entity.entity(), component),
-            Cold(ref mut c) => c.insert(**entity.entity(), component),
+            Some((_, c)) => c,
+            None => unreachable!(),
         }
     }
 
+    /// Adds a component taken from the pool, or a default one if the pool is empty.
+    ///
+    /// Pooled components are reset before they are returned, so that they can be adjusted.
+    pub fn add_pooled(&mut self, entity: &BuildData) -> &mut T where T: Poolable
+    {
+        let component = match self.pool.values.pop()
+        {
+            Some(mut component) => {
+                self.pool.reused += 1;
+                component.reset();
+                component
+            },
+            None => {
+                self.pool.created += 1;
+                Default::default()
+            },
+        };
+        self.add(entity, component);
+        match self.storage_mut().get_mut(**entity.0)
+        {
+            Some((_, c)) => c,
+            None => unreachable!(),
+        }
+    }
+
+    pub fn insert(&mut self, entity: &ModifyData, component: T) -> Option<T>
+    {
+        self.set(entity, component)
+    }
+
     pub fn remove(&mut self, entity: &ModifyData) -> Option<T>
     {
-        match self.0
+        self.changes.forget(entity.0);
+        let removed = self.storage_mut().remove(**entity.0);
+        if removed.is_some()
         {
-            Hot(ref mut c) => c.remove(entity.entity()),
-            Cold(ref mut c) => c.remove(entity.entity()),
+            self.mask.toggled(*entity.0);
         }
+        removed
     }
 
     pub fn set<U: EditData>(&mut self, entity: &U, component: T) -> Option<T>
     {
-        match self.0
+        let previous = self.storage_mut().insert(*entity.entity(), component);
+        self.changes.insert(*entity.entity(), previous.is_some());
+        if previous.is_none()
         {
-            Hot(ref mut c) => c.insert(**entity.entity(), component),
-            Cold(ref mut c) => c.insert(**entity.entity(), component),
+            self.mask.toggled(*entity.entity());
         }
+        previous
     }
 
-    pub fn get<U: EditData>(&self, entity: &U) -> Option<T> where T: Clone
+    /// Sets the component of an entity from a value of unknown type.
+    ///
+    /// Returns true if the component was added rather than replaced, or gives the value back if
+    /// it isn't of type `T`.
+    #[doc(hidden)]
+    pub unsafe fn set_any(&mut self, entity: &Entity, component: Box<Any>) -> Result<bool, Box<Any>>
     {
-        match self.0
+        match component.downcast::<T>()
         {
-            Hot(ref c) => c.get(entity.entity()).cloned(),
-            Cold(ref c) => c.get(entity.entity()).cloned(),
+            Ok(component) => Ok(self.set(&ModifyData(entity), *component).is_none()),
+            Err(component) => Err(component),
         }
     }
 
+    pub fn get<U: EditData>(&self, entity: &U) -> Option<T> where T: Clone
+    {
+        self.storage().get(**entity.entity()).map(|(_, c)| c.clone())
+    }
+
     pub fn has<U: EditData>(&self, entity: &U) -> bool
     {
-        match self.0
+        self.storage().contains(**entity.entity())
+    }
+
+    pub fn borrow<U: EditData>(&mut self, entity: &U) -> Option<&mut T>
+    {
+        if self.storage().contains(**entity.entity())
         {
-            Hot(ref c) => c.contains_key(entity.entity()),
-            Cold(ref c) => c.contains_key(entity.entity()),
+            self.changes.modify(*entity.entity());
         }
+        self.storage_mut().get_mut(**entity.entity()).map(|(_, c)| c)
     }
 
-    pub fn borrow<U: EditData>(&mut self, entity: &U) -> Option<&mut T>
+    /// Returns the component of an entity, if the entity has one.
+    ///
+    /// Components left behind by an entity that has been removed are never returned.
+    pub fn lookup(&self, entity: &Entity) -> Option<&T>
     {
-        match self.0
+        match self.storage().get(**entity)
         {
-            Hot(ref mut c) => c.get_mut(entity.entity()),
-            Cold(ref mut c) => c.get_mut(entity.entity()),
+            Some((owner, c)) if owner == *entity => Some(c),
+            _ => None,
         }
     }
 
-    pub unsafe fn clear(&mut self, entity: &Entity)
+    /// Returns a mutable reference to the component of an entity, if the entity has one.
+    pub fn lookup_mut(&mut self, entity: &Entity) -> Option<&mut T>
+    {
+        if self.changes.tracking && self.lookup(entity).is_some()
+        {
+            self.changes.modify(*entity);
+        }
+        match self.storage_mut().get_mut(**entity)
+        {
+            Some((owner, c)) if owner == *entity => Some(c),
+            _ => None,
+        }
+    }
+
+    /// Returns the component an entity had when the buffers were last swapped.
+    ///
+    /// Panics if the list isn't double buffered.
+    pub fn previous(&self, entity: &Entity) -> Option<&T>
+    {
+        match self.inner
+        {
+            Buffered(ref c) => match c.previous(**entity)
+            {
+                Some((owner, c)) if owner == *entity => Some(c),
+                _ => None,
+            },
+            _ => panic!("component list is not double buffered"),
+        }
+    }
+
+    /// Makes the current value of every component its previous value, if the list is double
+    /// buffered.
+    pub fn swap_buffers(&mut self)
+    {
+        if let Buffered(ref mut c) = self.inner
+        {
+            c.swap_buffers();
+        }
+    }
+
+    /// Returns the number of entities that have this component.
+    pub fn len(&self) -> usize
+    {
+        self.storage().len()
+    }
+
+    /// Returns an iterator over every entity that has this component, along with the component.
+    pub fn iter(&self) -> ComponentIter<T>
+    {
+        match self.inner
+        {
+            Hot(ref c) => ComponentIter::Hot(c.iter()),
+            Cold(ref c) => ComponentIter::Cold(c.iter()),
+            Null(ref c) => ComponentIter::Null(c.iter()),
+            Sparse(ref c) => ComponentIter::Sparse(c.iter()),
+            Buffered(ref c) => ComponentIter::Hot(c.iter()),
+            Custom(ref c) => ComponentIter::Custom(c.iter()),
+        }
+    }
+
+    /// Returns an iterator over every entity that has this component, along with a mutable
+    /// reference to the component.
+    ///
+    /// When tracking changes, every component in the list is considered modified.
+    pub fn iter_mut(&mut self) -> ComponentIterMut<T>
+    {
+        if self.changes.tracking
+        {
+            let entities: Vec<Entity> = self.iter().map(|(entity, _)| entity).collect();
+            for entity in entities.into_iter()
+            {
+                self.changes.modify(entity);
+            }
+        }
+        match self.inner
+        {
+            Hot(ref mut c) => ComponentIterMut::Hot(c.iter_mut()),
+            Cold(ref mut c) => ComponentIterMut::Cold(c.iter_mut()),
+            Null(ref mut c) => ComponentIterMut::Null(c.iter_mut()),
+            Sparse(ref mut c) => ComponentIterMut::Sparse(c.iter_mut()),
+            Buffered(ref mut c) => ComponentIterMut::Hot(c.iter_mut()),
+            Custom(ref mut c) => ComponentIterMut::Custom(c.iter_mut()),
+        }
+    }
+
+    /// Starts recording which entities have their component inserted or modified.
+    ///
+    /// Mutable access of any kind counts as a modification, whether or not the value changes.
+    pub fn track_changes(&mut self)
+    {
+        self.changes.tracking = true;
+    }
+
+    /// Returns the entities that were given this component since changes were last cleared.
+    pub fn inserted(&self) -> Cloned<btree_set::Iter<Entity>>
+    {
+        self.changes.inserted.iter().cloned()
+    }
+
+    /// Returns the entities whose component was mutably accessed or replaced since changes were
+    /// last cleared.
+    ///
+    /// Entities that were given the component and then modified are returned by both `inserted`
+    /// and `modified`.
+    pub fn modified(&self) -> Cloned<btree_set::Iter<Entity>>
+    {
+        self.changes.modified.iter().cloned()
+    }
+
+    /// Forgets all recorded changes.
+    ///
+    /// This doesn't affect `changed_since`, so several readers can follow changes independently.
+    pub fn clear_changes(&mut self)
+    {
+        self.changes.clear();
+    }
+
+    /// Returns a counter that goes up every time a change is recorded.
+    pub fn change_version(&self) -> u64
+    {
+        self.changes.version
+    }
+
+    /// Returns the entities whose component was inserted or modified after `change_version`
+    /// returned `version`, in ascending order.
+    ///
+    /// Entities that have since lost the component aren't included.
+    pub fn changed_since(&self, version: u64) -> Vec<Entity>
+    {
+        self.changes.versions.iter().filter(|&(_, v)| *v > version).map(|(entity, _)| *entity).collect()
+    }
+
+    /// Reserves space for components of entities with indices up to `capacity`.
+    ///
+    /// Only dense storages are reserved, as sparse ones are meant for rarely used components.
+    pub fn reserve(&mut self, capacity: usize)
+    {
+        self.storage_mut().reserve(capacity);
+    }
+
+    /// Keeps the components of removed entities in a pool, to be reused by `add_pooled`.
+    ///
+    /// Components removed with `remove` are returned to the caller instead.
+    pub fn enable_pooling(&mut self)
+    {
+        self.pool.enabled = true;
+    }
+
+    /// Drops every component in the pool, releasing the memory they own.
+    pub fn shrink_pool(&mut self)
+    {
+        self.pool.values = Vec::new();
+    }
+
+    pub fn pool_stats(&self) -> PoolStats
+    {
+        PoolStats {
+            available: self.pool.values.len(),
+            reused: self.pool.reused,
+            created: self.pool.created,
+        }
+    }
+
+    /// Returns statistics about this list, which is called `name` in its component manager.
+    pub fn stats(&self, name: &'static str) -> ComponentStats
+    {
+        ComponentStats {
+            name: name,
+            type_name: type_name::<T>(),
+            count: self.storage().len(),
+            capacity: self.storage().capacity(),
+            bytes: self.storage().memory_usage() + self.pool.values.capacity() * mem::size_of::<T>(),
+        }
+    }
+
+    /// Returns a copy of every component in the list, along with its entity.
+    pub fn snapshot(&self) -> Vec<(Entity, T)> where T: Clone
+    {
+        self.iter().map(|(entity, component)| (entity, component.clone())).collect()
+    }
+
+    /// Replaces every component in the list with the ones in a snapshot taken by `snapshot`.
+    ///
+    /// The restored components count as inserted.
+    #[doc(hidden)]
+    pub unsafe fn restore_snapshot(&mut self, snapshot: &Any) where T: Clone
     {
-        match self.0
+        let snapshot = match snapshot.downcast_ref::<Vec<(Entity, T)>>()
         {
-            Hot(ref mut c) => c.remove(entity),
-            Cold(ref mut c) => c.remove(entity),
+            Some(snapshot) => snapshot,
+            None => panic!("snapshot of the wrong component list"),
         };
+        let entities: Vec<Entity> = self.iter().map(|(entity, _)| entity).collect();
+        for entity in entities.iter()
+        {
+            self.clear(entity);
+        }
+        for &(entity, ref component) in snapshot.iter()
+        {
+            self.set(&ModifyData(&entity), component.clone());
+        }
+    }
+
+    pub unsafe fn clear_all(&mut self)
+    {
+        self.changes.clear();
+        self.changes.versions.clear();
+        if self.pool.enabled
+        {
+            let entities: Vec<Entity> = self.iter().map(|(entity, _)| entity).collect();
+            for entity in entities.iter()
+            {
+                self.clear(entity);
+            }
+        }
+        self.storage_mut().clear();
+        self.mask.cleared();
+    }
+
+    pub unsafe fn clear(&mut self, entity: &Entity)
+    {
+        self.changes.forget(entity);
+        let removed = self.storage_mut().remove(**entity);
+        if let Some(component) = removed
+        {
+            self.mask.toggled(*entity);
+            if self.pool.enabled
+            {
+                self.pool.values.push(component);
+            }
+        }
+    }
+
+    pub unsafe fn contains(&self, entity: &Entity) -> bool
+    {
+        self.storage().contains(**entity)
+    }
+
+    /// Moves the component of `from` into `other`, as the component of `to`.
+    pub unsafe fn move_to(&mut self, from: &Entity, other: &mut ComponentList<T>, to: &Entity)
+    {
+        self.changes.forget(from);
+        if let Some(component) = self.storage_mut().remove(**from)
+        {
+            self.mask.toggled(*from);
+            let previous = other.storage_mut().insert(*to, component);
+            other.changes.insert(*to, previous.is_some());
+            if previous.is_none()
+            {
+                other.mask.toggled(*to);
+            }
+        }
+    }
+
+    pub unsafe fn copy(&mut self, from: &Entity, to: &Entity) where T: Clone
+    {
+        let component = self.storage().get(**from).map(|(_, c)| c.clone());
+        if let Some(component) = component
+        {
+            let previous = self.storage_mut().insert(*to, component);
+            self.changes.insert(*to, previous.is_some());
+            if previous.is_none()
+            {
+                self.mask.toggled(*to);
+            }
+        }
     }
 }
 
@@ -110,10 +706,11 @@ impl<T: Component, U: EditData> Index<U> for ComponentList<T>
     type Output = T;
     fn index(&self, en: &U) -> &T
     {
-        match self.0
+        // A component left over from an earlier entity with the same index isn't this one's
+        match self.storage().get(**en.entity())
         {
-            Hot(ref c) => &c[**en.entity()],
-            Cold(ref c) => &c[**en.entity()],
+            Some((owner, c)) if owner == *en.entity() => c,
+            _ => panic!("entity {:?} does not have this component", en.entity()),
         }
     }
 }
@@ -122,17 +719,102 @@ impl<T: Component, U: EditData> IndexMut<U> for ComponentList<T>
 {
     fn index_mut(&mut self, en: &U) -> &mut T
     {
-        match self.0
+        let entity = *en.entity();
+        self.changes.modify(entity);
+        match self.storage_mut().get_mut(*entity)
         {
-            Hot(ref mut c) => &mut c[**en.entity()],
-            Cold(ref mut c) => &mut c[**en.entity()],
+            Some((owner, c)) if owner == entity => c,
+            _ => panic!("entity {:?} does not have this component", entity),
         }
     }
 }
 
+/// Iterator over the entities in a `ComponentList` and their components.
+pub enum ComponentIter<'a, T: Component>
+{
+    #[doc(hidden)]
+    Hot(VecIter<'a, T>),
+    #[doc(hidden)]
+    Cold(HashMapIter<'a, T>),
+    #[doc(hidden)]
+    Null(NullIter<'a, T>),
+    #[doc(hidden)]
+    Sparse(SparseSetIter<'a, T>),
+    #[doc(hidden)]
+    Custom(Box<Iterator<Item=(Entity, &'a T)>+'a>),
+}
+
+impl<'a, T: Component> Iterator for ComponentIter<'a, T>
+{
+    type Item = (Entity, &'a T);
+    fn next(&mut self) -> Option<(Entity, &'a T)>
+    {
+        match *self
+        {
+            ComponentIter::Hot(ref mut iter) => iter.next(),
+            ComponentIter::Cold(ref mut iter) => iter.next(),
+            ComponentIter::Null(ref mut iter) => iter.next(),
+            ComponentIter::Sparse(ref mut iter) => iter.next(),
+            ComponentIter::Custom(ref mut iter) => iter.next(),
+        }
+    }
+}
+
+/// Mutable iterator over the entities in a `ComponentList` and their components.
+pub enum ComponentIterMut<'a, T: Component>
+{
+    #[doc(hidden)]
+    Hot(VecIterMut<'a, T>),
+    #[doc(hidden)]
+    Cold(HashMapIterMut<'a, T>),
+    #[doc(hidden)]
+    Null(NullIterMut<'a, T>),
+    #[doc(hidden)]
+    Sparse(SparseSetIterMut<'a, T>),
+    #[doc(hidden)]
+    Custom(Box<Iterator<Item=(Entity, &'a mut T)>+'a>),
+}
+
+impl<'a, T: Component> Iterator for ComponentIterMut<'a, T>
+{
+    type Item = (Entity, &'a mut T);
+    fn next(&mut self) -> Option<(Entity, &'a mut T)>
+    {
+        match *self
+        {
+            ComponentIterMut::Hot(ref mut iter) => iter.next(),
+            ComponentIterMut::Cold(ref mut iter) => iter.next(),
+            ComponentIterMut::Null(ref mut iter) => iter.next(),
+            ComponentIterMut::Sparse(ref mut iter) => iter.next(),
+            ComponentIterMut::Custom(ref mut iter) => iter.next(),
+        }
+    }
+}
+
+/// Adds the components of a newly created entity.
+///
+/// Implemented for closures taking `BuildData` and the component manager. As `create_entity`
+/// accepts any builder, the closure's argument types have to be written out, as in
+/// `|e: BuildData, c: &mut MyComponents| { .. }`.
+///
+/// If a builder panics, the entity is removed again along with any components it had been given.
 pub trait EntityBuilder<T: ComponentManager>
 {
     fn build<'a>(&mut self, BuildData<'a>, &mut T);
+
+    /// Returns a builder that runs this one and then `other`, which can replace components
+    /// this one added.
+    fn and<B>(self, other: B) -> Both<Self, B> where Self: Sized, B: EntityBuilder<T>
+    {
+        Both(self, other)
+    }
+
+    /// Returns a builder that runs this one and then the closure. If this builder only works
+    /// with one component manager, the closure's argument types don't need to be written out.
+    fn map_after<F>(self, after: F) -> MapAfter<Self, F> where Self: Sized, F: FnMut(BuildData, &mut T)
+    {
+        MapAfter(self, after)
+    }
 }
 
 impl<T: ComponentManager, F> EntityBuilder<T> for F where F: FnMut(BuildData, &mut T)
@@ -145,9 +827,270 @@ impl<T: ComponentManager, F> EntityBuilder<T> for F where F: FnMut(BuildData, &m
 
 impl<T: ComponentManager> EntityBuilder<T> for () { fn build(&mut self, _: BuildData, _: &mut T) {} }
 
+// A tuple of components builds an entity with a copy of each of them. Every type in the tuple
+// needs its own list, so a type the manager doesn't store fails with a missing `HasComponent`.
+macro_rules! impl_tuple_builder {
+    ($($C:ident),+) => {
+        impl<T: ComponentManager, $($C: Component+Clone),+> EntityBuilder<T> for ($($C,)+)
+            where $(T: HasComponent<$C>),+
+        {
+            #[allow(non_snake_case)]
+            fn build(&mut self, e: BuildData, c: &mut T)
+            {
+                let &mut ($(ref $C,)+) = self;
+                $(
+                    HasComponent::<$C>::list_mut(c).add(&e, $C.clone());
+                )+
+            }
+        }
+    };
+}
+
+impl_tuple_builder!(A);
+impl_tuple_builder!(A, B);
+impl_tuple_builder!(A, B, C);
+impl_tuple_builder!(A, B, C, D);
+impl_tuple_builder!(A, B, C, D, E);
+impl_tuple_builder!(A, B, C, D, E, F);
+impl_tuple_builder!(A, B, C, D, E, F, G);
+impl_tuple_builder!(A, B, C, D, E, F, G, H);
+
+/// Gives out mutable access to several component lists at once through a shared reference.
+///
+/// Borrowing a list mutably while it is already borrowed panics, like a `RefCell` does. Code
+/// that has already made sure its borrows don't overlap can skip the checks with the unsafe
+/// `_unchecked` methods.
+pub struct ComponentBorrows<'a, T: ComponentManager>
+{
+    components: *mut T,
+    flags: BorrowFlags,
+    __phantom: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: ComponentManager> ComponentBorrows<'a, T>
+{
+    #[doc(hidden)]
+    pub fn new(components: &'a mut T) -> ComponentBorrows<'a, T>
+    {
+        ComponentBorrows {
+            components: components,
+            flags: BorrowFlags::new(),
+            __phantom: PhantomData,
+        }
+    }
+
+    /// Borrows the list of components of type `C`.
+    ///
+    /// Panics if the list is already borrowed mutably.
+    pub fn borrow<C>(&self) -> ListRef<C> where C: Component, T: HasComponent<C>
+    {
+        self.flags.acquire(TypeId::of::<C>(), type_name::<C>(), false);
+        ListRef {
+            list: unsafe { (*self.components).list() },
+            flags: &self.flags,
+        }
+    }
+
+    /// Borrows the list of components of type `C` mutably.
+    ///
+    /// Panics if the list is already borrowed.
+    pub fn borrow_mut<C>(&self) -> ListRefMut<C> where C: Component, T: HasComponent<C>
+    {
+        self.flags.acquire(TypeId::of::<C>(), type_name::<C>(), true);
+        ListRefMut {
+            list: unsafe { (*self.components).list_mut() },
+            flags: &self.flags,
+        }
+    }
+
+    /// Borrows the list of components of type `C` without checking whether it is borrowed.
+    ///
+    /// The caller has to make sure the list isn't borrowed mutably, unchecked or not, until the
+    /// returned reference is gone.
+    pub unsafe fn borrow_unchecked<C>(&self) -> &ComponentList<C> where C: Component, T: HasComponent<C>
+    {
+        (*self.components).list()
+    }
+
+    /// Borrows the list of components of type `C` mutably without checking whether it is
+    /// borrowed.
+    ///
+    /// The caller has to make sure the list isn't borrowed at all, unchecked or not, until the
+    /// returned reference is gone.
+    pub unsafe fn borrow_mut_unchecked<C>(&self) -> &mut ComponentList<C> where C: Component, T: HasComponent<C>
+    {
+        (*self.components).list_mut()
+    }
+}
+
+fn type_name<C: Component>() -> &'static str
+{
+    unsafe { ::std::intrinsics::type_name::<C>() }
+}
+
+/// A shared borrow of a component list, released when dropped.
+pub struct ListRef<'b, C: Component>
+{
+    list: &'b ComponentList<C>,
+    flags: &'b BorrowFlags,
+}
+
+impl<'b, C: Component> Deref for ListRef<'b, C>
+{
+    type Target = ComponentList<C>;
+    fn deref(&self) -> &ComponentList<C>
+    {
+        self.list
+    }
+}
+
+impl<'b, C: Component> Drop for ListRef<'b, C>
+{
+    fn drop(&mut self)
+    {
+        self.flags.release(TypeId::of::<C>(), false);
+    }
+}
+
+/// A mutable borrow of a component list, released when dropped.
+pub struct ListRefMut<'b, C: Component>
+{
+    list: &'b mut ComponentList<C>,
+    flags: &'b BorrowFlags,
+}
+
+impl<'b, C: Component> Deref for ListRefMut<'b, C>
+{
+    type Target = ComponentList<C>;
+    fn deref(&self) -> &ComponentList<C>
+    {
+        self.list
+    }
+}
+
+impl<'b, C: Component> DerefMut for ListRefMut<'b, C>
+{
+    fn deref_mut(&mut self) -> &mut ComponentList<C>
+    {
+        self.list
+    }
+}
+
+impl<'b, C: Component> Drop for ListRefMut<'b, C>
+{
+    fn drop(&mut self)
+    {
+        self.flags.release(TypeId::of::<C>(), true);
+    }
+}
+
+// Number of shared borrows of each list, or -1 if it is borrowed mutably.
+#[doc(hidden)]
+pub struct BorrowFlags(::std::cell::RefCell<::std::collections::HashMap<TypeId, isize>>);
+
+impl BorrowFlags
+{
+    fn new() -> BorrowFlags
+    {
+        BorrowFlags(::std::cell::RefCell::new(::std::collections::HashMap::new()))
+    }
+
+    fn acquire(&self, id: TypeId, name: &str, exclusive: bool)
+    {
+        let mut flags = self.0.borrow_mut();
+        let current = flags.get(&id).cloned().unwrap_or(0);
+        if current < 0 || (exclusive && current > 0)
+        {
+            panic!("components of type {} are already borrowed", name);
+        }
+        flags.insert(id, if exclusive { -1 } else { current + 1 });
+    }
+
+    fn release(&self, id: TypeId, exclusive: bool)
+    {
+        let mut flags = self.0.borrow_mut();
+        let current = flags.get(&id).cloned().unwrap_or(0);
+        flags.insert(id, if exclusive { 0 } else { current - 1 });
+    }
+}
+
+/// A tuple of component types that can all be added with their default values.
+pub trait DefaultComponents<T: ComponentManager>
+{
+    fn add_defaults(entity: &BuildData, components: &mut T);
+}
+
+macro_rules! impl_default_components {
+    ($($C:ident),+) => {
+        impl<T: ComponentManager, $($C: Component+Default),+> DefaultComponents<T> for ($($C,)+)
+            where $(T: HasComponent<$C>),+
+        {
+            fn add_defaults(entity: &BuildData, components: &mut T)
+            {
+                $(
+                    HasComponent::<$C>::list_mut(components).add(entity, <$C as Default>::default());
+                )+
+            }
+        }
+    };
+}
+
+impl_default_components!(A);
+impl_default_components!(A, B);
+impl_default_components!(A, B, C);
+impl_default_components!(A, B, C, D);
+impl_default_components!(A, B, C, D, E);
+impl_default_components!(A, B, C, D, E, F);
+impl_default_components!(A, B, C, D, E, F, G);
+impl_default_components!(A, B, C, D, E, F, G, H);
+
+/// Builder that adds the default value of every component in the tuple `C`, then runs another
+/// builder which can override some of them.
+pub struct DefaultBuilder<C, B>(B, PhantomData<C>);
+
+/// Returns a builder that adds the default value of every component in the tuple `C`.
+pub fn defaults<C>() -> DefaultBuilder<C, ()>
+{
+    DefaultBuilder((), PhantomData)
+}
+
+impl<C, B> DefaultBuilder<C, B>
+{
+    /// Runs `builder` after the default components have been added.
+    pub fn then<F>(self, builder: F) -> DefaultBuilder<C, F>
+    {
+        DefaultBuilder(builder, PhantomData)
+    }
+}
+
+impl<T: ComponentManager, C, B> EntityBuilder<T> for DefaultBuilder<C, B>
+    where C: DefaultComponents<T>, B: EntityBuilder<T>
+{
+    fn build(&mut self, e: BuildData, c: &mut T)
+    {
+        <C as DefaultComponents<T>>::add_defaults(&e, c);
+        self.0.build(e, c);
+    }
+}
+
+/// Changes the components of an existing entity.
+///
+/// Like `EntityBuilder`, this is implemented for closures whose argument types are written out.
 pub trait EntityModifier<T: ComponentManager>
 {
     fn modify<'a>(&mut self, ModifyData<'a>, &mut T);
+
+    /// Returns a modifier that runs this one and then `other`.
+    fn and<M>(self, other: M) -> Both<Self, M> where Self: Sized, M: EntityModifier<T>
+    {
+        Both(self, other)
+    }
+
+    /// Returns a modifier that runs this one and then the closure.
+    fn map_after<F>(self, after: F) -> MapAfter<Self, F> where Self: Sized, F: FnMut(ModifyData, &mut T)
+    {
+        MapAfter(self, after)
+    }
 }
 
 impl<T: ComponentManager, F> EntityModifier<T> for F where F: FnMut(ModifyData, &mut T)
@@ -159,3 +1102,49 @@ impl<T: ComponentManager, F> EntityModifier<T> for F where F: FnMut(ModifyData,
 }
 
 impl<T: ComponentManager> EntityModifier<T> for () { fn modify(&mut self, _: ModifyData, _: &mut T) {} }
+
+/// Builder or modifier that runs the first one it holds and then the second.
+pub struct Both<A, B>(pub A, pub B);
+
+impl<T: ComponentManager, A, B> EntityBuilder<T> for Both<A, B>
+    where A: EntityBuilder<T>, B: EntityBuilder<T>
+{
+    fn build(&mut self, e: BuildData, c: &mut T)
+    {
+        self.0.build(BuildData(e.0), c);
+        self.1.build(e, c);
+    }
+}
+
+impl<T: ComponentManager, A, B> EntityModifier<T> for Both<A, B>
+    where A: EntityModifier<T>, B: EntityModifier<T>
+{
+    fn modify(&mut self, e: ModifyData, c: &mut T)
+    {
+        self.0.modify(ModifyData(e.0), c);
+        self.1.modify(e, c);
+    }
+}
+
+/// Builder or modifier that runs another one and then a closure.
+pub struct MapAfter<B, F>(pub B, pub F);
+
+impl<T: ComponentManager, B, F> EntityBuilder<T> for MapAfter<B, F>
+    where B: EntityBuilder<T>, F: FnMut(BuildData, &mut T)
+{
+    fn build(&mut self, e: BuildData, c: &mut T)
+    {
+        self.0.build(BuildData(e.0), c);
+        (self.1)(e, c);
+    }
+}
+
+impl<T: ComponentManager, M, F> EntityModifier<T> for MapAfter<M, F>
+    where M: EntityModifier<T>, F: FnMut(ModifyData, &mut T)
+{
+    fn modify(&mut self, e: ModifyData, c: &mut T)
+    {
+        self.0.modify(ModifyData(e.0), c);
+        (self.1)(e, c);
+    }
+}
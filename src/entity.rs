@@ -1,23 +1,39 @@
 
 //! Entity identifier and manager types.
 
-use std::collections::hash_set::{HashSet, Iter, Drain};
+use std::collections::HashSet;
+use std::iter;
+use std::collections::btree_set::{BTreeSet, Iter};
+use std::mem;
+use std::convert::From;
 use std::default::Default;
+use std::error::Error;
+use std::fmt;
 use std::marker::PhantomData;
 use std::ops::Deref;
+use std::slice;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
 
 use Aspect;
 use ComponentManager;
+use DataHelper;
 use EntityData;
 
-pub type Id = u64;
+pub type Generation = u64;
+
+/// Old name for `Generation`, from when the second half of an entity was unique to it.
+#[deprecated]
+pub type Id = Generation;
 
 /// Dual identifier for an entity.
 ///
 /// The first element (usize) is the entity's index, used to locate components.
-/// This value can be recycled, so the second element (u64) is used as an identifier.
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
-pub struct Entity(usize, Id);
+/// This value can be recycled, so the second element (u64) is the generation of the index,
+/// which is incremented every time the index is recycled.
+///
+/// Entities are ordered by index, which is the order they are iterated in.
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Entity(usize, Generation);
 
 impl Entity
 {
@@ -26,6 +42,12 @@ impl Entity
         Entity(0, 0)
     }
 
+    #[doc(hidden)]
+    pub fn from_raw(index: usize, generation: Generation) -> Entity
+    {
+        Entity(index, generation)
+    }
+
     /// Returns the entity's index.
     #[inline]
     pub fn get_index(&self) -> usize
@@ -33,12 +55,27 @@ impl Entity
         self.0.clone()
     }
 
-    /// Returns the entity's unique identifier.
+    /// Returns the generation of the entity's index.
     #[inline]
-    pub fn get_id(&self) -> Id
+    pub fn get_generation(&self) -> Generation
     {
         self.1.clone()
     }
+
+    /// Old name for `get_generation`.
+    #[deprecated]
+    #[inline]
+    pub fn get_id(&self) -> Generation
+    {
+        self.get_generation()
+    }
+
+    /// Returns a weak handle to this entity.
+    #[inline]
+    pub fn downgrade(&self) -> WeakEntity
+    {
+        WeakEntity(*self)
+    }
 }
 
 impl Default for Entity
@@ -60,12 +97,228 @@ impl Deref for Entity
     }
 }
 
+/// Handle to an entity that might have been removed.
+///
+/// Unlike `Entity`, it can't be used directly, and has to be upgraded first.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct WeakEntity(Entity);
+
+impl WeakEntity
+{
+    /// Returns the entity if it is still valid.
+    pub fn upgrade<T: ComponentManager>(&self, data: &DataHelper<T>) -> Option<Entity>
+    {
+        if data.is_valid(&self.0)
+        {
+            Some(self.0)
+        }
+        else
+        {
+            None
+        }
+    }
+}
+
+impl From<Entity> for WeakEntity
+{
+    fn from(entity: Entity) -> WeakEntity
+    {
+        entity.downgrade()
+    }
+}
+
+/// Error returned by operations given an entity they can't use.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum InvalidEntity
+{
+    /// The entity has been removed, or was never created by this world.
+    Removed(Entity),
+    /// The entity is queued for removal, so changes to it would be dropped.
+    PendingRemoval(Entity),
+    /// The entity wasn't reserved with `reserve_ids`, or has already been built.
+    NotReserved(Entity),
+}
+
+impl InvalidEntity
+{
+    pub fn entity(&self) -> Entity
+    {
+        match *self
+        {
+            InvalidEntity::Removed(entity) | InvalidEntity::PendingRemoval(entity)
+                | InvalidEntity::NotReserved(entity) => entity,
+        }
+    }
+}
+
+impl fmt::Display for InvalidEntity
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} {:?}", self.description(), self.entity())
+    }
+}
+
+impl Error for InvalidEntity
+{
+    fn description(&self) -> &str
+    {
+        match *self
+        {
+            InvalidEntity::Removed(_) => "invalid entity",
+            InvalidEntity::PendingRemoval(_) => "entity queued for removal",
+            InvalidEntity::NotReserved(_) => "entity not reserved",
+        }
+    }
+}
+
+/// Entity paired with its debug name, for use in log and panic messages.
+pub struct DebugEntity<'a>(pub Entity, pub Option<&'a str>);
+
+impl<'a> fmt::Debug for DebugEntity<'a>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self.1
+        {
+            Some(name) => write!(f, "{:?} {:?}", self.0, name),
+            None => write!(f, "{:?}", self.0),
+        }
+    }
+}
+
+/// Stores debug names for entities. Compiled away when debug assertions are disabled.
+#[cfg(debug_assertions)]
+#[doc(hidden)]
+pub struct DebugNames
+{
+    names: ::std::collections::HashMap<Entity, String>,
+    // Copy of the names that `EntityData` can read while the world is updating or flushing
+    shared: SharedNames,
+}
+
+#[cfg(debug_assertions)]
+type SharedNames = ::std::rc::Rc<::std::cell::RefCell<::std::collections::HashMap<Entity, String>>>;
+
+#[cfg(not(debug_assertions))]
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct DebugNames;
+
+/// Makes a world's debug names available to `EntityData`'s `Debug` impl until it is dropped.
+#[cfg(debug_assertions)]
+#[doc(hidden)]
+pub struct NameScope(Option<SharedNames>);
+
+#[cfg(not(debug_assertions))]
+#[doc(hidden)]
+pub struct NameScope;
+
+#[cfg(debug_assertions)]
+thread_local!(static SCOPED_NAMES: ::std::cell::RefCell<Option<SharedNames>> = ::std::cell::RefCell::new(None));
+
+#[cfg(debug_assertions)]
+impl DebugNames
+{
+    pub fn new() -> DebugNames
+    {
+        let names = ::std::collections::HashMap::new();
+        DebugNames { names: names.clone(), shared: ::std::rc::Rc::new(::std::cell::RefCell::new(names)) }
+    }
+
+    pub fn set(&mut self, entity: Entity, name: &str)
+    {
+        self.names.insert(entity, name.to_string());
+        self.shared.borrow_mut().insert(entity, name.to_string());
+    }
+
+    pub fn get(&self, entity: &Entity) -> Option<&str>
+    {
+        self.names.get(entity).map(|name| &name[..])
+    }
+
+    pub fn remove(&mut self, entity: &Entity)
+    {
+        self.names.remove(entity);
+        self.shared.borrow_mut().remove(entity);
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.names.clear();
+        self.shared.borrow_mut().clear();
+    }
+
+    pub fn scope(&self) -> NameScope
+    {
+        let previous = SCOPED_NAMES.with(|names| mem::replace(&mut *names.borrow_mut(), Some(self.shared.clone())));
+        NameScope(previous)
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Clone for DebugNames
+{
+    fn clone(&self) -> DebugNames
+    {
+        DebugNames { names: self.names.clone(), shared: ::std::rc::Rc::new(::std::cell::RefCell::new(self.names.clone())) }
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for NameScope
+{
+    fn drop(&mut self)
+    {
+        let previous = self.0.take();
+        SCOPED_NAMES.with(|names| *names.borrow_mut() = previous);
+    }
+}
+
+#[cfg(not(debug_assertions))]
+impl DebugNames
+{
+    pub fn new() -> DebugNames { DebugNames }
+    pub fn set(&mut self, _: Entity, _: &str) {}
+    pub fn get(&self, _: &Entity) -> Option<&str> { None }
+    pub fn remove(&mut self, _: &Entity) {}
+    pub fn clear(&mut self) {}
+    pub fn scope(&self) -> NameScope { NameScope }
+}
+
+/// Calls `f` with the debug name of an entity in the world that is currently updating or
+/// flushing, if there is one.
+#[cfg(debug_assertions)]
+#[doc(hidden)]
+pub fn with_debug_name<F, R>(entity: &Entity, f: F) -> R where F: FnOnce(Option<&str>) -> R
+{
+    SCOPED_NAMES.with(|names| match *names.borrow()
+    {
+        Some(ref names) => f(names.borrow().get(entity).map(|name| &name[..])),
+        None => f(None),
+    })
+}
+
+#[cfg(not(debug_assertions))]
+#[doc(hidden)]
+pub fn with_debug_name<F, R>(_: &Entity, f: F) -> R where F: FnOnce(Option<&str>) -> R
+{
+    f(None)
+}
+
 pub struct EntityIter<'a, T: ComponentManager>
 {
-    inner: Iter<'a, Entity>,
+    inner: EntityIterInner<'a>,
+    excluded: Vec<&'a HashSet<Entity>>,
     __phantom: PhantomData<fn(T)>,
 }
 
+enum EntityIterInner<'a>
+{
+    Set(Iter<'a, Entity>),
+    Slice(slice::Iter<'a, Entity>),
+}
+
 pub struct FilteredEntityIter<'a, T: ComponentManager>
 {
     inner: EntityIter<'a, T>,
@@ -73,17 +326,57 @@ pub struct FilteredEntityIter<'a, T: ComponentManager>
     components: &'a T,
 }
 
+/// Iterator over entities accepted by a predicate on their components.
+pub struct PredicateEntityIter<'a, T: ComponentManager, F>
+{
+    inner: EntityIter<'a, T>,
+    predicate: F,
+    components: &'a T,
+}
+
 impl<'a, T: ComponentManager> EntityIter<'a, T>
 {
     pub fn new(iter: Iter<'a, Entity>) -> EntityIter<'a, T>
     {
         EntityIter
         {
-            inner: iter,
+            inner: EntityIterInner::Set(iter),
+            excluded: Vec::new(),
+            __phantom: PhantomData::<fn(T)>,
+        }
+    }
+
+    /// Iterates over the entities in the order they appear in the slice.
+    pub fn from_slice(entities: &'a [Entity]) -> EntityIter<'a, T>
+    {
+        EntityIter
+        {
+            inner: EntityIterInner::Slice(entities.iter()),
+            excluded: Vec::new(),
             __phantom: PhantomData::<fn(T)>,
         }
     }
 
+    /// Skips any entities in the `excluded` set.
+    #[doc(hidden)]
+    pub fn excluding(mut self, excluded: &'a HashSet<Entity>) -> EntityIter<'a, T>
+    {
+        self.excluded.push(excluded);
+        self
+    }
+
+    /// Only yields entities for which `predicate` returns true.
+    pub fn filter_components<F>(self, components: &'a T, predicate: F) -> PredicateEntityIter<'a, T, F>
+        where F: FnMut(&EntityData, &T) -> bool
+    {
+        PredicateEntityIter
+        {
+            inner: self,
+            predicate: predicate,
+            components: components,
+        }
+    }
+
     pub fn filter(self, aspect: Aspect<T>, components: &'a T) -> FilteredEntityIter<'a, T>
     {
         FilteredEntityIter
@@ -100,7 +393,37 @@ impl<'a, T: ComponentManager> Iterator for EntityIter<'a, T>
     type Item = EntityData<'a>;
     fn next(&mut self) -> Option<EntityData<'a>>
     {
-        self.inner.next().map(|x| EntityData(x))
+        loop
+        {
+            let next = match self.inner
+            {
+                EntityIterInner::Set(ref mut iter) => iter.next(),
+                EntityIterInner::Slice(ref mut iter) => iter.next(),
+            };
+            match next
+            {
+                Some(x) if self.excluded.iter().any(|excluded| excluded.contains(x)) => continue,
+                Some(x) => return Some(EntityData(x)),
+                None => return None,
+            }
+        }
+    }
+}
+
+impl<'a, T: ComponentManager, F> Iterator for PredicateEntityIter<'a, T, F>
+    where F: FnMut(&EntityData, &T) -> bool
+{
+    type Item = EntityData<'a>;
+    fn next(&mut self) -> Option<EntityData<'a>>
+    {
+        for x in self.inner.by_ref()
+        {
+            if (self.predicate)(&x, self.components)
+            {
+                return Some(x);
+            }
+        }
+        None
     }
 }
 
@@ -124,13 +447,81 @@ impl<'a, T: ComponentManager> Iterator for FilteredEntityIter<'a, T>
     }
 }
 
+/// Generation of every entity index, which storages that only keep a bit per entity share
+/// with the `EntityManager` to find out which entity owns a component.
+///
+/// Cloning copies the generations, while `share` returns a handle to the same ones.
+#[doc(hidden)]
+pub struct Generations(Arc<RwLock<Vec<Generation>>>);
+
+impl Generations
+{
+    pub fn new() -> Generations
+    {
+        Generations(Arc::new(RwLock::new(Vec::new())))
+    }
+
+    pub fn share(&self) -> Generations
+    {
+        Generations(self.0.clone())
+    }
+
+    /// Locks the generations for reading.
+    ///
+    /// Entities can't be created or removed while the lock is held, such as while a null list
+    /// is being iterated. Trying to panics instead of waiting for the lock, as only the thread
+    /// that owns the world can change its entities.
+    pub fn read(&self) -> RwLockReadGuard<Vec<Generation>>
+    {
+        match self.0.read()
+        {
+            Ok(generations) => generations,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn write(&self) -> RwLockWriteGuard<Vec<Generation>>
+    {
+        match self.0.try_write()
+        {
+            Ok(generations) => generations,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => panic!("entities can't be created or removed while a null list is being iterated"),
+        }
+    }
+
+    /// Returns the generation of an index, or zero if it has never been used.
+    pub fn get(&self, index: usize) -> Generation
+    {
+        self.read().get(index).cloned().unwrap_or(0)
+    }
+
+    pub fn set(&self, index: usize, generation: Generation)
+    {
+        let mut generations = self.write();
+        while generations.len() <= index
+        {
+            generations.push(0);
+        }
+        generations[index] = generation;
+    }
+}
+
+impl Clone for Generations
+{
+    fn clone(&self) -> Generations
+    {
+        Generations(Arc::new(RwLock::new(self.read().clone())))
+    }
+}
+
 /// Handles creation, activation, and validating of entities.
 #[doc(hidden)]
+#[derive(Clone)]
 pub struct EntityManager
 {
     indices: IndexPool,
-    entities: HashSet<Entity>,
-    next_id: Id,
+    entities: BTreeSet<Entity>,
 }
 
 impl EntityManager
@@ -141,54 +532,163 @@ impl EntityManager
         EntityManager
         {
             indices: IndexPool::new(),
-            entities: HashSet::new(),
-            next_id: 0,
+            entities: BTreeSet::new(),
         }
     }
 
+    /// Returns an iterator over all entities, in ascending order of index.
     pub fn iter<T: ComponentManager>(&self) -> EntityIter<T>
     {
         EntityIter::new(self.entities.iter())
     }
 
-    pub fn drain(&mut self) -> Drain<Entity>
+    /// Returns the number of valid entities.
+    pub fn count(&self) -> usize
+    {
+        self.indices.count()
+    }
+
+    /// Returns the number of indices allocated, including recycled ones.
+    pub fn capacity(&self) -> usize
     {
-        self.entities.drain()
+        self.indices.capacity()
     }
 
-    pub fn count(&self) -> usize
+    /// Returns the number of recycled indices waiting to be reused.
+    pub fn free_count(&self) -> usize
     {
-        self.indices.count()
+        self.indices.recycled.len()
+    }
+
+    /// Returns the highest index used by a valid entity.
+    pub fn highest_index(&self) -> Option<usize>
+    {
+        self.entities.iter().next_back().map(|entity| **entity)
     }
 
     /// Creates a new `Entity`, assigning it the first available index.
     pub fn create(&mut self) -> Entity
     {
-        self.next_id += 1;
-        let ret = Entity(self.indices.get_index(), self.next_id);
+        let index = self.indices.get_index();
+        let ret = Entity(index, self.indices.generation(index));
         self.entities.insert(ret.clone());
         ret
     }
 
+    /// Creates `count` entities at once, in the order `create` would have.
+    pub fn reserve_ids(&mut self, count: usize) -> Vec<Entity>
+    {
+        (0..count).map(|_| self.create()).collect()
+    }
+
     /// Returns true if an entity is valid (not removed from the manager).
+    ///
+    /// Both the index and the generation must match, so handles to recycled indices are invalid.
     #[inline]
     pub fn is_valid(&self, entity: &Entity) -> bool
     {
         self.entities.contains(entity)
     }
 
+    /// Reserves space for at least `additional` more entities.
+    pub fn reserve(&mut self, additional: usize)
+    {
+        self.indices.reserve(additional);
+    }
+
+    /// Returns the generations of every index, to be shared with storages.
+    ///
+    /// The shared generations are only kept up to date once this has been called, so worlds
+    /// without storages that need them don't pay for locking them.
+    pub fn share_generations(&mut self) -> &Generations
+    {
+        if !self.indices.sharing
+        {
+            *self.indices.shared.write() = self.indices.generations.clone();
+            self.indices.sharing = true;
+        }
+        &self.indices.shared
+    }
+
+    /// Returns the generation of every index, and the recycled indices in the order they were
+    /// returned.
+    pub fn indices(&self) -> (&[Generation], &[usize])
+    {
+        (&self.indices.generations, &self.indices.recycled)
+    }
+
+    /// Rebuilds a manager from what `indices` returned and the valid entities.
+    ///
+    /// Returns `None` if they don't fit together, so every index has to be either recycled or
+    /// used by exactly one entity with the same generation.
+    pub fn restore(generations: Vec<Generation>, recycled: Vec<usize>, entities: &[Entity]) -> Option<EntityManager>
+    {
+        let mut used: Vec<bool> = iter::repeat(false).take(generations.len()).collect();
+        for entity in entities.iter()
+        {
+            if entity.0 >= generations.len() || used[entity.0] || generations[entity.0] != entity.1
+            {
+                return None;
+            }
+            used[entity.0] = true;
+        }
+        for &index in recycled.iter()
+        {
+            if index >= generations.len() || used[index]
+            {
+                return None;
+            }
+            used[index] = true;
+        }
+        if used.iter().any(|used| !*used)
+        {
+            return None;
+        }
+        Some(EntityManager
+        {
+            indices: IndexPool
+            {
+                recycled: recycled,
+                generations: generations,
+                shared: Generations::new(),
+                sharing: false,
+            },
+            entities: entities.iter().cloned().collect(),
+        })
+    }
+
+    /// Deletes every entity from the manager.
+    ///
+    /// Generations are kept, so existing handles stay invalid after their indices are reused.
+    pub fn clear(&mut self)
+    {
+        let entities = mem::replace(&mut self.entities, BTreeSet::new());
+        let indices: Vec<usize> = entities.iter().map(|entity| **entity).collect();
+        // Recycled indices are popped from the back, so lower indices will be reused first
+        for index in indices.iter().rev()
+        {
+            self.indices.return_id(*index);
+        }
+    }
+
     /// Deletes an entity from the manager.
     pub fn remove(&mut self, entity: &Entity)
     {
-        self.entities.remove(entity);
-        self.indices.return_id(**entity);
+        if self.entities.remove(entity)
+        {
+            self.indices.return_id(**entity);
+        }
     }
 }
 
+#[derive(Clone)]
 struct IndexPool
 {
     recycled: Vec<usize>,
-    next_index: usize,
+    generations: Vec<Generation>,
+    // Copy of the generations that storages can read, once one has asked for it
+    shared: Generations,
+    sharing: bool,
 }
 
 impl IndexPool
@@ -198,13 +698,26 @@ impl IndexPool
         IndexPool
         {
             recycled: Vec::new(),
-            next_index: 0,
+            generations: Vec::new(),
+            shared: Generations::new(),
+            sharing: false,
         }
     }
 
     pub fn count(&self) -> usize
     {
-        self.next_index - self.recycled.len()
+        self.generations.len() - self.recycled.len()
+    }
+
+    pub fn capacity(&self) -> usize
+    {
+        self.generations.len()
+    }
+
+    pub fn reserve(&mut self, additional: usize)
+    {
+        let needed = additional.saturating_sub(self.recycled.len());
+        self.generations.reserve(needed);
     }
 
     pub fn get_index(&mut self) -> usize
@@ -213,14 +726,29 @@ impl IndexPool
         {
             Some(id) => id,
             None => {
-                self.next_index += 1;
-                self.next_index - 1
+                // Generations start at 1 so that no valid entity is equal to `Entity::nil()`
+                self.generations.push(1);
+                if self.sharing
+                {
+                    self.shared.set(self.generations.len() - 1, 1);
+                }
+                self.generations.len() - 1
             }
         }
     }
 
+    pub fn generation(&self, id: usize) -> Generation
+    {
+        self.generations[id]
+    }
+
     pub fn return_id(&mut self, id: usize)
     {
+        self.generations[id] += 1;
+        if self.sharing
+        {
+            self.shared.set(id, self.generations[id]);
+        }
         self.recycled.push(id);
     }
 }
@@ -32,25 +32,75 @@
 #![feature(core)]
 #![feature(collections)]
 #![feature(std_misc)]
+#![feature(unsafe_destructor)]
 
-pub use aspect::Aspect;
-pub use component::{Component, ComponentList};
-pub use component::{EntityBuilder, EntityModifier};
-pub use entity::{Entity, EntityIter};
-pub use system::{System, Process};
-pub use world::{ComponentManager, SystemManager, DataHelper, World};
+pub use aspect::{Aspect, ComponentSet};
+pub use component::{Component, ComponentList, ComponentIter, ComponentIterMut, HasComponent};
+pub use component::{ComponentBorrows, ComponentStats, ListRef, ListRefMut, Poolable, PoolStats};
+pub use component::{Both, DefaultBuilder, DefaultComponents, EntityBuilder, EntityModifier, MapAfter, defaults};
+pub use mask::ComponentMask;
+pub use query::{ComponentQuery, Query, QueryMut};
+pub use save::{SaveableComponent, SaveableComponents};
+pub use shared::Shared;
+pub use entity::{DebugEntity, Entity, EntityIter, InvalidEntity, PredicateEntityIter, WeakEntity};
+pub use storage::{ComponentStorage, DoubleBuffered, VecStorage, HashMapStorage, NullStorage, SparseSetStorage};
+pub use system::{DeactivationReason, Phase, System, SystemTiming, Process};
+pub use timestep::FixedTimestep;
+pub use world::{ComponentManager, SystemManager, DataHelper, QueuedEvent, QueuedEvents, World};
+pub use world::{CloneableComponents, DeferredQueue, EntityBuildHandle, ObserverHandle, UnknownPrefab};
+pub use world::{WorldBuilder, WorldSnapshot, WorldStats, WorldView};
 
+use std::fmt;
 use std::ops::{Deref};
 
 pub mod aspect;
 pub mod component;
 pub mod entity;
+pub mod mask;
+pub mod query;
+pub mod save;
+pub mod shared;
+pub mod storage;
 pub mod system;
+pub mod timestep;
 pub mod world;
 
+mod arena;
+
+/// The entity being built, passed to an `EntityBuilder`.
+///
+/// Components are added with `add`. Ones that have already been added during the same build
+/// can be read back, so a later builder can work from what an earlier one added.
 pub struct BuildData<'a>(&'a Entity);
+
+/// The entity being modified, passed to an `EntityModifier`.
+///
+/// Besides reading and changing components like `EntityData`, it can `insert` and `remove`
+/// whole components, which changes what the entity's systems see once the modifier is done.
 pub struct ModifyData<'a>(&'a Entity);
+
+/// An existing entity whose components can be read and changed, but not added or removed.
+///
+/// Lists can be indexed with it directly, as in `components.health[entity]`.
 pub struct EntityData<'a>(&'a Entity);
+
+impl<'a> BuildData<'a>
+{
+    /// Returns the entity's handle, for example to store it in another component.
+    pub fn entity(&self) -> Entity
+    {
+        *self.0
+    }
+}
+
+impl<'a> ModifyData<'a>
+{
+    /// Returns the entity's handle, for looking it up in other places.
+    pub fn entity(&self) -> Entity
+    {
+        *self.0
+    }
+}
 impl<'a> Deref for EntityData<'a>
 {
     type Target = Entity;
@@ -62,9 +112,22 @@ impl<'a> Deref for EntityData<'a>
 
 #[doc(hidden)]
 pub unsafe trait EditData { fn entity(&self) -> &Entity; }
+unsafe impl<'a> EditData for BuildData<'a> { fn entity(&self) -> &Entity { &self.0 } }
 unsafe impl<'a> EditData for ModifyData<'a> { fn entity(&self) -> &Entity { &self.0 } }
 unsafe impl<'a> EditData for EntityData<'a> { fn entity(&self) -> &Entity { &self.0 } }
 
+impl<'a> fmt::Debug for EntityData<'a>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        entity::with_debug_name(self.0, |name| match name
+        {
+            Some(name) => write!(f, "EntityData({:?} {:?})", self.0, name),
+            None => write!(f, "EntityData({:?})", self.0),
+        })
+    }
+}
+
 #[macro_use]
 mod macros
 {
@@ -77,6 +140,136 @@ mod macros
         };
     }
 
+    // Each type may only be listed once, as `HasComponent` is implemented per type. Managers
+    // declared with `#[access]` in `components!` get this for every list without repeating them.
+    #[macro_export]
+    macro_rules! component_access {
+        {
+            $Name:ident {
+                $($field_name:ident : $field_ty:ty),*
+            }
+        } => {
+            $(
+                impl $crate::HasComponent<$field_ty> for $Name
+                {
+                    fn list(&self) -> &$crate::ComponentList<$field_ty>
+                    {
+                        &self.$field_name
+                    }
+
+                    fn list_mut(&mut self) -> &mut $crate::ComponentList<$field_ty>
+                    {
+                        &mut self.$field_name
+                    }
+
+                    fn name(&self) -> &'static str
+                    {
+                        stringify!($field_name)
+                    }
+                }
+            )*
+        };
+    }
+
+    // Components are saved along with their field name, so fields can be added, removed and
+    // reordered without breaking old saves. Fields that aren't listed are not saved.
+    #[macro_export]
+    macro_rules! saveable_components {
+        {
+            $Name:ident {
+                $($field_name:ident),+
+            }
+        } => {
+            impl $crate::SaveableComponents for $Name
+            {
+                fn serialize_entity(&self, entity: &$crate::Entity, writer: &mut $crate::save::Writer)
+                    -> ::std::io::Result<()>
+                {
+                    let mut count = 0u32;
+                    $(
+                        if self.$field_name.lookup(entity).is_some()
+                        {
+                            count += 1;
+                        }
+                    )+
+                    try!(writer.write(&count));
+                    $(
+                        if let Some(component) = self.$field_name.lookup(entity)
+                        {
+                            try!(writer.write_component(stringify!($field_name), component));
+                        }
+                    )+
+                    Ok(())
+                }
+
+                fn deserialize_entity(&mut self, entity: $crate::BuildData, reader: &mut $crate::save::Reader)
+                    -> ::std::io::Result<()>
+                {
+                    let count: u32 = try!(reader.read());
+                    for _ in 0..count
+                    {
+                        // Components that are no longer saved are skipped
+                        let (name, data) = try!(reader.read_component_data());
+                        $(
+                            if name == stringify!($field_name)
+                            {
+                                let component = try!(reader.load_component(&data));
+                                self.$field_name.add(&entity, component);
+                            }
+                        )+
+                    }
+                    Ok(())
+                }
+            }
+        };
+        {
+            $Name:ident {
+                $($field_name:ident),+,
+            }
+        } => {
+            saveable_components! { $Name { $($field_name),+ } }
+        };
+    }
+
+    // Fields that aren't listed aren't rolled back by `World::restore`.
+    #[macro_export]
+    macro_rules! cloneable_components {
+        {
+            $Name:ident {
+                $($field_name:ident),+
+            }
+        } => {
+            impl $crate::CloneableComponents for $Name
+            {
+                fn clone_lists(&self) -> Vec<Box<::std::any::Any>>
+                {
+                    vec![$(
+                        Box::new(self.$field_name.snapshot()) as Box<::std::any::Any>,
+                    )+]
+                }
+
+                unsafe fn restore_lists(&mut self, lists: &[Box<::std::any::Any>])
+                {
+                    let mut lists = lists.iter();
+                    $(
+                        match lists.next()
+                        {
+                            Some(list) => self.$field_name.restore_snapshot(&**list),
+                            None => panic!("snapshot of a different component manager"),
+                        }
+                    )+
+                }
+            }
+        };
+        {
+            $Name:ident {
+                $($field_name:ident),+,
+            }
+        } => {
+            cloneable_components! { $Name { $($field_name),+ } }
+        };
+    }
+
     #[macro_export]
     macro_rules! components {
         {
@@ -95,27 +288,70 @@ mod macros
                 {
 
                 }
+
+                unsafe fn copy_all(&mut self, _: &$crate::Entity, _: &$crate::Entity)
+                {
+
+                }
+
+                unsafe fn move_all(&mut self, _: &$crate::Entity, _: &mut $Name, _: &$crate::Entity)
+                {
+
+                }
+
+                unsafe fn clear_all(&mut self)
+                {
+
+                }
+
+                unsafe fn component_mask(&self, _: &$crate::Entity) -> $crate::ComponentMask
+                {
+                    $crate::ComponentMask::empty()
+                }
             }
         };
         {
-            $Name:ident {
-                $(#[$kind:ident] $field_name:ident : $field_ty:ty),+
+            @define $Name:ident {
+                $($field_name:ident : $field_ty:ty = $list:expr,)*
+            } {
+                $($split_name:ident : $split_ty:ty,)*
             }
         } => {
             pub struct $Name {
                 $(
                     pub $field_name : $crate::ComponentList<$field_ty>,
-                )+
+                )*
+                $(
+                    pub $split_name : $split_ty,
+                )*
+                #[doc(hidden)]
+                pub __masks: $crate::mask::ComponentMasks,
             }
 
             unsafe impl $crate::ComponentManager for $Name
             {
                 unsafe fn new() -> $Name
                 {
+                    let mut bit = 0;
+                    $(
+                        assert!(bit < 64, "a component manager can have at most 64 lists and split storages");
+                        let $field_name = $list.assign_bit(bit);
+                        bit += 1;
+                    )*
+                    $(
+                        assert!(bit < 64, "a component manager can have at most 64 lists and split storages");
+                        let mut $split_name: $split_ty = ::std::default::Default::default();
+                        $crate::storage::SplitStorage::assign_bit(&mut $split_name, bit);
+                        bit += 1;
+                    )*
                     $Name {
                         $(
-                            $field_name : $crate::ComponentList::$kind(),
-                        )+
+                            $field_name : $field_name,
+                        )*
+                        $(
+                            $split_name : $split_name,
+                        )*
+                        __masks: $crate::mask::ComponentMasks::new(),
                     }
                 }
 
@@ -123,16 +359,268 @@ mod macros
                 {
                     $(
                         self.$field_name.clear(entity);
-                    )+
+                    )*
+                    $(
+                        $crate::storage::SplitStorage::remove_entity(&mut self.$split_name, entity);
+                    )*
+                    $crate::ComponentManager::update_masks(self);
+                }
+
+                unsafe fn copy_all(&mut self, from: &$crate::Entity, to: &$crate::Entity)
+                {
+                    $(
+                        self.$field_name.copy(from, to);
+                    )*
+                    $(
+                        $crate::storage::SplitStorage::copy_entity(&mut self.$split_name, from, to);
+                    )*
+                    $crate::ComponentManager::update_masks(self);
+                }
+
+                unsafe fn move_all(&mut self, from: &$crate::Entity, other: &mut $Name, to: &$crate::Entity)
+                {
+                    $(
+                        self.$field_name.move_to(from, &mut other.$field_name, to);
+                    )*
+                    $(
+                        $crate::storage::SplitStorage::move_entity(&mut self.$split_name, from, &mut other.$split_name, to);
+                    )*
+                    $crate::ComponentManager::update_masks(self);
+                    $crate::ComponentManager::update_masks(other);
+                }
+
+                unsafe fn clear_all(&mut self)
+                {
+                    $(
+                        self.$field_name.clear_all();
+                    )*
+                    $(
+                        $crate::storage::SplitStorage::clear_entities(&mut self.$split_name);
+                    )*
+                    self.__masks.clear();
+                    $crate::ComponentManager::update_masks(self);
+                }
+
+                fn needs_generations(&self) -> bool
+                {
+                    false $(|| self.$field_name.needs_generations())*
+                }
+
+                unsafe fn share_generations(&mut self, generations: &$crate::entity::Generations)
+                {
+                    $(
+                        self.$field_name.share_generations(generations);
+                    )*
+                }
+
+                unsafe fn component_mask(&self, entity: &$crate::Entity) -> $crate::ComponentMask
+                {
+                    let mut mask = $crate::ComponentMask::empty();
+                    $(
+                        if self.$field_name.contains(entity)
+                        {
+                            mask = mask | self.$field_name.mask();
+                        }
+                    )*
+                    $(
+                        mask = mask | $crate::storage::SplitStorage::component_mask(&self.$split_name, entity);
+                    )*
+                    mask
+                }
+
+                fn mask_of(&self, entity: &$crate::Entity) -> $crate::ComponentMask
+                {
+                    // Lists that have changed since the masks were last updated are looked in
+                    let mut mask = self.__masks.get(entity);
+                    $(
+                        mask = self.$field_name.update_mask(entity, mask);
+                    )*
+                    $(
+                        mask = $crate::storage::SplitStorage::update_mask(&self.$split_name, entity, mask);
+                    )*
+                    debug_assert!(mask == unsafe { self.component_mask(entity) },
+                        "component mask of {:?} doesn't match its components", entity);
+                    mask
+                }
+
+                unsafe fn update_masks(&mut self)
+                {
+                    $(
+                        self.$field_name.update_masks(&mut self.__masks);
+                    )*
+                    $(
+                        $crate::storage::SplitStorage::update_masks(&mut self.$split_name, &mut self.__masks);
+                    )*
+                }
+
+                fn stats(&self) -> Vec<$crate::ComponentStats>
+                {
+                    vec![$(
+                        self.$field_name.stats(stringify!($field_name)),
+                    )*]
+                }
+
+                fn component_types(&self) -> Vec<(&'static str, ::std::any::TypeId)>
+                {
+                    vec![$(
+                        (stringify!($field_name), ::std::any::TypeId::of::<$field_ty>()),
+                    )*]
                 }
+
+                fn get_dyn(&self, entity: &$crate::Entity, id: ::std::any::TypeId)
+                    -> Option<&::std::any::Any>
+                {
+                    $(
+                        if id == ::std::any::TypeId::of::<$field_ty>()
+                        {
+                            return self.$field_name.lookup(entity).map(|c| c as &::std::any::Any);
+                        }
+                    )*
+                    None
+                }
+
+                fn get_dyn_mut(&mut self, entity: &$crate::Entity, id: ::std::any::TypeId)
+                    -> Option<&mut ::std::any::Any>
+                {
+                    $(
+                        if id == ::std::any::TypeId::of::<$field_ty>()
+                        {
+                            return self.$field_name.lookup_mut(entity).map(|c| c as &mut ::std::any::Any);
+                        }
+                    )*
+                    None
+                }
+
+                unsafe fn set_dyn(&mut self, entity: &$crate::Entity, component: Box<::std::any::Any>)
+                    -> Result<bool, Box<::std::any::Any>>
+                {
+                    $(
+                        let component = match self.$field_name.set_any(entity, component)
+                        {
+                            Ok(added) => return Ok(added),
+                            Err(component) => component,
+                        };
+                    )*
+                    Err(component)
+                }
+
+                unsafe fn reserve(&mut self, capacity: usize)
+                {
+                    $(
+                        self.$field_name.reserve(capacity);
+                    )*
+                }
+
+                unsafe fn clear_changes(&mut self)
+                {
+                    $(
+                        self.$field_name.clear_changes();
+                    )*
+                }
+
+                fn swap_buffers(&mut self)
+                {
+                    $(
+                        self.$field_name.swap_buffers();
+                    )*
+                }
+            }
+        };
+        {
+            @fields $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*]
+        } => {
+            components! { @define $Name { $($done)* } { $($split)* } }
+            components! { @access [$($access)*] $Name { $($done)* } }
+        };
+        {
+            @access [] $Name:ident { $($fields:tt)* }
+        } => {
+
+        };
+        {
+            @access [access] $Name:ident {
+                $($field_name:ident : $field_ty:ty = $list:expr,)*
             }
+        } => {
+            component_access! { $Name { $($field_name : $field_ty),* } }
+        };
+        {
+            @fields $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] #[split] $field_name:ident : $field_ty:ty
+        } => {
+            components! { @fields $Name [$($access)*] [$($done)*] [$($split)* $field_name : $field_ty,] }
+        };
+        {
+            @fields $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] #[split] $field_name:ident : $field_ty:ty, $($rest:tt)*
+        } => {
+            components! { @fields $Name [$($access)*] [$($done)*] [$($split)* $field_name : $field_ty,] $($rest)* }
+        };
+        {
+            @fields $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] #[$kind:ident] $field_name:ident : $field_ty:ty
+        } => {
+            components! { @fields $Name [$($access)*] [$($done)* $field_name : $field_ty = $crate::ComponentList::$kind(),] [$($split)*] }
+        };
+        {
+            @fields $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] #[$kind:ident] $field_name:ident : $field_ty:ty, $($rest:tt)*
+        } => {
+            components! { @fields $Name [$($access)*] [$($done)* $field_name : $field_ty = $crate::ComponentList::$kind(),] [$($split)*] $($rest)* }
+        };
+        {
+            @fields $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] $field_name:ident : $field_ty:ty
+        } => {
+            components! { @fields $Name [$($access)*] [$($done)* $field_name : $field_ty = $crate::ComponentList::hot(),] [$($split)*] }
+        };
+        {
+            @fields $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] $field_name:ident : $field_ty:ty, $($rest:tt)*
+        } => {
+            components! { @fields $Name [$($access)*] [$($done)* $field_name : $field_ty = $crate::ComponentList::hot(),] [$($split)*] $($rest)* }
+        };
+        {
+            @fields $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] $field_name:ident : $($rest:tt)+
+        } => {
+            components! { @storage $Name [$($access)*] [$($done)*] [$($split)*] $field_name [] $($rest)+ }
+        };
+        {
+            @storage $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] $field_name:ident [$($field_ty:tt)+] -> $storage:ident
+        } => {
+            components! { @storage $Name [$($access)*] [$($done)*] [$($split)*] $field_name [$($field_ty)+] -> $storage, }
+        };
+        {
+            @storage $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] $field_name:ident [$($field_ty:tt)+] -> $storage:ident, $($rest:tt)*
+        } => {
+            components! { @fields $Name [$($access)*] [$($done)* $field_name : $($field_ty)+ =
+                <$crate::storage::$storage<$($field_ty)+> as $crate::storage::ListStorage<$($field_ty)+>>::list(),]
+                [$($split)*] $($rest)* }
+        };
+        {
+            @storage $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] $field_name:ident [$($field_ty:tt)+] = $storage:expr
+        } => {
+            components! { @storage $Name [$($access)*] [$($done)*] [$($split)*] $field_name [$($field_ty)+] = $storage, }
+        };
+        {
+            @storage $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] $field_name:ident [$($field_ty:tt)+] = $storage:expr, $($rest:tt)*
+        } => {
+            components! { @fields $Name [$($access)*] [$($done)* $field_name : $($field_ty)+ =
+                $crate::ComponentList::with_storage($storage),]
+                [$($split)*] $($rest)* }
+        };
+        {
+            @storage $Name:ident [$($access:tt)*] [$($done:tt)*] [$($split:tt)*] $field_name:ident [$($field_ty:tt)*] $next:tt $($rest:tt)*
+        } => {
+            components! { @storage $Name [$($access)*] [$($done)*] [$($split)*] $field_name [$($field_ty)* $next] $($rest)* }
+        };
+        {
+            #[access] $Name:ident {
+                $($fields:tt)+
+            }
+        } => {
+            components! { @fields $Name [access] [] [] $($fields)+ }
         };
         {
             $Name:ident {
-                $(#[$kind:ident] $field_name:ident : $field_ty:ty),+,
+                $($fields:tt)+
             }
         } => {
-            components! { $Name { $(#[$kind] $field_name : $field_ty),+ } }
+            components! { @fields $Name [] [] [] $($fields)+ }
         };
     }
 
@@ -162,7 +650,7 @@ mod macros
 
                 }
 
-                unsafe fn deactivated(&mut self, _: $crate::EntityData, _: &$components)
+                unsafe fn deactivated(&mut self, _: $crate::EntityData, _: &$components, _: $crate::DeactivationReason)
                 {
 
                 }
@@ -171,70 +659,291 @@ mod macros
                 {
 
                 }
+
+                fn system_count(&self) -> usize
+                {
+                    0
+                }
             }
         };
         {
-            $Name:ident<$components:ty> {
-                $($field_name:ident : $field_ty:ty = $field_init:expr),+
+            @define $Name:ident<$components:ty> {
+                $($field_name:ident : $field_ty:ty = $field_init:expr, $priority:expr, $phase:expr;)+
             }
         } => {
             pub struct $Name {
                 $(
                     pub $field_name : $field_ty,
                 )+
+                #[doc(hidden)]
+                pub __order: Vec<usize>,
+                #[doc(hidden)]
+                pub __phases: Vec<$crate::Phase>,
+                #[doc(hidden)]
+                pub __inactive: ::std::collections::HashSet<::std::any::TypeId>,
+                #[doc(hidden)]
+                pub __profiling: bool,
+                #[doc(hidden)]
+                pub __profile: Vec<$crate::SystemTiming>,
+            }
+
+            impl $Name
+            {
+                /// Stops or resumes processing every system of type `S`.
+                ///
+                /// Inactive systems are still notified about entities, so they are up to date
+                /// when processing resumes.
+                pub fn set_active<S: 'static>(&mut self, active: bool)
+                {
+                    let id = ::std::any::TypeId::of::<S>();
+                    debug_assert!(false $(|| id == ::std::any::TypeId::of::<$field_ty>())+,
+                        "none of the systems have the given type");
+                    if active
+                    {
+                        self.__inactive.remove(&id);
+                    }
+                    else
+                    {
+                        self.__inactive.insert(id);
+                    }
+                }
+
+                /// Starts or stops recording how long each system takes to process.
+                pub fn set_profiling(&mut self, profiling: bool)
+                {
+                    self.__profiling = profiling;
+                }
+
+                /// Returns the time each system has spent processing while profiling was on, in
+                /// the order the systems were declared.
+                pub fn profile(&self) -> &[$crate::SystemTiming]
+                {
+                    &self.__profile
+                }
+
+                /// Sets every system's recorded time and number of calls back to zero.
+                pub fn reset_profile(&mut self)
+                {
+                    for timing in self.__profile.iter_mut()
+                    {
+                        timing.reset();
+                    }
+                }
+
+                /// Returns the names of the systems in the order they are processed.
+                pub fn system_order(&self) -> Vec<&'static str>
+                {
+                    let names = [$(stringify!($field_name)),+];
+                    self.__order.iter().map(|i| names[*i]).collect()
+                }
+
+                /// Returns false if systems of type `S` have been stopped with `set_active`.
+                pub fn is_system_active<S: 'static>(&self) -> bool
+                {
+                    !self.__inactive.contains(&::std::any::TypeId::of::<S>())
+                }
             }
 
+            // Systems are visited in the resolved order by looking each index up in turn.
             unsafe impl $crate::SystemManager for $Name
             {
                 type Components = $components;
                 #[allow(unused_unsafe)] // The aspect macro is probably going to be used here and it also expands to an unsafe block.
                 unsafe fn new() -> $Name
                 {
+                    let mut priorities = Vec::new();
+                    let mut phases = Vec::new();
+                    let mut after = Vec::new();
+                    let mut before = Vec::new();
+                    $(
+                        let $field_name: $field_ty = $field_init;
+                        let priority: Option<i32> = $priority;
+                        let phase: Option<$crate::Phase> = $phase;
+                        priorities.push(priority.unwrap_or_else(|| $crate::System::priority(&$field_name)));
+                        phases.push(phase.unwrap_or_else(|| $crate::System::phase(&$field_name)));
+                        after.push($crate::System::run_after(&$field_name));
+                        before.push($crate::System::run_before(&$field_name));
+                    )+
+                    let order = $crate::system::order_systems(&[$(stringify!($field_name)),+],
+                        &[$(::std::any::TypeId::of::<$field_ty>()),+], &priorities, &after, &before);
                     $Name {
                         $(
-                            $field_name : $field_init,
+                            $field_name : $field_name,
                         )+
+                        __order: order,
+                        __phases: phases,
+                        __inactive: ::std::collections::HashSet::new(),
+                        __profiling: false,
+                        __profile: vec![$($crate::SystemTiming::new::<$field_ty>(stringify!($field_name))),+],
                     }
                 }
 
+                #[allow(unused_assignments)]
                 unsafe fn activated(&mut self, en: $crate::EntityData, co: &$components)
                 {
-                    $(
-                        self.$field_name.activated(&en, co);
-                    )+
+                    for &index in self.__order.iter()
+                    {
+                        let mut i = 0;
+                        $(
+                            if i == index
+                            {
+                                self.$field_name.activated(&en, co);
+                            }
+                            i += 1;
+                        )+
+                    }
+                }
+
+                // Each system is told about the whole batch before the next one is
+                #[allow(unused_assignments)]
+                unsafe fn activated_batch(&mut self, entities: &[$crate::Entity], co: &$components)
+                {
+                    for &index in self.__order.iter()
+                    {
+                        let mut i = 0;
+                        $(
+                            if i == index
+                            {
+                                for en in $crate::EntityIter::<$components>::from_slice(entities)
+                                {
+                                    self.$field_name.activated(&en, co);
+                                }
+                            }
+                            i += 1;
+                        )+
+                    }
                 }
 
+                #[allow(unused_assignments)]
                 unsafe fn reactivated(&mut self, en: $crate::EntityData, co: &$components)
                 {
-                    $(
-                        self.$field_name.reactivated(&en, co);
-                    )+
+                    for &index in self.__order.iter()
+                    {
+                        let mut i = 0;
+                        $(
+                            if i == index
+                            {
+                                self.$field_name.reactivated(&en, co);
+                            }
+                            i += 1;
+                        )+
+                    }
                 }
 
-                unsafe fn deactivated(&mut self, en: $crate::EntityData, co: &$components)
+                #[allow(unused_assignments)]
+                unsafe fn deactivated(&mut self, en: $crate::EntityData, co: &$components, reason: $crate::DeactivationReason)
                 {
-                    $(
-                        self.$field_name.deactivated(&en, co);
-                    )+
+                    for &index in self.__order.iter()
+                    {
+                        let mut i = 0;
+                        $(
+                            if i == index
+                            {
+                                $crate::System::deactivated_with_reason(&mut self.$field_name, &en, co, reason);
+                            }
+                            i += 1;
+                        )+
+                    }
                 }
 
                 unsafe fn update(&mut self, co: &mut $crate::DataHelper<$components>)
                 {
-                    $(
-                        if self.$field_name.is_active() {
-                            $crate::Process::process(&mut self.$field_name, co);
+                    for &phase in $crate::Phase::all().iter()
+                    {
+                        $crate::SystemManager::update_phase(self, phase, co);
+                    }
+                }
+
+                fn system_count(&self) -> usize
+                {
+                    self.__order.len()
+                }
+
+                #[allow(unused_assignments)]
+                unsafe fn update_phase(&mut self, phase: $crate::Phase, co: &mut $crate::DataHelper<$components>)
+                {
+                    for &index in self.__order.iter()
+                    {
+                        if self.__phases[index] != phase
+                        {
+                            continue;
                         }
-                    )+
+                        let mut i = 0;
+                        $(
+                            if i == index && self.$field_name.is_active() &&
+                                !self.__inactive.contains(&::std::any::TypeId::of::<$field_ty>())
+                            {
+                                if self.__profiling
+                                {
+                                    self.__profile[index].process(&mut self.$field_name, co);
+                                }
+                                else
+                                {
+                                    $crate::Process::process(&mut self.$field_name, co);
+                                }
+                            }
+                            i += 1;
+                        )+
+                    }
                 }
             }
         };
+        // Each field is moved into `[$($done)*]` along with the priority and phase from any
+        // annotations before it, which are `None` when it doesn't have them.
+        {
+            @fields $Name:ident<$components:ty> [$($done:tt)*] [$($priority:tt)*] [$($phase:tt)*]
+        } => {
+            systems! { @define $Name<$components> { $($done)* } }
+        };
+        {
+            @fields $Name:ident<$components:ty> [$($done:tt)*] [$($priority:tt)*] [$($phase:tt)*]
+                #[priority($new_priority:expr)] $($rest:tt)*
+        } => {
+            systems! { @fields $Name<$components> [$($done)*] [Some($new_priority)] [$($phase)*] $($rest)* }
+        };
+        {
+            @fields $Name:ident<$components:ty> [$($done:tt)*] [$($priority:tt)*] [$($phase:tt)*]
+                #[phase($new_phase:ident)] $($rest:tt)*
+        } => {
+            systems! { @fields $Name<$components> [$($done)*] [$($priority)*] [Some($crate::Phase::$new_phase)] $($rest)* }
+        };
+        {
+            @fields $Name:ident<$components:ty> [$($done:tt)*] [$($priority:tt)*] [$($phase:tt)*]
+                $field_name:ident : $field_ty:ty = $field_init:expr, $($rest:tt)*
+        } => {
+            systems! { @fields $Name<$components>
+                [$($done)* $field_name : $field_ty = $field_init, $($priority)*, $($phase)*;] [None] [None] $($rest)* }
+        };
+        {
+            @fields $Name:ident<$components:ty> [$($done:tt)*] [$($priority:tt)*] [$($phase:tt)*]
+                $field_name:ident : $field_ty:ty = $field_init:expr
+        } => {
+            systems! { @fields $Name<$components>
+                [$($done)* $field_name : $field_ty = $field_init, $($priority)*, $($phase)*;] [None] [None] }
+        };
+        {
+            @fields $Name:ident<$components:ty> [$($done:tt)*] [$($priority:tt)*] [$($phase:tt)*]
+                $field_name:ident : $field_ty:ty, $($rest:tt)*
+        } => {
+            systems! { @fields $Name<$components>
+                [$($done)* $field_name : $field_ty = ::std::default::Default::default(), $($priority)*, $($phase)*;]
+                [None] [None] $($rest)* }
+        };
+        {
+            @fields $Name:ident<$components:ty> [$($done:tt)*] [$($priority:tt)*] [$($phase:tt)*]
+                $field_name:ident : $field_ty:ty
+        } => {
+            systems! { @fields $Name<$components>
+                [$($done)* $field_name : $field_ty = ::std::default::Default::default(), $($priority)*, $($phase)*;]
+                [None] [None] }
+        };
         {
             $Name:ident<$components:ty> {
-                $($field_name:ident : $field_ty:ty = $field_init:expr),+,
+                $($fields:tt)+
             }
         } => {
-            systems! { $Name<$components> { $($field_name : $field_ty = $field_init),+ } }
-        }
+            systems! { @fields $Name<$components> [] [None] [None] $($fields)+ }
+        };
     }
 
     #[macro_export]
@@ -242,15 +951,56 @@ mod macros
         {
             <$components:ty>
             all: [$($all_field:ident),*]
+            $(any: [$($any_field:ident),*])*
             none: [$($none_field:ident),*]
         } => {
             unsafe {
-                $crate::Aspect::new(Box::new(|en: &$crate::EntityData, co: &$components| {
-                    ($(co.$all_field.has(en) &&)* true) &&
-                    !($(co.$none_field.has(en) ||)* false)
+                $crate::Aspect::watching(Box::new(|en: &$crate::EntityData, co: &$components| {
+                    let mask = $crate::ComponentManager::mask_of(co, &**en);
+                    mask.contains($crate::ComponentMask::empty() $(| co.$all_field.mask())*) &&
+                    $(mask.intersects($crate::ComponentMask::empty() $(| co.$any_field.mask())*) &&)*
+                    !mask.intersects($crate::ComponentMask::empty() $(| co.$none_field.mask())*)
+                }), Box::new(|co: &$components| {
+                    $crate::ComponentMask::empty() $(| co.$all_field.mask())* $($(| co.$any_field.mask())*)*
+                        $(| co.$none_field.mask())*
                 }))
             }
         };
+        {
+            <$components:ty>
+            all: [$($all_field:ident),*]
+            any: [$($any_field:ident),*]
+        } => {
+            aspect!(
+                <$components>
+                all: [$($all_field),*]
+                any: [$($any_field),*]
+                none: []
+            )
+        };
+        {
+            <$components:ty>
+            any: [$($any_field:ident),*]
+            none: [$($none_field:ident),*]
+        } => {
+            aspect!(
+                <$components>
+                all: []
+                any: [$($any_field),*]
+                none: [$($none_field),*]
+            )
+        };
+        {
+            <$components:ty>
+            any: [$($field:ident),*]
+        } => {
+            aspect!(
+                <$components>
+                all: []
+                any: [$($field),*]
+                none: []
+            )
+        };
         {
             <$components:ty>
             all: [$($field:ident),*]
@@ -272,4 +1022,378 @@ mod macros
             )
         };
     }
+
+    // The first component drives iteration, so the rarest component should be listed first.
+    #[macro_export]
+    macro_rules! join {
+        {
+            $data:expr,
+            read: [$first:ident $(, $read:ident)*]
+            write: [$($write:ident),*]
+            $(maybe: [$($maybe:ident),*])*,
+            |$entity:ident| $body:block
+        } => {
+            {
+                let (components, filter) = $data.join_parts();
+                for ($entity, $first) in components.$first.iter()
+                {
+                    if filter.excludes(&$entity) { continue; }
+                    $(
+                        let $read = match components.$read.lookup(&$entity) { Some(c) => c, None => continue };
+                    )*
+                    $(
+                        let $write = match components.$write.lookup_mut(&$entity) { Some(c) => c, None => continue };
+                    )*
+                    $($(
+                        let $maybe = components.$maybe.lookup(&$entity);
+                    )*)*
+                    let $entity = filter.entity_data(&$entity);
+                    $body
+                }
+            }
+        };
+        {
+            $data:expr,
+            read: []
+            write: [$first:ident $(, $write:ident)*]
+            $(maybe: [$($maybe:ident),*])*,
+            |$entity:ident| $body:block
+        } => {
+            {
+                let (components, filter) = $data.join_parts();
+                for ($entity, $first) in components.$first.iter_mut()
+                {
+                    if filter.excludes(&$entity) { continue; }
+                    $(
+                        let $write = match components.$write.lookup_mut(&$entity) { Some(c) => c, None => continue };
+                    )*
+                    $($(
+                        let $maybe = components.$maybe.lookup(&$entity);
+                    )*)*
+                    let $entity = filter.entity_data(&$entity);
+                    $body
+                }
+            }
+        };
+        {
+            $data:expr,
+            read: [$($read:ident),*]
+            $(maybe: [$($maybe:ident),*])*,
+            |$entity:ident| $body:block
+        } => {
+            join!($data, read: [$($read),*] write: [] $(maybe: [$($maybe),*])*, |$entity| $body)
+        };
+        {
+            $data:expr,
+            write: [$($write:ident),*]
+            $(maybe: [$($maybe:ident),*])*,
+            |$entity:ident| $body:block
+        } => {
+            join!($data, read: [] write: [$($write),*] $(maybe: [$($maybe),*])*, |$entity| $body)
+        };
+    }
+
+    // Each field is kept in an array of its own, so code that only touches a few fields doesn't
+    // have to load the rest. The names after the `->` are for the storage, the proxies returned
+    // by lookups and iteration, and the arrays returned by `columns`.
+    #[macro_export]
+    macro_rules! split_component {
+        {
+            $(#[$attr:meta])*
+            $Name:ident -> ($Storage:ident, $Ref:ident, $Mut:ident, $Columns:ident) {
+                $($field:ident : $field_ty:ty),+
+            }
+        } => {
+            $(#[$attr])*
+            pub struct $Name {
+                $(
+                    pub $field : $field_ty,
+                )+
+            }
+
+            pub struct $Ref<'a> {
+                $(
+                    pub $field : &'a $field_ty,
+                )+
+            }
+
+            pub struct $Mut<'a> {
+                $(
+                    pub $field : &'a mut $field_ty,
+                )+
+            }
+
+            pub struct $Columns<'a> {
+                $(
+                    pub $field : &'a mut [$field_ty],
+                )+
+            }
+
+            pub struct $Storage {
+                __index: $crate::storage::SparseIndex,
+                __mask: $crate::mask::ManagedMask,
+                $(
+                    $field : Vec<$field_ty>,
+                )+
+            }
+
+            impl $Storage
+            {
+                pub fn new() -> $Storage
+                {
+                    $Storage {
+                        __index: $crate::storage::SparseIndex::new(),
+                        __mask: $crate::mask::ManagedMask::new(),
+                        $(
+                            $field : Vec::new(),
+                        )+
+                    }
+                }
+
+                pub fn len(&self) -> usize
+                {
+                    self.__index.len()
+                }
+
+                pub fn is_empty(&self) -> bool
+                {
+                    self.__index.len() == 0
+                }
+
+                /// Returns the entities in the order their components are stored in.
+                pub fn entities(&self) -> &[$crate::Entity]
+                {
+                    self.__index.entities()
+                }
+
+                pub fn contains(&self, entity: &$crate::Entity) -> bool
+                {
+                    self.slot(entity).is_some()
+                }
+
+                /// Returns the storage's bit in its component manager's masks, for use in aspects.
+                pub fn mask(&self) -> $crate::ComponentMask
+                {
+                    self.__mask.mask()
+                }
+
+                fn slot(&self, entity: &$crate::Entity) -> Option<usize>
+                {
+                    match self.__index.slot(**entity)
+                    {
+                        Some(slot) if self.__index.entities()[slot] == *entity => Some(slot),
+                        _ => None,
+                    }
+                }
+
+                /// Adds a component to an entity, splitting it into its fields.
+                pub fn insert(&mut self, entity: $crate::Entity, component: $Name) -> Option<$Name>
+                {
+                    match self.__index.insert(entity)
+                    {
+                        Some(slot) => Some($Name {
+                            $(
+                                $field : ::std::mem::replace(&mut self.$field[slot], component.$field),
+                            )+
+                        }),
+                        None =>
+                        {
+                            self.__mask.toggled(entity);
+                            $(
+                                self.$field.push(component.$field);
+                            )+
+                            None
+                        },
+                    }
+                }
+
+                /// Removes the component of an entity, putting its fields back together.
+                pub fn remove(&mut self, entity: &$crate::Entity) -> Option<$Name>
+                {
+                    match self.slot(entity)
+                    {
+                        Some(slot) =>
+                        {
+                            self.__index.remove(**entity);
+                            self.__mask.toggled(*entity);
+                            Some($Name {
+                                $(
+                                    $field : self.$field.swap_remove(slot),
+                                )+
+                            })
+                        },
+                        None => None,
+                    }
+                }
+
+                pub fn lookup(&self, entity: &$crate::Entity) -> Option<$Ref>
+                {
+                    match self.slot(entity)
+                    {
+                        Some(slot) => Some($Ref {
+                            $(
+                                $field : &self.$field[slot],
+                            )+
+                        }),
+                        None => None,
+                    }
+                }
+
+                pub fn lookup_mut(&mut self, entity: &$crate::Entity) -> Option<$Mut>
+                {
+                    match self.slot(entity)
+                    {
+                        Some(slot) => Some($Mut {
+                            $(
+                                $field : &mut self.$field[slot],
+                            )+
+                        }),
+                        None => None,
+                    }
+                }
+
+                pub fn iter<'a>(&'a self) -> Box<Iterator<Item=($crate::Entity, $Ref<'a>)>+'a>
+                {
+                    Box::new(self.__index.entities().iter().enumerate().map(move |(slot, &entity)| {
+                        (entity, $Ref {
+                            $(
+                                $field : &self.$field[slot],
+                            )+
+                        })
+                    }))
+                }
+
+                pub fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=($crate::Entity, $Mut<'a>)>+'a>
+                {
+                    $(
+                        let $field = self.$field.as_mut_ptr();
+                    )+
+                    Box::new(self.__index.entities().iter().enumerate().map(move |(slot, &entity)| {
+                        // Each slot is only visited once, so the references never alias
+                        (entity, unsafe { $Mut {
+                            $(
+                                $field : &mut *$field.offset(slot as isize),
+                            )+
+                        } })
+                    }))
+                }
+
+                /// Returns every array at once, in the order of `entities`.
+                pub fn columns(&mut self) -> $Columns
+                {
+                    $Columns {
+                        $(
+                            $field : &mut self.$field[..],
+                        )+
+                    }
+                }
+
+                pub fn clear(&mut self)
+                {
+                    self.__index.clear();
+                    self.__mask.cleared();
+                    $(
+                        self.$field.clear();
+                    )+
+                }
+
+                /// Reserves space for entities with indices up to `capacity`.
+                pub fn reserve(&mut self, capacity: usize)
+                {
+                    let additional = capacity.saturating_sub(self.__index.len());
+                    self.__index.reserve(capacity);
+                    $(
+                        self.$field.reserve(additional);
+                    )+
+                }
+
+                /// Returns roughly how many bytes of memory the storage has allocated.
+                pub fn memory_usage(&self) -> usize
+                {
+                    let mut bytes = self.__index.memory_usage();
+                    $(
+                        bytes += self.$field.capacity() * ::std::mem::size_of::<$field_ty>();
+                    )+
+                    bytes
+                }
+            }
+
+            impl Default for $Storage
+            {
+                fn default() -> $Storage
+                {
+                    $Storage::new()
+                }
+            }
+
+            impl $crate::storage::SplitStorage for $Storage
+            {
+                fn remove_entity(&mut self, entity: &$crate::Entity)
+                {
+                    self.remove(entity);
+                }
+
+                fn copy_entity(&mut self, from: &$crate::Entity, to: &$crate::Entity)
+                {
+                    let component = match self.slot(from)
+                    {
+                        Some(slot) => $Name {
+                            $(
+                                $field : self.$field[slot].clone(),
+                            )+
+                        },
+                        None => return,
+                    };
+                    self.insert(*to, component);
+                }
+
+                fn move_entity(&mut self, from: &$crate::Entity, other: &mut $Storage, to: &$crate::Entity)
+                {
+                    if let Some(component) = self.remove(from)
+                    {
+                        other.insert(*to, component);
+                    }
+                }
+
+                fn clear_entities(&mut self)
+                {
+                    self.clear();
+                }
+
+                fn assign_bit(&mut self, bit: usize)
+                {
+                    self.__mask.assign_bit(bit);
+                }
+
+                fn component_mask(&self, entity: &$crate::Entity) -> $crate::ComponentMask
+                {
+                    if self.contains(entity) { self.mask() } else { $crate::ComponentMask::empty() }
+                }
+
+                fn update_masks(&mut self, masks: &mut $crate::mask::ComponentMasks)
+                {
+                    let mut changes = ::std::mem::replace(&mut self.__mask, $crate::mask::ManagedMask::new());
+                    changes.update_masks(masks, |entity| self.contains(entity));
+                    self.__mask = changes;
+                }
+
+                fn update_mask(&self, entity: &$crate::Entity, mask: $crate::ComponentMask)
+                    -> $crate::ComponentMask
+                {
+                    self.__mask.update_mask(entity, mask, |entity| self.contains(entity))
+                }
+            }
+        };
+        {
+            $(#[$attr:meta])*
+            $Name:ident -> ($Storage:ident, $Ref:ident, $Mut:ident, $Columns:ident) {
+                $($field:ident : $field_ty:ty),+,
+            }
+        } => {
+            split_component! {
+                $(#[$attr])*
+                $Name -> ($Storage, $Ref, $Mut, $Columns) { $($field : $field_ty),+ }
+            }
+        };
+    }
 }
@@ -0,0 +1,220 @@
+
+use std::collections::VecMap;
+use std::ops::{BitOr, BitXor};
+
+use Entity;
+
+/// Set of component types, with one bit for each component list and split storage of a
+/// `ComponentManager`.
+///
+/// Bits are assigned in declaration order when the component manager is created, lists first,
+/// so a manager can hold at most 64 lists and split storages between them.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ComponentMask(u64);
+
+impl ComponentMask
+{
+    pub fn empty() -> ComponentMask
+    {
+        ComponentMask(0)
+    }
+
+    #[doc(hidden)]
+    pub fn bit(bit: usize) -> ComponentMask
+    {
+        debug_assert!(bit < 64, "component mask bit {} is out of range", bit);
+        ComponentMask(1 << bit)
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.0 == 0
+    }
+
+    /// Returns true if every component in `other` is also in this mask.
+    pub fn contains(&self, other: ComponentMask) -> bool
+    {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns this mask without the components in `other`.
+    pub fn without(&self, other: ComponentMask) -> ComponentMask
+    {
+        ComponentMask(self.0 & !other.0)
+    }
+
+    /// Returns true if this mask and `other` have at least one component in common.
+    pub fn intersects(&self, other: ComponentMask) -> bool
+    {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for ComponentMask
+{
+    type Output = ComponentMask;
+    fn bitor(self, other: ComponentMask) -> ComponentMask
+    {
+        ComponentMask(self.0 | other.0)
+    }
+}
+
+impl BitXor for ComponentMask
+{
+    type Output = ComponentMask;
+    fn bitxor(self, other: ComponentMask) -> ComponentMask
+    {
+        ComponentMask(self.0 ^ other.0)
+    }
+}
+
+/// Masks of the components of a set of entities, as they were last seen by a system or
+/// component manager.
+#[doc(hidden)]
+pub struct ComponentMasks(VecMap<ComponentMask>);
+
+impl ComponentMasks
+{
+    pub fn new() -> ComponentMasks
+    {
+        ComponentMasks(VecMap::new())
+    }
+
+    pub fn get(&self, entity: &Entity) -> ComponentMask
+    {
+        self.0.get(&**entity).cloned().unwrap_or(ComponentMask::empty())
+    }
+
+    pub fn set(&mut self, entity: &Entity, mask: ComponentMask)
+    {
+        self.0.insert(**entity, mask);
+    }
+
+    pub fn remove(&mut self, entity: &Entity)
+    {
+        self.0.remove(&**entity);
+    }
+
+    pub fn add_bits(&mut self, entity: &Entity, mask: ComponentMask)
+    {
+        let mask = self.get(entity) | mask;
+        self.set(entity, mask);
+    }
+
+    pub fn remove_bits(&mut self, entity: &Entity, mask: ComponentMask)
+    {
+        let mask = self.get(entity).without(mask);
+        if mask.is_empty()
+        {
+            self.remove(entity);
+        }
+        else
+        {
+            self.set(entity, mask);
+        }
+    }
+
+    pub fn remove_bits_from_all(&mut self, mask: ComponentMask)
+    {
+        for (_, entity_mask) in self.0.iter_mut()
+        {
+            *entity_mask = entity_mask.without(mask);
+        }
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.0.clear();
+    }
+}
+
+/// Bit of a component list or split storage in its component manager's masks, along with the
+/// entities that have gained or lost the component since the manager last updated them.
+#[doc(hidden)]
+pub struct ManagedMask
+{
+    bit: Option<usize>,
+    entities: Vec<Entity>,
+    cleared: bool,
+}
+
+impl ManagedMask
+{
+    pub fn new() -> ManagedMask
+    {
+        ManagedMask { bit: None, entities: Vec::new(), cleared: false }
+    }
+
+    pub fn assign_bit(&mut self, bit: usize)
+    {
+        self.bit = Some(bit);
+    }
+
+    /// Returns the mask with this bit set, or an empty mask if there is no component manager.
+    pub fn mask(&self) -> ComponentMask
+    {
+        match self.bit
+        {
+            Some(bit) => ComponentMask::bit(bit),
+            None => ComponentMask::empty(),
+        }
+    }
+
+    /// Records that an entity has gained or lost the component.
+    pub fn toggled(&mut self, entity: Entity)
+    {
+        // Storages that don't belong to a component manager have no masks to update
+        if self.bit.is_some()
+        {
+            self.entities.push(entity);
+        }
+    }
+
+    /// Records that every entity has lost the component.
+    pub fn cleared(&mut self)
+    {
+        self.entities.clear();
+        self.cleared = self.bit.is_some();
+    }
+
+    pub fn update_masks<F>(&mut self, masks: &mut ComponentMasks, contains: F) where F: Fn(&Entity) -> bool
+    {
+        let mask = self.mask();
+        if self.cleared
+        {
+            masks.remove_bits_from_all(mask);
+            self.cleared = false;
+        }
+        for entity in self.entities.iter()
+        {
+            if contains(entity)
+            {
+                masks.add_bits(entity, mask);
+            }
+            else
+            {
+                masks.remove_bits(entity, mask);
+            }
+        }
+        self.entities.clear();
+    }
+
+    /// Corrects the bit in `mask`, which was read from the manager's masks, if the component
+    /// has changed since they were updated.
+    pub fn update_mask<F>(&self, entity: &Entity, mask: ComponentMask, contains: F) -> ComponentMask
+        where F: Fn(&Entity) -> bool
+    {
+        if self.entities.is_empty() && !self.cleared
+        {
+            mask
+        }
+        else if contains(entity)
+        {
+            mask | self.mask()
+        }
+        else
+        {
+            mask.without(self.mask())
+        }
+    }
+}
@@ -0,0 +1,156 @@
+
+use std::any::TypeId;
+use std::marker::PhantomData;
+
+use {Component, ComponentManager, Entity, EntityIter, HasComponent};
+
+/// A tuple of component types that can be looked up together with `DataHelper::query`.
+pub trait ComponentQuery<'a, T: ComponentManager>
+{
+    /// The entity followed by a reference to each of its components.
+    type Item;
+    /// The entity followed by a mutable reference to each of its components.
+    type ItemMut;
+    fn fetch(entity: &Entity, components: &'a T) -> Option<Self::Item>;
+    /// The component types have to be different, or the references would alias.
+    unsafe fn fetch_mut(entity: &Entity, components: *mut T) -> Option<Self::ItemMut>;
+    fn types() -> Vec<TypeId>;
+}
+
+macro_rules! impl_component_query {
+    ($($C:ident $c:ident),+) => {
+        impl<'a, T: ComponentManager+'a, $($C: Component),+> ComponentQuery<'a, T> for ($($C,)+)
+            where $(T: HasComponent<$C>),+
+        {
+            type Item = (Entity, $(&'a $C),+);
+            type ItemMut = (Entity, $(&'a mut $C),+);
+
+            fn fetch(entity: &Entity, components: &'a T) -> Option<(Entity, $(&'a $C),+)>
+            {
+                $(
+                    let $c = match HasComponent::<$C>::list(components).lookup(entity) { Some(c) => c, None => return None };
+                )+
+                Some((*entity, $($c),+))
+            }
+
+            unsafe fn fetch_mut(entity: &Entity, components: *mut T) -> Option<(Entity, $(&'a mut $C),+)>
+            {
+                // Check first, so that components of entities that don't match aren't marked as modified
+                if !($(HasComponent::<$C>::list(&*components).lookup(entity).is_some())&&+)
+                {
+                    return None;
+                }
+                $(
+                    let $c = match HasComponent::<$C>::list_mut(&mut *components).lookup_mut(entity) { Some(c) => c, None => return None };
+                )+
+                Some((*entity, $($c),+))
+            }
+
+            fn types() -> Vec<TypeId>
+            {
+                vec![$(TypeId::of::<$C>()),+]
+            }
+        }
+    };
+}
+
+impl_component_query!(A a);
+impl_component_query!(A a, B b);
+impl_component_query!(A a, B b, C c);
+impl_component_query!(A a, B b, C c, D d);
+impl_component_query!(A a, B b, C c, D d, E e);
+impl_component_query!(A a, B b, C c, D d, E e, F f);
+impl_component_query!(A a, B b, C c, D d, E e, F f, G g);
+impl_component_query!(A a, B b, C c, D d, E e, F f, G g, H h);
+
+/// Iterator over the entities that have every component in a query, with their components.
+pub struct Query<'a, T: ComponentManager+'a, Q>
+{
+    entities: EntityIter<'a, T>,
+    components: &'a T,
+    __phantom: PhantomData<fn(Q)>,
+}
+
+impl<'a, T: ComponentManager, Q: ComponentQuery<'a, T>> Query<'a, T, Q>
+{
+    #[doc(hidden)]
+    pub fn new(entities: EntityIter<'a, T>, components: &'a T) -> Query<'a, T, Q>
+    {
+        Query
+        {
+            entities: entities,
+            components: components,
+            __phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: ComponentManager, Q: ComponentQuery<'a, T>> Iterator for Query<'a, T, Q>
+{
+    type Item = <Q as ComponentQuery<'a, T>>::Item;
+    fn next(&mut self) -> Option<<Q as ComponentQuery<'a, T>>::Item>
+    {
+        loop
+        {
+            let entity = match self.entities.next()
+            {
+                Some(entity) => *entity,
+                None => return None,
+            };
+            if let Some(item) = Q::fetch(&entity, self.components)
+            {
+                return Some(item);
+            }
+        }
+    }
+}
+
+/// Iterator over the entities that have every component in a query, with mutable references
+/// to their components.
+pub struct QueryMut<'a, T: ComponentManager+'a, Q>
+{
+    entities: EntityIter<'a, T>,
+    components: *mut T,
+    __phantom: PhantomData<(&'a mut T, fn(Q))>,
+}
+
+impl<'a, T: ComponentManager, Q: ComponentQuery<'a, T>> QueryMut<'a, T, Q>
+{
+    /// Panics if the query has the same component type more than once.
+    #[doc(hidden)]
+    pub fn new(entities: EntityIter<'a, T>, components: &'a mut T) -> QueryMut<'a, T, Q>
+    {
+        let types = Q::types();
+        for (i, id) in types.iter().enumerate()
+        {
+            assert!(!types[..i].contains(id), "a mutable query can't have the same component type twice");
+        }
+        QueryMut
+        {
+            entities: entities,
+            components: components,
+            __phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: ComponentManager, Q: ComponentQuery<'a, T>> Iterator for QueryMut<'a, T, Q>
+{
+    type Item = <Q as ComponentQuery<'a, T>>::ItemMut;
+    fn next(&mut self) -> Option<<Q as ComponentQuery<'a, T>>::ItemMut>
+    {
+        loop
+        {
+            let entity = match self.entities.next()
+            {
+                Some(entity) => *entity,
+                None => return None,
+            };
+            // Each entity is only yielded once, so the references never alias
+            if let Some(item) = unsafe { Q::fetch_mut(&entity, self.components) }
+            {
+                return Some(item);
+            }
+        }
+    }
+}
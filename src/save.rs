@@ -0,0 +1,393 @@
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::mem;
+
+use {BuildData, Entity};
+use ComponentManager;
+
+const MAGIC: &'static [u8] = b"ECS\0";
+const SNAPSHOT_MAGIC: &'static [u8] = b"ECSS";
+const VERSION: u32 = 1;
+
+/// Values that can be written to and read back from a saved world.
+///
+/// Implemented for primitive types, `String`, `Entity`, `Option` and `Vec`, so components can
+/// usually be saved one field at a time.
+pub trait SaveableComponent: Sized
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()>;
+    fn load(reader: &mut Reader) -> io::Result<Self>;
+}
+
+/// Component managers that can save and load the components of a single entity.
+///
+/// Implemented by the `saveable_components!` macro.
+pub trait SaveableComponents: ComponentManager
+{
+    fn serialize_entity(&self, entity: &Entity, writer: &mut Writer) -> io::Result<()>;
+    fn deserialize_entity(&mut self, entity: BuildData, reader: &mut Reader) -> io::Result<()>;
+}
+
+/// Writes values to a saved world.
+pub struct Writer<'a>
+{
+    inner: &'a mut (Write+'a),
+}
+
+impl<'a> Writer<'a>
+{
+    pub fn new(inner: &'a mut (Write+'a)) -> Writer<'a>
+    {
+        Writer { inner: inner }
+    }
+
+    pub fn write<S: SaveableComponent>(&mut self, value: &S) -> io::Result<()>
+    {
+        value.save(self)
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()>
+    {
+        self.inner.write_all(bytes)
+    }
+
+    /// Writes a component along with its name and length, so it can be skipped when loading.
+    #[doc(hidden)]
+    pub fn write_component<C: SaveableComponent>(&mut self, name: &str, component: &C) -> io::Result<()>
+    {
+        let mut data = Vec::new();
+        try!(component.save(&mut Writer::new(&mut data)));
+        try!(self.write(&name.to_string()));
+        try!(self.write(&(data.len() as u64)));
+        self.write_bytes(&data)
+    }
+}
+
+/// Reads values from a saved world.
+///
+/// Entities are remapped to the entities created for them when the world was loaded.
+pub struct Reader<'a>
+{
+    inner: &'a mut (Read+'a),
+    remap: &'a HashMap<Entity, Entity>,
+}
+
+impl<'a> Reader<'a>
+{
+    pub fn new(inner: &'a mut (Read+'a), remap: &'a HashMap<Entity, Entity>) -> Reader<'a>
+    {
+        Reader { inner: inner, remap: remap }
+    }
+
+    pub fn read<S: SaveableComponent>(&mut self) -> io::Result<S>
+    {
+        S::load(self)
+    }
+
+    /// Fills `buf` completely, failing if the save ends first.
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()>
+    {
+        let mut filled = 0;
+        while filled < buf.len()
+        {
+            match try!(self.inner.read(&mut buf[filled..]))
+            {
+                0 => return Err(invalid_data("unexpected end of saved world")),
+                n => filled += n,
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads `len` bytes into a new vector, failing if the save ends first.
+    ///
+    /// The bytes are read a chunk at a time, so a corrupt length can't make the vector any
+    /// larger than what is actually left in the save.
+    pub fn read_vec(&mut self, len: u64) -> io::Result<Vec<u8>>
+    {
+        let mut data = Vec::new();
+        let mut chunk = [0; 4096];
+        let mut left = len;
+        while left > 0
+        {
+            let size = cmp::min(left, chunk.len() as u64) as usize;
+            try!(self.read_bytes(&mut chunk[..size]));
+            data.extend(chunk[..size].iter().cloned());
+            left -= size as u64;
+        }
+        Ok(data)
+    }
+
+    /// Returns the entity created for a saved entity.
+    ///
+    /// Entities that weren't saved (because they had already been removed) become `Entity::nil()`,
+    /// which is never valid.
+    pub fn remap(&self, entity: Entity) -> Entity
+    {
+        self.remap.get(&entity).cloned().unwrap_or(Entity::nil())
+    }
+
+    #[doc(hidden)]
+    pub fn read_component_data(&mut self) -> io::Result<(String, Vec<u8>)>
+    {
+        let name: String = try!(self.read());
+        let len: u64 = try!(self.read());
+        let data = try!(self.read_vec(len));
+        Ok((name, data))
+    }
+
+    #[doc(hidden)]
+    pub fn load_component<C: SaveableComponent>(&self, data: &[u8]) -> io::Result<C>
+    {
+        let mut data = data;
+        let mut reader = Reader::new(&mut data, self.remap);
+        reader.read()
+    }
+}
+
+#[doc(hidden)]
+pub fn invalid_data(description: &'static str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidInput, description, None)
+}
+
+/// Writes the header of a saved world: every entity, and whether it is disabled.
+#[doc(hidden)]
+pub fn write_header(writer: &mut Writer, entities: &[(Entity, bool)]) -> io::Result<()>
+{
+    write_entities(writer, MAGIC, entities)
+}
+
+#[doc(hidden)]
+pub fn read_header(reader: &mut Reader) -> io::Result<Vec<(Entity, bool)>>
+{
+    read_entities(reader, MAGIC, "not a saved world")
+}
+
+/// Writes the header of a snapshot, which is laid out like a saved world's.
+#[doc(hidden)]
+pub fn write_snapshot_header(writer: &mut Writer, entities: &[(Entity, bool)]) -> io::Result<()>
+{
+    write_entities(writer, SNAPSHOT_MAGIC, entities)
+}
+
+#[doc(hidden)]
+pub fn read_snapshot_header(reader: &mut Reader) -> io::Result<Vec<(Entity, bool)>>
+{
+    read_entities(reader, SNAPSHOT_MAGIC, "not a snapshot of a world")
+}
+
+fn write_entities(writer: &mut Writer, magic: &[u8], entities: &[(Entity, bool)]) -> io::Result<()>
+{
+    try!(writer.write_bytes(magic));
+    try!(writer.write(&VERSION));
+    try!(writer.write(&(entities.len() as u64)));
+    for &(entity, disabled) in entities.iter()
+    {
+        try!(writer.write(&(entity.get_index() as u64)));
+        try!(writer.write(&entity.get_generation()));
+        try!(writer.write(&disabled));
+    }
+    Ok(())
+}
+
+fn read_entities(reader: &mut Reader, magic: &[u8], description: &'static str) -> io::Result<Vec<(Entity, bool)>>
+{
+    let mut found = [0; 4];
+    try!(reader.read_bytes(&mut found));
+    if &found[..] != magic
+    {
+        return Err(invalid_data(description));
+    }
+    let version: u32 = try!(reader.read());
+    if version != VERSION
+    {
+        return Err(invalid_data("unsupported saved world version"));
+    }
+    let count: u64 = try!(reader.read());
+    let mut entities = Vec::new();
+    for _ in 0..count
+    {
+        let index: u64 = try!(reader.read());
+        let generation = try!(reader.read());
+        let disabled = try!(reader.read());
+        entities.push((Entity::from_raw(index as usize, generation), disabled));
+    }
+    Ok(entities)
+}
+
+macro_rules! impl_saveable_int {
+    ($($ty:ty),+) => {
+        $(
+            impl SaveableComponent for $ty
+            {
+                fn save(&self, writer: &mut Writer) -> io::Result<()>
+                {
+                    let mut bytes = [0; 8];
+                    for i in 0..mem::size_of::<$ty>()
+                    {
+                        bytes[i] = (*self as u64 >> (i * 8)) as u8;
+                    }
+                    writer.write_bytes(&bytes[..mem::size_of::<$ty>()])
+                }
+
+                fn load(reader: &mut Reader) -> io::Result<$ty>
+                {
+                    let mut bytes = [0; 8];
+                    try!(reader.read_bytes(&mut bytes[..mem::size_of::<$ty>()]));
+                    let mut value = 0u64;
+                    for i in 0..mem::size_of::<$ty>()
+                    {
+                        value |= (bytes[i] as u64) << (i * 8);
+                    }
+                    Ok(value as $ty)
+                }
+            }
+        )+
+    };
+}
+
+impl_saveable_int!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+// Sizes vary between platforms, so these are always saved as 64 bit integers.
+impl SaveableComponent for usize
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()> { writer.write(&(*self as u64)) }
+    fn load(reader: &mut Reader) -> io::Result<usize> { reader.read::<u64>().map(|v| v as usize) }
+}
+
+impl SaveableComponent for isize
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()> { writer.write(&(*self as i64)) }
+    fn load(reader: &mut Reader) -> io::Result<isize> { reader.read::<i64>().map(|v| v as isize) }
+}
+
+impl SaveableComponent for f32
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()>
+    {
+        writer.write(&unsafe { mem::transmute::<f32, u32>(*self) })
+    }
+
+    fn load(reader: &mut Reader) -> io::Result<f32>
+    {
+        reader.read::<u32>().map(|bits| unsafe { mem::transmute::<u32, f32>(bits) })
+    }
+}
+
+impl SaveableComponent for f64
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()>
+    {
+        writer.write(&unsafe { mem::transmute::<f64, u64>(*self) })
+    }
+
+    fn load(reader: &mut Reader) -> io::Result<f64>
+    {
+        reader.read::<u64>().map(|bits| unsafe { mem::transmute::<u64, f64>(bits) })
+    }
+}
+
+impl SaveableComponent for bool
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()>
+    {
+        writer.write(&(*self as u8))
+    }
+
+    fn load(reader: &mut Reader) -> io::Result<bool>
+    {
+        match try!(reader.read::<u8>())
+        {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data("invalid bool in saved world")),
+        }
+    }
+}
+
+impl SaveableComponent for String
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()>
+    {
+        try!(writer.write(&(self.len() as u64)));
+        writer.write_bytes(self.as_bytes())
+    }
+
+    fn load(reader: &mut Reader) -> io::Result<String>
+    {
+        let len: u64 = try!(reader.read());
+        let bytes = try!(reader.read_vec(len));
+        String::from_utf8(bytes).map_err(|_| invalid_data("invalid string in saved world"))
+    }
+}
+
+/// Entities are saved as their index and generation, and remapped when loaded.
+impl SaveableComponent for Entity
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()>
+    {
+        try!(writer.write(&(self.get_index() as u64)));
+        writer.write(&self.get_generation())
+    }
+
+    fn load(reader: &mut Reader) -> io::Result<Entity>
+    {
+        let index: u64 = try!(reader.read());
+        let generation = try!(reader.read());
+        Ok(reader.remap(Entity::from_raw(index as usize, generation)))
+    }
+}
+
+impl<T: SaveableComponent> SaveableComponent for Option<T>
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()>
+    {
+        match *self
+        {
+            Some(ref value) => {
+                try!(writer.write(&true));
+                writer.write(value)
+            },
+            None => writer.write(&false),
+        }
+    }
+
+    fn load(reader: &mut Reader) -> io::Result<Option<T>>
+    {
+        if try!(reader.read())
+        {
+            reader.read().map(Some)
+        }
+        else
+        {
+            Ok(None)
+        }
+    }
+}
+
+impl<T: SaveableComponent> SaveableComponent for Vec<T>
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()>
+    {
+        try!(writer.write(&(self.len() as u64)));
+        for value in self.iter()
+        {
+            try!(writer.write(value));
+        }
+        Ok(())
+    }
+
+    fn load(reader: &mut Reader) -> io::Result<Vec<T>>
+    {
+        let len: u64 = try!(reader.read());
+        let mut values = Vec::new();
+        for _ in 0..len
+        {
+            values.push(try!(reader.read()));
+        }
+        Ok(values)
+    }
+}
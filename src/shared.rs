@@ -0,0 +1,70 @@
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use EditData;
+use component::ComponentList;
+
+/// Immutable component value that can be shared between many entities.
+///
+/// Cloning a `Shared` only clones the handle, so every clone points at the same value. The
+/// value is dropped once the last entity holding it is removed.
+pub struct Shared<T: Send+Sync>(Arc<T>);
+
+impl<T: Send+Sync> Shared<T>
+{
+    pub fn new(value: T) -> Shared<T>
+    {
+        Shared(Arc::new(value))
+    }
+
+    /// Returns true if both handles point at the same value.
+    pub fn ptr_eq(&self, other: &Shared<T>) -> bool
+    {
+        &*self.0 as *const T == &*other.0 as *const T
+    }
+}
+
+impl<T: Send+Sync+Clone> Shared<T>
+{
+    /// Returns a mutable reference to the value, copying it first if other handles point at it.
+    pub fn make_mut(&mut self) -> &mut T
+    {
+        self.0.make_unique()
+    }
+}
+
+impl<T: Send+Sync> Clone for Shared<T>
+{
+    fn clone(&self) -> Shared<T>
+    {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T: Send+Sync> Deref for Shared<T>
+{
+    type Target = T;
+    fn deref(&self) -> &T
+    {
+        &*self.0
+    }
+}
+
+impl<T: Send+Sync+fmt::Debug> fmt::Debug for Shared<T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "Shared({:?})", &*self.0)
+    }
+}
+
+impl<T: Send+Sync+Clone+'static> ComponentList<Shared<T>>
+{
+    /// Gives an entity its own copy of its shared component, leaving other entities untouched.
+    pub fn make_mut<U: EditData>(&mut self, entity: &U) -> Option<&mut T>
+    {
+        self.borrow(entity).map(|shared| shared.make_mut())
+    }
+}
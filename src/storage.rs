@@ -0,0 +1,951 @@
+
+use std::collections::{HashMap, VecMap};
+use std::collections::hash_map;
+use std::collections::vec_map;
+use std::cmp;
+use std::iter::Zip;
+use std::marker::PhantomData;
+use std::mem;
+use std::slice;
+use std::sync::RwLockReadGuard;
+
+use {Component, ComponentList, Entity};
+use entity::{Generation, Generations};
+use mask::{ComponentMask, ComponentMasks};
+
+/// Storage for the components of a single type, keyed by entity index.
+///
+/// Every component is stored along with the entity that owns it.
+pub trait ComponentStorage<T: Component>
+{
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T>;
+    fn remove(&mut self, index: usize) -> Option<T>;
+    fn get(&self, index: usize) -> Option<(Entity, &T)>;
+    fn get_mut(&mut self, index: usize) -> Option<(Entity, &mut T)>;
+    fn contains(&self, index: usize) -> bool;
+    fn len(&self) -> usize;
+    /// Returns the number of components that can be stored without allocating.
+    fn capacity(&self) -> usize
+    {
+        self.len()
+    }
+    /// Returns roughly how many bytes of memory the storage has allocated.
+    fn memory_usage(&self) -> usize
+    {
+        self.capacity() * mem::size_of::<(Entity, T)>()
+    }
+    fn clear(&mut self);
+    fn iter<'a>(&'a self) -> Box<Iterator<Item=(Entity, &'a T)>+'a>;
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=(Entity, &'a mut T)>+'a>;
+    /// Optional method to reserve space for entities with indices up to `capacity`.
+    fn reserve(&mut self, _capacity: usize)
+    {
+
+    }
+}
+
+/// Storage that can back a list declared with `field: Type -> Storage` in `components!`.
+#[doc(hidden)]
+pub trait ListStorage<T: Component>
+{
+    fn list() -> ComponentList<T>;
+}
+
+/// Storage generated by `split_component!`, which can be declared with `#[split]` in
+/// `components!` so that an entity's component is removed along with the entity.
+///
+/// Copying or moving an entity copies or moves its component too, and the component has a bit
+/// in the manager's masks like a list does.
+pub trait SplitStorage: Default
+{
+    fn remove_entity(&mut self, entity: &Entity);
+    fn copy_entity(&mut self, from: &Entity, to: &Entity);
+    fn move_entity(&mut self, from: &Entity, other: &mut Self, to: &Entity);
+    fn clear_entities(&mut self);
+    fn assign_bit(&mut self, bit: usize);
+    fn component_mask(&self, entity: &Entity) -> ComponentMask;
+    fn update_masks(&mut self, masks: &mut ComponentMasks);
+    fn update_mask(&self, entity: &Entity, mask: ComponentMask) -> ComponentMask;
+}
+
+impl<T: Component> ListStorage<T> for VecStorage<T>
+{
+    fn list() -> ComponentList<T>
+    {
+        ComponentList::hot()
+    }
+}
+
+impl<T: Component> ListStorage<T> for HashMapStorage<T>
+{
+    fn list() -> ComponentList<T>
+    {
+        ComponentList::cold()
+    }
+}
+
+impl<T: Component> ListStorage<T> for SparseSetStorage<T>
+{
+    fn list() -> ComponentList<T>
+    {
+        ComponentList::sparse()
+    }
+}
+
+impl<T: Component+Clone> ListStorage<T> for DoubleBuffered<T>
+{
+    fn list() -> ComponentList<T>
+    {
+        ComponentList::double_buffered()
+    }
+}
+
+impl<T: Component+Default> ListStorage<T> for NullStorage<T>
+{
+    fn list() -> ComponentList<T>
+    {
+        ComponentList::null()
+    }
+}
+
+/// Dense storage, with a slot for every entity index.
+///
+/// Fast to access and iterate, but takes up memory for entities without the component.
+pub struct VecStorage<T: Component>
+{
+    components: VecMap<(Entity, T)>,
+    len: usize,
+}
+
+impl<T: Component> VecStorage<T>
+{
+    pub fn new() -> VecStorage<T>
+    {
+        VecStorage { components: VecMap::new(), len: 0 }
+    }
+
+    pub fn iter(&self) -> VecIter<T>
+    {
+        VecIter(self.components.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> VecIterMut<T>
+    {
+        VecIterMut(self.components.iter_mut())
+    }
+}
+
+impl<T: Component> ComponentStorage<T> for VecStorage<T>
+{
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T>
+    {
+        let previous = self.components.insert(*entity, (entity, component)).map(|(_, c)| c);
+        if previous.is_none()
+        {
+            self.len += 1;
+        }
+        previous
+    }
+
+    fn remove(&mut self, index: usize) -> Option<T>
+    {
+        let removed = self.components.remove(&index).map(|(_, c)| c);
+        if removed.is_some()
+        {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    fn get(&self, index: usize) -> Option<(Entity, &T)>
+    {
+        self.components.get(&index).map(|&(entity, ref c)| (entity, c))
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<(Entity, &mut T)>
+    {
+        self.components.get_mut(&index).map(|&mut (entity, ref mut c)| (entity, c))
+    }
+
+    fn contains(&self, index: usize) -> bool
+    {
+        self.components.contains_key(&index)
+    }
+
+    fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    fn capacity(&self) -> usize
+    {
+        self.components.capacity()
+    }
+
+    fn memory_usage(&self) -> usize
+    {
+        self.components.capacity() * mem::size_of::<Option<(Entity, T)>>()
+    }
+
+    fn clear(&mut self)
+    {
+        self.components.clear();
+        self.len = 0;
+    }
+
+    fn iter<'a>(&'a self) -> Box<Iterator<Item=(Entity, &'a T)>+'a>
+    {
+        Box::new(VecIter(self.components.iter()))
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=(Entity, &'a mut T)>+'a>
+    {
+        Box::new(VecIterMut(self.components.iter_mut()))
+    }
+
+    fn reserve(&mut self, capacity: usize)
+    {
+        self.components.reserve_len(capacity);
+    }
+}
+
+/// Sparse storage, only taking up memory for entities that have the component.
+pub struct HashMapStorage<T: Component>(HashMap<usize, (Entity, T)>);
+
+impl<T: Component> HashMapStorage<T>
+{
+    pub fn new() -> HashMapStorage<T>
+    {
+        HashMapStorage(HashMap::new())
+    }
+
+    pub fn iter(&self) -> HashMapIter<T>
+    {
+        HashMapIter(self.0.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> HashMapIterMut<T>
+    {
+        HashMapIterMut(self.0.iter_mut())
+    }
+}
+
+impl<T: Component> ComponentStorage<T> for HashMapStorage<T>
+{
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T>
+    {
+        self.0.insert(*entity, (entity, component)).map(|(_, c)| c)
+    }
+
+    fn remove(&mut self, index: usize) -> Option<T>
+    {
+        self.0.remove(&index).map(|(_, c)| c)
+    }
+
+    fn get(&self, index: usize) -> Option<(Entity, &T)>
+    {
+        self.0.get(&index).map(|&(entity, ref c)| (entity, c))
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<(Entity, &mut T)>
+    {
+        self.0.get_mut(&index).map(|&mut (entity, ref mut c)| (entity, c))
+    }
+
+    fn contains(&self, index: usize) -> bool
+    {
+        self.0.contains_key(&index)
+    }
+
+    fn len(&self) -> usize
+    {
+        self.0.len()
+    }
+
+    fn capacity(&self) -> usize
+    {
+        self.0.capacity()
+    }
+
+    fn memory_usage(&self) -> usize
+    {
+        // Each bucket also holds the hash of its key
+        self.0.capacity() * (mem::size_of::<u64>() + mem::size_of::<(usize, (Entity, T))>())
+    }
+
+    fn clear(&mut self)
+    {
+        self.0.clear();
+    }
+
+    fn iter<'a>(&'a self) -> Box<Iterator<Item=(Entity, &'a T)>+'a>
+    {
+        Box::new(HashMapIter(self.0.iter()))
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=(Entity, &'a mut T)>+'a>
+    {
+        Box::new(HashMapIterMut(self.0.iter_mut()))
+    }
+}
+
+/// Table from entity index to position in a packed array, along with the entity at each position.
+///
+/// Used by `SparseSetStorage` and the storages generated by `split_component!`, which keep
+/// their arrays in the same order as the index.
+#[doc(hidden)]
+pub struct SparseIndex
+{
+    entities: Vec<Entity>,
+    sparse: Vec<Option<usize>>,
+}
+
+impl SparseIndex
+{
+    pub fn new() -> SparseIndex
+    {
+        SparseIndex { entities: Vec::new(), sparse: Vec::new() }
+    }
+
+    /// Returns the position of the entity with this index.
+    pub fn slot(&self, index: usize) -> Option<usize>
+    {
+        match self.sparse.get(index)
+        {
+            Some(&slot) => slot,
+            None => None,
+        }
+    }
+
+    /// Returns the position the entity's index already had, replacing the entity stored there.
+    ///
+    /// Otherwise the entity is added at the end, and the caller must push its data to the end
+    /// of each array.
+    pub fn insert(&mut self, entity: Entity) -> Option<usize>
+    {
+        let index = *entity;
+        match self.slot(index)
+        {
+            Some(slot) =>
+            {
+                self.entities[slot] = entity;
+                Some(slot)
+            },
+            None =>
+            {
+                while self.sparse.len() <= index
+                {
+                    self.sparse.push(None);
+                }
+                self.sparse[index] = Some(self.entities.len());
+                self.entities.push(entity);
+                None
+            },
+        }
+    }
+
+    /// Removes the entity with this index, returning the position it had.
+    ///
+    /// The last entity is moved into its place, so the caller must `swap_remove` the same
+    /// position from each array.
+    pub fn remove(&mut self, index: usize) -> Option<usize>
+    {
+        let slot = self.slot(index);
+        if let Some(slot) = slot
+        {
+            self.sparse[index] = None;
+            self.entities.swap_remove(slot);
+            if slot < self.entities.len()
+            {
+                self.sparse[*self.entities[slot]] = Some(slot);
+            }
+        }
+        slot
+    }
+
+    pub fn entities(&self) -> &[Entity]
+    {
+        &self.entities
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.entities.len()
+    }
+
+    pub fn capacity(&self) -> usize
+    {
+        self.entities.capacity()
+    }
+
+    pub fn memory_usage(&self) -> usize
+    {
+        self.entities.capacity() * mem::size_of::<Entity>() + self.sparse.capacity() * mem::size_of::<Option<usize>>()
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.entities.clear();
+        self.sparse.clear();
+    }
+
+    /// Reserves space for entities with indices up to `capacity`.
+    pub fn reserve(&mut self, capacity: usize)
+    {
+        if capacity > self.entities.len()
+        {
+            let additional = capacity - self.entities.len();
+            self.entities.reserve(additional);
+        }
+        if capacity > self.sparse.len()
+        {
+            let additional = capacity - self.sparse.len();
+            self.sparse.reserve(additional);
+        }
+    }
+}
+
+/// Packed storage, keeping the components in one array with no gaps between them.
+///
+/// A table from entity index to position in the array makes insertion, removal and lookup
+/// constant time, and iteration is a linear walk over the array. Removing a component moves the
+/// last one into its place, so the order of iteration changes as components are removed.
+pub struct SparseSetStorage<T: Component>
+{
+    dense: Vec<T>,
+    index: SparseIndex,
+}
+
+impl<T: Component> SparseSetStorage<T>
+{
+    pub fn new() -> SparseSetStorage<T>
+    {
+        SparseSetStorage { dense: Vec::new(), index: SparseIndex::new() }
+    }
+
+    pub fn iter(&self) -> SparseSetIter<T>
+    {
+        SparseSetIter(self.index.entities().iter().zip(self.dense.iter()))
+    }
+
+    pub fn iter_mut(&mut self) -> SparseSetIterMut<T>
+    {
+        SparseSetIterMut(self.index.entities().iter().zip(self.dense.iter_mut()))
+    }
+
+    /// Returns the components in the order they are iterated over.
+    pub fn as_slice(&self) -> &[T]
+    {
+        &self.dense
+    }
+}
+
+impl<T: Component> ComponentStorage<T> for SparseSetStorage<T>
+{
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T>
+    {
+        match self.index.insert(entity)
+        {
+            Some(slot) => Some(mem::replace(&mut self.dense[slot], component)),
+            None =>
+            {
+                self.dense.push(component);
+                None
+            },
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> Option<T>
+    {
+        self.index.remove(index).map(|slot| self.dense.swap_remove(slot))
+    }
+
+    fn get(&self, index: usize) -> Option<(Entity, &T)>
+    {
+        self.index.slot(index).map(|slot| (self.index.entities()[slot], &self.dense[slot]))
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<(Entity, &mut T)>
+    {
+        match self.index.slot(index)
+        {
+            Some(slot) => Some((self.index.entities()[slot], &mut self.dense[slot])),
+            None => None,
+        }
+    }
+
+    fn contains(&self, index: usize) -> bool
+    {
+        self.index.slot(index).is_some()
+    }
+
+    fn len(&self) -> usize
+    {
+        self.dense.len()
+    }
+
+    fn capacity(&self) -> usize
+    {
+        cmp::min(self.dense.capacity(), self.index.capacity())
+    }
+
+    fn memory_usage(&self) -> usize
+    {
+        self.dense.capacity() * mem::size_of::<T>() + self.index.memory_usage()
+    }
+
+    fn clear(&mut self)
+    {
+        self.dense.clear();
+        self.index.clear();
+    }
+
+    fn iter<'a>(&'a self) -> Box<Iterator<Item=(Entity, &'a T)>+'a>
+    {
+        Box::new(SparseSetStorage::iter(self))
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=(Entity, &'a mut T)>+'a>
+    {
+        Box::new(SparseSetStorage::iter_mut(self))
+    }
+
+    fn reserve(&mut self, capacity: usize)
+    {
+        if capacity > self.dense.len()
+        {
+            let additional = capacity - self.dense.len();
+            self.dense.reserve(additional);
+        }
+        self.index.reserve(capacity);
+    }
+}
+
+/// Dense storage that also keeps the value each component had when the buffers were last
+/// swapped.
+///
+/// Writes only change the current value, so a system can read the `previous` value of every
+/// entity while it writes new ones, whatever order it visits them in. A component added since the
+/// last swap starts out with a copy of its first value as its previous one.
+pub struct DoubleBuffered<T: Component>
+{
+    current: VecStorage<T>,
+    previous: VecMap<T>,
+    copy: fn(&T) -> T,
+}
+
+fn copy_component<T: Clone>(component: &T) -> T
+{
+    component.clone()
+}
+
+impl<T: Component> DoubleBuffered<T>
+{
+    pub fn new() -> DoubleBuffered<T> where T: Clone
+    {
+        DoubleBuffered
+        {
+            current: VecStorage::new(),
+            previous: VecMap::new(),
+            copy: copy_component::<T>,
+        }
+    }
+
+    pub fn iter(&self) -> VecIter<T>
+    {
+        self.current.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> VecIterMut<T>
+    {
+        self.current.iter_mut()
+    }
+
+    /// Returns the component at `index` as it was when the buffers were last swapped.
+    pub fn previous(&self, index: usize) -> Option<(Entity, &T)>
+    {
+        match self.current.get(index)
+        {
+            Some((entity, _)) => self.previous.get(&index).map(|c| (entity, c)),
+            None => None,
+        }
+    }
+
+    /// Makes the current value of every component its previous value.
+    pub fn swap_buffers(&mut self)
+    {
+        for (index, &(_, ref component)) in self.current.components.iter()
+        {
+            self.previous.insert(index, (self.copy)(component));
+        }
+    }
+}
+
+impl<T: Component> ComponentStorage<T> for DoubleBuffered<T>
+{
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T>
+    {
+        let owned = match self.current.get(*entity)
+        {
+            Some((owner, _)) => owner == entity,
+            None => false,
+        };
+        if !owned
+        {
+            self.previous.insert(*entity, (self.copy)(&component));
+        }
+        self.current.insert(entity, component)
+    }
+
+    fn remove(&mut self, index: usize) -> Option<T>
+    {
+        self.previous.remove(&index);
+        self.current.remove(index)
+    }
+
+    fn get(&self, index: usize) -> Option<(Entity, &T)>
+    {
+        self.current.get(index)
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<(Entity, &mut T)>
+    {
+        self.current.get_mut(index)
+    }
+
+    fn contains(&self, index: usize) -> bool
+    {
+        self.current.contains(index)
+    }
+
+    fn len(&self) -> usize
+    {
+        self.current.len()
+    }
+
+    fn capacity(&self) -> usize
+    {
+        self.current.capacity()
+    }
+
+    fn memory_usage(&self) -> usize
+    {
+        self.current.memory_usage() + self.previous.capacity() * mem::size_of::<Option<T>>()
+    }
+
+    fn clear(&mut self)
+    {
+        self.current.clear();
+        self.previous.clear();
+    }
+
+    fn iter<'a>(&'a self) -> Box<Iterator<Item=(Entity, &'a T)>+'a>
+    {
+        Box::new(DoubleBuffered::iter(self))
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=(Entity, &'a mut T)>+'a>
+    {
+        Box::new(DoubleBuffered::iter_mut(self))
+    }
+
+    fn reserve(&mut self, capacity: usize)
+    {
+        self.current.reserve(capacity);
+        self.previous.reserve_len(capacity);
+    }
+}
+
+/// Storage for zero-sized marker components, which only records which entities have them.
+///
+/// Each entity index takes up a single bit. The generation of the entity that has the marker is
+/// looked up in the world's `EntityManager`, or kept by the storage itself if it isn't part of a
+/// world. As all values of a zero-sized type are the same, a single value is shared by every
+/// entity.
+pub struct NullStorage<T: Component>
+{
+    bits: Vec<u64>,
+    generations: Generations,
+    shared: bool,
+    len: usize,
+    marker: T,
+    default: fn() -> T,
+}
+
+impl<T: Component+Default> NullStorage<T>
+{
+    /// Panics if `T` is not zero-sized.
+    pub fn new() -> NullStorage<T>
+    {
+        assert!(mem::size_of::<T>() == 0, "NullStorage can only store zero-sized components");
+        NullStorage {
+            bits: Vec::new(),
+            generations: Generations::new(),
+            shared: false,
+            len: 0,
+            marker: Default::default(),
+            default: Default::default,
+        }
+    }
+}
+
+impl<T: Component> NullStorage<T>
+{
+    /// Looks up generations in the world's entity manager instead of keeping them.
+    #[doc(hidden)]
+    pub fn share_generations(&mut self, generations: &Generations)
+    {
+        self.generations = generations.share();
+        self.shared = true;
+    }
+
+    pub fn iter(&self) -> NullIter<T>
+    {
+        NullIter(BitIter { bits: &self.bits, generations: self.generations.read(), next: 0 }, &self.marker)
+    }
+
+    pub fn iter_mut(&mut self) -> NullIterMut<T>
+    {
+        NullIterMut(BitIter { bits: &self.bits, generations: self.generations.read(), next: 0 }, PhantomData)
+    }
+}
+
+impl<T: Component> ComponentStorage<T> for NullStorage<T>
+{
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T>
+    {
+        let index = *entity;
+        while self.bits.len() <= index / 64
+        {
+            self.bits.push(0);
+        }
+        if !self.shared
+        {
+            self.generations.set(index, entity.get_generation());
+        }
+        if self.contains(index)
+        {
+            Some(component)
+        }
+        else
+        {
+            self.bits[index / 64] |= 1 << (index % 64);
+            self.len += 1;
+            None
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> Option<T>
+    {
+        if self.contains(index)
+        {
+            self.bits[index / 64] &= !(1 << (index % 64));
+            self.len -= 1;
+            Some((self.default)())
+        }
+        else
+        {
+            None
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<(Entity, &T)>
+    {
+        if self.contains(index)
+        {
+            Some((Entity::from_raw(index, self.generations.get(index)), &self.marker))
+        }
+        else
+        {
+            None
+        }
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<(Entity, &mut T)>
+    {
+        if self.contains(index)
+        {
+            Some((Entity::from_raw(index, self.generations.get(index)), &mut self.marker))
+        }
+        else
+        {
+            None
+        }
+    }
+
+    fn contains(&self, index: usize) -> bool
+    {
+        match self.bits.get(index / 64)
+        {
+            Some(&word) => word & (1 << (index % 64)) != 0,
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    fn capacity(&self) -> usize
+    {
+        self.bits.capacity() * 64
+    }
+
+    fn memory_usage(&self) -> usize
+    {
+        self.bits.capacity() * mem::size_of::<u64>()
+    }
+
+    fn clear(&mut self)
+    {
+        for word in self.bits.iter_mut()
+        {
+            *word = 0;
+        }
+        self.len = 0;
+    }
+
+    fn iter<'a>(&'a self) -> Box<Iterator<Item=(Entity, &'a T)>+'a>
+    {
+        Box::new(NullStorage::iter(self))
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=(Entity, &'a mut T)>+'a>
+    {
+        Box::new(NullStorage::iter_mut(self))
+    }
+
+    fn reserve(&mut self, capacity: usize)
+    {
+        let words = (capacity + 63) / 64;
+        if words > self.bits.len()
+        {
+            let additional = words - self.bits.len();
+            self.bits.reserve(additional);
+        }
+    }
+}
+
+// Iterates over the set bits of a `NullStorage`, skipping empty words.
+struct BitIter<'a>
+{
+    bits: &'a [u64],
+    generations: RwLockReadGuard<'a, Vec<Generation>>,
+    next: usize,
+}
+
+impl<'a> Iterator for BitIter<'a>
+{
+    type Item = Entity;
+    fn next(&mut self) -> Option<Entity>
+    {
+        while self.next / 64 < self.bits.len()
+        {
+            let index = self.next;
+            let word = self.bits[index / 64] >> (index % 64);
+            if word == 0
+            {
+                self.next = (index / 64 + 1) * 64;
+                continue;
+            }
+            self.next += 1;
+            if word & 1 != 0
+            {
+                return Some(Entity::from_raw(index, self.generations.get(index).cloned().unwrap_or(0)));
+            }
+        }
+        None
+    }
+}
+
+pub struct VecIter<'a, T: Component>(vec_map::Iter<'a, (Entity, T)>);
+
+impl<'a, T: Component> Iterator for VecIter<'a, T>
+{
+    type Item = (Entity, &'a T);
+    fn next(&mut self) -> Option<(Entity, &'a T)>
+    {
+        self.0.next().map(|(_, &(entity, ref c))| (entity, c))
+    }
+}
+
+pub struct VecIterMut<'a, T: Component>(vec_map::IterMut<'a, (Entity, T)>);
+
+impl<'a, T: Component> Iterator for VecIterMut<'a, T>
+{
+    type Item = (Entity, &'a mut T);
+    fn next(&mut self) -> Option<(Entity, &'a mut T)>
+    {
+        self.0.next().map(|(_, &mut (entity, ref mut c))| (entity, c))
+    }
+}
+
+pub struct HashMapIter<'a, T: Component>(hash_map::Iter<'a, usize, (Entity, T)>);
+
+impl<'a, T: Component> Iterator for HashMapIter<'a, T>
+{
+    type Item = (Entity, &'a T);
+    fn next(&mut self) -> Option<(Entity, &'a T)>
+    {
+        self.0.next().map(|(_, &(entity, ref c))| (entity, c))
+    }
+}
+
+pub struct HashMapIterMut<'a, T: Component>(hash_map::IterMut<'a, usize, (Entity, T)>);
+
+impl<'a, T: Component> Iterator for HashMapIterMut<'a, T>
+{
+    type Item = (Entity, &'a mut T);
+    fn next(&mut self) -> Option<(Entity, &'a mut T)>
+    {
+        self.0.next().map(|(_, &mut (entity, ref mut c))| (entity, c))
+    }
+}
+
+pub struct SparseSetIter<'a, T: Component>(Zip<slice::Iter<'a, Entity>, slice::Iter<'a, T>>);
+
+impl<'a, T: Component> Iterator for SparseSetIter<'a, T>
+{
+    type Item = (Entity, &'a T);
+    fn next(&mut self) -> Option<(Entity, &'a T)>
+    {
+        self.0.next().map(|(&entity, c)| (entity, c))
+    }
+}
+
+pub struct SparseSetIterMut<'a, T: Component>(Zip<slice::Iter<'a, Entity>, slice::IterMut<'a, T>>);
+
+impl<'a, T: Component> Iterator for SparseSetIterMut<'a, T>
+{
+    type Item = (Entity, &'a mut T);
+    fn next(&mut self) -> Option<(Entity, &'a mut T)>
+    {
+        self.0.next().map(|(&entity, c)| (entity, c))
+    }
+}
+
+pub struct NullIter<'a, T: Component>(BitIter<'a>, &'a T);
+
+impl<'a, T: Component> Iterator for NullIter<'a, T>
+{
+    type Item = (Entity, &'a T);
+    fn next(&mut self) -> Option<(Entity, &'a T)>
+    {
+        let marker = self.1;
+        self.0.next().map(|entity| (entity, marker))
+    }
+}
+
+pub struct NullIterMut<'a, T: Component>(BitIter<'a>, PhantomData<&'a mut T>);
+
+impl<'a, T: Component> Iterator for NullIterMut<'a, T>
+{
+    type Item = (Entity, &'a mut T);
+    fn next(&mut self) -> Option<(Entity, &'a mut T)>
+    {
+        // Every item gets its own reference to a dangling but well aligned address.
+        // This is only sound because `NullStorage::new` makes sure the marker is zero-sized.
+        self.0.next().map(|entity| (entity, unsafe { &mut *(mem::align_of::<T>() as *mut T) }))
+    }
+}
@@ -0,0 +1,162 @@
+
+use std::any::TypeId;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+use DataHelper;
+use Entity;
+use EntityData;
+use {Phase, Process, System};
+
+pub trait BatchedProcess: System
+{
+    /// Processes the world, along with the entities that changed since the last process.
+    fn process(&mut self, &EntityBatch, &mut DataHelper<<Self as System>::Components>);
+}
+
+/// Entities that were activated, reactivated or deactivated between two processes, in the order
+/// it happened.
+///
+/// Each entity is only in one of the lists. An entity that was activated and then deactivated
+/// before the system was processed isn't in any of them, and one that was deactivated and then
+/// activated again counts as reactivated.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntityBatch
+{
+    pub activated: Vec<Entity>,
+    pub reactivated: Vec<Entity>,
+    pub deactivated: Vec<Entity>,
+}
+
+impl EntityBatch
+{
+    pub fn is_empty(&self) -> bool
+    {
+        self.activated.is_empty() && self.reactivated.is_empty() && self.deactivated.is_empty()
+    }
+}
+
+/// System which gets entity notifications in a batch at the start of its next process, where
+/// it has mutable access to the world, instead of as they happen.
+///
+/// The inner system's own `activated`, `reactivated` and `deactivated` methods aren't called.
+pub struct BatchedSystem<T: BatchedProcess>
+{
+    batch: EntityBatch,
+    pub inner: T,
+}
+
+impl<T: BatchedProcess> BatchedSystem<T>
+{
+    pub fn new(system: T) -> BatchedSystem<T>
+    {
+        BatchedSystem
+        {
+            batch: EntityBatch::default(),
+            inner: system,
+        }
+    }
+
+    /// Returns the notifications waiting for the next process.
+    pub fn pending(&self) -> &EntityBatch
+    {
+        &self.batch
+    }
+}
+
+fn remove(list: &mut Vec<Entity>, entity: &Entity) -> bool
+{
+    match list.iter().position(|other| other == entity)
+    {
+        Some(index) =>
+        {
+            list.remove(index);
+            true
+        },
+        None => false,
+    }
+}
+
+impl<T: BatchedProcess> Deref for BatchedSystem<T>
+{
+    type Target = T;
+    fn deref(&self) -> &T
+    {
+        &self.inner
+    }
+}
+
+impl<T: BatchedProcess> DerefMut for BatchedSystem<T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.inner
+    }
+}
+
+impl<T: BatchedProcess> Process for BatchedSystem<T>
+{
+    fn process(&mut self, c: &mut DataHelper<<T as System>::Components>)
+    {
+        let batch = mem::replace(&mut self.batch, EntityBatch::default());
+        self.inner.process(&batch, c);
+    }
+}
+
+impl<T: BatchedProcess> System for BatchedSystem<T>
+{
+    type Components = <T as System>::Components;
+    fn activated(&mut self, e: &EntityData, _: &<T as System>::Components)
+    {
+        if remove(&mut self.batch.deactivated, &**e)
+        {
+            self.batch.reactivated.push(**e);
+        }
+        else
+        {
+            self.batch.activated.push(**e);
+        }
+    }
+
+    fn reactivated(&mut self, e: &EntityData, _: &<T as System>::Components)
+    {
+        if !self.batch.activated.contains(&**e) && !self.batch.reactivated.contains(&**e)
+        {
+            self.batch.reactivated.push(**e);
+        }
+    }
+
+    fn deactivated(&mut self, e: &EntityData, _: &<T as System>::Components)
+    {
+        if !remove(&mut self.batch.activated, &**e)
+        {
+            remove(&mut self.batch.reactivated, &**e);
+            self.batch.deactivated.push(**e);
+        }
+    }
+
+    fn is_active(&self) -> bool
+    {
+        self.inner.is_active()
+    }
+
+    fn priority(&self) -> i32
+    {
+        self.inner.priority()
+    }
+
+    fn phase(&self) -> Phase
+    {
+        self.inner.phase()
+    }
+
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        self.inner.run_after()
+    }
+
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        self.inner.run_before()
+    }
+}
@@ -0,0 +1,115 @@
+
+use std::any::{Any, TypeId};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use DataHelper;
+use EntityData;
+use {DeactivationReason, Phase, Process, System};
+
+/// Process which is given data from outside the world along with the `DataHelper`.
+pub trait ProcessWithContext<C: Any>: System
+{
+    fn process(&mut self, &mut C, &mut DataHelper<<Self as System>::Components>);
+}
+
+/// System which is processed with the context passed to `World::update_ctx`.
+///
+/// The system is skipped by updates that weren't given a context of type `C`.
+pub struct ContextSystem<T: ProcessWithContext<C>, C: Any>
+{
+    pub inner: T,
+    context: PhantomData<fn(&mut C)>,
+}
+
+impl<T: ProcessWithContext<C>, C: Any> ContextSystem<T, C>
+{
+    pub fn new(system: T) -> ContextSystem<T, C>
+    {
+        ContextSystem
+        {
+            inner: system,
+            context: PhantomData,
+        }
+    }
+}
+
+impl<T: ProcessWithContext<C>, C: Any> Deref for ContextSystem<T, C>
+{
+    type Target = T;
+    fn deref(&self) -> &T
+    {
+        &self.inner
+    }
+}
+
+impl<T: ProcessWithContext<C>, C: Any> DerefMut for ContextSystem<T, C>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.inner
+    }
+}
+
+impl<T: ProcessWithContext<C>, C: Any> Process for ContextSystem<T, C>
+{
+    fn process(&mut self, c: &mut DataHelper<<T as System>::Components>)
+    {
+        if let Some(context) = c.context_ptr(TypeId::of::<C>())
+        {
+            // The context is borrowed by `update_ctx` for the whole update, and isn't part of
+            // the world, so it can't alias anything the process is given.
+            let context = unsafe { &mut *(context as *mut C) };
+            self.inner.process(context, c);
+        }
+    }
+}
+
+impl<T: ProcessWithContext<C>, C: Any> System for ContextSystem<T, C>
+{
+    type Components = <T as System>::Components;
+    fn activated(&mut self, e: &EntityData, w: &<T as System>::Components)
+    {
+        self.inner.activated(e, w);
+    }
+
+    fn reactivated(&mut self, e: &EntityData, w: &<T as System>::Components)
+    {
+        self.inner.reactivated(e, w);
+    }
+
+    fn deactivated_with_reason(&mut self, e: &EntityData, w: &<T as System>::Components, reason: DeactivationReason)
+    {
+        self.inner.deactivated_with_reason(e, w, reason);
+    }
+
+    fn is_active(&self) -> bool
+    {
+        self.inner.is_active()
+    }
+
+    fn processed_entities(&self) -> u64
+    {
+        self.inner.processed_entities()
+    }
+
+    fn priority(&self) -> i32
+    {
+        self.inner.priority()
+    }
+
+    fn phase(&self) -> Phase
+    {
+        self.inner.phase()
+    }
+
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        self.inner.run_after()
+    }
+
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        self.inner.run_before()
+    }
+}
@@ -0,0 +1,124 @@
+
+use ComponentManager;
+use DataHelper;
+use Entity;
+use EntityData;
+use {DeactivationReason, Phase, Process, SystemTiming};
+
+/// Identifies a system added to a world with `World::add_dynamic_system`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SystemHandle(u64);
+
+/// Systems added to a world while it is running, kept in order of priority.
+#[doc(hidden)]
+pub struct DynamicSystems<T: ComponentManager>
+{
+    systems: Vec<(SystemHandle, Box<Process<Components=T>>)>,
+    next: u64,
+    profiling: bool,
+    // Timings of every system, in the same order
+    profile: Vec<SystemTiming>,
+}
+
+impl<T: ComponentManager> DynamicSystems<T>
+{
+    pub fn new() -> DynamicSystems<T>
+    {
+        DynamicSystems
+        {
+            systems: Vec::new(),
+            next: 0,
+            profiling: false,
+            profile: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, system: Box<Process<Components=T>>) -> SystemHandle
+    {
+        let handle = SystemHandle(self.next);
+        self.next += 1;
+        let priority = system.priority();
+        let index = self.systems.iter().position(|&(_, ref other)| other.priority() < priority)
+            .unwrap_or(self.systems.len());
+        self.systems.insert(index, (handle, system));
+        self.profile.insert(index, SystemTiming::new::<Box<Process<Components=T>>>("dynamic"));
+        handle
+    }
+
+    pub fn remove(&mut self, handle: SystemHandle) -> Option<Box<Process<Components=T>>>
+    {
+        match self.systems.iter().position(|&(other, _)| other == handle)
+        {
+            Some(index) => {
+                self.profile.remove(index);
+                Some(self.systems.remove(index).1)
+            },
+            None => None,
+        }
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.systems.len()
+    }
+
+    pub fn set_profiling(&mut self, profiling: bool)
+    {
+        self.profiling = profiling;
+    }
+
+    pub fn profile(&self) -> Vec<(SystemHandle, SystemTiming)>
+    {
+        self.systems.iter().map(|&(handle, _)| handle).zip(self.profile.iter().cloned()).collect()
+    }
+
+    pub fn reset_profile(&mut self)
+    {
+        for timing in self.profile.iter_mut()
+        {
+            timing.reset();
+        }
+    }
+
+    pub fn activated(&mut self, entity: &Entity, components: &T)
+    {
+        for &mut (_, ref mut system) in self.systems.iter_mut()
+        {
+            system.activated(&EntityData(entity), components);
+        }
+    }
+
+    pub fn reactivated(&mut self, entity: &Entity, components: &T)
+    {
+        for &mut (_, ref mut system) in self.systems.iter_mut()
+        {
+            system.reactivated(&EntityData(entity), components);
+        }
+    }
+
+    pub fn deactivated(&mut self, entity: &Entity, components: &T, reason: DeactivationReason)
+    {
+        for &mut (_, ref mut system) in self.systems.iter_mut()
+        {
+            system.deactivated_with_reason(&EntityData(entity), components, reason);
+        }
+    }
+
+    pub fn update_phase(&mut self, phase: Phase, data: &mut DataHelper<T>)
+    {
+        for (&mut (_, ref mut system), timing) in self.systems.iter_mut().zip(self.profile.iter_mut())
+        {
+            if system.phase() == phase && system.is_active()
+            {
+                if self.profiling
+                {
+                    timing.process(&mut **system, data);
+                }
+                else
+                {
+                    system.process(data);
+                }
+            }
+        }
+    }
+}
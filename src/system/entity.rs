@@ -1,25 +1,46 @@
 
 //! Systems to specifically deal with entities.
 
-use std::collections::HashSet;
+use std::any::TypeId;
+use std::collections::BTreeSet;
+use std::collections::btree_set;
+use std::iter::Cloned;
 use std::ops::{Deref, DerefMut};
 
 use Aspect;
-use DataHelper;
+use {ComponentManager, DataHelper};
 use Entity;
 use EntityData;
 use EntityIter;
-use {System, Process};
+use mask::ComponentMasks;
+use {DeactivationReason, Phase, System, Process};
 
 pub trait EntityProcess: System
 {
+    /// Processes the entities the system is interested in.
+    ///
+    /// Unless the system has been given a key to sort by, entities are always passed in
+    /// ascending order of index, however the system was notified about them.
     fn process<'a>(&mut self, EntityIter<'a, <Self as System>::Components>, &mut DataHelper<<Self as System>::Components>);
 }
 
+/// System which processes the entities matching an aspect.
+///
+/// Entities are passed to the inner process in ascending order of index, unless it has been
+/// given a key to sort them by.
+///
+/// When the aspect only looks at which components an entity has, as those made with `aspect!`
+/// and `Aspect::with` do, reactivations that don't add or remove any of those components are
+/// ignored, so the inner process is only told about changes to the components it cares about.
 pub struct EntitySystem<T: EntityProcess>
 {
-    interested: HashSet<Entity>,
+    interested: BTreeSet<Entity>,
+    // The components each interested entity had when it was last checked
+    masks: ComponentMasks,
     aspect: Aspect<<T as System>::Components>,
+    filter: Option<Box<Fn(EntityData, &<T as System>::Components) -> bool>>,
+    sort: Option<Box<Fn(&mut Vec<Entity>, &<T as System>::Components)>>,
+    processed: u64,
     pub inner: T,
 }
 
@@ -29,11 +50,126 @@ impl<T: EntityProcess> EntitySystem<T>
     {
         EntitySystem
         {
-            interested: HashSet::new(),
+            interested: BTreeSet::new(),
+            masks: ComponentMasks::new(),
             aspect: aspect,
+            filter: None,
+            sort: None,
+            processed: 0,
             inner: inner,
         }
     }
+
+    /// Returns the entities the system currently processes, in ascending order of index.
+    pub fn entities(&self) -> Cloned<btree_set::Iter<Entity>>
+    {
+        self.interested.iter().cloned()
+    }
+
+    /// Returns true if the system currently processes `entity`.
+    pub fn contains(&self, entity: &Entity) -> bool
+    {
+        self.interested.contains(entity)
+    }
+
+    /// Only processes entities matching the aspect for which `filter` returns true.
+    ///
+    /// The filter is only checked when the entity is activated or reactivated, so membership
+    /// goes stale when the values it looks at change. Call `refresh` or `refresh_all` after
+    /// changing them.
+    pub fn filtered_by<F>(mut self, filter: F) -> EntitySystem<T>
+        where F: Fn(EntityData, &<T as System>::Components) -> bool + 'static
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Checks again whether the system should process `entity`.
+    pub fn refresh(&mut self, entity: &Entity, data: &DataHelper<<T as System>::Components>)
+    {
+        if data.is_valid(entity) && data.is_activated(entity)
+        {
+            self.update(&EntityData(entity), &data.components);
+        }
+        else if self.interested.remove(entity)
+        {
+            self.masks.remove(entity);
+            let reason = if data.is_valid(entity) { DeactivationReason::Disabled } else { DeactivationReason::Removed };
+            self.inner.deactivated_with_reason(&EntityData(entity), &data.components, reason);
+        }
+    }
+
+    /// Checks again which entities the system should process.
+    pub fn refresh_all(&mut self, data: &DataHelper<<T as System>::Components>)
+    {
+        // Entities that have been removed or disabled aren't among the activated ones
+        let stale: Vec<Entity> = self.interested.iter().cloned()
+            .filter(|entity| !(data.is_valid(entity) && data.is_activated(entity)))
+            .collect();
+        for entity in stale.iter()
+        {
+            self.refresh(entity, data);
+        }
+        for entity in data.activated_entities()
+        {
+            self.update(&entity, &data.components);
+        }
+    }
+
+    fn accepts(&self, entity: &EntityData, world: &<T as System>::Components) -> bool
+    {
+        self.aspect.check(entity, world) && match self.filter
+        {
+            Some(ref filter) => (**filter)(EntityData(&**entity), world),
+            None => true,
+        }
+    }
+
+    // Adds or removes the entity, returning true if it was already being processed and still is
+    fn update(&mut self, entity: &EntityData, world: &<T as System>::Components) -> bool
+    {
+        let accepted = self.accepts(entity, world);
+        if self.interested.contains(&**entity)
+        {
+            if accepted
+            {
+                self.masks.set(entity, world.mask_of(entity));
+            }
+            else
+            {
+                self.interested.remove(&**entity);
+                self.masks.remove(entity);
+                self.inner.deactivated_with_reason(entity, world, DeactivationReason::Changed);
+            }
+            accepted
+        }
+        else
+        {
+            if accepted
+            {
+                self.interested.insert(**entity);
+                self.masks.set(entity, world.mask_of(entity));
+                self.inner.activated(entity, world);
+            }
+            false
+        }
+    }
+
+    /// Passes entities to the inner process in ascending order of the key returned by `key`.
+    ///
+    /// Keys are worked out again before every process, so they can depend on components that
+    /// change. Entities with equal keys stay in ascending order of index.
+    pub fn sorted_by<K, F>(mut self, key: F) -> EntitySystem<T>
+        where K: Ord, F: Fn(EntityData, &<T as System>::Components) -> K + 'static
+    {
+        self.sort = Some(Box::new(move |entities: &mut Vec<Entity>, components: &<T as System>::Components| {
+            let mut keyed: Vec<(K, Entity)> = entities.iter().map(|entity| (key(EntityData(entity), components), *entity)).collect();
+            keyed.sort_by(|a, b| a.0.cmp(&b.0));
+            entities.clear();
+            entities.extend(keyed.into_iter().map(|(_, entity)| entity));
+        }));
+        self
+    }
 }
 
 impl<T: EntityProcess> Deref for EntitySystem<T>
@@ -58,39 +194,39 @@ impl<T: EntityProcess> System for EntitySystem<T>
     type Components = <T as System>::Components;
     fn activated(&mut self, entity: &EntityData, world: &<T as System>::Components)
     {
-        if self.aspect.check(entity, world)
+        if self.accepts(entity, world)
         {
             self.interested.insert(**entity);
+            self.masks.set(entity, world.mask_of(entity));
             self.inner.activated(entity, world);
         }
     }
 
     fn reactivated(&mut self, entity: &EntityData, world: &<T as System>::Components)
     {
-        if self.interested.contains(&**entity)
+        // Filters can look at values, so they have to be checked again whatever changed
+        if self.filter.is_none() && self.interested.contains(&**entity)
         {
-            if self.aspect.check(entity, world)
+            if let Some(watched) = self.aspect.watched(world)
             {
-                self.inner.reactivated(entity, world);
-            }
-            else
-            {
-                self.interested.remove(&**entity);
-                self.inner.deactivated(entity, world);
+                if !(self.masks.get(entity) ^ world.mask_of(entity)).intersects(watched)
+                {
+                    return;
+                }
             }
         }
-        else if self.aspect.check(entity, world)
+        if self.update(entity, world)
         {
-            self.interested.insert(**entity);
-            self.inner.activated(entity, world);
+            self.inner.reactivated(entity, world);
         }
     }
 
-    fn deactivated(&mut self, entity: &EntityData, world: &<T as System>::Components)
+    fn deactivated_with_reason(&mut self, entity: &EntityData, world: &<T as System>::Components, reason: DeactivationReason)
     {
         if self.interested.remove(&**entity)
         {
-            self.inner.deactivated(entity, world);
+            self.masks.remove(entity);
+            self.inner.deactivated_with_reason(entity, world, reason);
         }
     }
 
@@ -98,12 +234,47 @@ impl<T: EntityProcess> System for EntitySystem<T>
     {
         self.inner.is_active()
     }
+
+    fn processed_entities(&self) -> u64
+    {
+        self.processed
+    }
+
+    fn priority(&self) -> i32
+    {
+        self.inner.priority()
+    }
+
+    fn phase(&self) -> Phase
+    {
+        self.inner.phase()
+    }
+
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        self.inner.run_after()
+    }
+
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        self.inner.run_before()
+    }
 }
 
 impl<T: EntityProcess> Process for EntitySystem<T>
 {
     fn process(&mut self, c: &mut DataHelper<<T as System>::Components>)
     {
-        self.inner.process(EntityIter::new(self.interested.iter()), c);
+        self.processed += self.interested.len() as u64;
+        match self.sort
+        {
+            Some(ref sort) =>
+            {
+                let mut sorted: Vec<Entity> = self.interested.iter().cloned().collect();
+                (**sort)(&mut sorted, &c.components);
+                self.inner.process(EntityIter::from_slice(&sorted), c);
+            },
+            None => self.inner.process(EntityIter::new(self.interested.iter()), c),
+        }
     }
 }
@@ -1,42 +1,167 @@
 
 //! System to specifically deal with interactions between two types of entity.
 
-use std::collections::HashSet;
+use std::any::TypeId;
+use std::collections::BTreeSet;
+use std::collections::btree_set;
+use std::iter::Cloned;
+use std::ops::{Deref, DerefMut};
 
 use Aspect;
 use DataHelper;
 use Entity;
 use EntityData;
 use EntityIter;
-use {Process, System};
+use {DeactivationReason, Phase, Process, System};
 
+/// Both methods are called each time the system is processed, so a process only needs to
+/// implement one of them.
 pub trait InteractProcess: System
 {
-    fn process<'a>(&self, EntityIter<'a, <Self as System>::Components>, EntityIter<'a, <Self as System>::Components>, &mut DataHelper<<Self as System>::Components>);
+    /// Processes the entities matching each of the two aspects.
+    fn process<'a>(&self, EntityIter<'a, <Self as System>::Components>, EntityIter<'a, <Self as System>::Components>, &mut DataHelper<<Self as System>::Components>)
+    {
+
+    }
+
+    /// Processes every pair of an entity matching the first aspect and one matching the second,
+    /// filtered by the system's options.
+    fn process_pairs<'a>(&self, EntityPairs<'a>, &mut DataHelper<<Self as System>::Components>)
+    {
+
+    }
+}
+
+/// Options to filter the pairs given to `InteractProcess::process_pairs`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct InteractOptions
+{
+    /// Skips pairs of an entity with itself, when it matches both aspects.
+    pub skip_self: bool,
+    /// Only gives one of `(a, b)` and `(b, a)` when both entities match both aspects.
+    pub unordered: bool,
+    /// Doesn't process at all when no entities match one of the aspects.
+    pub skip_empty: bool,
 }
 
 pub struct InteractSystem<T: InteractProcess>
 {
-    interested_a: HashSet<Entity>,
-    interested_b: HashSet<Entity>,
+    interested_a: BTreeSet<Entity>,
+    interested_b: BTreeSet<Entity>,
     aspect_a: Aspect<<T as System>::Components>,
     aspect_b: Aspect<<T as System>::Components>,
-    inner: T,
+    options: InteractOptions,
+    pub inner: T,
 }
 
 impl<T: InteractProcess> InteractSystem<T>
 {
     pub fn new(inner: T, aspect_a: Aspect<<T as System>::Components>, aspect_b: Aspect<<T as System>::Components>) -> InteractSystem<T>
+    {
+        InteractSystem::with_options(inner, aspect_a, aspect_b, InteractOptions::default())
+    }
+
+    pub fn with_options(inner: T, aspect_a: Aspect<<T as System>::Components>, aspect_b: Aspect<<T as System>::Components>,
+                        options: InteractOptions) -> InteractSystem<T>
     {
         InteractSystem
         {
-            interested_a: HashSet::new(),
-            interested_b: HashSet::new(),
+            interested_a: BTreeSet::new(),
+            interested_b: BTreeSet::new(),
             aspect_a: aspect_a,
             aspect_b: aspect_b,
+            options: options,
             inner: inner,
         }
     }
+
+    /// Returns the entities matching the first aspect, in ascending order of index.
+    pub fn entities_a(&self) -> Cloned<btree_set::Iter<Entity>>
+    {
+        self.interested_a.iter().cloned()
+    }
+
+    /// Returns the entities matching the second aspect, in ascending order of index.
+    pub fn entities_b(&self) -> Cloned<btree_set::Iter<Entity>>
+    {
+        self.interested_b.iter().cloned()
+    }
+
+    /// Returns true if `entity` currently matches the first aspect.
+    pub fn contains_a(&self, entity: &Entity) -> bool
+    {
+        self.interested_a.contains(entity)
+    }
+
+    /// Returns true if `entity` currently matches the second aspect.
+    pub fn contains_b(&self, entity: &Entity) -> bool
+    {
+        self.interested_b.contains(entity)
+    }
+}
+
+/// Iterator over pairs of entities from the two sides of an `InteractSystem`.
+///
+/// Pairs are yielded in ascending order of the first entity, then of the second.
+pub struct EntityPairs<'a>
+{
+    a: Vec<&'a Entity>,
+    b: Vec<&'a Entity>,
+    set_a: &'a BTreeSet<Entity>,
+    set_b: &'a BTreeSet<Entity>,
+    i: usize,
+    j: usize,
+    options: InteractOptions,
+}
+
+impl<'a> Iterator for EntityPairs<'a>
+{
+    type Item = (EntityData<'a>, EntityData<'a>);
+    fn next(&mut self) -> Option<(EntityData<'a>, EntityData<'a>)>
+    {
+        loop
+        {
+            if self.i >= self.a.len()
+            {
+                return None;
+            }
+            if self.j >= self.b.len()
+            {
+                self.i += 1;
+                self.j = 0;
+                continue;
+            }
+            let (x, y) = (self.a[self.i], self.b[self.j]);
+            self.j += 1;
+            if self.options.skip_self && x == y
+            {
+                continue;
+            }
+            // The same pair comes up the other way round, so only keep the ordered one
+            if self.options.unordered && *x > *y && self.set_b.contains(x) && self.set_a.contains(y)
+            {
+                continue;
+            }
+            return Some((EntityData(x), EntityData(y)));
+        }
+    }
+}
+
+impl<T: InteractProcess> Deref for InteractSystem<T>
+{
+    type Target = T;
+    fn deref(&self) -> &T
+    {
+        &self.inner
+    }
+}
+
+impl<T: InteractProcess> DerefMut for InteractSystem<T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.inner
+    }
 }
 
 impl<T: InteractProcess> System for InteractSystem<T>
@@ -67,7 +192,7 @@ impl<T: InteractProcess> System for InteractSystem<T>
             else
             {
                 self.interested_a.remove(&**entity);
-                self.inner.deactivated(entity, world);
+                self.inner.deactivated_with_reason(entity, world, DeactivationReason::Changed);
             }
         }
         else if self.aspect_a.check(entity, world)
@@ -84,7 +209,7 @@ impl<T: InteractProcess> System for InteractSystem<T>
             else
             {
                 self.interested_b.remove(&**entity);
-                self.inner.deactivated(entity, world);
+                self.inner.deactivated_with_reason(entity, world, DeactivationReason::Changed);
             }
         }
         else if self.aspect_b.check(entity, world)
@@ -94,15 +219,15 @@ impl<T: InteractProcess> System for InteractSystem<T>
         }
     }
 
-    fn deactivated(&mut self, entity: &EntityData, world: &<T as System>::Components)
+    fn deactivated_with_reason(&mut self, entity: &EntityData, world: &<T as System>::Components, reason: DeactivationReason)
     {
         if self.interested_a.remove(&**entity)
         {
-            self.inner.deactivated(entity, world);
+            self.inner.deactivated_with_reason(entity, world, reason);
         }
         if self.interested_b.remove(&**entity)
         {
-            self.inner.deactivated(entity, world);
+            self.inner.deactivated_with_reason(entity, world, reason);
         }
     }
 
@@ -110,12 +235,47 @@ impl<T: InteractProcess> System for InteractSystem<T>
     {
         self.inner.is_active()
     }
+
+    fn priority(&self) -> i32
+    {
+        self.inner.priority()
+    }
+
+    fn phase(&self) -> Phase
+    {
+        self.inner.phase()
+    }
+
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        self.inner.run_after()
+    }
+
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        self.inner.run_before()
+    }
 }
 
 impl<T: InteractProcess> Process for InteractSystem<T>
 {
     fn process(&mut self, c: &mut DataHelper<<T as System>::Components>)
     {
+        if self.options.skip_empty && (self.interested_a.is_empty() || self.interested_b.is_empty())
+        {
+            return;
+        }
         self.inner.process(EntityIter::new(self.interested_a.iter()), EntityIter::new(self.interested_b.iter()), c);
+        let pairs = EntityPairs
+        {
+            a: self.interested_a.iter().collect(),
+            b: self.interested_b.iter().collect(),
+            set_a: &self.interested_a,
+            set_b: &self.interested_b,
+            i: 0,
+            j: 0,
+            options: self.options,
+        };
+        self.inner.process_pairs(pairs, c);
     }
 }
@@ -1,28 +1,78 @@
 
+use std::any::TypeId;
+use std::ops::{Deref, DerefMut};
+
 use DataHelper;
 use EntityData;
-use {Process, System};
+use {DeactivationReason, Phase, Process, System};
 
 /// System which operates every certain number of updates.
+///
+/// The inner process sees the time passed since it last ran as the delta.
 pub struct IntervalSystem<T: Process>
 {
     interval: u8,
     ticker: u8,
+    elapsed: f64,
     inner: T,
 }
 
 impl<T: Process> IntervalSystem<T>
 {
     /// Create a new interval system with the specified number of updates between processes.
+    ///
+    /// It first runs in the last update of the first interval, like `with_offset` with an
+    /// offset of `interval - 1`.
+    ///
+    /// Panics if the interval is zero.
     pub fn new(system: T, interval: u8) -> IntervalSystem<T>
     {
+        assert!(interval > 0, "the interval of an interval system must be positive");
+        IntervalSystem::with_offset(system, interval, interval - 1)
+    }
+
+    /// Create a new interval system that first runs `offset` updates after it is created, counting
+    /// from zero, and then every `interval` updates.
+    ///
+    /// Panics if the interval is zero or the offset isn't less than the interval.
+    pub fn with_offset(system: T, interval: u8, offset: u8) -> IntervalSystem<T>
+    {
+        assert!(interval > 0, "the interval of an interval system must be positive");
+        assert!(offset < interval, "the offset of an interval system must be less than its interval");
         IntervalSystem
         {
             interval: interval,
-            ticker: 0,
+            ticker: interval - 1 - offset,
+            elapsed: 0.0,
             inner: system,
         }
     }
+
+    /// Create the `index`th of `count` interval systems with the same interval, spreading their
+    /// offsets evenly so they don't all run in the same update.
+    pub fn spread(system: T, interval: u8, index: u8, count: u8) -> IntervalSystem<T>
+    {
+        assert!(index < count, "the index of a spread interval system must be less than the count");
+        let offset = (index as u32 * interval as u32 / count as u32) as u8;
+        IntervalSystem::with_offset(system, interval, offset)
+    }
+}
+
+impl<T: Process> Deref for IntervalSystem<T>
+{
+    type Target = T;
+    fn deref(&self) -> &T
+    {
+        &self.inner
+    }
+}
+
+impl<T: Process> DerefMut for IntervalSystem<T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.inner
+    }
 }
 
 impl<T: Process> Process for IntervalSystem<T>
@@ -30,10 +80,15 @@ impl<T: Process> Process for IntervalSystem<T>
     fn process(&mut self, c: &mut DataHelper<<T as System>::Components>)
     {
         self.ticker += 1;
+        self.elapsed += c.delta();
         if self.ticker == self.interval
         {
+            let delta = c.delta();
+            c.set_delta(self.elapsed);
             self.ticker = 0;
+            self.elapsed = 0.0;
             self.inner.process(c);
+            c.set_delta(delta);
         }
     }
 }
@@ -51,13 +106,38 @@ impl<T: Process> System for IntervalSystem<T>
         self.inner.reactivated(e, w);
     }
 
-    fn deactivated(&mut self, e: &EntityData, w: &<T as System>::Components)
+    fn deactivated_with_reason(&mut self, e: &EntityData, w: &<T as System>::Components, reason: DeactivationReason)
     {
-        self.inner.deactivated(e, w);
+        self.inner.deactivated_with_reason(e, w, reason);
     }
 
     fn is_active(&self) -> bool
     {
         self.inner.is_active()
     }
+
+    fn processed_entities(&self) -> u64
+    {
+        self.inner.processed_entities()
+    }
+
+    fn priority(&self) -> i32
+    {
+        self.inner.priority()
+    }
+
+    fn phase(&self) -> Phase
+    {
+        self.inner.phase()
+    }
+
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        self.inner.run_after()
+    }
+
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        self.inner.run_before()
+    }
 }
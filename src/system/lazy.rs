@@ -0,0 +1,161 @@
+
+use std::any::TypeId;
+use std::cell::Cell;
+use std::cmp;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+use DataHelper;
+use EntityData;
+use {DeactivationReason, Phase, Process, System};
+
+/// System which only operates after it has been triggered.
+///
+/// Triggers are checked when the system's turn comes during an update, so a system that runs
+/// before this one (has a higher priority) can trigger it to run later in the same update. If it
+/// is triggered after its turn, it runs in the next update instead.
+pub struct LazySystem<T: Process>
+{
+    triggers: LazyTrigger,
+    pub inner: T,
+}
+
+/// Handle that can trigger a `LazySystem` from elsewhere, such as from inside another system.
+#[derive(Clone)]
+pub struct LazyTrigger(Rc<Cell<usize>>);
+
+impl LazyTrigger
+{
+    /// Makes the system run once, the next time it gets the chance.
+    ///
+    /// Triggering it several times before it runs still only runs it once.
+    pub fn trigger(&self)
+    {
+        self.trigger_n(1);
+    }
+
+    /// Makes the system run in each of the next `times` updates.
+    pub fn trigger_n(&self, times: usize)
+    {
+        self.0.set(cmp::max(self.0.get(), times));
+    }
+
+    /// Returns true if the system will run the next time it gets the chance.
+    pub fn is_triggered(&self) -> bool
+    {
+        self.0.get() > 0
+    }
+}
+
+impl<T: Process> LazySystem<T>
+{
+    pub fn new(system: T) -> LazySystem<T>
+    {
+        LazySystem
+        {
+            triggers: LazyTrigger(Rc::new(Cell::new(0))),
+            inner: system,
+        }
+    }
+
+    /// Returns a handle that can trigger this system.
+    pub fn trigger_handle(&self) -> LazyTrigger
+    {
+        self.triggers.clone()
+    }
+
+    pub fn trigger(&self)
+    {
+        self.triggers.trigger();
+    }
+
+    pub fn trigger_n(&self, times: usize)
+    {
+        self.triggers.trigger_n(times);
+    }
+
+    pub fn is_triggered(&self) -> bool
+    {
+        self.triggers.is_triggered()
+    }
+}
+
+impl<T: Process> Deref for LazySystem<T>
+{
+    type Target = T;
+    fn deref(&self) -> &T
+    {
+        &self.inner
+    }
+}
+
+impl<T: Process> DerefMut for LazySystem<T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.inner
+    }
+}
+
+impl<T: Process> Process for LazySystem<T>
+{
+    fn process(&mut self, c: &mut DataHelper<<T as System>::Components>)
+    {
+        let remaining = self.triggers.0.get();
+        if remaining > 0
+        {
+            // Cleared before processing, so the inner process can trigger itself again
+            self.triggers.0.set(remaining - 1);
+            self.inner.process(c);
+        }
+    }
+}
+
+impl<T: Process> System for LazySystem<T>
+{
+    type Components = <T as System>::Components;
+    fn activated(&mut self, e: &EntityData, w: &<T as System>::Components)
+    {
+        self.inner.activated(e, w);
+    }
+
+    fn reactivated(&mut self, e: &EntityData, w: &<T as System>::Components)
+    {
+        self.inner.reactivated(e, w);
+    }
+
+    fn deactivated_with_reason(&mut self, e: &EntityData, w: &<T as System>::Components, reason: DeactivationReason)
+    {
+        self.inner.deactivated_with_reason(e, w, reason);
+    }
+
+    fn is_active(&self) -> bool
+    {
+        self.inner.is_active()
+    }
+
+    fn processed_entities(&self) -> u64
+    {
+        self.inner.processed_entities()
+    }
+
+    fn priority(&self) -> i32
+    {
+        self.inner.priority()
+    }
+
+    fn phase(&self) -> Phase
+    {
+        self.inner.phase()
+    }
+
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        self.inner.run_after()
+    }
+
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        self.inner.run_before()
+    }
+}
@@ -1,17 +1,37 @@
 
 //! Types to process the world and entities.
 
+pub use self::batched::{BatchedProcess, BatchedSystem, EntityBatch};
+pub use self::context::{ContextSystem, ProcessWithContext};
+pub use self::dynamic::{SystemHandle};
 pub use self::entity::{EntitySystem, EntityProcess};
-pub use self::interact::{InteractSystem, InteractProcess};
+pub use self::interact::{EntityPairs, InteractOptions, InteractSystem, InteractProcess};
 pub use self::interval::{IntervalSystem};
+pub use self::lazy::{LazySystem, LazyTrigger};
+pub use self::parallel::{Commands, ParallelProcess, ParallelSystem};
+pub use self::reactive::{ReactiveProcess, ReactiveSystem};
+pub use self::startup::{StartupSystem};
+pub use self::timed::{TimedSystem};
+
+use std::any::TypeId;
+use std::iter::repeat;
+use std::time::Duration;
 
 use EntityData;
 use ComponentManager;
 use DataHelper;
 
+pub mod batched;
+pub mod context;
+pub mod dynamic;
 pub mod entity;
 pub mod interact;
 pub mod interval;
+pub mod lazy;
+pub mod parallel;
+pub mod reactive;
+pub mod startup;
+pub mod timed;
 
 /// Generic base system type.
 pub trait System: 'static
@@ -25,10 +45,10 @@ pub trait System: 'static
 
     /// Optional method called when an entity is reactivated.
     ///
-    /// By default it calls deactivated() followed by activated()
+    /// By default it calls deactivated_with_reason() followed by activated()
     fn reactivated(&mut self, e: &EntityData, c: &Self::Components)
     {
-        self.deactivated(e, c);
+        self.deactivated_with_reason(e, c, DeactivationReason::Changed);
         self.activated(e, c);
     }
 
@@ -38,10 +58,155 @@ pub trait System: 'static
 
     }
 
+    /// Optional method called when an entity is deactivated, along with why.
+    ///
+    /// By default it calls deactivated(), so only one of the two needs to be implemented.
+    fn deactivated_with_reason(&mut self, e: &EntityData, c: &Self::Components, _: DeactivationReason)
+    {
+        self.deactivated(e, c);
+    }
+
     fn is_active(&self) -> bool
     {
         true
     }
+
+    /// Optional method returning how many entities the system has processed in total, counting
+    /// an entity again every time it is processed. Profiling uses it to count entities.
+    fn processed_entities(&self) -> u64
+    {
+        0
+    }
+
+    /// Optional method to change when the system runs, which is checked once when it is created.
+    ///
+    /// Systems with a higher priority are processed and notified about entities first. Systems
+    /// with the same priority run in the order they were declared.
+    fn priority(&self) -> i32
+    {
+        0
+    }
+
+    /// Optional method to change which phase of an update the system runs in, which is checked
+    /// once when it is created.
+    fn phase(&self) -> Phase
+    {
+        Phase::Update
+    }
+
+    /// Optional method listing the types of systems this one has to run after.
+    ///
+    /// Like the priority, this is checked once when the systems are created. Ordering
+    /// constraints take precedence over priorities, which only decide between systems that
+    /// aren't constrained.
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        Vec::new()
+    }
+
+    /// Optional method listing the types of systems this one has to run before.
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        Vec::new()
+    }
+}
+
+/// The phases of an update, which run one after the other.
+///
+/// Entities created, changed or removed during a phase are dealt with before the next one starts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase
+{
+    /// For setting up the world before anything else runs, such as in a `StartupSystem`.
+    Startup,
+    /// For work like reading input that the rest of the update depends on.
+    PreUpdate,
+    /// The default phase.
+    Update,
+    /// For work like cleaning up or rendering after everything else is done.
+    PostUpdate,
+}
+
+impl Phase
+{
+    /// Returns every phase, in the order they run.
+    pub fn all() -> [Phase; 4]
+    {
+        [Phase::Startup, Phase::PreUpdate, Phase::Update, Phase::PostUpdate]
+    }
+}
+
+/// Why a system stopped being told about an entity.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeactivationReason
+{
+    /// The entity was removed from the world, or the world was cleared.
+    Removed,
+    /// The entity was disabled, and is activated again if it is enabled.
+    Disabled,
+    /// The entity still exists, but no longer matches what the system is interested in.
+    Changed,
+}
+
+/// Works out the order systems are processed in.
+///
+/// Panics if the systems' ordering constraints contradict each other.
+#[doc(hidden)]
+pub fn order_systems(names: &[&'static str], types: &[TypeId], priorities: &[i32],
+                     after: &[Vec<TypeId>], before: &[Vec<TypeId>]) -> Vec<usize>
+{
+    let count = names.len();
+    // The systems that have to run before each system
+    let mut edges: Vec<Vec<usize>> = (0..count).map(|_| Vec::new()).collect();
+    for i in 0..count
+    {
+        for j in 0..count
+        {
+            if i != j && (after[i].contains(&types[j]) || before[j].contains(&types[i]))
+            {
+                edges[i].push(j);
+            }
+        }
+    }
+
+    let mut by_priority: Vec<usize> = (0..count).collect();
+    by_priority.sort_by(|a, b| priorities[*b].cmp(&priorities[*a]));
+
+    let mut placed: Vec<bool> = repeat(false).take(count).collect();
+    let mut order = Vec::with_capacity(count);
+    while order.len() < count
+    {
+        let next = by_priority.iter().map(|i| *i)
+            .find(|i| !placed[*i] && edges[*i].iter().all(|j| placed[*j]));
+        match next
+        {
+            Some(i) =>
+            {
+                placed[i] = true;
+                order.push(i);
+            },
+            None => panic!("systems have contradictory ordering constraints: {}",
+                           describe_cycle(names, &edges, &placed)),
+        }
+    }
+    order
+}
+
+// Follows unplaced dependencies until one repeats, giving a cycle in the order it would run.
+fn describe_cycle(names: &[&'static str], edges: &[Vec<usize>], placed: &[bool]) -> String
+{
+    let mut path: Vec<usize> = Vec::new();
+    let mut current = placed.iter().position(|placed| !*placed).unwrap();
+    while !path.contains(&current)
+    {
+        path.push(current);
+        current = *edges[current].iter().find(|j| !placed[**j]).unwrap();
+    }
+    let start = path.iter().position(|i| *i == current).unwrap();
+    let mut cycle: Vec<&str> = path[start..].iter().rev().map(|i| names[*i]).collect();
+    let first = cycle[0];
+    cycle.push(first);
+    cycle.connect(" runs before ")
 }
 
 pub trait Process: System
@@ -49,3 +214,55 @@ pub trait Process: System
     /// Process the world.
     fn process(&mut self, &mut DataHelper<<Self as System>::Components>);
 }
+
+/// How long a system has spent processing, recorded while profiling is turned on.
+#[derive(Clone, Debug)]
+pub struct SystemTiming
+{
+    /// The name of the system in the `systems!` macro, or `"dynamic"` for systems added with
+    /// `World::add_dynamic_system`.
+    pub name: &'static str,
+    pub type_name: &'static str,
+    /// The total time spent in `process`.
+    pub duration: Duration,
+    /// The number of times `process` has been called.
+    pub calls: u64,
+    /// The total number of entities processed, for systems that keep count. See
+    /// `System::processed_entities`.
+    pub entities: u64,
+}
+
+impl SystemTiming
+{
+    #[doc(hidden)]
+    pub fn new<S: 'static>(name: &'static str) -> SystemTiming
+    {
+        SystemTiming
+        {
+            name: name,
+            type_name: unsafe { ::std::intrinsics::type_name::<S>() },
+            duration: Duration::zero(),
+            calls: 0,
+            entities: 0,
+        }
+    }
+
+    /// Processes the system, adding the time it took.
+    #[doc(hidden)]
+    pub fn process<T: Process+?Sized>(&mut self, system: &mut T, data: &mut DataHelper<<T as System>::Components>)
+    {
+        let before = system.processed_entities();
+        let duration = Duration::span(|| system.process(data));
+        self.duration = self.duration + duration;
+        self.calls += 1;
+        self.entities += system.processed_entities() - before;
+    }
+
+    #[doc(hidden)]
+    pub fn reset(&mut self)
+    {
+        self.duration = Duration::zero();
+        self.calls = 0;
+        self.entities = 0;
+    }
+}
@@ -0,0 +1,345 @@
+
+use std::any::TypeId;
+use std::sync::mpsc::{channel, Receiver, Sender, SendError};
+use std::thread;
+
+use {BuildData, ModifyData};
+use ComponentManager;
+use DataHelper;
+use Entity;
+use EntityData;
+use {EntityBuilder, EntityModifier};
+use {DeactivationReason, Phase, Process, System};
+
+/// Process that can run at the same time as other parallel processes.
+///
+/// It can only read the components, and changes to the world are recorded into a command buffer
+/// that is queued once every process in the group has finished.
+pub trait ParallelProcess: System + Send
+{
+    fn process(&mut self, &<Self as System>::Components, &mut Commands<<Self as System>::Components>);
+
+    /// Returns the types of the components the process reads.
+    fn reads(&self) -> Vec<TypeId>;
+
+    /// Returns the types of the components the process changes on existing entities through
+    /// its commands. Creating and removing entities doesn't count.
+    fn writes(&self) -> Vec<TypeId>;
+}
+
+/// System which runs two parallel processes on separate threads.
+///
+/// The second process runs on a worker thread that is started along with the system and
+/// stopped when it is dropped. Groups of more than two processes can be made by nesting parallel
+/// systems. Commands are queued in the order the processes are given in.
+pub struct ParallelSystem<A, B>
+{
+    pub first: A,
+    pub second: B,
+    worker: Worker,
+}
+
+impl<A, B> ParallelSystem<A, B>
+    where A: ParallelProcess, B: ParallelProcess<Components=<A as System>::Components>,
+          <A as System>::Components: Sync
+{
+    /// Panics if either process writes components that the other one reads or writes.
+    pub fn new(first: A, second: B) -> ParallelSystem<A, B>
+    {
+        let (first_writes, second_writes) = (first.writes(), second.writes());
+        assert!(disjoint(&first_writes, &second.reads()) && disjoint(&second_writes, &first.reads())
+            && disjoint(&first_writes, &second_writes),
+            "parallel processes can't write components that the other one reads or writes");
+        ParallelSystem
+        {
+            first: first,
+            second: second,
+            worker: Worker::new(),
+        }
+    }
+}
+
+impl<A, B> ParallelProcess for ParallelSystem<A, B>
+    where A: ParallelProcess, B: ParallelProcess<Components=<A as System>::Components>,
+          <A as System>::Components: Sync
+{
+    fn process(&mut self, c: &<A as System>::Components, commands: &mut Commands<<A as System>::Components>)
+    {
+        let mut second = Commands::new();
+        self.worker.start(Job {
+            run: run_job::<B>,
+            process: &mut self.second as *mut B as *mut (),
+            components: c as *const <A as System>::Components as *const (),
+            commands: &mut second as *mut Commands<<A as System>::Components> as *mut (),
+        });
+        let pending = Pending { worker: &self.worker, waited: false };
+        self.first.process(c, commands);
+        if !pending.wait()
+        {
+            panic!("the second process of a parallel system panicked");
+        }
+        commands.append(second);
+    }
+
+    fn reads(&self) -> Vec<TypeId>
+    {
+        let mut reads = self.first.reads();
+        reads.extend(self.second.reads().into_iter());
+        reads
+    }
+
+    fn writes(&self) -> Vec<TypeId>
+    {
+        let mut writes = self.first.writes();
+        writes.extend(self.second.writes().into_iter());
+        writes
+    }
+}
+
+fn disjoint(first: &[TypeId], second: &[TypeId]) -> bool
+{
+    !first.iter().any(|id| second.contains(id))
+}
+
+// Process for the worker to run, with everything it borrows from `ParallelSystem::process`.
+struct Job
+{
+    run: unsafe fn(*mut (), *const (), *mut ()),
+    process: *mut (),
+    components: *const (),
+    commands: *mut (),
+}
+
+// The pointers are only used until the worker says it's done, and `ParallelSystem::process`
+// always waits for that before returning, even when it unwinds.
+unsafe impl Send for Job {}
+
+unsafe fn run_job<B: ParallelProcess>(process: *mut (), components: *const (), commands: *mut ())
+{
+    let process = &mut *(process as *mut B);
+    process.process(&*(components as *const <B as System>::Components),
+                    &mut *(commands as *mut Commands<<B as System>::Components>));
+}
+
+// Thread that runs jobs one at a time, until the channel is closed.
+struct Worker
+{
+    jobs: Option<Sender<Job>>,
+    done: Receiver<()>,
+    thread: Option<thread::JoinHandle>,
+}
+
+impl Worker
+{
+    fn new() -> Worker
+    {
+        let (jobs, received) = channel::<Job>();
+        let (finished, done) = channel();
+        let thread = thread::spawn(move || {
+            for job in received.iter()
+            {
+                unsafe { (job.run)(job.process, job.components, job.commands); }
+                if finished.send(()).is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Worker { jobs: Some(jobs), done: done, thread: Some(thread) }
+    }
+
+    fn start(&mut self, job: Job)
+    {
+        let job = match self.jobs.as_ref().unwrap().send(job)
+        {
+            Ok(()) => return,
+            Err(SendError(job)) => job,
+        };
+        // The worker stops when one of its jobs panics, so a new one takes its place
+        *self = Worker::new();
+        if self.jobs.as_ref().unwrap().send(job).is_err()
+        {
+            unreachable!();
+        }
+    }
+}
+
+impl Drop for Worker
+{
+    fn drop(&mut self)
+    {
+        // Closing the channel ends the worker's loop
+        self.jobs = None;
+        if let Some(thread) = self.thread.take()
+        {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Waits for the worker's job to finish, which has to happen before the components it borrows
+// go away, even if the first process panics.
+struct Pending<'a>
+{
+    worker: &'a Worker,
+    waited: bool,
+}
+
+impl<'a> Pending<'a>
+{
+    // Returns false if the job panicked
+    fn wait(mut self) -> bool
+    {
+        self.waited = true;
+        self.worker.done.recv().is_ok()
+    }
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for Pending<'a>
+{
+    fn drop(&mut self)
+    {
+        if !self.waited
+        {
+            let _ = self.worker.done.recv();
+        }
+    }
+}
+
+impl<A, B> Process for ParallelSystem<A, B>
+    where A: ParallelProcess, B: ParallelProcess<Components=<A as System>::Components>,
+          <A as System>::Components: Sync
+{
+    fn process(&mut self, data: &mut DataHelper<<A as System>::Components>)
+    {
+        let mut commands = Commands::new();
+        ParallelProcess::process(self, &data.components, &mut commands);
+        commands.queue(data);
+    }
+}
+
+impl<A, B> System for ParallelSystem<A, B>
+    where A: ParallelProcess, B: ParallelProcess<Components=<A as System>::Components>,
+          <A as System>::Components: Sync
+{
+    type Components = <A as System>::Components;
+    fn activated(&mut self, e: &EntityData, w: &<A as System>::Components)
+    {
+        self.first.activated(e, w);
+        self.second.activated(e, w);
+    }
+
+    fn reactivated(&mut self, e: &EntityData, w: &<A as System>::Components)
+    {
+        self.first.reactivated(e, w);
+        self.second.reactivated(e, w);
+    }
+
+    fn deactivated_with_reason(&mut self, e: &EntityData, w: &<A as System>::Components, reason: DeactivationReason)
+    {
+        self.first.deactivated_with_reason(e, w, reason);
+        self.second.deactivated_with_reason(e, w, reason);
+    }
+
+    fn is_active(&self) -> bool
+    {
+        self.first.is_active() || self.second.is_active()
+    }
+
+    fn priority(&self) -> i32
+    {
+        self.first.priority()
+    }
+
+    fn phase(&self) -> Phase
+    {
+        self.first.phase()
+    }
+
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        let mut after = self.first.run_after();
+        after.extend(self.second.run_after().into_iter());
+        after
+    }
+
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        let mut before = self.first.run_before();
+        before.extend(self.second.run_before().into_iter());
+        before
+    }
+}
+
+enum Command<T: ComponentManager>
+{
+    Create(Box<EntityBuilder<T>+Send>),
+    Modify(Entity, Box<EntityModifier<T>+Send>),
+    Remove(Entity),
+}
+
+/// Changes to the world recorded by a parallel process.
+pub struct Commands<T: ComponentManager>
+{
+    commands: Vec<Command<T>>,
+}
+
+impl<T: ComponentManager> Commands<T>
+{
+    pub fn new() -> Commands<T>
+    {
+        Commands
+        {
+            commands: Vec::new(),
+        }
+    }
+
+    /// Records an entity to be created.
+    ///
+    /// The entity doesn't exist yet, so it only gets an id when the commands are queued.
+    pub fn create_entity<B>(&mut self, builder: B) where B: EntityBuilder<T>+Send+'static
+    {
+        self.commands.push(Command::Create(Box::new(builder)));
+    }
+
+    pub fn modify_entity<M>(&mut self, entity: Entity, modifier: M) where M: EntityModifier<T>+Send+'static
+    {
+        self.commands.push(Command::Modify(entity, Box::new(modifier)));
+    }
+
+    pub fn remove_entity(&mut self, entity: Entity)
+    {
+        self.commands.push(Command::Remove(entity));
+    }
+
+    /// Moves the commands from `other` to the end of this buffer.
+    pub fn append(&mut self, other: Commands<T>)
+    {
+        self.commands.extend(other.commands.into_iter());
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.commands.is_empty()
+    }
+
+    /// Adds the commands to the world's queue, in the order they were recorded.
+    pub fn queue(self, data: &mut DataHelper<T>)
+    {
+        for command in self.commands.into_iter()
+        {
+            match command
+            {
+                Command::Create(mut builder) => { data.create_entity(move |e: BuildData, c: &mut T| builder.build(e, c)); },
+                Command::Modify(entity, mut modifier) => data.modify_entity(entity, move |e: ModifyData, c: &mut T| modifier.modify(e, c)),
+                Command::Remove(entity) => data.remove_entity(entity),
+            }
+        }
+    }
+}
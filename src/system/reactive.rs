@@ -0,0 +1,209 @@
+
+use std::any::TypeId;
+use std::collections::BTreeSet;
+use std::collections::btree_set;
+use std::iter::Cloned;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+use Aspect;
+use {Component, HasComponent};
+use DataHelper;
+use Entity;
+use EntityData;
+use EntityIter;
+use {DeactivationReason, Phase, Process, System};
+
+pub trait ReactiveProcess: System
+{
+    /// Processes the changed entities, and those that no longer match since the last run.
+    fn process<'a>(&mut self, EntityIter<'a, <Self as System>::Components>, &[Entity],
+                   &mut DataHelper<<Self as System>::Components>);
+}
+
+/// System which only processes the entities matching an aspect whose watched components have
+/// been inserted or modified since it last ran.
+///
+/// Entities that start matching the aspect count as changed. The first time it runs, every
+/// matching entity counts as changed.
+pub struct ReactiveSystem<T: ReactiveProcess>
+{
+    interested: BTreeSet<Entity>,
+    entered: BTreeSet<Entity>,
+    removed: Vec<Entity>,
+    watched: Vec<(fn(&mut <T as System>::Components, u64, &mut BTreeSet<Entity>) -> u64, u64)>,
+    started: bool,
+    aspect: Aspect<<T as System>::Components>,
+    pub inner: T,
+}
+
+impl<T: ReactiveProcess> ReactiveSystem<T>
+{
+    pub fn new(inner: T, aspect: Aspect<<T as System>::Components>) -> ReactiveSystem<T>
+    {
+        ReactiveSystem
+        {
+            interested: BTreeSet::new(),
+            entered: BTreeSet::new(),
+            removed: Vec::new(),
+            watched: Vec::new(),
+            started: false,
+            aspect: aspect,
+            inner: inner,
+        }
+    }
+
+    /// Returns the entities matching the system's aspect, in ascending order of index.
+    pub fn entities(&self) -> Cloned<btree_set::Iter<Entity>>
+    {
+        self.interested.iter().cloned()
+    }
+
+    /// Returns true if `entity` currently matches the system's aspect.
+    pub fn contains(&self, entity: &Entity) -> bool
+    {
+        self.interested.contains(entity)
+    }
+
+    /// Also treats entities as changed when their component of type `C` changes.
+    ///
+    /// This turns on change tracking for the component's list.
+    pub fn watch<C: Component>(mut self) -> ReactiveSystem<T>
+        where <T as System>::Components: HasComponent<C>
+    {
+        self.watched.push((collect_changes::<<T as System>::Components, C>, 0));
+        self
+    }
+}
+
+fn collect_changes<T, C>(components: &mut T, version: u64, changed: &mut BTreeSet<Entity>) -> u64
+    where T: HasComponent<C>, C: Component
+{
+    let list = components.list_mut();
+    list.track_changes();
+    for entity in list.changed_since(version).into_iter()
+    {
+        changed.insert(entity);
+    }
+    list.change_version()
+}
+
+impl<T: ReactiveProcess> Deref for ReactiveSystem<T>
+{
+    type Target = T;
+    fn deref(&self) -> &T
+    {
+        &self.inner
+    }
+}
+
+impl<T: ReactiveProcess> DerefMut for ReactiveSystem<T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.inner
+    }
+}
+
+impl<T: ReactiveProcess> ReactiveSystem<T>
+{
+    fn enter(&mut self, entity: &EntityData, world: &<T as System>::Components)
+    {
+        self.interested.insert(**entity);
+        self.entered.insert(**entity);
+        self.removed.retain(|removed| *removed != **entity);
+        self.inner.activated(entity, world);
+    }
+
+    fn leave(&mut self, entity: &EntityData, world: &<T as System>::Components, reason: DeactivationReason)
+    {
+        self.interested.remove(&**entity);
+        self.entered.remove(&**entity);
+        self.removed.push(**entity);
+        self.inner.deactivated_with_reason(entity, world, reason);
+    }
+}
+
+impl<T: ReactiveProcess> System for ReactiveSystem<T>
+{
+    type Components = <T as System>::Components;
+    fn activated(&mut self, entity: &EntityData, world: &<T as System>::Components)
+    {
+        if self.aspect.check(entity, world)
+        {
+            self.enter(entity, world);
+        }
+    }
+
+    fn reactivated(&mut self, entity: &EntityData, world: &<T as System>::Components)
+    {
+        if self.interested.contains(&**entity)
+        {
+            if self.aspect.check(entity, world)
+            {
+                self.inner.reactivated(entity, world);
+            }
+            else
+            {
+                self.leave(entity, world, DeactivationReason::Changed);
+            }
+        }
+        else if self.aspect.check(entity, world)
+        {
+            self.enter(entity, world);
+        }
+    }
+
+    fn deactivated_with_reason(&mut self, entity: &EntityData, world: &<T as System>::Components, reason: DeactivationReason)
+    {
+        if self.interested.contains(&**entity)
+        {
+            self.leave(entity, world, reason);
+        }
+    }
+
+    fn is_active(&self) -> bool
+    {
+        self.inner.is_active()
+    }
+
+    fn priority(&self) -> i32
+    {
+        self.inner.priority()
+    }
+
+    fn phase(&self) -> Phase
+    {
+        self.inner.phase()
+    }
+
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        self.inner.run_after()
+    }
+
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        self.inner.run_before()
+    }
+}
+
+impl<T: ReactiveProcess> Process for ReactiveSystem<T>
+{
+    fn process(&mut self, c: &mut DataHelper<<T as System>::Components>)
+    {
+        let mut changed = mem::replace(&mut self.entered, BTreeSet::new());
+        for watched in self.watched.iter_mut()
+        {
+            watched.1 = (watched.0)(&mut c.components, watched.1, &mut changed);
+        }
+        if !self.started
+        {
+            self.started = true;
+            changed = self.interested.clone();
+        }
+        let changed: BTreeSet<Entity> = changed.intersection(&self.interested).cloned().collect();
+        let removed = mem::replace(&mut self.removed, Vec::new());
+        self.inner.process(EntityIter::new(changed.iter()), &removed, c);
+    }
+}
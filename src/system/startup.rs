@@ -0,0 +1,115 @@
+
+use std::any::TypeId;
+use std::ops::{Deref, DerefMut};
+
+use DataHelper;
+use EntityData;
+use {DeactivationReason, Phase, Process, System};
+
+/// System which operates once, in the first update after it is created or reset.
+///
+/// It runs in `Phase::Startup`, so entities it creates are built before any other systems run.
+pub struct StartupSystem<T: Process>
+{
+    done: bool,
+    pub inner: T,
+}
+
+impl<T: Process> StartupSystem<T>
+{
+    pub fn new(system: T) -> StartupSystem<T>
+    {
+        StartupSystem
+        {
+            done: false,
+            inner: system,
+        }
+    }
+
+    /// Makes the system run again in the next update, such as after clearing the world.
+    pub fn reset(&mut self)
+    {
+        self.done = false;
+    }
+
+    /// Returns true if the system has run since it was created or last reset.
+    pub fn has_run(&self) -> bool
+    {
+        self.done
+    }
+}
+
+impl<T: Process> Deref for StartupSystem<T>
+{
+    type Target = T;
+    fn deref(&self) -> &T
+    {
+        &self.inner
+    }
+}
+
+impl<T: Process> DerefMut for StartupSystem<T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.inner
+    }
+}
+
+impl<T: Process> Process for StartupSystem<T>
+{
+    fn process(&mut self, c: &mut DataHelper<<T as System>::Components>)
+    {
+        self.done = true;
+        self.inner.process(c);
+    }
+}
+
+impl<T: Process> System for StartupSystem<T>
+{
+    type Components = <T as System>::Components;
+    fn activated(&mut self, e: &EntityData, w: &<T as System>::Components)
+    {
+        self.inner.activated(e, w);
+    }
+
+    fn reactivated(&mut self, e: &EntityData, w: &<T as System>::Components)
+    {
+        self.inner.reactivated(e, w);
+    }
+
+    fn deactivated_with_reason(&mut self, e: &EntityData, w: &<T as System>::Components, reason: DeactivationReason)
+    {
+        self.inner.deactivated_with_reason(e, w, reason);
+    }
+
+    fn is_active(&self) -> bool
+    {
+        !self.done && self.inner.is_active()
+    }
+
+    fn processed_entities(&self) -> u64
+    {
+        self.inner.processed_entities()
+    }
+
+    fn priority(&self) -> i32
+    {
+        self.inner.priority()
+    }
+
+    fn phase(&self) -> Phase
+    {
+        Phase::Startup
+    }
+
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        self.inner.run_after()
+    }
+
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        self.inner.run_before()
+    }
+}
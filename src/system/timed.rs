@@ -0,0 +1,166 @@
+
+use std::any::TypeId;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+use DataHelper;
+use EntityData;
+use {DeactivationReason, Phase, Process, System};
+
+/// System which operates every certain amount of time.
+///
+/// Time is taken from the delta passed to `World::update_with_delta`, and the inner process
+/// sees the interval in seconds as its delta.
+pub struct TimedSystem<T: Process>
+{
+    interval: Duration,
+    // The interval in seconds, to compare with the delta
+    seconds: f64,
+    elapsed: f64,
+    max_catch_up: u32,
+    pub inner: T,
+}
+
+impl<T: Process> TimedSystem<T>
+{
+    /// Create a new timed system with the specified time between processes.
+    ///
+    /// Panics if the interval isn't positive.
+    pub fn new(system: T, interval: Duration) -> TimedSystem<T>
+    {
+        assert!(interval > Duration::zero(), "the interval of a timed system must be positive");
+        TimedSystem
+        {
+            interval: interval,
+            seconds: seconds(interval),
+            elapsed: 0.0,
+            max_catch_up: 1,
+            inner: system,
+        }
+    }
+
+    pub fn interval(&self) -> Duration
+    {
+        self.interval
+    }
+
+    /// Changes the interval, starting the new one from where the old one left off.
+    ///
+    /// Time that has already built up is capped to a single interval, so shortening the
+    /// interval never runs the system several times in one update.
+    pub fn set_interval(&mut self, interval: Duration)
+    {
+        assert!(interval > Duration::zero(), "the interval of a timed system must be positive");
+        self.interval = interval;
+        self.seconds = seconds(interval);
+        if self.elapsed > self.seconds
+        {
+            self.elapsed = self.seconds;
+        }
+    }
+
+    /// Sets how many times the system can run in a single update to catch up after a long
+    /// frame. Any intervals beyond that are skipped. Defaults to 1.
+    pub fn set_max_catch_up(&mut self, max_catch_up: u32)
+    {
+        self.max_catch_up = max_catch_up;
+    }
+}
+
+impl<T: Process> Deref for TimedSystem<T>
+{
+    type Target = T;
+    fn deref(&self) -> &T
+    {
+        &self.inner
+    }
+}
+
+impl<T: Process> DerefMut for TimedSystem<T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.inner
+    }
+}
+
+impl<T: Process> Process for TimedSystem<T>
+{
+    fn process(&mut self, c: &mut DataHelper<<T as System>::Components>)
+    {
+        let delta = c.delta();
+        self.elapsed += delta;
+        c.set_delta(self.seconds);
+        let mut runs = 0;
+        while self.elapsed >= self.seconds && runs < self.max_catch_up
+        {
+            self.elapsed -= self.seconds;
+            runs += 1;
+            self.inner.process(c);
+        }
+        c.set_delta(delta);
+        if self.elapsed >= self.seconds
+        {
+            self.elapsed = self.elapsed % self.seconds;
+        }
+    }
+}
+
+// Converts a duration to seconds, falling back to milliseconds if it's too long for nanoseconds
+fn seconds(duration: Duration) -> f64
+{
+    match duration.num_nanoseconds()
+    {
+        Some(nanoseconds) => nanoseconds as f64 / 1e9,
+        None => duration.num_milliseconds() as f64 / 1e3,
+    }
+}
+
+impl<T: Process> System for TimedSystem<T>
+{
+    type Components = <T as System>::Components;
+    fn activated(&mut self, e: &EntityData, w: &<T as System>::Components)
+    {
+        self.inner.activated(e, w);
+    }
+
+    fn reactivated(&mut self, e: &EntityData, w: &<T as System>::Components)
+    {
+        self.inner.reactivated(e, w);
+    }
+
+    fn deactivated_with_reason(&mut self, e: &EntityData, w: &<T as System>::Components, reason: DeactivationReason)
+    {
+        self.inner.deactivated_with_reason(e, w, reason);
+    }
+
+    fn is_active(&self) -> bool
+    {
+        self.inner.is_active()
+    }
+
+    fn processed_entities(&self) -> u64
+    {
+        self.inner.processed_entities()
+    }
+
+    fn priority(&self) -> i32
+    {
+        self.inner.priority()
+    }
+
+    fn phase(&self) -> Phase
+    {
+        self.inner.phase()
+    }
+
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        self.inner.run_after()
+    }
+
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        self.inner.run_before()
+    }
+}
@@ -0,0 +1,66 @@
+
+use {ComponentManager, SystemManager, World};
+
+/// Updates a world in steps of a fixed length, however much time passes between frames.
+///
+/// Each step is a full `World::update_with_delta`, so entities created or removed in one step
+/// are built or removed before the next step runs.
+pub struct FixedTimestep
+{
+    step: f64,
+    accumulator: f64,
+    max_steps: u32,
+}
+
+impl FixedTimestep
+{
+    /// Creates a timestep running steps of `step` seconds, and at most 8 steps per update.
+    ///
+    /// Panics if the step isn't positive.
+    pub fn new(step: f64) -> FixedTimestep
+    {
+        assert!(step > 0.0, "the length of a fixed timestep must be positive");
+        FixedTimestep
+        {
+            step: step,
+            accumulator: 0.0,
+            max_steps: 8,
+        }
+    }
+
+    pub fn step(&self) -> f64
+    {
+        self.step
+    }
+
+    /// Sets how many steps `update` may run at once.
+    ///
+    /// If updating takes longer than the time it simulates, more and more steps would be needed
+    /// each frame. Any time that would need more steps than this is dropped instead.
+    pub fn set_max_steps(&mut self, max_steps: u32)
+    {
+        self.max_steps = max_steps;
+    }
+
+    /// Adds `delta` seconds of real time, and updates the world once for every whole step.
+    ///
+    /// Returns how far into the next step the leftover time is, between 0 and 1, which can be
+    /// used to interpolate between the last two steps when rendering.
+    pub fn update<T, U>(&mut self, world: &mut World<T, U>, delta: f64) -> f64
+        where T: ComponentManager, U: SystemManager<Components=T>
+    {
+        self.accumulator += delta;
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_steps
+        {
+            self.accumulator -= self.step;
+            steps += 1;
+            world.update_with_delta(self.step);
+        }
+        if self.accumulator >= self.step
+        {
+            self.accumulator = self.accumulator % self.step;
+        }
+        self.accumulator / self.step
+    }
+}
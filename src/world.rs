@@ -1,35 +1,493 @@
 
+use std::any::{Any, TypeId};
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::vec_deque;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::iter::Cloned;
+use std::marker::PhantomData;
+use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::slice::Iter;
+use std::thread;
+use std::vec::Drain;
 
 use {BuildData, EntityData, ModifyData};
-use {Entity, EntityIter, EntityBuilder, EntityModifier};
-use {System};
-use entity::EntityManager;
+use arena::Arena;
+use {Component, Entity, EntityIter, EntityBuilder, EntityModifier, HasComponent};
+use {DeactivationReason, Phase, Process, System, SystemTiming};
+use component::{ComponentBorrows, ComponentStats};
+use mask::ComponentMask;
+use query::{ComponentQuery, Query, QueryMut};
+use system::dynamic::{DynamicSystems, SystemHandle};
+use save::{self, Reader, SaveableComponents, Writer};
+use entity::{DebugEntity, DebugNames, EntityManager, Generation, Generations, InvalidEntity, PredicateEntityIter};
 
 enum Event<'a, T> where T: ComponentManager
 {
-    BuildEntity(Entity, Box<EntityBuilder<T>+'a>),
-    ModifyEntity(Entity, Box<EntityModifier<T>+'a>),
+    BuildEntity(Entity, Queued<T>),
+    SpawnPrefab(Entity, String, Box<EntityBuilder<T>+'a>),
+    ActivateEntity(Entity),
+    AfterBuild(Entity, Box<FnMut(Entity, &mut DataHelper<T>)+'a>),
+    ModifyEntity(Entity, Queued<T>),
+    // Modifiers held for a reserved entity are boxed, as they can wait for any number of flushes
+    // and the arena only starts again from the beginning once it's empty
+    ModifyHeld(Entity, Box<EntityModifier<T>+'a>),
     RemoveEntity(Entity),
+    DisableEntity(Entity),
+    EnableEntity(Entity),
+    ReactivateEntity(Entity),
+    AddComponent(Entity, Box<Any>, fn(&mut T, &Entity, Box<Any>) -> bool),
+    RemoveComponent(Entity, fn(&mut T, &Entity) -> bool),
 }
 
+// A builder or modifier stored in the queue's arena, and the function that takes it out and runs
+// it on an entity.
+struct Queued<T>
+{
+    key: usize,
+    run: unsafe fn(&mut Arena, usize, &Entity, &mut T),
+}
+
+unsafe fn run_builder<T: ComponentManager, B: EntityBuilder<T>>(arena: &mut Arena, key: usize,
+                                                                entity: &Entity, components: &mut T)
+{
+    let mut builder: B = arena.take(key);
+    builder.build(BuildData(entity), components);
+}
+
+unsafe fn run_boxed_builder<T: ComponentManager, B: EntityBuilder<T>>(arena: &mut Arena, key: usize,
+                                                                      entity: &Entity, components: &mut T)
+{
+    let mut builder: Box<B> = arena.take(key);
+    builder.build(BuildData(entity), components);
+}
+
+unsafe fn run_modifier<T: ComponentManager, M: EntityModifier<T>>(arena: &mut Arena, key: usize,
+                                                                  entity: &Entity, components: &mut T)
+{
+    let mut modifier: M = arena.take(key);
+    modifier.modify(ModifyData(entity), components);
+}
+
+unsafe fn run_boxed_modifier<T: ComponentManager, M: EntityModifier<T>>(arena: &mut Arena, key: usize,
+                                                                        entity: &Entity, components: &mut T)
+{
+    let mut modifier: Box<M> = arena.take(key);
+    modifier.modify(ModifyData(entity), components);
+}
+
+impl<'a, T: ComponentManager> Event<'a, T>
+{
+    // Returns the key the event's builder or modifier is kept in the arena under
+    fn arena_key(&self) -> Option<usize>
+    {
+        match *self
+        {
+            Event::BuildEntity(_, ref queued) | Event::ModifyEntity(_, ref queued) => Some(queued.key),
+            _ => None,
+        }
+    }
+
+    fn entity(&self) -> &Entity
+    {
+        match *self
+        {
+            Event::BuildEntity(ref entity, _) => entity,
+            Event::SpawnPrefab(ref entity, _, _) => entity,
+            Event::ActivateEntity(ref entity) => entity,
+            Event::AfterBuild(ref entity, _) => entity,
+            Event::ModifyEntity(ref entity, _) => entity,
+            Event::ModifyHeld(ref entity, _) => entity,
+            Event::RemoveEntity(ref entity) => entity,
+            Event::DisableEntity(ref entity) => entity,
+            Event::EnableEntity(ref entity) => entity,
+            Event::ReactivateEntity(ref entity) => entity,
+            Event::AddComponent(ref entity, _, _) => entity,
+            Event::RemoveComponent(ref entity, _) => entity,
+        }
+    }
+}
+
+/// Kind of a change waiting in the event queue, and the entity it is for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum QueuedEvent
+{
+    Build(Entity),
+    Activate(Entity),
+    AfterBuild(Entity),
+    Modify(Entity),
+    Remove(Entity),
+    Disable(Entity),
+    Enable(Entity),
+    Reactivate(Entity),
+    AddComponent(Entity),
+    RemoveComponent(Entity),
+}
+
+impl QueuedEvent
+{
+    pub fn entity(&self) -> Entity
+    {
+        match *self
+        {
+            QueuedEvent::Build(entity) | QueuedEvent::Activate(entity) | QueuedEvent::AfterBuild(entity) |
+            QueuedEvent::Modify(entity) | QueuedEvent::Remove(entity) |
+            QueuedEvent::Disable(entity) | QueuedEvent::Enable(entity) | QueuedEvent::Reactivate(entity) |
+            QueuedEvent::AddComponent(entity) | QueuedEvent::RemoveComponent(entity) => entity,
+        }
+    }
+
+    /// Returns true if the event changes the entity's components.
+    pub fn is_modification(&self) -> bool
+    {
+        match *self
+        {
+            QueuedEvent::Modify(_) | QueuedEvent::AddComponent(_) | QueuedEvent::RemoveComponent(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Iterator over the events waiting in a world's queue, in the order they will be applied.
+pub struct QueuedEvents<'a, T: ComponentManager>
+{
+    iter: vec_deque::Iter<'a, Event<'static, T>>,
+}
+
+impl<'a, T: ComponentManager> Iterator for QueuedEvents<'a, T>
+{
+    type Item = QueuedEvent;
+    fn next(&mut self) -> Option<QueuedEvent>
+    {
+        self.iter.next().map(|event| {
+            let entity = *event.entity();
+            match *event
+            {
+                Event::BuildEntity(..) | Event::SpawnPrefab(..) => QueuedEvent::Build(entity),
+                Event::ActivateEntity(..) => QueuedEvent::Activate(entity),
+                Event::AfterBuild(..) => QueuedEvent::AfterBuild(entity),
+                Event::ModifyEntity(..) | Event::ModifyHeld(..) => QueuedEvent::Modify(entity),
+                Event::RemoveEntity(..) => QueuedEvent::Remove(entity),
+                Event::DisableEntity(..) => QueuedEvent::Disable(entity),
+                Event::EnableEntity(..) => QueuedEvent::Enable(entity),
+                Event::ReactivateEntity(..) => QueuedEvent::Reactivate(entity),
+                Event::AddComponent(..) => QueuedEvent::AddComponent(entity),
+                Event::RemoveComponent(..) => QueuedEvent::RemoveComponent(entity),
+            }
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>)
+    {
+        self.iter.size_hint()
+    }
+}
+
+/// Number of times `World::flush` goes over the queue before giving up.
+const MAX_FLUSH_PASSES: usize = 100;
+
 pub struct World<T, U> where T: ComponentManager, U: SystemManager<Components=T>
 {
     pub systems: U,
     pub data: DataHelper<T>,
+    dynamic: DynamicSystems<T>,
 }
 
 pub struct DataHelper<T> where T: ComponentManager
 {
     pub components: T,
     entities: EntityManager,
-    event_queue: Vec<Event<'static, T>>,
+    event_queue: VecDeque<Event<'static, T>>,
+    arena: Arena,
+    pending_removal: HashSet<Entity>,
+    pending_build: HashSet<Entity>,
+    // Entities from `reserve_ids` that haven't been given a builder yet, which are also pending build,
+    // along with the changes queued for them, which are held back until the build is queued.
+    reserved: HashMap<Entity, Vec<Event<'static, T>>>,
+    disabled: HashSet<Entity>,
+    // Entities whose components were added or removed since the last pass, which can contain
+    // duplicates. The list of those being reactivated is kept so the space is reused.
+    changed: Vec<Entity>,
+    reactivating: Vec<Entity>,
+    // Entities that have been built but not activated yet, and the list of those in a batch
+    // that are still activated, which is kept so the space is reused.
+    activating: Vec<Entity>,
+    active: Vec<Entity>,
+    names: HashMap<String, Entity>,
+    entity_names: HashMap<Entity, String>,
+    debug_names: DebugNames,
+    tags: HashMap<TypeId, Entity>,
+    resources: HashMap<TypeId, Box<Any>>,
+    messages: HashMap<TypeId, (Box<Any>, fn(&mut Any))>,
+    parents: HashMap<Entity, Entity>,
+    children: HashMap<Entity, Vec<Entity>>,
+    callbacks: EntityCallbacks<T>,
+    prefabs: HashMap<String, Box<EntityBuilder<T>>>,
+    delta: f64,
+    paused: bool,
+    context: Option<(TypeId, *mut ())>,
+}
+
+/// Error returned when spawning a prefab that hasn't been registered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownPrefab(pub String);
+
+impl fmt::Display for UnknownPrefab
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} {:?}", self.description(), self.0)
+    }
+}
+
+impl Error for UnknownPrefab
+{
+    fn description(&self) -> &str
+    {
+        "unknown prefab"
+    }
+}
+
+/// Identifies a callback registered with `DataHelper::on_entity_created` or one of its siblings.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverHandle(u64);
+
+struct EntityCallbacks<T>
+{
+    created: Vec<(ObserverHandle, Box<FnMut(EntityData, &T)>)>,
+    modified: Vec<(ObserverHandle, Box<FnMut(EntityData, &T)>)>,
+    removed: Vec<(ObserverHandle, Box<FnMut(EntityData, &T)>)>,
+    next: u64,
+}
+
+impl<T> EntityCallbacks<T>
+{
+    fn next_handle(&mut self) -> ObserverHandle
+    {
+        let handle = ObserverHandle(self.next);
+        self.next += 1;
+        handle
+    }
+}
+
+fn run_callbacks<T>(callbacks: &mut Vec<(ObserverHandle, Box<FnMut(EntityData, &T)>)>, entity: &Entity, components: &T)
+{
+    for &mut (_, ref mut callback) in callbacks.iter_mut()
+    {
+        (**callback)(EntityData(entity), components);
+    }
+}
+
+/// Used by the `join!` macro to skip entities that systems wouldn't see.
+#[doc(hidden)]
+pub struct JoinFilter<'a>
+{
+    disabled: &'a HashSet<Entity>,
+    pending_removal: &'a HashSet<Entity>,
+}
+
+impl<'a> JoinFilter<'a>
+{
+    pub fn excludes(&self, entity: &Entity) -> bool
+    {
+        self.disabled.contains(entity) || self.pending_removal.contains(entity)
+    }
+
+    pub fn entity_data<'b>(&self, entity: &'b Entity) -> EntityData<'b>
+    {
+        EntityData(entity)
+    }
+}
+
+/// Read-only view of a world's entities and components, returned by `World::freeze`.
+///
+/// Nothing can be changed through a view, and it only holds shared references, so it can be
+/// given to other threads when the components are `Sync`. The world can't be changed until
+/// every view of it has been dropped.
+pub struct WorldView<'a, T> where T: ComponentManager
+{
+    components: &'a T,
+    entities: &'a EntityManager,
+    disabled: &'a HashSet<Entity>,
+    pending_removal: &'a HashSet<Entity>,
+}
+
+impl<'a, T: ComponentManager> WorldView<'a, T>
+{
+    /// Returns true if the entity has not been removed.
+    pub fn is_valid(&self, entity: &Entity) -> bool
+    {
+        self.entities.is_valid(entity)
+    }
+
+    /// Returns the number of valid entities.
+    pub fn entity_count(&self) -> usize
+    {
+        self.entities.count()
+    }
+
+    /// Returns an iterator over every entity, in ascending order of index.
+    ///
+    /// Disabled entities and entities queued for removal are skipped, like in `join!`.
+    pub fn entities(&self) -> EntityIter<'a, T>
+    {
+        self.entities.iter().excluding(self.disabled).excluding(self.pending_removal)
+    }
+
+    /// Returns the component of type `C` for an entity, if it has one.
+    pub fn get<C>(&self, entity: &Entity) -> Option<&'a C> where C: Component, T: HasComponent<C>
+    {
+        self.components.list().lookup(entity)
+    }
+
+    /// Returns an iterator over the entities that have every component in `Q`, like
+    /// `DataHelper::query`.
+    pub fn query<Q>(&self) -> Query<'a, T, Q> where Q: ComponentQuery<'a, T>
+    {
+        Query::new(self.entities(), self.components)
+    }
+
+    /// Only the components are handed out, so `join!` over a view can't write to them.
+    #[doc(hidden)]
+    pub fn join_parts(&self) -> (&'a T, JoinFilter<'a>)
+    {
+        (self.components, JoinFilter { disabled: self.disabled, pending_removal: self.pending_removal })
+    }
+}
+
+impl<'a, T: ComponentManager> Deref for WorldView<'a, T>
+{
+    type Target = T;
+    fn deref(&self) -> &T
+    {
+        self.components
+    }
 }
 
 pub unsafe trait ComponentManager: 'static
 {
     unsafe fn new() -> Self;
     unsafe fn remove_all(&mut self, en: &Entity);
+    unsafe fn copy_all(&mut self, from: &Entity, to: &Entity);
+    /// Moves every component of `from` into another manager, as the components of `to`.
+    unsafe fn move_all(&mut self, from: &Entity, other: &mut Self, to: &Entity) where Self: Sized;
+    unsafe fn clear_all(&mut self);
+    /// Works out which components an entity has by looking in every component list.
+    unsafe fn component_mask(&self, en: &Entity) -> ComponentMask;
+    /// Optional method to say whether any storage needs to be given the world's generations.
+    fn needs_generations(&self) -> bool
+    {
+        false
+    }
+    /// Optional method to give storages that don't keep generations access to the world's.
+    unsafe fn share_generations(&mut self, _generations: &Generations)
+    {
+
+    }
+    /// Returns the components an entity currently has.
+    ///
+    /// By default the mask is worked out from the component lists on every call.
+    fn mask_of(&self, en: &Entity) -> ComponentMask
+    {
+        unsafe { self.component_mask(en) }
+    }
+    /// Optional method to update the masks kept for `mask_of` with the components that have been
+    /// added or removed since the last update.
+    unsafe fn update_masks(&mut self)
+    {
+
+    }
+    /// Returns statistics about every component list, to help track down leaks.
+    fn stats(&self) -> Vec<ComponentStats>
+    {
+        Vec::new()
+    }
+    /// Returns the field name and type of every component list.
+    fn component_types(&self) -> Vec<(&'static str, TypeId)>
+    {
+        Vec::new()
+    }
+    /// Returns the component of an entity from the first component list of type `id`.
+    fn get_dyn(&self, _en: &Entity, _id: TypeId) -> Option<&Any>
+    {
+        None
+    }
+    /// Returns the component of an entity from the first component list of type `id`.
+    fn get_dyn_mut(&mut self, _en: &Entity, _id: TypeId) -> Option<&mut Any>
+    {
+        None
+    }
+    /// Sets a component in the first component list of its type, without updating the mask.
+    ///
+    /// Returns true if the component was added rather than replaced, or gives the component back
+    /// if no component list has its type.
+    unsafe fn set_dyn(&mut self, _en: &Entity, component: Box<Any>) -> Result<bool, Box<Any>>
+    {
+        Err(component)
+    }
+    /// Optional method to reserve storage for entities with indices up to `capacity`.
+    unsafe fn reserve(&mut self, _capacity: usize)
+    {
+
+    }
+    /// Optional method to forget the changes recorded by every component list.
+    unsafe fn clear_changes(&mut self)
+    {
+
+    }
+    /// Optional method to swap the buffers of every double buffered component list.
+    fn swap_buffers(&mut self)
+    {
+
+    }
+}
+
+/// Component managers whose lists can be copied into a `WorldSnapshot`.
+///
+/// Implemented by the `cloneable_components!` macro.
+pub trait CloneableComponents: ComponentManager
+{
+    fn clone_lists(&self) -> Vec<Box<Any>>;
+    unsafe fn restore_lists(&mut self, lists: &[Box<Any>]);
+}
+
+/// Copy of a world's entities and components, taken by `World::snapshot`.
+pub struct WorldSnapshot<T> where T: CloneableComponents
+{
+    entities: EntityManager,
+    disabled: HashSet<Entity>,
+    names: HashMap<String, Entity>,
+    entity_names: HashMap<Entity, String>,
+    debug_names: DebugNames,
+    tags: HashMap<TypeId, Entity>,
+    parents: HashMap<Entity, Entity>,
+    children: HashMap<Entity, Vec<Entity>>,
+    reserved: HashSet<Entity>,
+    lists: Vec<Box<Any>>,
+    __phantom: PhantomData<fn(T)>,
+}
+
+impl<T> WorldSnapshot<T> where T: CloneableComponents
+{
+    /// Returns the number of entities in the snapshot.
+    pub fn entity_count(&self) -> usize
+    {
+        self.entities.count()
+    }
+
+    /// Returns true if the entity was valid when the snapshot was taken.
+    pub fn contains(&self, entity: &Entity) -> bool
+    {
+        self.entities.is_valid(entity)
+    }
+
+    fn is_activated(&self, entity: &Entity) -> bool
+    {
+        self.entities.is_valid(entity) && !self.disabled.contains(entity) && !self.reserved.contains(entity)
+    }
 }
 
 pub unsafe trait SystemManager: 'static
@@ -38,8 +496,36 @@ pub unsafe trait SystemManager: 'static
     unsafe fn new() -> Self;
     unsafe fn activated(&mut self, en: EntityData, co: &Self::Components);
     unsafe fn reactivated(&mut self, en: EntityData, co: &Self::Components);
-    unsafe fn deactivated(&mut self, en: EntityData, co: &Self::Components);
+    unsafe fn deactivated(&mut self, en: EntityData, co: &Self::Components, reason: DeactivationReason);
     unsafe fn update(&mut self, co: &mut DataHelper<Self::Components>);
+
+    /// Activates several entities at once, in the order they were built.
+    ///
+    /// When the queue is flushed, entities built one after another are activated together.
+    /// Any other queued event ends the batch, including the callback queued by each
+    /// `create_entity_then`, so entities created that way are passed in batches of one.
+    /// By default `activated` is called for each of them.
+    unsafe fn activated_batch(&mut self, entities: &[Entity], co: &Self::Components)
+    {
+        for entity in entities.iter()
+        {
+            self.activated(EntityData(entity), co);
+        }
+    }
+
+    /// Returns the number of systems in the manager.
+    fn system_count(&self) -> usize;
+
+    /// Processes the systems belonging to one phase of an update.
+    ///
+    /// By default every system belongs to `Phase::Update`.
+    unsafe fn update_phase(&mut self, phase: Phase, co: &mut DataHelper<Self::Components>)
+    {
+        if phase == Phase::Update
+        {
+            self.update(co);
+        }
+    }
 }
 
 impl<T: ComponentManager, U: SystemManager<Components=T>> Deref for World<T, U>
@@ -78,121 +564,2294 @@ impl<T: ComponentManager> DerefMut for DataHelper<T>
 
 impl<T: ComponentManager> DataHelper<T>
 {
-    pub fn with_entity_data<F, R>(&mut self, entity: &Entity, mut call: F) -> Option<R>
-        where F: FnMut(EntityData, &mut T) -> R
+    /// Returns true if the entity has not been removed.
+    ///
+    /// Entities queued for removal are still valid until the queue is flushed.
+    /// Use `is_pending_removal` to check for those.
+    pub fn is_valid(&self, entity: &Entity) -> bool
     {
-        if self.entities.is_valid(entity) {
-            Some(call(EntityData(entity), self))
-        } else {
-            None
-        }
+        self.entities.is_valid(entity)
     }
 
-    pub fn create_entity<B>(&mut self, builder: B) -> Entity where B: EntityBuilder<T>+'static
+    /// Returns true if the entity is queued for removal at the next flush.
+    pub fn is_pending_removal(&self, entity: &Entity) -> bool
     {
-        let entity = self.entities.create();
-        self.event_queue.push(Event::BuildEntity(entity, Box::new(builder)));
-        entity
+        self.pending_removal.contains(entity)
     }
 
-    pub fn modify_entity<M>(&mut self, entity: Entity, modifier: M) where M: EntityModifier<T>+'static
+    /// Returns true if a change to the entity's components is queued for the next flush.
+    pub fn is_pending_modification(&self, entity: &Entity) -> bool
     {
-        self.event_queue.push(Event::ModifyEntity(entity, Box::new(modifier)));
+        self.queued_events().any(|event| event.is_modification() && event.entity() == *entity)
     }
 
-    pub fn remove_entity(&mut self, entity: Entity)
+    /// Returns true if the entity was created through `DataHelper` and hasn't been activated yet.
+    ///
+    /// Such entities are already valid, but systems don't know about them. They have no
+    /// components until their builder runs, unless they were made with `create_entity_now`.
+    pub fn is_pending_build(&self, entity: &Entity) -> bool
     {
-        self.event_queue.push(Event::RemoveEntity(entity));
+        self.pending_build.contains(entity)
     }
-}
 
-impl<T: ComponentManager, U: SystemManager<Components=T>> World<T, U>
-{
-    pub fn new() -> World<T, U>
+    /// Returns true if the entity has been disabled.
+    ///
+    /// Disabled entities are still valid and keep their components, but systems don't see them.
+    pub fn is_disabled(&self, entity: &Entity) -> bool
     {
-        World {
-            systems: unsafe { <U as SystemManager>::new() },
-            data: DataHelper {
-                components: unsafe { <T as ComponentManager>::new() },
-                entities: EntityManager::new(),
-                event_queue: Vec::new(),
-            },
-        }
+        self.disabled.contains(entity)
     }
 
-    pub fn create_entity<B>(&mut self, mut builder: B) -> Entity where B: EntityBuilder<T>
+    /// Returns true if systems currently know about the entity.
+    #[doc(hidden)]
+    pub fn is_activated(&self, entity: &Entity) -> bool
     {
-        let entity = self.data.entities.create();
-        builder.build(BuildData(&entity), &mut self.data.components);
-        unsafe { self.systems.activated(EntityData(&entity), &self.data.components); }
-        entity
+        !self.pending_build.contains(entity) && !self.disabled.contains(entity)
     }
 
-    pub fn with_entity_data<F, R>(&mut self, entity: &Entity, mut call: F) -> Option<R>
-        where F: FnMut(EntityData, &mut T) -> R
+    /// Returns an iterator over the entities systems currently know about.
+    #[doc(hidden)]
+    pub fn activated_entities(&self) -> EntityIter<T>
     {
-        if self.data.entities.is_valid(entity) {
-            Some(call(EntityData(entity), &mut self.data))
-        } else {
-            None
+        self.entities.iter().excluding(&self.disabled).excluding(&self.pending_build)
+    }
+
+    /// Returns the number of valid entities.
+    ///
+    /// Entities created through `DataHelper` are counted as soon as they are created, even
+    /// though they aren't built until the queue is flushed. Entities queued for removal are
+    /// counted until the queue is flushed.
+    pub fn entity_count(&self) -> usize
+    {
+        self.entities.count()
+    }
+
+    /// Returns the number of seconds the current update covers, as passed to `update_with_delta`.
+    ///
+    /// Systems wrapped in an `IntervalSystem` or `TimedSystem` see the time since they last ran
+    /// instead. `update` counts as no time passing.
+    pub fn delta(&self) -> f64
+    {
+        self.delta
+    }
+
+    /// Returns true if the world is paused.
+    ///
+    /// Systems only run while paused when the world is stepped with `World::step`.
+    pub fn is_paused(&self) -> bool
+    {
+        self.paused
+    }
+
+    /// Returns the context passed to `World::update_ctx`, if it has the type `id`.
+    #[doc(hidden)]
+    pub fn context_ptr(&self, id: TypeId) -> Option<*mut ()>
+    {
+        match self.context
+        {
+            Some((context_id, context)) if context_id == id => Some(context),
+            _ => None,
         }
     }
 
-    pub fn entities(&self) -> EntityIter<T>
+    #[doc(hidden)]
+    pub fn set_delta(&mut self, delta: f64)
     {
-        self.data.entities.iter()
+        self.delta = delta;
     }
 
-    pub fn modify_entity<M>(&mut self, entity: Entity, mut modifier: M) where M: EntityModifier<T>
+    /// Returns the number of entity indices that have been allocated, including free ones.
+    pub fn entity_capacity(&self) -> usize
     {
-        modifier.modify(ModifyData(&entity), &mut self.data.components);
-        unsafe { self.systems.reactivated(EntityData(&entity), &self.data.components); }
+        self.entities.capacity()
     }
 
-    pub fn remove_entity(&mut self, entity: Entity)
+    /// Returns the number of events waiting for the queue to be flushed.
+    pub fn pending_events(&self) -> usize
     {
-        self.process_event(Event::RemoveEntity(entity));
+        self.event_queue.len()
     }
 
-    fn process_event(&mut self, event: Event<T>)
+    /// Returns the number of events the queue can hold without allocating.
+    ///
+    /// Flushing empties the queue but keeps its space, so once it has grown to fit the busiest
+    /// update it stops allocating.
+    pub fn queue_capacity(&self) -> usize
+    {
+        self.event_queue.capacity()
+    }
+
+    /// Returns the events waiting for the queue to be flushed, in the order they will be applied.
+    pub fn queued_events(&self) -> QueuedEvents<T>
     {
-        process_event(&mut self.data.components, &mut self.systems, &mut self.data.entities, event);
+        QueuedEvents { iter: self.event_queue.iter() }
     }
 
-    fn flush_queue(&mut self)
+    /// Returns the component of type `C` of an entity.
+    ///
+    /// Returns `None` if the entity doesn't have the component or is no longer valid.
+    pub fn get<C>(&self, entity: &Entity) -> Option<&C> where C: Component, T: HasComponent<C>
     {
-        for event in self.data.event_queue.drain()
+        if !self.entities.is_valid(entity)
         {
-            process_event(&mut self.data.components, &mut self.systems, &mut self.data.entities, event);
+            return None;
         }
+        self.components.list().lookup(entity)
     }
 
-    pub fn update(&mut self)
+    /// Returns a mutable reference to the component of type `C` of an entity.
+    ///
+    /// Returns `None` if the entity doesn't have the component or is no longer valid.
+    pub fn get_mut<C>(&mut self, entity: &Entity) -> Option<&mut C> where C: Component, T: HasComponent<C>
     {
-        self.flush_queue();
-        unsafe { self.systems.update(&mut self.data); }
+        if !self.entities.is_valid(entity)
+        {
+            return None;
+        }
+        self.components.list_mut().lookup_mut(entity)
     }
-}
 
-// This function has to be external to World because of borrowing rules
-fn process_event<T: ComponentManager, U: SystemManager<Components=T>>(components: &mut T, systems: &mut U, entities: &mut EntityManager, event: Event<T>)
-{
-    match event
+    /// Returns the component with the type `id` of an entity, for when the type is only known at runtime.
+    ///
+    /// If several component lists have the same type, the first one is used.
+    pub fn get_dyn(&self, entity: &Entity, id: TypeId) -> Option<&Any>
     {
-        Event::BuildEntity(entity, mut builder) => {
-            builder.build(BuildData(&entity), components);
-            unsafe { systems.activated(EntityData(&entity), components); }
-        },
-        Event::ModifyEntity(entity, mut modifier) => {
-            modifier.modify(ModifyData(&entity), components);
-            unsafe { systems.reactivated(EntityData(&entity), components); }
+        if !self.entities.is_valid(entity)
+        {
+            return None;
+        }
+        self.components.get_dyn(entity, id)
+    }
+
+    /// Returns a mutable reference to the component with the type `id` of an entity.
+    pub fn get_dyn_mut(&mut self, entity: &Entity, id: TypeId) -> Option<&mut Any>
+    {
+        if !self.entities.is_valid(entity)
+        {
+            return None;
+        }
+        self.components.get_dyn_mut(entity, id)
+    }
+
+    /// Queues a component of a type only known at runtime to be set on an entity.
+    ///
+    /// Works like `add_component`, but gives the component back straight away if no component
+    /// list has its type.
+    pub fn set_dyn(&mut self, entity: Entity, component: Box<Any>) -> Result<(), Box<Any>>
+    {
+        let id = (*component).get_type_id();
+        if !self.components.component_types().iter().any(|&(_, ty)| ty == id)
+        {
+            return Err(component);
+        }
+        self.event_queue.push_back(Event::AddComponent(entity, component, set_dyn_component::<T>));
+        Ok(())
+    }
+
+    /// Forgets the changes recorded by every component list that tracks changes.
+    ///
+    /// Changes are never cleared automatically, so call this once they have been handled,
+    /// for example after sending them over the network.
+    pub fn clear_changes(&mut self)
+    {
+        unsafe { self.components.clear_changes(); }
+    }
+
+    /// Makes the current value of every double buffered component its previous value.
+    ///
+    /// This is done at the start of every update, so only call it to swap the buffers between
+    /// updates.
+    pub fn swap_buffers(&mut self)
+    {
+        self.components.swap_buffers();
+    }
+
+    /// Allows several component lists to be borrowed mutably at once through a shared reference.
+    pub fn borrow_components(&mut self) -> ComponentBorrows<T>
+    {
+        ComponentBorrows::new(&mut self.components)
+    }
+
+    /// Returns an iterator over the entities that have every component in the tuple `Q`,
+    /// along with references to those components.
+    ///
+    /// Like `join!`, disabled entities and entities queued for removal are skipped.
+    pub fn query<'a, Q>(&'a self) -> Query<'a, T, Q> where Q: ComponentQuery<'a, T>
+    {
+        Query::new(self.entities.iter().excluding(&self.disabled).excluding(&self.pending_removal), &self.components)
+    }
+
+    /// Works like `query`, but with mutable references to the components.
+    ///
+    /// Panics if `Q` has the same component type more than once.
+    pub fn query_mut<'a, Q>(&'a mut self) -> QueryMut<'a, T, Q> where Q: ComponentQuery<'a, T>
+    {
+        let entities = self.entities.iter().excluding(&self.disabled).excluding(&self.pending_removal);
+        QueryMut::new(entities, &mut self.components)
+    }
+
+    #[doc(hidden)]
+    pub fn join_parts(&mut self) -> (&mut T, JoinFilter)
+    {
+        (&mut self.components, JoinFilter { disabled: &self.disabled, pending_removal: &self.pending_removal })
+    }
+
+    /// Returns an iterator over every entity, in ascending order of index.
+    ///
+    /// Disabled entities and entities queued for removal are skipped, like in `join!`. Entities
+    /// created this update that haven't been built yet are included, without any components.
+    pub fn entities(&self) -> EntityIter<T>
+    {
+        self.entities.iter().excluding(&self.disabled).excluding(&self.pending_removal)
+    }
+
+    /// Returns an iterator over the entities accepted by `predicate`.
+    ///
+    /// Entities queued for removal are skipped.
+    pub fn entities_filtered<F>(&self, predicate: F) -> PredicateEntityIter<T, F>
+        where F: FnMut(&EntityData, &T) -> bool
+    {
+        self.entities.iter()
+            .excluding(&self.disabled)
+            .excluding(&self.pending_removal)
+            .filter_components(&self.components, predicate)
+    }
+
+    /// Gives an entity a unique name, replacing any name it had before.
+    ///
+    /// If another entity already has this name, the name is taken away from it.
+    pub fn set_name(&mut self, entity: Entity, name: &str)
+    {
+        if !self.entities.is_valid(&entity)
+        {
+            return;
+        }
+        self.remove_name(&entity);
+        if let Some(previous) = self.names.insert(name.to_string(), entity)
+        {
+            self.entity_names.remove(&previous);
+        }
+        self.entity_names.insert(entity, name.to_string());
+    }
+
+    /// Removes the name of an entity, if it has one.
+    pub fn remove_name(&mut self, entity: &Entity)
+    {
+        if let Some(name) = self.entity_names.remove(entity)
+        {
+            self.names.remove(&name);
+        }
+    }
+
+    /// Returns the entity with the given name, if it is still valid.
+    pub fn entity_named(&self, name: &str) -> Option<Entity>
+    {
+        match self.names.get(name)
+        {
+            Some(entity) if self.entities.is_valid(entity) => Some(*entity),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of an entity, if it has one.
+    pub fn name_of(&self, entity: &Entity) -> Option<&str>
+    {
+        self.entity_names.get(entity).map(|name| &name[..])
+    }
+
+    /// Attaches a debug name to an entity, to make it easier to identify in logs.
+    ///
+    /// The name is shown when the entity's `EntityData` is printed with `{:?}` while the world
+    /// is updating or flushing its queue, such as from a system or a callback.
+    ///
+    /// Debug names are not stored when debug assertions are disabled, so this does nothing in
+    /// release builds.
+    pub fn set_debug_name(&mut self, entity: Entity, name: &str)
+    {
+        if self.entities.is_valid(&entity)
+        {
+            self.debug_names.set(entity, name);
+        }
+    }
+
+    /// Returns the debug name of an entity. Always `None` in release builds.
+    pub fn debug_name(&self, entity: &Entity) -> Option<&str>
+    {
+        self.debug_names.get(entity)
+    }
+
+    /// Returns the entity along with its debug name, which can be printed with `{:?}`.
+    pub fn debug_entity(&self, entity: &Entity) -> DebugEntity
+    {
+        DebugEntity(*entity, self.debug_names.get(entity))
+    }
+
+    /// Assigns the tag `G` to an entity.
+    ///
+    /// At most one entity can hold a tag at a time, so if another entity already holds it,
+    /// the tag is moved to the new entity.
+    pub fn set_tag<G: 'static>(&mut self, entity: Entity)
+    {
+        if self.entities.is_valid(&entity)
+        {
+            self.tags.insert(TypeId::of::<G>(), entity);
+        }
+    }
+
+    /// Removes the tag `G`, returning the entity that held it.
+    pub fn remove_tag<G: 'static>(&mut self) -> Option<Entity>
+    {
+        self.tags.remove(&TypeId::of::<G>())
+    }
+
+    /// Returns the entity holding the tag `G`, if any.
+    pub fn tagged<G: 'static>(&self) -> Option<Entity>
+    {
+        match self.tags.get(&TypeId::of::<G>())
+        {
+            Some(entity) if self.entities.is_valid(entity) => Some(*entity),
+            _ => None,
+        }
+    }
+
+    /// Stores a value shared by the whole world, such as the input state or the score.
+    ///
+    /// There can only be one resource of each type, so this returns the one it replaced.
+    /// Resources are kept when the world is cleared.
+    pub fn insert_resource<R: Any>(&mut self, resource: R) -> Option<R>
+    {
+        match self.resources.insert(TypeId::of::<R>(), Box::new(resource))
+        {
+            Some(old) => old.downcast::<R>().ok().map(|old| *old),
+            None => None,
+        }
+    }
+
+    /// Removes the resource of type `R`, returning it.
+    pub fn remove_resource<R: Any>(&mut self) -> Option<R>
+    {
+        match self.resources.remove(&TypeId::of::<R>())
+        {
+            Some(old) => old.downcast::<R>().ok().map(|old| *old),
+            None => None,
+        }
+    }
+
+    pub fn has_resource<R: Any>(&self) -> bool
+    {
+        self.resources.contains_key(&TypeId::of::<R>())
+    }
+
+    pub fn get_resource<R: Any>(&self) -> Option<&R>
+    {
+        self.resources.get(&TypeId::of::<R>()).and_then(|resource| resource.downcast_ref::<R>())
+    }
+
+    pub fn get_resource_mut<R: Any>(&mut self) -> Option<&mut R>
+    {
+        self.resources.get_mut(&TypeId::of::<R>()).and_then(|resource| resource.downcast_mut::<R>())
+    }
+
+    /// Returns the resource of type `R`.
+    ///
+    /// Panics if there isn't one.
+    pub fn resource<R: Any>(&self) -> &R
+    {
+        match self.get_resource::<R>()
+        {
+            Some(resource) => resource,
+            None => panic!("there is no resource of type {}", resource_name::<R>()),
+        }
+    }
+
+    /// Returns the resource of type `R` mutably.
+    ///
+    /// Panics if there isn't one.
+    pub fn resource_mut<R: Any>(&mut self) -> &mut R
+    {
+        match self.get_resource_mut::<R>()
+        {
+            Some(resource) => resource,
+            None => panic!("there is no resource of type {}", resource_name::<R>()),
+        }
+    }
+
+    /// Sends a message to any system that wants it, such as a collision system telling the
+    /// health system how much damage to deal.
+    ///
+    /// Messages are read in the update after the one they were sent in, so every system sees
+    /// them no matter which order the systems run in. Messages sent between updates are read
+    /// in the next update.
+    pub fn emit<M: Any>(&mut self, message: M)
+    {
+        self.message_queue::<M>().sent.push(message);
+    }
+
+    /// Returns the messages of type `M` sent during the previous update.
+    pub fn messages<M: Any>(&self) -> &[M]
+    {
+        let queue = self.messages.get(&TypeId::of::<M>())
+            .and_then(|&(ref queue, _)| queue.downcast_ref::<MessageQueue<M>>());
+        match queue
+        {
+            Some(queue) => &queue.received[..],
+            None => &[],
+        }
+    }
+
+    /// Takes the messages of type `M` sent during the previous update, so systems that run
+    /// later in this update don't see them.
+    pub fn drain_messages<M: Any>(&mut self) -> Drain<M>
+    {
+        self.message_queue::<M>().received.drain()
+    }
+
+    fn message_queue<M: Any>(&mut self) -> &mut MessageQueue<M>
+    {
+        let id = TypeId::of::<M>();
+        if !self.messages.contains_key(&id)
+        {
+            let queue = MessageQueue::<M> { sent: Vec::new(), received: Vec::new() };
+            self.messages.insert(id, (Box::new(queue) as Box<Any>, deliver_messages::<M> as fn(&mut Any)));
+        }
+        match self.messages.get_mut(&id).and_then(|&mut (ref mut queue, _)| queue.downcast_mut::<MessageQueue<M>>())
+        {
+            Some(queue) => queue,
+            None => unreachable!(),
+        }
+    }
+
+    /// Makes the messages sent since the last update readable, and forgets the ones before.
+    fn deliver_messages(&mut self)
+    {
+        for (_, &mut (ref mut queue, deliver)) in self.messages.iter_mut()
+        {
+            deliver(&mut **queue);
+        }
+    }
+
+    fn remove_tags(&mut self, entity: &Entity)
+    {
+        let tags: Vec<TypeId> = self.tags.iter()
+            .filter(|&(_, tagged)| tagged == entity)
+            .map(|(tag, _)| *tag)
+            .collect();
+        for tag in tags.iter()
+        {
+            self.tags.remove(tag);
+        }
+    }
+
+    /// Makes `child` a child of `parent`, detaching it from its previous parent.
+    ///
+    /// Panics if `parent` is `child` or one of its descendants.
+    pub fn set_parent(&mut self, child: Entity, parent: Entity)
+    {
+        if !self.entities.is_valid(&child) || !self.entities.is_valid(&parent)
+        {
+            return;
+        }
+        let mut ancestor = Some(parent);
+        while let Some(current) = ancestor
+        {
+            if current == child
+            {
+                panic!("cannot make {:?} a child of {:?}: it would create a cycle", child, parent);
+            }
+            ancestor = self.parents.get(&current).cloned();
+        }
+        self.remove_parent(&child);
+        self.parents.insert(child, parent);
+        if !self.children.contains_key(&parent)
+        {
+            self.children.insert(parent, Vec::new());
+        }
+        self.children.get_mut(&parent).unwrap().push(child);
+    }
+
+    /// Detaches an entity from its parent, returning the parent.
+    pub fn remove_parent(&mut self, child: &Entity) -> Option<Entity>
+    {
+        let parent = self.parents.remove(child);
+        if let Some(ref parent) = parent
+        {
+            if let Some(siblings) = self.children.get_mut(parent)
+            {
+                siblings.retain(|sibling| sibling != child);
+            }
+        }
+        parent
+    }
+
+    /// Returns the parent of an entity, if it has one.
+    pub fn parent_of(&self, child: &Entity) -> Option<Entity>
+    {
+        self.parents.get(child).cloned()
+    }
+
+    /// Returns an iterator over the children of an entity, in the order they were attached.
+    pub fn children_of(&self, parent: &Entity) -> Cloned<Iter<Entity>>
+    {
+        match self.children.get(parent)
+        {
+            Some(children) => children.iter().cloned(),
+            None => [].iter().cloned(),
+        }
+    }
+
+    /// Appends all descendants of an entity to `out`, children before their parents.
+    fn descendants(&self, entity: &Entity, out: &mut Vec<Entity>)
+    {
+        if let Some(children) = self.children.get(entity)
+        {
+            for child in children.iter()
+            {
+                self.descendants(child, out);
+                out.push(*child);
+            }
+        }
+    }
+
+    // Null lists look up generations in the entity manager, which only keeps a copy up to date
+    // for them once it has been asked for one
+    fn share_generations(&mut self)
+    {
+        if self.components.needs_generations()
+        {
+            unsafe { self.components.share_generations(self.entities.share_generations()); }
+        }
+    }
+
+    /// Returns the entity followed by its descendants, in removal order.
+    fn removal_order(&self, entity: Entity) -> Vec<Entity>
+    {
+        let mut entities = Vec::new();
+        self.descendants(&entity, &mut entities);
+        entities.push(entity);
+        entities
+    }
+
+    /// Removes an entity, along with its components and everything else known about it.
+    fn forget(&mut self, entity: &Entity)
+    {
+        self.pending_build.remove(entity);
+        if let Some(held) = self.reserved.remove(entity)
+        {
+            self.discard_events(held);
+        }
+        self.disabled.remove(entity);
+        unsafe { self.components.remove_all(entity); }
+        self.remove_name(entity);
+        self.debug_names.remove(entity);
+        self.remove_tags(entity);
+        self.remove_hierarchy(entity);
+        self.entities.remove(entity);
+    }
+
+    fn remove_hierarchy(&mut self, entity: &Entity)
+    {
+        self.remove_parent(entity);
+        if let Some(children) = self.children.remove(entity)
+        {
+            for child in children.iter()
+            {
+                self.parents.remove(child);
+            }
+        }
+    }
+
+    /// Registers a callback that is called whenever an entity has been built, and returns a
+    /// handle that can be passed to `remove_observer`.
+    ///
+    /// Callbacks only see the entity and its components, so they can't register further
+    /// callbacks or otherwise reach the world while they run. If a callback panics while the
+    /// queue is being flushed, the changes that hadn't been applied yet stay queued.
+    pub fn on_entity_created<F>(&mut self, callback: F) -> ObserverHandle where F: FnMut(EntityData, &T)+'static
+    {
+        let handle = self.callbacks.next_handle();
+        self.callbacks.created.push((handle, Box::new(callback)));
+        handle
+    }
+
+    /// Registers a callback that is called whenever an entity has been modified.
+    pub fn on_entity_modified<F>(&mut self, callback: F) -> ObserverHandle where F: FnMut(EntityData, &T)+'static
+    {
+        let handle = self.callbacks.next_handle();
+        self.callbacks.modified.push((handle, Box::new(callback)));
+        handle
+    }
+
+    /// Registers a callback that is called whenever an entity is about to be removed.
+    ///
+    /// The entity still has all of its components when the callback runs.
+    pub fn on_entity_removed<F>(&mut self, callback: F) -> ObserverHandle where F: FnMut(EntityData, &T)+'static
+    {
+        let handle = self.callbacks.next_handle();
+        self.callbacks.removed.push((handle, Box::new(callback)));
+        handle
+    }
+
+    /// Unregisters a callback, returning false if it has already been removed.
+    pub fn remove_observer(&mut self, handle: ObserverHandle) -> bool
+    {
+        let callbacks = &mut self.callbacks;
+        for list in [&mut callbacks.created, &mut callbacks.modified, &mut callbacks.removed].iter_mut()
+        {
+            if let Some(index) = list.iter().position(|&(other, _)| other == handle)
+            {
+                let _ = list.remove(index);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Drops events that will never be applied, along with anything they keep in the arena.
+    fn discard_events(&mut self, events: Vec<Event<'static, T>>)
+    {
+        for event in events.into_iter()
+        {
+            if let Some(key) = event.arena_key()
+            {
+                self.arena.discard(key);
+            }
+        }
+    }
+
+    // Changes to a reserved entity are held back until it is given a builder, so that they are
+    // applied after it is built.
+    fn queue_change(&mut self, entity: Entity, event: Event<'static, T>)
+    {
+        match self.reserved.get_mut(&entity)
+        {
+            Some(held) => held.push(event),
+            None => self.event_queue.push_back(event),
+        }
+    }
+
+    /// Forgets all bookkeeping about entities.
+    fn reset(&mut self)
+    {
+        self.pending_removal.clear();
+        self.pending_build.clear();
+        let reserved: Vec<Entity> = self.reserved.keys().cloned().collect();
+        for entity in reserved.iter()
+        {
+            if let Some(held) = self.reserved.remove(entity)
+            {
+                self.discard_events(held);
+            }
+        }
+        self.disabled.clear();
+        self.changed.clear();
+        self.names.clear();
+        self.entity_names.clear();
+        self.debug_names.clear();
+        self.tags.clear();
+        self.parents.clear();
+        self.children.clear();
+    }
+
+    pub fn with_entity_data<F, R>(&mut self, entity: &Entity, call: F) -> Option<R>
+        where F: FnOnce(EntityData, &mut T) -> R
+    {
+        if self.entities.is_valid(entity) {
+            Some(call(EntityData(entity), self))
+        } else {
+            None
+        }
+    }
+
+    /// Works like `with_entity_data`, but only gives the closure read access to the components,
+    /// so it can be used while the world is borrowed elsewhere.
+    pub fn with_entity_data_ref<F, R>(&self, entity: &Entity, call: F) -> Option<R>
+        where F: FnOnce(EntityData, &T) -> R
+    {
+        if self.entities.is_valid(entity) {
+            Some(call(EntityData(entity), &self.components))
+        } else {
+            None
+        }
+    }
+
+    /// Works like `with_entity_data`, but says why the entity couldn't be used.
+    pub fn try_with_entity_data<F, R>(&mut self, entity: &Entity, call: F) -> Result<R, InvalidEntity>
+        where F: FnOnce(EntityData, &mut T) -> R
+    {
+        if self.entities.is_valid(entity) {
+            Ok(call(EntityData(entity), self))
+        } else {
+            Err(InvalidEntity::Removed(*entity))
+        }
+    }
+
+    /// Checks that changes queued for an entity won't be dropped.
+    fn check_queued(&self, entity: &Entity) -> Result<(), InvalidEntity>
+    {
+        if !self.entities.is_valid(entity)
+        {
+            Err(InvalidEntity::Removed(*entity))
+        }
+        else if self.pending_removal.contains(entity)
+        {
+            Err(InvalidEntity::PendingRemoval(*entity))
+        }
+        else
+        {
+            Ok(())
+        }
+    }
+
+    /// Queues the creation of an entity.
+    ///
+    /// The returned entity is valid straight away, but isn't built until the queue is flushed.
+    /// Events are processed in the order they are queued, so modifications queued for this
+    /// entity are applied after it is built.
+    pub fn create_entity<B>(&mut self, builder: B) -> Entity where B: EntityBuilder<T>+'static
+    {
+        let entity = self.entities.create();
+        self.pending_build.insert(entity);
+        self.queue_build(entity, builder);
+        entity
+    }
+
+    fn queue_build<B>(&mut self, entity: Entity, builder: B) where B: EntityBuilder<T>+'static
+    {
+        let queued = if Arena::fits::<B>()
+        {
+            Queued { key: self.arena.push(builder), run: run_builder::<T, B> }
+        }
+        else
+        {
+            Queued { key: self.arena.push(Box::new(builder)), run: run_boxed_builder::<T, B> }
+        };
+        self.event_queue.push_back(Event::BuildEntity(entity, queued));
+    }
+
+    /// Creates `count` entities without building them, so their handles can be given out
+    /// before their components are known, such as to objects arriving over the network.
+    ///
+    /// The entities are valid, but have no components and aren't activated until they are
+    /// given a builder with `build_reserved`. Their indices aren't reused until they are
+    /// removed, which can be done with `remove_entity` whether or not they have been built.
+    pub fn reserve_ids(&mut self, count: usize) -> Vec<Entity>
+    {
+        let entities = self.entities.reserve_ids(count);
+        for &entity in entities.iter()
+        {
+            self.pending_build.insert(entity);
+            self.reserved.insert(entity, Vec::new());
+        }
+        entities
+    }
+
+    /// Returns true if the entity was made with `reserve_ids` and hasn't been given a builder.
+    pub fn is_reserved(&self, entity: &Entity) -> bool
+    {
+        self.reserved.contains_key(entity)
+    }
+
+    /// Queues the build of an entity made with `reserve_ids`, as if it had just been created
+    /// with `create_entity`.
+    ///
+    /// Changes queued for the entity before it was given a builder are applied after it is built.
+    /// Fails if the entity isn't reserved, including if it has already been given a builder.
+    pub fn build_reserved<B>(&mut self, entity: Entity, builder: B) -> Result<(), InvalidEntity>
+        where B: EntityBuilder<T>+'static
+    {
+        try!(self.check_queued(&entity));
+        let held = match self.reserved.remove(&entity)
+        {
+            Some(held) => held,
+            None => return Err(InvalidEntity::NotReserved(entity)),
+        };
+        self.queue_build(entity, builder);
+        self.event_queue.extend(held.into_iter());
+        Ok(())
+    }
+
+    /// Creates entities whose builders don't have to be `'static`, such as ones that borrow a
+    /// prefab instead of cloning it.
+    ///
+    /// The builders are run in order when `call` returns, so the entities can be looked up
+    /// straight away afterwards, like those made with `create_entity_now`. Their activation is
+    /// queued, so systems aren't told about them until the queue is flushed.
+    pub fn with_deferred<'a, F, R>(&mut self, call: F) -> R
+        where F: for<'d> FnOnce(&mut DeferredQueue<'d, 'a, T>) -> R
+    {
+        let (result, builders) = {
+            let mut queue = DeferredQueue
+            {
+                entities: &mut self.entities,
+                pending_build: &mut self.pending_build,
+                builders: Vec::new(),
+            };
+            let result = call(&mut queue);
+            (result, queue.builders)
+        };
+        for (entity, mut builder) in builders.into_iter()
+        {
+            forget_on_panic(self, entity, |data| builder.build(BuildData(&entity), &mut data.components));
+            self.event_queue.push_back(Event::ActivateEntity(entity));
+        }
+        result
+    }
+
+    /// Queues the creation of an entity, like `create_entity`, and then runs `then` once it
+    /// has been built.
+    ///
+    /// `then` can queue more changes, such as creating the entity's children, and those are
+    /// applied in the same flush. It doesn't run if the entity is removed before it is built.
+    pub fn create_entity_then<B, F>(&mut self, builder: B, then: F) -> Entity
+        where B: EntityBuilder<T>+'static, F: FnMut(Entity, &mut DataHelper<T>)+'static
+    {
+        let entity = self.create_entity(builder);
+        self.event_queue.push_back(Event::AfterBuild(entity, Box::new(then)));
+        entity
+    }
+
+    /// Creates an entity and builds its components straight away.
+    ///
+    /// Component lookups and joins can see the entity at once, so systems that run later in
+    /// the same update can read it. Systems can't be reached from here, though, so only its
+    /// activation is queued: systems aren't told about it, and creation callbacks don't run,
+    /// until the queue is flushed.
+    pub fn create_entity_now<B>(&mut self, mut builder: B) -> Entity where B: EntityBuilder<T>
+    {
+        let entity = self.entities.create();
+        forget_on_panic(self, entity, |data| builder.build(BuildData(&entity), &mut data.components));
+        self.pending_build.insert(entity);
+        self.event_queue.push_back(Event::ActivateEntity(entity));
+        entity
+    }
+
+    /// Registers a builder that can be used any number of times with `spawn_deferred` and
+    /// `World::spawn`, replacing any prefab already registered as `name`.
+    pub fn register_prefab<B>(&mut self, name: &str, builder: B) where B: EntityBuilder<T>+'static
+    {
+        self.prefabs.insert(name.to_string(), Box::new(builder));
+    }
+
+    /// Returns true if a prefab has been registered as `name`.
+    pub fn has_prefab(&self, name: &str) -> bool
+    {
+        self.prefabs.contains_key(name)
+    }
+
+    /// Queues the creation of an entity from a prefab, like `create_entity`.
+    pub fn spawn_deferred(&mut self, name: &str) -> Result<Entity, UnknownPrefab>
+    {
+        self.spawn_deferred_with(name, ())
+    }
+
+    /// Queues the creation of an entity from a prefab, running `overrides` after the prefab's
+    /// builder so it can replace some of its components.
+    pub fn spawn_deferred_with<B>(&mut self, name: &str, overrides: B) -> Result<Entity, UnknownPrefab>
+        where B: EntityBuilder<T>+'static
+    {
+        if !self.prefabs.contains_key(name)
+        {
+            return Err(UnknownPrefab(name.to_string()));
+        }
+        let entity = self.entities.create();
+        self.pending_build.insert(entity);
+        self.event_queue.push_back(Event::SpawnPrefab(entity, name.to_string(), Box::new(overrides)));
+        Ok(entity)
+    }
+
+    /// Queues the modification of an entity.
+    ///
+    /// Modifications are applied after any build already queued for the entity. Those of an
+    /// entity made with `reserve_ids` wait until it is given a builder.
+    pub fn modify_entity<M>(&mut self, entity: Entity, modifier: M) where M: EntityModifier<T>+'static
+    {
+        if let Some(held) = self.reserved.get_mut(&entity)
+        {
+            held.push(Event::ModifyHeld(entity, Box::new(modifier)));
+            return;
+        }
+        let queued = if Arena::fits::<M>()
+        {
+            Queued { key: self.arena.push(modifier), run: run_modifier::<T, M> }
+        }
+        else
+        {
+            Queued { key: self.arena.push(Box::new(modifier)), run: run_boxed_modifier::<T, M> }
+        };
+        self.queue_change(entity, Event::ModifyEntity(entity, queued));
+    }
+
+    /// Queues the modification of an entity, like `modify_entity`, but fails if the entity has
+    /// been removed or is already queued for removal.
+    pub fn try_modify_entity<M>(&mut self, entity: Entity, modifier: M) -> Result<(), InvalidEntity>
+        where M: EntityModifier<T>+'static
+    {
+        try!(self.check_queued(&entity));
+        self.modify_entity(entity, modifier);
+        Ok(())
+    }
+
+    /// Queues a component to be added to an entity, replacing any component of the same type.
+    ///
+    /// Components added to entities that have been removed by the time the queue is flushed are
+    /// dropped. Systems are notified once the whole queue has been flushed, so an entity is
+    /// only reactivated once no matter how many components are added or removed.
+    pub fn add_component<C>(&mut self, entity: Entity, component: C) where C: Component, T: HasComponent<C>
+    {
+        self.queue_change(entity, Event::AddComponent(entity, Box::new(component), add_component::<T, C>));
+    }
+
+    /// Queues the component of type `C` to be removed from an entity.
+    ///
+    /// Nothing happens if the entity doesn't have the component when the queue is flushed.
+    pub fn remove_component<C>(&mut self, entity: Entity) where C: Component, T: HasComponent<C>
+    {
+        self.queue_change(entity, Event::RemoveComponent(entity, remove_component::<T, C>));
+    }
+
+    /// Queues the removal of an entity.
+    ///
+    /// Entities that are already queued for removal are only queued once.
+    pub fn remove_entity(&mut self, entity: Entity)
+    {
+        if self.entities.is_valid(&entity) && self.pending_removal.insert(entity)
+        {
+            self.event_queue.push_back(Event::RemoveEntity(entity));
+        }
+    }
+
+    /// Queues the removal of an entity, like `remove_entity`, but fails if the entity has
+    /// already been removed or is already queued for removal.
+    pub fn try_remove_entity(&mut self, entity: Entity) -> Result<(), InvalidEntity>
+    {
+        try!(self.check_queued(&entity));
+        self.remove_entity(entity);
+        Ok(())
+    }
+
+    /// Queues an entity to be disabled, deactivating it without removing its components.
+    pub fn disable_entity(&mut self, entity: Entity)
+    {
+        self.event_queue.push_back(Event::DisableEntity(entity));
+    }
+
+    /// Queues a disabled entity to be enabled again.
+    pub fn enable_entity(&mut self, entity: Entity)
+    {
+        self.event_queue.push_back(Event::EnableEntity(entity));
+    }
+
+    /// Queues an entity to be reactivated, so that systems can reevaluate it.
+    ///
+    /// Modifications only reactivate an entity if they add or remove components, so this is
+    /// needed after changing component storage directly in a way that systems should notice.
+    pub fn force_reactivate(&mut self, entity: Entity)
+    {
+        self.event_queue.push_back(Event::ReactivateEntity(entity));
+    }
+
+    /// Queues the removal of several entities, ignoring duplicates.
+    pub fn remove_entities<I>(&mut self, entities: I) where I: IntoIterator<Item=Entity>
+    {
+        for entity in entities
+        {
+            self.remove_entity(entity);
+        }
+    }
+
+    /// Queues the removal of an entity and all of its current descendants.
+    ///
+    /// Children are removed before their parents.
+    pub fn remove_entity_recursive(&mut self, entity: Entity)
+    {
+        for entity in self.removal_order(entity).into_iter()
+        {
+            self.remove_entity(entity);
+        }
+    }
+}
+
+/// Entities waiting to be built by `DataHelper::with_deferred`.
+///
+/// The builders can borrow anything that outlives the call to `with_deferred`, as they are all
+/// run before it returns.
+pub struct DeferredQueue<'d, 'a, T: ComponentManager+'d>
+{
+    entities: &'d mut EntityManager,
+    pending_build: &'d mut HashSet<Entity>,
+    builders: Vec<(Entity, Box<EntityBuilder<T>+'a>)>,
+}
+
+impl<'d, 'a, T: ComponentManager> DeferredQueue<'d, 'a, T>
+{
+    /// Creates an entity that is built when the scope ends.
+    ///
+    /// The returned entity is valid straight away, but has no components until then.
+    pub fn create_entity<B>(&mut self, builder: B) -> Entity where B: EntityBuilder<T>+'a
+    {
+        let entity = self.entities.create();
+        self.pending_build.insert(entity);
+        self.builders.push((entity, Box::new(builder)));
+        entity
+    }
+
+    /// Returns the number of entities waiting to be built.
+    pub fn len(&self) -> usize
+    {
+        self.builders.len()
+    }
+}
+
+/// Entity put together one component at a time, returned by `World::build_entity`.
+///
+/// Nothing is created until `build` is called, so dropping the handle leaves the world as it
+/// was.
+pub struct EntityBuildHandle<'w, T, U> where T: ComponentManager+'w, U: SystemManager<Components=T>+'w
+{
+    world: &'w mut World<T, U>,
+    components: Vec<(Box<Any>, fn(&mut T, &Entity, Box<Any>) -> bool)>,
+}
+
+impl<'w, T: ComponentManager, U: SystemManager<Components=T>> EntityBuildHandle<'w, T, U>
+{
+    /// Adds a component, replacing one of the same type added earlier.
+    pub fn with<C: Component>(mut self, component: C) -> EntityBuildHandle<'w, T, U> where T: HasComponent<C>
+    {
+        self.components.push((Box::new(component), add_component::<T, C>));
+        self
+    }
+
+    /// Creates the entity with every component that was added, and tells the systems about it.
+    pub fn build(self) -> Entity
+    {
+        let mut components = self.components;
+        self.world.create_entity(move |e: BuildData, c: &mut T| {
+            for (component, add) in components.drain()
+            {
+                add(c, &e.entity(), component);
+            }
+        })
+    }
+}
+
+/// Counters describing the size of a world, returned by `World::stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldStats
+{
+    /// Number of valid entities, including disabled ones.
+    pub entities: usize,
+    pub disabled: usize,
+    /// Highest index used by a valid entity.
+    pub highest_index: Option<usize>,
+    /// Number of recycled indices waiting to be reused.
+    pub free_indices: usize,
+    /// Number of events waiting for the queue to be flushed.
+    pub pending_events: usize,
+    /// Bytes set aside for queued builders and modifiers. The space is reused once the queue
+    /// has been flushed, so this stops growing after the busiest update.
+    pub queue_bytes: usize,
+    /// Number of systems, including dynamic ones.
+    pub systems: usize,
+    /// Statistics about every component list, if the component manager provides them.
+    pub components: Vec<ComponentStats>,
+}
+
+/// Sets up a world in stages, so that it is fully initialised before its first update.
+///
+/// Resources, startup closures and system configuration are applied in the order they were
+/// added, then the event queue is flushed.
+pub struct WorldBuilder<T, U> where T: ComponentManager, U: SystemManager<Components=T>
+{
+    capacity: usize,
+    steps: Vec<Box<FnMut(&mut World<T, U>)>>,
+}
+
+impl<T: ComponentManager, U: SystemManager<Components=T>> WorldBuilder<T, U>
+{
+    pub fn new() -> WorldBuilder<T, U>
+    {
+        WorldBuilder
+        {
+            capacity: 0,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Reserves space for `capacity` entities before anything else is done.
+    pub fn with_capacity(mut self, capacity: usize) -> WorldBuilder<T, U>
+    {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Inserts a resource, replacing any earlier one of the same type.
+    pub fn with_resource<R: Any>(mut self, resource: R) -> WorldBuilder<T, U>
+    {
+        let mut resource = Some(resource);
+        self.steps.push(Box::new(move |world: &mut World<T, U>| {
+            if let Some(resource) = resource.take()
+            {
+                world.data.insert_resource(resource);
+            }
+        }));
+        self
+    }
+
+    /// Runs a closure that sets up the world, such as by creating its initial entities.
+    ///
+    /// Changes it queues are applied before `build` returns.
+    pub fn with_startup<F>(mut self, mut startup: F) -> WorldBuilder<T, U>
+        where F: FnMut(&mut DataHelper<T>)+'static
+    {
+        self.steps.push(Box::new(move |world: &mut World<T, U>| startup(&mut world.data)));
+        self
+    }
+
+    /// Runs a closure that configures the systems.
+    pub fn with_systems<F>(mut self, mut configure: F) -> WorldBuilder<T, U>
+        where F: FnMut(&mut U)+'static
+    {
+        self.steps.push(Box::new(move |world: &mut World<T, U>| configure(&mut world.systems)));
+        self
+    }
+
+    /// Creates the world, applies each stage in order, and flushes the event queue.
+    pub fn build(self) -> World<T, U>
+    {
+        let mut world = World::<T, U>::new();
+        world.reserve(self.capacity);
+        for mut step in self.steps.into_iter()
+        {
+            (*step)(&mut world);
+        }
+        world.flush();
+        world
+    }
+}
+
+impl<T: ComponentManager, U: SystemManager<Components=T>> World<T, U>
+{
+    pub fn new() -> World<T, U>
+    {
+        let mut world = World {
+            systems: unsafe { <U as SystemManager>::new() },
+            data: DataHelper {
+                components: unsafe { <T as ComponentManager>::new() },
+                entities: EntityManager::new(),
+                event_queue: VecDeque::new(),
+                arena: Arena::new(),
+                pending_removal: HashSet::new(),
+                pending_build: HashSet::new(),
+                reserved: HashMap::new(),
+                disabled: HashSet::new(),
+                changed: Vec::new(),
+                reactivating: Vec::new(),
+                activating: Vec::new(),
+                active: Vec::new(),
+                names: HashMap::new(),
+                entity_names: HashMap::new(),
+                debug_names: DebugNames::new(),
+                tags: HashMap::new(),
+                resources: HashMap::new(),
+                messages: HashMap::new(),
+                parents: HashMap::new(),
+                children: HashMap::new(),
+                callbacks: EntityCallbacks {
+                    created: Vec::new(),
+                    modified: Vec::new(),
+                    removed: Vec::new(),
+                    next: 0,
+                },
+                prefabs: HashMap::new(),
+                delta: 0.0,
+                paused: false,
+                context: None,
+            },
+            dynamic: DynamicSystems::new(),
+        };
+        world.data.share_generations();
+        world
+    }
+
+    pub fn create_entity<B>(&mut self, mut builder: B) -> Entity where B: EntityBuilder<T>
+    {
+        let entity = self.data.entities.create();
+        forget_on_panic(&mut self.data, entity, |data| builder.build(BuildData(&entity), &mut data.components));
+        self.activate_created(entity);
+        entity
+    }
+
+    /// Builds an entity made with `reserve_ids` straight away.
+    ///
+    /// Use `data.build_reserved` to queue the build instead. Changes queued for the entity
+    /// before it was given a builder stay queued until the next flush. Fails if the entity isn't
+    /// reserved, including if it has already been given a builder.
+    pub fn build_reserved<B>(&mut self, entity: Entity, mut builder: B) -> Result<(), InvalidEntity>
+        where B: EntityBuilder<T>
+    {
+        try!(self.data.check_queued(&entity));
+        let held = match self.data.reserved.remove(&entity)
+        {
+            Some(held) => held,
+            None => return Err(InvalidEntity::NotReserved(entity)),
+        };
+        self.data.pending_build.remove(&entity);
+        forget_on_panic(&mut self.data, entity, |data| builder.build(BuildData(&entity), &mut data.components));
+        self.activate_created(entity);
+        self.data.event_queue.extend(held.into_iter());
+        Ok(())
+    }
+
+    /// Starts creating an entity whose components are added one at a time.
+    pub fn build_entity(&mut self) -> EntityBuildHandle<T, U>
+    {
+        EntityBuildHandle
+        {
+            world: self,
+            components: Vec::new(),
+        }
+    }
+
+    /// Creates an entity from a prefab registered with `register_prefab`.
+    pub fn spawn(&mut self, name: &str) -> Result<Entity, UnknownPrefab>
+    {
+        self.spawn_with(name, ())
+    }
+
+    /// Creates an entity from a prefab, running `overrides` after the prefab's builder so it
+    /// can replace some of its components.
+    pub fn spawn_with<B>(&mut self, name: &str, mut overrides: B) -> Result<Entity, UnknownPrefab>
+        where B: EntityBuilder<T>
+    {
+        if !self.data.prefabs.contains_key(name)
+        {
+            return Err(UnknownPrefab(name.to_string()));
+        }
+        let entity = self.data.entities.create();
+        forget_on_panic(&mut self.data, entity, |data| {
+            if let Some(prefab) = data.prefabs.get_mut(name)
+            {
+                prefab.build(BuildData(&entity), &mut data.components);
+            }
+            overrides.build(BuildData(&entity), &mut data.components);
+        });
+        self.activate_created(entity);
+        Ok(entity)
+    }
+
+    fn activate_created(&mut self, entity: Entity)
+    {
+        unsafe { self.systems.activated(EntityData(&entity), &self.data.components); }
+        self.dynamic.activated(&entity, &self.data.components);
+        run_callbacks(&mut self.data.callbacks.created, &entity, &self.data.components);
+    }
+
+    /// Creates a new entity with a copy of every component of `source`.
+    ///
+    /// Returns `None` if `source` is not a valid entity.
+    pub fn clone_entity(&mut self, source: Entity) -> Option<Entity>
+    {
+        if !self.data.entities.is_valid(&source)
+        {
+            return None;
+        }
+        let entity = self.data.entities.create();
+        unsafe {
+            self.data.components.copy_all(&source, &entity);
+            self.systems.activated(EntityData(&entity), &self.data.components);
+            self.dynamic.activated(&entity, &self.data.components);
+        }
+        run_callbacks(&mut self.data.callbacks.created, &entity, &self.data.components);
+        Some(entity)
+    }
+
+    pub fn with_entity_data<F, R>(&mut self, entity: &Entity, call: F) -> Option<R>
+        where F: FnOnce(EntityData, &mut T) -> R
+    {
+        if self.data.entities.is_valid(entity) {
+            Some(call(EntityData(entity), &mut self.data))
+        } else {
+            None
+        }
+    }
+
+    /// Works like `with_entity_data`, but only gives the closure read access to the components,
+    /// so it can be used while the world is borrowed elsewhere.
+    pub fn with_entity_data_ref<F, R>(&self, entity: &Entity, call: F) -> Option<R>
+        where F: FnOnce(EntityData, &T) -> R
+    {
+        self.data.with_entity_data_ref(entity, call)
+    }
+
+    /// Moves an entity and its components into another world, returning its handle there.
+    ///
+    /// The entity is removed from this world, so its old handle becomes invalid, and it stays
+    /// disabled if it was disabled. Its name, tags and place in the hierarchy are left behind.
+    /// Queued changes are applied first. Returns `None` if `entity` is not a valid entity.
+    ///
+    /// `Entity` fields in the moved components still refer to entities in this world. Use
+    /// `transfer_entity_with` to change them.
+    pub fn transfer_entity<V>(&mut self, entity: Entity, other: &mut World<T, V>) -> Option<Entity>
+        where V: SystemManager<Components=T>
+    {
+        self.transfer_entity_with(entity, other, ())
+    }
+
+    /// Moves an entity into another world, like `transfer_entity`, and runs `modifier` on it
+    /// before the other world's systems are told about it.
+    pub fn transfer_entity_with<V, M>(&mut self, entity: Entity, other: &mut World<T, V>, mut modifier: M) -> Option<Entity>
+        where V: SystemManager<Components=T>, M: EntityModifier<T>
+    {
+        self.flush();
+        if !self.data.entities.is_valid(&entity)
+        {
+            return None;
+        }
+        let disabled = self.data.disabled.contains(&entity);
+        if self.data.is_activated(&entity)
+        {
+            unsafe { self.systems.deactivated(EntityData(&entity), &self.data.components, DeactivationReason::Removed); }
+            self.dynamic.deactivated(&entity, &self.data.components, DeactivationReason::Removed);
+        }
+        run_callbacks(&mut self.data.callbacks.removed, &entity, &self.data.components);
+
+        let moved = other.data.entities.create();
+        unsafe { self.data.components.move_all(&entity, &mut other.data.components, &moved); }
+        self.data.forget(&entity);
+        modifier.modify(ModifyData(&moved), &mut other.data.components);
+        if disabled
+        {
+            other.data.disabled.insert(moved);
+        }
+        else
+        {
+            unsafe { other.systems.activated(EntityData(&moved), &other.data.components); }
+            other.dynamic.activated(&moved, &other.data.components);
+        }
+        run_callbacks(&mut other.data.callbacks.created, &moved, &other.data.components);
+        Some(moved)
+    }
+
+    /// Returns an iterator over all entities, except disabled ones and those queued for
+    /// removal, like `DataHelper::entities`.
+    pub fn entities(&self) -> EntityIter<T>
+    {
+        self.data.entities()
+    }
+
+    /// Returns a read-only view of the world, which can be shared with other threads.
+    pub fn freeze(&self) -> WorldView<T>
+    {
+        WorldView
+        {
+            components: &self.data.components,
+            entities: &self.data.entities,
+            disabled: &self.data.disabled,
+            pending_removal: &self.data.pending_removal,
+        }
+    }
+
+    /// Returns counters describing the size of the world, such as to log every so often to
+    /// spot leaks.
+    ///
+    /// Nothing is scanned, so this is cheap enough to call every frame.
+    pub fn stats(&self) -> WorldStats
+    {
+        WorldStats
+        {
+            entities: self.data.entities.count(),
+            disabled: self.data.disabled.len(),
+            highest_index: self.data.entities.highest_index(),
+            free_indices: self.data.entities.free_count(),
+            pending_events: self.data.event_queue.len(),
+            queue_bytes: self.data.arena.capacity(),
+            systems: self.systems.system_count() + self.dynamic.len(),
+            components: self.data.components.stats(),
+        }
+    }
+
+    /// Returns an iterator over all entities, including disabled ones.
+    pub fn entities_including_disabled(&self) -> EntityIter<T>
+    {
+        self.data.entities.iter()
+    }
+
+    /// Modifies an entity immediately.
+    ///
+    /// Systems are only notified if components were added or removed, and never if the
+    /// entity hasn't been built yet or is disabled. Use `modify_entity_deferred` to queue the
+    /// modification instead, like `DataHelper::modify_entity` does.
+    pub fn modify_entity<M>(&mut self, entity: Entity, mut modifier: M) where M: EntityModifier<T>
+    {
+        if !self.data.entities.is_valid(&entity)
+        {
+            return;
+        }
+        let before = self.data.components.mask_of(&entity);
+        modifier.modify(ModifyData(&entity), &mut self.data.components);
+        let changed = self.data.components.mask_of(&entity) != before;
+        if changed && self.data.is_activated(&entity)
+        {
+            unsafe { self.systems.reactivated(EntityData(&entity), &self.data.components); }
+            self.dynamic.reactivated(&entity, &self.data.components);
+        }
+        run_callbacks(&mut self.data.callbacks.modified, &entity, &self.data.components);
+    }
+
+    /// Sets a component of a type only known at runtime on an entity, reactivating the entity if
+    /// the component was added.
+    ///
+    /// Gives the component back if the entity is not valid or no component list has its type.
+    pub fn set_dyn(&mut self, entity: Entity, component: Box<Any>) -> Result<(), Box<Any>>
+    {
+        if !self.data.entities.is_valid(&entity)
+        {
+            return Err(component);
+        }
+        let added = try!(unsafe { self.data.components.set_dyn(&entity, component) });
+        if added
+        {
+            if self.data.is_activated(&entity)
+            {
+                unsafe { self.systems.reactivated(EntityData(&entity), &self.data.components); }
+                self.dynamic.reactivated(&entity, &self.data.components);
+            }
+        }
+        run_callbacks(&mut self.data.callbacks.modified, &entity, &self.data.components);
+        Ok(())
+    }
+
+    /// Removes an entity immediately.
+    ///
+    /// Use `remove_entity_deferred` to queue the removal instead, like
+    /// `DataHelper::remove_entity` does.
+    pub fn remove_entity(&mut self, entity: Entity)
+    {
+        self.process_event(Event::RemoveEntity(entity));
+    }
+
+    /// Queues the modification of an entity, to be applied when the queue is next flushed.
+    ///
+    /// This is the deferred form of `modify_entity`, and the same as `DataHelper::modify_entity`.
+    pub fn modify_entity_deferred<M>(&mut self, entity: Entity, modifier: M) where M: EntityModifier<T>+'static
+    {
+        self.data.modify_entity(entity, modifier);
+    }
+
+    /// Queues the removal of an entity, to be applied when the queue is next flushed.
+    ///
+    /// This is the deferred form of `remove_entity`, and the same as `DataHelper::remove_entity`.
+    pub fn remove_entity_deferred(&mut self, entity: Entity)
+    {
+        self.data.remove_entity(entity);
+    }
+
+    /// Disables an entity, deactivating it without removing its components.
+    pub fn disable_entity(&mut self, entity: Entity)
+    {
+        self.process_event(Event::DisableEntity(entity));
+    }
+
+    /// Enables a disabled entity, activating it again.
+    pub fn enable_entity(&mut self, entity: Entity)
+    {
+        self.process_event(Event::EnableEntity(entity));
+    }
+
+    /// Reactivates an entity immediately, so that systems can reevaluate it.
+    pub fn force_reactivate(&mut self, entity: Entity)
+    {
+        self.process_event(Event::ReactivateEntity(entity));
+    }
+
+    /// Removes several entities immediately, ignoring duplicates.
+    pub fn remove_entities<I>(&mut self, entities: I) where I: IntoIterator<Item=Entity>
+    {
+        for entity in entities
+        {
+            self.remove_entity(entity);
+        }
+    }
+
+    /// Removes an entity and all of its descendants.
+    ///
+    /// Children are removed before their parents.
+    pub fn remove_entity_recursive(&mut self, entity: Entity)
+    {
+        for entity in self.data.removal_order(entity).into_iter()
+        {
+            self.process_event(Event::RemoveEntity(entity));
+        }
+    }
+
+    fn process_event(&mut self, event: Event<T>)
+    {
+        process_event(&mut self.systems, &mut self.dynamic, &mut self.data, event);
+    }
+
+    /// Builds, modifies and removes the entities queued through `DataHelper`.
+    ///
+    /// Updates flush the queue before each phase and once more after the last one, so changes made
+    /// by the systems in one phase are seen by the next, and are all applied by the time the
+    /// update returns. Flushing by hand is only needed to see changes queued outside of an
+    /// update, such as after building a scene. Anything queued while the queue is being
+    /// flushed is dealt with in the same flush.
+    ///
+    /// Changes are applied in the order they were queued, so an entity is always built before
+    /// the changes queued for it after it was created. Changes for an entity that has been
+    /// removed by the time they are applied are dropped, even if its index has been reused
+    /// since. Systems are only told about entities that have been built and aren't disabled,
+    /// so removing or disabling an entity that was never built doesn't deactivate it anywhere.
+    ///
+    /// Panics if changes are still being queued after 100 passes over the queue, as that most
+    /// likely means something queues new changes every time it is run.
+    pub fn flush(&mut self)
+    {
+        let _names = self.data.debug_names.scope();
+        // Systems may have added or removed components directly since the last flush
+        unsafe { self.data.components.update_masks(); }
+        let mut passes = 0;
+        loop
+        {
+            // Entities can be left built but not activated by a builder that panicked later
+            // in the same batch, so they're activated by the next flush even if nothing is queued
+            if self.data.event_queue.is_empty() && self.data.changed.is_empty() && self.data.activating.is_empty()
+            {
+                break;
+            }
+            passes += 1;
+            assert!(passes <= MAX_FLUSH_PASSES,
+                "the event queue was still being added to after {} passes while flushing", MAX_FLUSH_PASSES);
+            // Events are only taken off the queue as they are applied, so a panic leaves the
+            // rest of them queued
+            for _ in 0..self.data.event_queue.len()
+            {
+                match self.data.event_queue.pop_front()
+                {
+                    Some(event) => process_event(&mut self.systems, &mut self.dynamic, &mut self.data, event),
+                    None => break,
+                }
+            }
+            activate_built(&mut self.systems, &mut self.dynamic, &mut self.data);
+            mem::swap(&mut self.data.changed, &mut self.data.reactivating);
+            self.data.reactivating.sort();
+            self.data.reactivating.dedup();
+            for i in 0..self.data.reactivating.len()
+            {
+                let entity = self.data.reactivating[i];
+                if self.data.entities.is_valid(&entity) && self.data.is_activated(&entity)
+                {
+                    unsafe { self.systems.reactivated(EntityData(&entity), &self.data.components); }
+                    self.dynamic.reactivated(&entity, &self.data.components);
+                }
+            }
+            self.data.reactivating.clear();
+        }
+    }
+
+    /// Reserves space for at least `additional` more entities.
+    ///
+    /// This is only a hint to avoid reallocating during large spawns, and never shrinks anything.
+    pub fn reserve(&mut self, additional: usize)
+    {
+        let capacity = cmp::max(self.data.entities.capacity(), self.data.entities.count() + additional);
+        self.data.entities.reserve(additional);
+        unsafe { self.data.components.reserve(capacity); }
+    }
+
+    /// Removes every entity from the world.
+    ///
+    /// The event queue is flushed first, then every entity is deactivated and passed to the
+    /// removal callbacks, and all components are cleared at once. All existing `Entity` handles become invalid.
+    pub fn clear(&mut self)
+    {
+        self.flush();
+        // Reserved entities that were never built aren't known to systems, like disabled ones
+        for entity in self.data.activated_entities()
+        {
+            self.dynamic.deactivated(&*entity, &self.data.components, DeactivationReason::Removed);
+            unsafe { self.systems.deactivated(entity, &self.data.components, DeactivationReason::Removed); }
+        }
+        for entity in self.data.entities.iter::<T>()
+        {
+            run_callbacks(&mut self.data.callbacks.removed, &entity, &self.data.components);
+        }
+        unsafe { self.data.components.clear_all(); }
+        self.data.entities.clear();
+        self.data.reset();
+    }
+
+    /// Saves every entity along with the components listed in `saveable_components!`.
+    ///
+    /// Queued changes are applied first. Names, tags and parents are not saved, and neither are
+    /// reserved entities that haven't been built.
+    pub fn save(&mut self, writer: &mut Write) -> io::Result<()> where T: SaveableComponents
+    {
+        self.flush();
+        let entities = self.saved_entities();
+        let mut writer = Writer::new(writer);
+        try!(save::write_header(&mut writer, &entities));
+        for &(entity, _) in entities.iter()
+        {
+            try!(self.data.components.serialize_entity(&entity, &mut writer));
+        }
+        Ok(())
+    }
+
+    /// Replaces every entity in the world with the entities of a saved world.
+    ///
+    /// Loaded entities get new handles. Entity handles stored in components are remapped while
+    /// loading, and the returned map can be used to remap any others. Systems are only told about
+    /// the new entities once they've all been loaded. If loading fails, the world is left empty.
+    pub fn load(&mut self, reader: &mut Read) -> io::Result<HashMap<Entity, Entity>>
+        where T: SaveableComponents
+    {
+        self.clear();
+        let mut remap = HashMap::new();
+        let saved = try!(save::read_header(&mut Reader::new(reader, &remap)));
+        let entities: Vec<(Entity, bool)> = saved.iter()
+            .map(|&(_, disabled)| (self.data.entities.create(), disabled))
+            .collect();
+        for (&(old, _), &(new, _)) in saved.iter().zip(entities.iter())
+        {
+            remap.insert(old, new);
+        }
+
+        let loaded = {
+            let mut reader = Reader::new(reader, &remap);
+            let mut loaded = Ok(());
+            for &(entity, _) in entities.iter()
+            {
+                loaded = self.data.components.deserialize_entity(BuildData(&entity), &mut reader);
+                if loaded.is_err()
+                {
+                    break;
+                }
+            }
+            loaded
+        };
+        if let Err(error) = loaded
+        {
+            unsafe { self.data.components.clear_all(); }
+            self.data.entities.clear();
+            return Err(error);
+        }
+
+        for &(entity, disabled) in entities.iter()
+        {
+            if disabled
+            {
+                self.data.disabled.insert(entity);
+            }
+            else
+            {
+                unsafe { self.systems.activated(EntityData(&entity), &self.data.components); }
+                self.dynamic.activated(&entity, &self.data.components);
+            }
+            run_callbacks(&mut self.data.callbacks.created, &entity, &self.data.components);
+        }
+        Ok(remap)
+    }
+
+    /// Saves a snapshot of the whole world, which `load_snapshot` turns back into the same world.
+    ///
+    /// Unlike `save`, entities keep their handles, the handles that will be given to new
+    /// entities are the same, and names and parents are saved too. Debug names, tags, resources
+    /// and dynamic systems aren't saved. Queued changes are applied first.
+    ///
+    /// Entities that are reserved but haven't been built are still reserved once loaded. Fails
+    /// if changes are being held for one of them, as those can't be saved.
+    pub fn save_snapshot(&mut self, writer: &mut Write) -> io::Result<()> where T: SaveableComponents
+    {
+        self.flush();
+        if self.data.reserved.values().any(|held| !held.is_empty())
+        {
+            return Err(save::invalid_data("changes are held for a reserved entity"));
+        }
+        let entities = self.saved_entities();
+        let mut writer = Writer::new(writer);
+        try!(save::write_snapshot_header(&mut writer, &entities));
+        {
+            let (generations, recycled) = self.data.entities.indices();
+            try!(writer.write(&generations.to_vec()));
+            try!(writer.write(&recycled.to_vec()));
+            let reserved: Vec<usize> = self.data.reserved.keys().map(|entity| entity.get_index()).collect();
+            try!(writer.write(&reserved));
+        }
+        for &(entity, _) in entities.iter()
+        {
+            try!(self.data.components.serialize_entity(&entity, &mut writer));
+        }
+
+        let names: Vec<(Entity, &String)> = entities.iter()
+            .filter_map(|&(entity, _)| self.data.entity_names.get(&entity).map(|name| (entity, name)))
+            .collect();
+        try!(writer.write(&(names.len() as u64)));
+        for &(entity, name) in names.iter()
+        {
+            try!(writer.write(&entity));
+            try!(writer.write(name));
+        }
+        let parents: Vec<(Entity, &Vec<Entity>)> = entities.iter()
+            .filter_map(|&(entity, _)| self.data.children.get(&entity).map(|children| (entity, children)))
+            .collect();
+        try!(writer.write(&(parents.len() as u64)));
+        for &(entity, children) in parents.iter()
+        {
+            try!(writer.write(&entity));
+            try!(writer.write(children));
+        }
+        Ok(())
+    }
+
+    /// Creates a world from a snapshot saved with `save_snapshot`.
+    ///
+    /// The systems are created fresh, and told about every entity once the whole snapshot has
+    /// been read, so anything they keep track of is rebuilt rather than loaded.
+    pub fn load_snapshot(reader: &mut Read) -> io::Result<World<T, U>> where T: SaveableComponents
+    {
+        let mut world = World::<T, U>::new();
+        let mut remap = HashMap::new();
+        let saved = try!(save::read_snapshot_header(&mut Reader::new(reader, &remap)));
+        for &(entity, _) in saved.iter()
+        {
+            remap.insert(entity, entity);
+        }
+
+        {
+            let mut reader = Reader::new(reader, &remap);
+            let generations: Vec<Generation> = try!(reader.read());
+            let recycled: Vec<usize> = try!(reader.read());
+            let reserved: Vec<usize> = try!(reader.read());
+            if reserved.iter().any(|&index| index >= generations.len())
+            {
+                return Err(save::invalid_data("inconsistent entities in snapshot"));
+            }
+            let reserved: Vec<Entity> = reserved.iter().map(|&index| Entity::from_raw(index, generations[index])).collect();
+            let entities: Vec<Entity> = saved.iter().map(|&(entity, _)| entity).chain(reserved.iter().cloned()).collect();
+            world.data.entities = match EntityManager::restore(generations, recycled, &entities)
+            {
+                Some(entities) => entities,
+                None => return Err(save::invalid_data("inconsistent entities in snapshot")),
+            };
+            for &entity in reserved.iter()
+            {
+                world.data.pending_build.insert(entity);
+                world.data.reserved.insert(entity, Vec::new());
+            }
+            world.data.share_generations();
+            for &(entity, _) in saved.iter()
+            {
+                try!(world.data.components.deserialize_entity(BuildData(&entity), &mut reader));
+            }
+
+            let names: u64 = try!(reader.read());
+            for _ in 0..names
+            {
+                let entity: Entity = try!(reader.read());
+                let name: String = try!(reader.read());
+                world.data.set_name(entity, &name);
+            }
+            let parents: u64 = try!(reader.read());
+            for _ in 0..parents
+            {
+                let parent: Entity = try!(reader.read());
+                let children: Vec<Entity> = try!(reader.read());
+                for child in children.iter()
+                {
+                    world.data.parents.insert(*child, parent);
+                }
+                world.data.children.insert(parent, children);
+            }
+        }
+
+        for &(entity, disabled) in saved.iter()
+        {
+            if disabled
+            {
+                world.data.disabled.insert(entity);
+            }
+            else
+            {
+                unsafe { world.systems.activated(EntityData(&entity), &world.data.components); }
+            }
+        }
+        Ok(world)
+    }
+
+    /// Copies the world's entities and components, so it can be rolled back with `restore`.
+    ///
+    /// Queued changes are applied first. Only the component lists in `cloneable_components!`
+    /// are copied, which is much faster than `save_snapshot`. Systems, resources and messages
+    /// aren't part of the snapshot. Entities that are reserved but haven't been built are
+    /// reserved again when the snapshot is restored.
+    ///
+    /// Panics if changes are being held for a reserved entity, as those can't be copied.
+    pub fn snapshot(&mut self) -> WorldSnapshot<T> where T: CloneableComponents
+    {
+        self.flush();
+        assert!(self.data.reserved.values().all(|held| held.is_empty()),
+                "can't snapshot the world while changes are held for a reserved entity");
+        WorldSnapshot
+        {
+            entities: self.data.entities.clone(),
+            disabled: self.data.disabled.clone(),
+            names: self.data.names.clone(),
+            entity_names: self.data.entity_names.clone(),
+            debug_names: self.data.debug_names.clone(),
+            tags: self.data.tags.clone(),
+            parents: self.data.parents.clone(),
+            children: self.data.children.clone(),
+            reserved: self.data.reserved.keys().cloned().collect(),
+            lists: self.data.components.clone_lists(),
+            __phantom: PhantomData,
+        }
+    }
+
+    /// Rolls the world back to a snapshot taken by `snapshot`, dropping any queued changes.
+    ///
+    /// Systems are told what changed: entities that are no longer active are deactivated while
+    /// they still have their current components, and once the components are restored, newly
+    /// active entities are activated and the rest are reactivated. Entity callbacks aren't run.
+    /// Lists that aren't in `cloneable_components!` keep their current components, except
+    /// for entities that weren't in the snapshot.
+    pub fn restore(&mut self, snapshot: &WorldSnapshot<T>) where T: CloneableComponents
+    {
+        activate_built(&mut self.systems, &mut self.dynamic, &mut self.data);
+        let before: Vec<Entity> = self.data.activated_entities().map(|entity| *entity).collect();
+        self.data.event_queue.clear();
+        self.data.arena.clear();
+        self.data.pending_removal.clear();
+        self.data.changed.clear();
+
+        for entity in before.iter()
+        {
+            if !snapshot.is_activated(entity)
+            {
+                let reason = if snapshot.contains(entity) { DeactivationReason::Disabled } else { DeactivationReason::Removed };
+                unsafe { self.systems.deactivated(EntityData(entity), &self.data.components, reason); }
+                self.dynamic.deactivated(entity, &self.data.components, reason);
+            }
+        }
+        let stale: Vec<Entity> = self.data.entities.iter::<T>()
+            .map(|entity| *entity)
+            .filter(|entity| !snapshot.contains(entity))
+            .collect();
+        for entity in stale.iter()
+        {
+            unsafe { self.data.components.remove_all(entity); }
+        }
+
+        self.data.entities = snapshot.entities.clone();
+        self.data.share_generations();
+        self.data.disabled = snapshot.disabled.clone();
+        self.data.names = snapshot.names.clone();
+        self.data.entity_names = snapshot.entity_names.clone();
+        self.data.debug_names = snapshot.debug_names.clone();
+        self.data.tags = snapshot.tags.clone();
+        self.data.parents = snapshot.parents.clone();
+        self.data.children = snapshot.children.clone();
+        // Nothing was queued when the snapshot was taken, so the only entities waiting to be
+        // built were the reserved ones
+        self.data.pending_build = snapshot.reserved.clone();
+        self.data.reserved = snapshot.reserved.iter().map(|&entity| (entity, Vec::new())).collect();
+        unsafe { self.data.components.restore_lists(&snapshot.lists); }
+
+        let before: HashSet<Entity> = before.into_iter().collect();
+        let after: Vec<Entity> = self.data.activated_entities().map(|entity| *entity).collect();
+        for entity in after.iter()
+        {
+            if before.contains(entity)
+            {
+                unsafe { self.systems.reactivated(EntityData(entity), &self.data.components); }
+                self.dynamic.reactivated(entity, &self.data.components);
+            }
+            else
+            {
+                unsafe { self.systems.activated(EntityData(entity), &self.data.components); }
+                self.dynamic.activated(entity, &self.data.components);
+            }
+        }
+    }
+
+    fn saved_entities(&self) -> Vec<(Entity, bool)>
+    {
+        self.data.entities.iter::<T>()
+            .excluding(&self.data.pending_build)
+            .map(|entity| (*entity, self.data.disabled.contains(&*entity)))
+            .collect()
+    }
+
+    /// Adds a system that isn't part of the system manager, such as one loaded by a mod.
+    ///
+    /// It is told about every entity that is currently active straight away. Dynamic systems run
+    /// after the system manager's systems in the same phase, in order of their priorities, and
+    /// their `run_after` and `run_before` constraints are ignored.
+    pub fn add_dynamic_system(&mut self, mut system: Box<Process<Components=T>>) -> SystemHandle
+    {
+        for entity in self.data.entities.iter::<T>()
+        {
+            if self.data.is_activated(&*entity)
+            {
+                system.activated(&entity, &self.data.components);
+            }
+        }
+        self.dynamic.add(system)
+    }
+
+    /// Removes a system added by `add_dynamic_system`, and returns it.
+    ///
+    /// Returns `None` if it has already been removed.
+    pub fn remove_dynamic_system(&mut self, handle: SystemHandle) -> Option<Box<Process<Components=T>>>
+    {
+        self.dynamic.remove(handle)
+    }
+
+    /// Returns the number of systems added by `add_dynamic_system`.
+    pub fn dynamic_system_count(&self) -> usize
+    {
+        self.dynamic.len()
+    }
+
+    /// Starts or stops recording how long each dynamic system takes to process, like
+    /// `set_profiling` does for the system manager's systems.
+    pub fn set_dynamic_profiling(&mut self, profiling: bool)
+    {
+        self.dynamic.set_profiling(profiling);
+    }
+
+    /// Returns the time each dynamic system has spent processing while profiling was on, in the
+    /// order they run.
+    pub fn dynamic_profile(&self) -> Vec<(SystemHandle, SystemTiming)>
+    {
+        self.dynamic.profile()
+    }
+
+    /// Sets the recorded time and number of calls of every dynamic system back to zero.
+    pub fn reset_dynamic_profile(&mut self)
+    {
+        self.dynamic.reset_profile();
+    }
+
+    /// Updates the world without any time passing, so `delta` is zero.
+    pub fn update(&mut self)
+    {
+        self.update_with_delta(0.0);
+    }
+
+    /// Updates the world, letting systems know that `delta` seconds have passed since the last update.
+    ///
+    /// While the world is paused this only flushes the queue.
+    pub fn update_with_delta(&mut self, delta: f64)
+    {
+        if self.data.paused
+        {
+            self.flush();
+        }
+        else
+        {
+            self.step_with_delta(delta);
+        }
+    }
+
+    /// Updates the world, giving `context` to the systems wrapped in a `ContextSystem`.
+    pub fn update_ctx<C: Any>(&mut self, context: &mut C)
+    {
+        self.update_with_delta_ctx(0.0, context);
+    }
+
+    /// Updates the world like `update_with_delta`, giving `context` to the systems wrapped in a
+    /// `ContextSystem`.
+    pub fn update_with_delta_ctx<C: Any>(&mut self, delta: f64, context: &mut C)
+    {
+        self.data.context = Some((TypeId::of::<C>(), context as *mut C as *mut ()));
+        let guard = ContextGuard { world: self };
+        guard.world.update_with_delta(delta);
+    }
+
+    /// Pauses or resumes the world.
+    ///
+    /// Updating a paused world still builds, modifies and removes queued entities, but doesn't
+    /// run any systems, so interval and timed systems don't count paused updates either.
+    /// Messages are kept until the next update that runs the systems.
+    pub fn set_paused(&mut self, paused: bool)
+    {
+        self.data.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool
+    {
+        self.data.paused
+    }
+
+    /// Runs exactly one update, even if the world is paused.
+    pub fn step(&mut self)
+    {
+        self.step_with_delta(0.0);
+    }
+
+    /// Runs exactly one update covering `delta` seconds, even if the world is paused.
+    pub fn step_with_delta(&mut self, delta: f64)
+    {
+        let _names = self.data.debug_names.scope();
+        self.data.delta = delta;
+        self.data.components.swap_buffers();
+        self.data.deliver_messages();
+        for &phase in Phase::all().iter()
+        {
+            self.flush();
+            unsafe { self.systems.update_phase(phase, &mut self.data); }
+            self.dynamic.update_phase(phase, &mut self.data);
+        }
+        self.flush();
+    }
+}
+
+// Both buffers are kept, so their allocations are reused from one update to the next.
+struct MessageQueue<M>
+{
+    sent: Vec<M>,
+    received: Vec<M>,
+}
+
+fn deliver_messages<M: Any>(queue: &mut Any)
+{
+    if let Some(queue) = queue.downcast_mut::<MessageQueue<M>>()
+    {
+        queue.received.clear();
+        mem::swap(&mut queue.sent, &mut queue.received);
+    }
+}
+
+fn resource_name<R: Any>() -> &'static str
+{
+    unsafe { ::std::intrinsics::type_name::<R>() }
+}
+
+fn add_component<T, C>(components: &mut T, entity: &Entity, component: Box<Any>) -> bool
+    where T: HasComponent<C>, C: Component
+{
+    let component = match component.downcast::<C>()
+    {
+        Ok(component) => *component,
+        Err(_) => unreachable!(),
+    };
+    components.list_mut().set(&ModifyData(entity), component).is_none()
+}
+
+fn set_dyn_component<T: ComponentManager>(components: &mut T, entity: &Entity, component: Box<Any>) -> bool
+{
+    match unsafe { components.set_dyn(entity, component) }
+    {
+        Ok(added) => added,
+        Err(_) => unreachable!(),
+    }
+}
+
+fn remove_component<T, C>(components: &mut T, entity: &Entity) -> bool
+    where T: HasComponent<C>, C: Component
+{
+    components.list_mut().remove(&ModifyData(entity)).is_some()
+}
+
+// Removes an entity whose builder or removal observer panicked, along with its components, so
+// that unwinding doesn't leave behind a valid entity that systems were never told about or have
+// already been told is gone.
+struct ForgetGuard<'a, T: ComponentManager+'a>
+{
+    data: &'a mut DataHelper<T>,
+    entity: Entity,
+}
+
+#[unsafe_destructor]
+impl<'a, T: ComponentManager> Drop for ForgetGuard<'a, T>
+{
+    fn drop(&mut self)
+    {
+        if thread::panicking()
+        {
+            self.data.forget(&self.entity);
+        }
+    }
+}
+
+// Clears the context of an update once it's done, even if a system panicked, so that the world
+// never keeps a pointer to a context that has gone out of scope.
+struct ContextGuard<'a, T, U> where T: ComponentManager+'a, U: SystemManager<Components=T>+'a
+{
+    world: &'a mut World<T, U>,
+}
+
+#[unsafe_destructor]
+impl<'a, T, U> Drop for ContextGuard<'a, T, U> where T: ComponentManager, U: SystemManager<Components=T>
+{
+    fn drop(&mut self)
+    {
+        self.world.data.context = None;
+    }
+}
+
+fn forget_on_panic<T, F>(data: &mut DataHelper<T>, entity: Entity, call: F)
+    where T: ComponentManager, F: FnOnce(&mut DataHelper<T>)
+{
+    let guard = ForgetGuard { data: data, entity: entity };
+    call(&mut *guard.data);
+}
+
+// Built entities are activated together once something other than a build comes up in the queue,
+// or the queue runs out.
+fn finish_build<T: ComponentManager>(data: &mut DataHelper<T>, entity: Entity)
+{
+    let pending = data.pending_build.remove(&entity);
+    debug_assert!(pending, "{:?} was built twice", entity);
+    data.activating.push(entity);
+}
+
+fn activate_built<T: ComponentManager, U: SystemManager<Components=T>>(systems: &mut U, dynamic: &mut DynamicSystems<T>,
+                                                                     data: &mut DataHelper<T>)
+{
+    if data.activating.is_empty()
+    {
+        return;
+    }
+    let mut built = mem::replace(&mut data.activating, Vec::new());
+    built.retain(|entity| data.entities.is_valid(entity));
+    if built.iter().all(|entity| data.is_activated(entity))
+    {
+        unsafe { systems.activated_batch(&built, &data.components); }
+    }
+    else
+    {
+        let mut active = mem::replace(&mut data.active, Vec::new());
+        active.extend(built.iter().cloned().filter(|entity| data.is_activated(entity)));
+        unsafe { systems.activated_batch(&active, &data.components); }
+        active.clear();
+        data.active = active;
+    }
+    for entity in built.iter()
+    {
+        if data.is_activated(entity)
+        {
+            dynamic.activated(entity, &data.components);
+        }
+    }
+    for entity in built.iter()
+    {
+        run_callbacks(&mut data.callbacks.created, entity, &data.components);
+    }
+    built.clear();
+    data.activating = built;
+}
+
+fn apply_modifier<T, U, F>(systems: &mut U, dynamic: &mut DynamicSystems<T>, data: &mut DataHelper<T>, entity: Entity, modify: F)
+    where T: ComponentManager, U: SystemManager<Components=T>, F: FnOnce(&mut DataHelper<T>)
+{
+    let before = data.components.mask_of(&entity);
+    modify(data);
+    let changed = data.components.mask_of(&entity) != before;
+    if changed && data.is_activated(&entity)
+    {
+        unsafe { systems.reactivated(EntityData(&entity), &data.components); }
+        dynamic.reactivated(&entity, &data.components);
+    }
+    run_callbacks(&mut data.callbacks.modified, &entity, &data.components);
+}
+
+// This function has to be external to World because of borrowing rules
+fn process_event<T: ComponentManager, U: SystemManager<Components=T>>(systems: &mut U, dynamic: &mut DynamicSystems<T>,
+                                                                    data: &mut DataHelper<T>, event: Event<T>)
+{
+    match event
+    {
+        Event::BuildEntity(..) | Event::SpawnPrefab(..) | Event::ActivateEntity(..) => (),
+        _ => activate_built(systems, dynamic, data),
+    }
+    if let Event::RemoveEntity(ref entity) = event
+    {
+        data.pending_removal.remove(entity);
+    }
+    // Events may have been queued for an entity that has since been removed.
+    // Handles are generational, so recycling the index doesn't make them valid again.
+    if !data.entities.is_valid(event.entity())
+    {
+        if let Some(key) = event.arena_key()
+        {
+            data.arena.discard(key);
+        }
+        return;
+    }
+    match event
+    {
+        Event::BuildEntity(entity, queued) => {
+            forget_on_panic(data, entity, |data| unsafe {
+                (queued.run)(&mut data.arena, queued.key, &entity, &mut data.components);
+            });
+            finish_build(data, entity);
+        },
+        Event::SpawnPrefab(entity, name, mut overrides) => {
+            forget_on_panic(data, entity, |data| {
+                if let Some(prefab) = data.prefabs.get_mut(&name)
+                {
+                    prefab.build(BuildData(&entity), &mut data.components);
+                }
+                overrides.build(BuildData(&entity), &mut data.components);
+            });
+            finish_build(data, entity);
+        },
+        Event::ActivateEntity(entity) => finish_build(data, entity),
+        Event::AfterBuild(entity, mut then) => (*then)(entity, data),
+        Event::ModifyEntity(entity, queued) => apply_modifier(systems, dynamic, data, entity, |data| unsafe {
+            (queued.run)(&mut data.arena, queued.key, &entity, &mut data.components);
+        }),
+        Event::ModifyHeld(entity, mut modifier) => apply_modifier(systems, dynamic, data, entity, |data| {
+            modifier.modify(ModifyData(&entity), &mut data.components);
+        }),
+        Event::AddComponent(entity, component, add) => {
+            if add(&mut data.components, &entity, component)
+            {
+                data.changed.push(entity);
+                run_callbacks(&mut data.callbacks.modified, &entity, &data.components);
+            }
+        },
+        Event::RemoveComponent(entity, remove) => {
+            if remove(&mut data.components, &entity)
+            {
+                data.changed.push(entity);
+                run_callbacks(&mut data.callbacks.modified, &entity, &data.components);
+            }
+        },
+        Event::ReactivateEntity(entity) => {
+            if data.is_activated(&entity)
+            {
+                unsafe { systems.reactivated(EntityData(&entity), &data.components); }
+                dynamic.reactivated(&entity, &data.components);
+            }
+        },
+        Event::DisableEntity(entity) => {
+            if data.is_activated(&entity)
+            {
+                unsafe { systems.deactivated(EntityData(&entity), &data.components, DeactivationReason::Disabled); }
+                dynamic.deactivated(&entity, &data.components, DeactivationReason::Disabled);
+            }
+            data.disabled.insert(entity);
+        },
+        Event::EnableEntity(entity) => {
+            if data.disabled.remove(&entity) && data.is_activated(&entity)
+            {
+                unsafe { systems.activated(EntityData(&entity), &data.components); }
+                dynamic.activated(&entity, &data.components);
+            }
         },
         Event::RemoveEntity(entity) => {
-            unsafe {
-                systems.deactivated(EntityData(&entity), components);
-                components.remove_all(&entity);
+            // Entities that were never built or are disabled aren't known to systems
+            if data.is_activated(&entity)
+            {
+                unsafe { systems.deactivated(EntityData(&entity), &data.components, DeactivationReason::Removed); }
+                dynamic.deactivated(&entity, &data.components, DeactivationReason::Removed);
             }
-            entities.remove(&entity);
+            forget_on_panic(data, entity, |data| run_callbacks(&mut data.callbacks.removed, &entity, &data.components));
+            data.forget(&entity);
         }
     }
+    unsafe { data.components.update_masks(); }
 }
@@ -0,0 +1,173 @@
+#[macro_use]
+extern crate ecs;
+
+use std::alloc::{GlobalAlloc, Layout, System as Heap};
+use std::cell::Cell;
+
+use ecs::{BuildData, Entity, ModifyData, World};
+
+// Counts the allocations made on each thread, so that tests running in parallel don't see each
+// other's.
+struct CountingAllocator;
+
+thread_local!(static ALLOCATIONS: Cell<usize> = Cell::new(0));
+
+unsafe impl GlobalAlloc for CountingAllocator
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8
+    {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        Heap.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+    {
+        Heap.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8
+    {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        Heap.realloc(ptr, layout, size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<F: FnOnce()>(call: F) -> usize
+{
+    let before = ALLOCATIONS.with(|count| count.get());
+    call();
+    ALLOCATIONS.with(|count| count.get()) - before
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Position(f32, f32);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Velocity(f32, f32);
+
+components! {
+    TestComponents {
+        #[hot] position: Position,
+        #[hot] velocity: Velocity
+    }
+}
+
+systems! {
+    TestSystems<TestComponents>;
+}
+
+const SPAWNS: usize = 2_000;
+
+// Queues a frame's worth of bullets, each built and then nudged by a small closure.
+fn spawn_bullets(world: &mut World<TestComponents, TestSystems>, entities: &[Entity])
+{
+    for (i, &entity) in entities.iter().enumerate()
+    {
+        let speed = i as f32;
+        world.data.build_reserved(entity, move |e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position(0.0, 0.0));
+            c.velocity.add(&e, Velocity(speed, 0.0));
+        }).unwrap();
+        world.data.modify_entity(entity, move |e: ModifyData, c: &mut TestComponents| {
+            c.position[e].1 = speed;
+        });
+    }
+}
+
+#[test]
+fn queued_builders_are_not_boxed()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    // The first frame grows the queue and the components to fit
+    let entities = world.data.reserve_ids(SPAWNS);
+    spawn_bullets(&mut world, &entities);
+    world.flush();
+    for &entity in entities.iter()
+    {
+        world.data.remove_entity(entity);
+    }
+    world.flush();
+
+    // After that, queueing the same number of builders and modifiers allocates nothing. Adding
+    // the entities to the entity manager can, so they are reserved beforehand.
+    let entities = world.data.reserve_ids(SPAWNS);
+    assert_eq!(0, allocations(|| spawn_bullets(&mut world, &entities)));
+    world.flush();
+    assert_eq!(SPAWNS, world.position.len());
+    assert_eq!(Some(&Position(0.0, 7.0)), world.position.lookup(&entities[7]));
+}
+
+// Builds the entities in one batch, with every fourth one disabled before it's activated, and
+// removes them all again.
+fn churn(world: &mut World<TestComponents, TestSystems>, entities: &[Entity])
+{
+    for (i, &entity) in entities.iter().enumerate()
+    {
+        world.data.build_reserved(entity, |e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position(0.0, 0.0));
+        }).unwrap();
+        if i % 4 == 0
+        {
+            world.disable_entity(entity);
+        }
+    }
+    world.flush();
+    for &entity in entities.iter()
+    {
+        world.data.remove_entity(entity);
+    }
+    world.flush();
+}
+
+#[test]
+fn flushing_reuses_its_buffers()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let entities = world.data.reserve_ids(SPAWNS);
+    churn(&mut world, &entities);
+    let capacity = world.data.queue_capacity();
+    assert!(capacity >= SPAWNS);
+
+    // Once the queue and the lists used while flushing have grown, pushing and flushing the
+    // same number of events again allocates nothing
+    let entities = world.data.reserve_ids(SPAWNS);
+    assert_eq!(0, allocations(|| churn(&mut world, &entities)));
+    assert_eq!(capacity, world.data.queue_capacity());
+    assert_eq!(0, world.entity_count());
+}
+
+#[test]
+fn held_changes_do_not_grow_the_queue()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    // A change queued for a reserved entity is held until the entity is built, which here is
+    // never
+    let reserved = world.data.reserve_ids(1)[0];
+    world.data.modify_entity(reserved, |e: ModifyData, c: &mut TestComponents| {
+        c.position[e].0 = 1.0;
+    });
+
+    let entities = world.data.reserve_ids(SPAWNS);
+    spawn_bullets(&mut world, &entities);
+    world.flush();
+    let bytes = world.stats().queue_bytes;
+    for _ in 0..100
+    {
+        for &entity in entities.iter()
+        {
+            world.data.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+                c.velocity[e].1 += 1.0;
+            });
+        }
+        world.flush();
+    }
+
+    // The space taken by the queued modifiers is reused every flush, while the held one waits
+    assert_eq!(bytes, world.stats().queue_bytes);
+    assert_eq!(Some(&Velocity(7.0, 100.0)), world.velocity.lookup(&entities[7]));
+}
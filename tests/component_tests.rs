@@ -0,0 +1,1303 @@
+
+#[macro_use]
+extern crate ecs;
+
+use ecs::{BuildData, EditData, ModifyData, EntityBuilder, EntityModifier, defaults};
+use ecs::{Component, ComponentBorrows, ComponentStorage, HasComponent, NullStorage, Poolable, PoolStats};
+use ecs::SparseSetStorage;
+use ecs::entity::EntityManager;
+use ecs::{ComponentManager, ComponentMask, Entity, World, WorldView, DataHelper};
+use ecs::{Process, Shared, System};
+
+use std::any::{Any, TypeId};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Position
+{
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Velocity
+{
+    pub dx: f32,
+    pub dy: f32,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Frozen;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Path(Vec<Position>);
+
+impl Poolable for Path
+{
+    fn reset(&mut self)
+    {
+        self.0.clear();
+    }
+}
+
+#[derive(Clone)]
+pub struct Stats
+{
+    pub health: i32,
+    pub drops: Arc<AtomicUsize>,
+}
+
+impl Drop for Stats
+{
+    fn drop(&mut self)
+    {
+        self.drops.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+split_component! {
+    #[derive(Clone, Debug, PartialEq)]
+    Body -> (BodyStorage, BodyRef, BodyMut, BodyColumns) {
+        position: Position,
+        velocity: Velocity,
+        name: String,
+    }
+}
+
+components! {
+    TestComponents {
+        #[hot] position: Position,
+        #[cold] velocity: Velocity,
+        #[null] frozen: Frozen,
+        #[cold] path: Path,
+        #[cold] stats: Shared<Stats>
+    }
+}
+
+components! {
+    PlainComponents {
+        position: Position,
+        velocity: Velocity -> HashMapStorage,
+        frozen: Frozen -> NullStorage,
+        #[cold] path: Path,
+    }
+}
+
+systems! {
+    PlainSystems<PlainComponents>;
+}
+
+components! {
+    SplitComponents {
+        #[hot] velocity: Velocity,
+        #[split] body: BodyStorage
+    }
+}
+
+systems! {
+    SplitSystems<SplitComponents>;
+}
+
+components! {
+    BodyComponents {
+        #[split] body: BodyStorage
+    }
+}
+
+systems! {
+    BodySystems<BodyComponents>;
+}
+
+// Storage defined outside of the crate, which keeps its components ordered by entity index
+pub struct OrderedStorage<T>(BTreeMap<usize, (Entity, T)>);
+
+impl<T: Component> ComponentStorage<T> for OrderedStorage<T>
+{
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T>
+    {
+        self.0.insert(*entity, (entity, component)).map(|(_, c)| c)
+    }
+    fn remove(&mut self, index: usize) -> Option<T>
+    {
+        self.0.remove(&index).map(|(_, c)| c)
+    }
+    fn get(&self, index: usize) -> Option<(Entity, &T)>
+    {
+        self.0.get(&index).map(|&(entity, ref c)| (entity, c))
+    }
+    fn get_mut(&mut self, index: usize) -> Option<(Entity, &mut T)>
+    {
+        self.0.get_mut(&index).map(|&mut (entity, ref mut c)| (entity, c))
+    }
+    fn contains(&self, index: usize) -> bool
+    {
+        self.0.contains_key(&index)
+    }
+    fn len(&self) -> usize
+    {
+        self.0.len()
+    }
+    fn clear(&mut self)
+    {
+        self.0.clear();
+    }
+    fn iter<'a>(&'a self) -> Box<Iterator<Item=(Entity, &'a T)>+'a>
+    {
+        Box::new(self.0.values().map(|&(entity, ref c)| (entity, c)))
+    }
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=(Entity, &'a mut T)>+'a>
+    {
+        Box::new(self.0.values_mut().map(|&mut (entity, ref mut c)| (entity, c)))
+    }
+}
+
+components! {
+    CustomComponents {
+        position: Position,
+        velocity: Velocity = OrderedStorage(BTreeMap::new()),
+    }
+}
+
+systems! {
+    CustomSystems<CustomComponents>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Follow(Entity);
+
+components! {
+    #[access] BufferedComponents {
+        position: Position -> DoubleBuffered,
+        #[cold] follow: Follow,
+    }
+}
+
+systems! {
+    BufferedSystems<BufferedComponents> {
+        chase: Chase = Chase
+    }
+}
+
+// Moves the leader forward, and every follower to where the entity it follows was
+pub struct Chase;
+impl Process for Chase
+{
+    fn process(&mut self, data: &mut DataHelper<BufferedComponents>)
+    {
+        let components = &mut data.components;
+        let entities: Vec<Entity> = components.position.iter().map(|(entity, _)| entity).collect();
+        for entity in entities.into_iter()
+        {
+            let target = match components.follow.lookup(&entity)
+            {
+                Some(&Follow(leader)) => components.position.previous(&leader).unwrap().x,
+                None => components.position.previous(&entity).unwrap().x + 10.0,
+            };
+            components.position.lookup_mut(&entity).unwrap().x = target;
+        }
+    }
+}
+impl System for Chase { type Components = BufferedComponents; }
+
+component_access! {
+    TestComponents {
+        position: Position,
+        velocity: Velocity,
+        frozen: Frozen
+    }
+}
+
+systems! {
+    TestSystems<TestComponents> {
+        motion: Motion = Motion
+    }
+}
+
+pub struct Motion;
+impl Process for Motion
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        let components = &mut data.components;
+        for (entity, velocity) in components.velocity.iter()
+        {
+            if let Some(position) = components.position.lookup_mut(&entity)
+            {
+                position.x += velocity.dx;
+                position.y += velocity.dy;
+            }
+        }
+    }
+}
+impl System for Motion { type Components = TestComponents; }
+
+fn sorted(mut entities: Vec<Entity>) -> Vec<Entity>
+{
+    entities.sort_by(|a, b| a.get_index().cmp(&b.get_index()));
+    entities
+}
+
+#[test]
+fn iterate_component_lists()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let mut entities = Vec::new();
+    for i in 0..5
+    {
+        entities.push(world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position { x: i as f32, y: 0.0 });
+            if i % 2 == 0
+            {
+                c.velocity.add(&e, Velocity { dx: 1.0, dy: 1.0 });
+            }
+        }));
+    }
+
+    // Leave holes behind
+    world.remove_entity(entities[1]);
+    world.modify_entity(entities[2], |e: ModifyData, c: &mut TestComponents| {
+        c.velocity.remove(&e);
+    });
+
+    let with_position = sorted(world.position.iter().map(|(e, _)| e).collect());
+    assert_eq!(vec![entities[0], entities[2], entities[3], entities[4]], with_position);
+    let with_velocity = sorted(world.velocity.iter().map(|(e, _)| e).collect());
+    assert_eq!(vec![entities[0], entities[4]], with_velocity);
+
+    for (_, position) in world.position.iter_mut()
+    {
+        position.y = 10.0;
+    }
+    world.update();
+
+    world.with_entity_data(&entities[4], |e, c| {
+        assert_eq!(Position { x: 5.0, y: 11.0 }, c.position[e]);
+    });
+    world.with_entity_data(&entities[3], |e, c| {
+        assert_eq!(Position { x: 3.0, y: 10.0 }, c.position[e]);
+    });
+}
+
+#[test]
+fn access_components_by_type()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 1.0, y: 2.0 });
+    });
+    assert_eq!(Some(&Position { x: 1.0, y: 2.0 }), world.get::<Position>(&entity));
+    assert_eq!(None, world.get::<Velocity>(&entity));
+
+    world.get_mut::<Position>(&entity).unwrap().x = 3.0;
+    assert_eq!(Some(&Position { x: 3.0, y: 2.0 }), world.data.get::<Position>(&entity));
+
+    world.remove_entity(entity);
+    assert_eq!(None, world.get::<Position>(&entity));
+    assert_eq!(None, world.get_mut::<Position>(&entity));
+
+    // The index is reused, but the old handle still can't see the new entity's components
+    let reused = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 5.0, y: 5.0 });
+    });
+    assert_eq!(entity.get_index(), reused.get_index());
+    assert_eq!(None, world.get::<Position>(&entity));
+    assert_eq!(Some(&Position { x: 5.0, y: 5.0 }), world.get::<Position>(&reused));
+}
+
+fn stored_entities<S: ComponentStorage<T>+?Sized, T: Component>(storage: &S) -> Vec<Entity>
+{
+    sorted(storage.iter().map(|(e, _)| e).collect())
+}
+
+#[test]
+fn storage_backends()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let mut entities = Vec::new();
+    for i in 0..4
+    {
+        entities.push(world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position { x: i as f32, y: 0.0 });
+            c.velocity.add(&e, Velocity { dx: 0.0, dy: 0.0 });
+            if i % 2 == 1
+            {
+                c.frozen.add(&e, Frozen);
+            }
+        }));
+    }
+    assert_eq!(2, world.frozen.len());
+    assert_eq!(Some(&Frozen), world.get::<Frozen>(&entities[1]));
+    assert_eq!(None, world.get::<Frozen>(&entities[2]));
+
+    world.remove_entity(entities[1]);
+    world.modify_entity(entities[0], |e: ModifyData, c: &mut TestComponents| {
+        c.frozen.insert(&e, Frozen);
+        c.velocity.remove(&e);
+    });
+
+    let expected = vec![entities[0], entities[2], entities[3]];
+    assert_eq!(expected, stored_entities(world.position.storage()));
+    assert_eq!(vec![entities[2], entities[3]], stored_entities(world.velocity.storage()));
+    assert_eq!(vec![entities[0], entities[3]], stored_entities(world.frozen.storage()));
+    assert_eq!(2, world.frozen.iter_mut().count());
+}
+
+#[test]
+fn declare_storage_after_type()
+{
+    let mut world = World::<PlainComponents, PlainSystems>::new();
+
+    let first = world.create_entity(|e: BuildData, c: &mut PlainComponents| {
+        c.position.add(&e, Position { x: 1.0, y: 2.0 });
+        c.velocity.add(&e, Velocity { dx: 3.0, dy: 4.0 });
+        c.frozen.add(&e, Frozen);
+        c.path.add(&e, Path(Vec::new()));
+    });
+    assert_eq!(Position { x: 1.0, y: 2.0 }, *world.position.lookup(&first).unwrap());
+    assert_eq!(Velocity { dx: 3.0, dy: 4.0 }, *world.velocity.lookup(&first).unwrap());
+    assert!(world.frozen.lookup(&first).is_some());
+    assert_eq!(vec!["position", "velocity", "frozen", "path"],
+        world.components.stats().iter().map(|stats| stats.name).collect::<Vec<_>>());
+    assert_eq!(0, world.stats().systems);
+
+    world.remove_entity(first);
+    let second = world.create_entity(());
+    assert_eq!(*first, *second);
+    assert!(!world.position.lookup(&second).is_some());
+    assert!(!world.velocity.lookup(&second).is_some());
+    assert!(!world.frozen.lookup(&second).is_some());
+    assert!(!world.path.lookup(&second).is_some());
+}
+
+// Handle that outlived its entity, which the world never hands out itself
+struct StaleData(Entity);
+
+unsafe impl EditData for StaleData
+{
+    fn entity(&self) -> &Entity
+    {
+        &self.0
+    }
+}
+
+#[test]
+#[should_panic(expected = "does not have this component")]
+fn index_with_stale_entity_panics()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let removed = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 0.0, y: 0.0 });
+    });
+    world.remove_entity(removed);
+    world.flush();
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 1.0, y: 0.0 });
+    });
+    assert_eq!(*removed, *entity);
+
+    // The component at the index belongs to the new entity
+    world.position[StaleData(removed)].x = 2.0;
+}
+
+#[test]
+#[should_panic]
+fn null_storage_rejects_sized_components()
+{
+    NullStorage::<u32>::new();
+}
+
+#[test]
+fn null_storage_iter_mut_yields_separate_references()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    for _ in 0..3
+    {
+        world.create_entity(|e: BuildData, c: &mut TestComponents| { c.frozen.add(&e, Frozen); });
+    }
+    let markers: Vec<&mut Frozen> = world.frozen.iter_mut().map(|(_, marker)| marker).collect();
+    assert_eq!(3, markers.len());
+    for marker in markers.into_iter()
+    {
+        *marker = Frozen;
+    }
+}
+
+#[test]
+fn custom_storage()
+{
+    let mut world = World::<CustomComponents, CustomSystems>::new();
+    let first = world.create_entity(|e: BuildData, c: &mut CustomComponents| {
+        c.velocity.add(&e, Velocity { dx: 1.0, dy: 0.0 });
+    });
+    let second = world.create_entity(|e: BuildData, c: &mut CustomComponents| {
+        c.position.add(&e, Position { x: 1.0, y: 2.0 });
+        c.velocity.add(&e, Velocity { dx: 2.0, dy: 0.0 });
+    });
+    for (_, velocity) in world.velocity.iter_mut()
+    {
+        velocity.dy = 1.0;
+    }
+    assert_eq!(vec![(first, Velocity { dx: 1.0, dy: 1.0 }), (second, Velocity { dx: 2.0, dy: 1.0 })],
+        world.velocity.snapshot());
+
+    world.remove_entity(first);
+    assert_eq!(1, world.velocity.len());
+    assert!(world.velocity.lookup(&first).is_none());
+    assert_eq!(Some(&Velocity { dx: 2.0, dy: 1.0 }), world.velocity.lookup(&second));
+}
+
+#[test]
+fn sparse_set_storage()
+{
+    let mut storage = SparseSetStorage::new();
+    let entities: Vec<Entity> = (0..4).map(|index| Entity::from_raw(index * 10, 0)).collect();
+    for (value, &entity) in entities.iter().enumerate()
+    {
+        assert_eq!(None, storage.insert(entity, value));
+    }
+    assert_eq!(Some(3), storage.insert(entities[3], 30));
+    assert_eq!(&[0, 1, 2, 30], storage.as_slice());
+
+    // The last component takes the place of the removed one
+    assert_eq!(Some(1), storage.remove(10));
+    assert_eq!(None, storage.remove(10));
+    assert_eq!(&[0, 30, 2], storage.as_slice());
+    assert_eq!(Some((entities[3], &30)), ComponentStorage::get(&storage, 30));
+    assert!(!storage.contains(10));
+    *storage.get_mut(30).unwrap().1 += 1;
+    assert_eq!(vec![(entities[0], 0), (entities[3], 31), (entities[2], 2)],
+        storage.iter().map(|(entity, &value)| (entity, value)).collect::<Vec<_>>());
+
+    assert_eq!(Some(31), storage.remove(30));
+    assert_eq!(Some(2), storage.remove(20));
+    assert_eq!(Some(0), storage.remove(0));
+    assert_eq!(0, storage.len());
+    assert_eq!(None, storage.insert(entities[1], 5));
+    assert_eq!(Some((entities[1], &5)), ComponentStorage::get(&storage, 10));
+}
+
+#[test]
+fn double_buffered_components()
+{
+    let mut world = World::<BufferedComponents, BufferedSystems>::new();
+    let mut entities: Vec<Entity> = Vec::new();
+    for i in 0..4
+    {
+        let leader = entities.last().cloned();
+        entities.push(world.create_entity(move |e: BuildData, c: &mut BufferedComponents| {
+            c.position.add(&e, Position { x: i as f32, y: 0.0 });
+            if let Some(leader) = leader
+            {
+                c.follow.add(&e, Follow(leader));
+            }
+        }));
+    }
+    let xs = |world: &World<BufferedComponents, BufferedSystems>| -> Vec<f32> {
+        entities.iter().map(|entity| world.components.position.lookup(entity).unwrap().x).collect()
+    };
+    // New components start out with their first value as their previous one
+    assert_eq!(Some(&Position { x: 3.0, y: 0.0 }), world.components.position.previous(&entities[3]));
+
+    // Followers see where their leader was before this update, whichever was moved first
+    world.update();
+    assert_eq!(vec![10.0, 0.0, 1.0, 2.0], xs(&world));
+    world.update();
+    assert_eq!(vec![20.0, 10.0, 0.0, 1.0], xs(&world));
+    assert_eq!(Some(&Position { x: 10.0, y: 0.0 }), world.components.position.previous(&entities[0]));
+
+    world.swap_buffers();
+    assert_eq!(Some(&Position { x: 20.0, y: 0.0 }), world.components.position.previous(&entities[0]));
+    world.remove_entity(entities[3]);
+    world.flush();
+    assert_eq!(None, world.components.position.previous(&entities[3]));
+}
+
+#[test]
+fn access_declared_with_the_manager()
+{
+    let mut world = World::<BufferedComponents, BufferedSystems>::new();
+    let leader = world.create_entity(|e: BuildData, c: &mut BufferedComponents| {
+        c.position.add(&e, Position { x: 1.0, y: 0.0 });
+    });
+    let follower = world.create_entity(|e: BuildData, c: &mut BufferedComponents| {
+        c.position.add(&e, Position { x: 0.0, y: 0.0 });
+        c.follow.add(&e, Follow(Entity::nil()));
+    });
+    world.get_mut::<Follow>(&follower).unwrap().0 = leader;
+
+    assert_eq!(Some(&Follow(leader)), world.get::<Follow>(&follower));
+    assert_eq!(Some(&Position { x: 1.0, y: 0.0 }), world.get::<Position>(&leader));
+    assert_eq!("follow", HasComponent::<Follow>::name(&world.data.components));
+    assert_eq!("position", HasComponent::<Position>::name(&world.data.components));
+}
+
+#[test]
+fn split_component_storage()
+{
+    let body = |x: f32, name: &str| Body {
+        position: Position { x: x, y: 0.0 },
+        velocity: Velocity { dx: 1.0, dy: 2.0 },
+        name: name.to_string(),
+    };
+    let entities: Vec<Entity> = (0..3).map(|index| Entity::from_raw(index, 0)).collect();
+    let mut storage = BodyStorage::new();
+    for (index, &entity) in entities.iter().enumerate()
+    {
+        assert_eq!(None, storage.insert(entity, body(index as f32, "body")));
+    }
+    assert_eq!(Some(body(2.0, "body")), storage.insert(entities[2], body(2.0, "last")));
+    assert_eq!("last", *storage.lookup(&entities[2]).unwrap().name);
+
+    // A stale entity with the same index doesn't see the component
+    assert!(!storage.contains(&Entity::from_raw(1, 1)));
+    assert_eq!(Some(body(1.0, "body")), storage.remove(&entities[1]));
+    assert_eq!(None, storage.remove(&entities[1]));
+    assert_eq!(&[entities[0], entities[2]], storage.entities());
+
+    {
+        let columns = storage.columns();
+        for (position, velocity) in columns.position.iter_mut().zip(columns.velocity.iter())
+        {
+            position.x += velocity.dx;
+        }
+    }
+    for (_, body) in storage.iter_mut()
+    {
+        body.position.y += body.velocity.dy;
+    }
+    storage.lookup_mut(&entities[0]).unwrap().velocity.dx = 0.0;
+    assert_eq!(vec![(entities[0], Position { x: 1.0, y: 2.0 }, 0.0), (entities[2], Position { x: 3.0, y: 2.0 }, 1.0)],
+        storage.iter().map(|(entity, body)| (entity, *body.position, body.velocity.dx)).collect::<Vec<_>>());
+}
+
+#[test]
+fn split_component_in_manager()
+{
+    let body = |x: f32| Body {
+        position: Position { x: x, y: 0.0 },
+        velocity: Velocity { dx: 0.0, dy: 0.0 },
+        name: "body".to_string(),
+    };
+    let mut world = World::<SplitComponents, SplitSystems>::new();
+    let mut other = World::<SplitComponents, SplitSystems>::new();
+    let entities: Vec<Entity> = (0..3).map(|i| world.create_entity(move |e: BuildData, c: &mut SplitComponents| {
+        c.body.insert(e.entity(), body(i as f32));
+    })).collect();
+
+    // Removing an entity removes its split component too
+    world.remove_entity(entities[0]);
+    assert!(!world.body.contains(&entities[0]));
+    assert_eq!(2, world.body.len());
+
+    // Moving an entity to another world takes it along
+    let moved = world.transfer_entity(entities[1], &mut other).unwrap();
+    assert_eq!(&[entities[2]], world.body.entities());
+    assert_eq!(Position { x: 1.0, y: 0.0 }, *other.body.lookup(&moved).unwrap().position);
+
+    world.clear();
+    assert!(world.body.is_empty());
+}
+
+#[test]
+fn manager_with_only_split_components()
+{
+    let mut world = World::<BodyComponents, BodySystems>::new();
+    let entity = world.create_entity(|e: BuildData, c: &mut BodyComponents| {
+        c.body.insert(e.entity(), Body {
+            position: Position { x: 1.0, y: 2.0 },
+            velocity: Velocity { dx: 0.0, dy: 0.0 },
+            name: "body".to_string(),
+        });
+    });
+    let empty = world.create_entity(|_: BuildData, _: &mut BodyComponents| {});
+    assert_eq!(world.body.mask(), world.mask_of(&entity));
+    assert!(world.mask_of(&empty).is_empty());
+
+    // Copies of an entity get a copy of its split component, which aspects can see
+    let copy = world.clone_entity(entity).unwrap();
+    assert_eq!(Position { x: 1.0, y: 2.0 }, *world.body.lookup(&copy).unwrap().position);
+    assert_eq!(world.body.mask(), world.mask_of(&copy));
+    let bodies: Vec<Entity> = world.entities().filter(aspect!(<BodyComponents> all: [body]), &world)
+        .map(|e| *e).collect();
+    assert_eq!(vec![entity, copy], bodies);
+
+    // Removing the component or the entity clears its bit
+    world.body.remove(&entity);
+    assert!(world.mask_of(&entity).is_empty());
+    world.remove_entity(copy);
+    world.flush();
+    assert!(world.body.is_empty());
+    assert_eq!(0, world.entities().filter(aspect!(<BodyComponents> all: [body]), &world).count());
+}
+
+#[test]
+fn change_tracking()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    world.position.track_changes();
+
+    let first = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 0.0, y: 0.0 });
+        c.velocity.add(&e, Velocity { dx: 0.0, dy: 0.0 });
+    });
+    let second = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 0.0, y: 0.0 });
+    });
+    assert_eq!(vec![first, second], world.position.inserted().collect::<Vec<_>>());
+    assert_eq!(0, world.position.modified().count());
+    // Lists that don't track changes never record any
+    assert_eq!(0, world.velocity.inserted().count());
+
+    world.clear_changes();
+    assert_eq!(0, world.position.inserted().count());
+
+    // Reading doesn't count as a change
+    world.with_entity_data(&first, |e, c| {
+        assert_eq!(0.0, c.position[e].x);
+    });
+    assert_eq!(0, world.position.modified().count());
+
+    world.get_mut::<Position>(&second).unwrap().x = 1.0;
+    assert_eq!(vec![second], world.position.modified().collect::<Vec<_>>());
+    assert_eq!(0, world.position.inserted().count());
+
+    world.with_entity_data(&first, |e, c| {
+        c.position[e].y = 1.0;
+    });
+    assert_eq!(vec![first, second], world.position.modified().collect::<Vec<_>>());
+
+    // Removed entities are forgotten
+    world.remove_entity(second);
+    assert_eq!(vec![first], world.position.modified().collect::<Vec<_>>());
+}
+
+#[test]
+fn iterate_mutably_with_split_borrows()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let moving = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 0.0, y: 0.0 });
+        c.velocity.add(&e, Velocity { dx: 2.0, dy: 3.0 });
+    });
+    let still = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 1.0, y: 1.0 });
+    });
+    let removed = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 0.0, y: 0.0 });
+        c.velocity.add(&e, Velocity { dx: 1.0, dy: 1.0 });
+    });
+    world.remove_entity(removed);
+
+    let mut visited = Vec::new();
+    {
+        let components = &mut world.data.components;
+        for (entity, position) in components.position.iter_mut()
+        {
+            visited.push(entity);
+            if let Some(velocity) = components.velocity.lookup(&entity)
+            {
+                position.x += velocity.dx;
+                position.y += velocity.dy;
+            }
+        }
+    }
+    assert_eq!(vec![moving, still], sorted(visited));
+    assert_eq!(Some(&Position { x: 2.0, y: 3.0 }), world.get::<Position>(&moving));
+    assert_eq!(Some(&Position { x: 1.0, y: 1.0 }), world.get::<Position>(&still));
+}
+
+#[test]
+fn join_components()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let mut entities = Vec::new();
+    for i in 0..6
+    {
+        entities.push(world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position { x: i as f32, y: 0.0 });
+            if i % 2 == 0
+            {
+                c.velocity.add(&e, Velocity { dx: 1.0, dy: 0.0 });
+            }
+        }));
+    }
+    world.data.remove_entity(entities[2]);
+    world.disable_entity(entities[4]);
+
+    let mut joined = Vec::new();
+    join!(world, read: [velocity] write: [position], |entity| {
+        position.x += velocity.dx;
+        joined.push(*entity);
+    });
+    assert_eq!(vec![entities[0]], joined);
+    assert_eq!(Some(&Position { x: 1.0, y: 0.0 }), world.get::<Position>(&entities[0]));
+    assert_eq!(Some(&Position { x: 2.0, y: 0.0 }), world.get::<Position>(&entities[2]));
+    assert_eq!(Some(&Position { x: 4.0, y: 0.0 }), world.get::<Position>(&entities[4]));
+
+    let mut total = 0.0;
+    join!(world.data, read: [position], |entity| {
+        assert!(entity.get_index() != entities[4].get_index());
+        total += position.x;
+    });
+    assert_eq!(1.0 + 1.0 + 3.0 + 5.0, total);
+
+    join!(world, write: [velocity, position], |entity| {
+        velocity.dy = position.x;
+    });
+    assert_eq!(Some(&Velocity { dx: 1.0, dy: 1.0 }), world.get::<Velocity>(&entities[0]));
+}
+
+fn assert_shareable<S: Send+Sync>(_: &S) {}
+
+#[test]
+fn read_through_frozen_view()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let moving = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 1.0, y: 0.0 });
+        c.velocity.add(&e, Velocity { dx: 2.0, dy: 0.0 });
+    });
+    let resting = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 3.0, y: 0.0 });
+    });
+    let disabled = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 5.0, y: 0.0 });
+    });
+    world.disable_entity(disabled);
+
+    let view: WorldView<TestComponents> = world.freeze();
+    assert_shareable(&view);
+    assert!(view.is_valid(&resting));
+    assert_eq!(3, view.entity_count());
+    assert_eq!(vec![moving, resting], view.entities().map(|entity| *entity).collect::<Vec<_>>());
+    assert_eq!(Some(&Velocity { dx: 2.0, dy: 0.0 }), view.get::<Velocity>(&moving));
+    assert_eq!(None, view.get::<Velocity>(&resting));
+    assert_eq!(Some(&Position { x: 3.0, y: 0.0 }), view.position.lookup(&resting));
+    assert_eq!(1, view.query::<(Position, Velocity)>().count());
+
+    let mut total = 0.0;
+    join!(view, read: [position], |entity| {
+        total += position.x;
+    });
+    assert_eq!(4.0, total);
+
+    // Any number of views can be read at once
+    let other = world.freeze();
+    assert_eq!(view.entities().count(), other.entities().count());
+}
+
+#[test]
+fn query_components_by_type()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let mut entities = Vec::new();
+    for i in 0..6
+    {
+        entities.push(world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position { x: i as f32, y: 0.0 });
+            if i % 2 == 0
+            {
+                c.velocity.add(&e, Velocity { dx: 1.0, dy: 0.0 });
+            }
+        }));
+    }
+    world.data.remove_entity(entities[2]);
+    world.disable_entity(entities[4]);
+
+    // Shared queries can be used alongside other shared borrows of the world
+    let mut queried = Vec::new();
+    for entity in world.entities()
+    {
+        for (other, position, velocity) in world.query::<(Position, Velocity)>()
+        {
+            if other == *entity
+            {
+                queried.push((other, *position, *velocity));
+            }
+        }
+    }
+    assert_eq!(vec![(entities[0], Position { x: 0.0, y: 0.0 }, Velocity { dx: 1.0, dy: 0.0 })], queried);
+    assert_eq!(4, world.query::<(Position,)>().count());
+
+    for (_, position, velocity) in world.query_mut::<(Position, Velocity)>()
+    {
+        position.x += velocity.dx;
+        velocity.dy = position.x;
+    }
+    assert_eq!(Some(&Position { x: 1.0, y: 0.0 }), world.get::<Position>(&entities[0]));
+    assert_eq!(Some(&Velocity { dx: 1.0, dy: 1.0 }), world.get::<Velocity>(&entities[0]));
+    assert_eq!(Some(&Position { x: 4.0, y: 0.0 }), world.get::<Position>(&entities[4]));
+
+    // Items can be kept for as long as the world is borrowed
+    let positions: Vec<&Position> = world.query::<(Position,)>().map(|(_, position)| position).collect();
+    assert_eq!(Position { x: 5.0, y: 0.0 }, *positions[3]);
+}
+
+#[test]
+#[should_panic(expected = "same component type twice")]
+fn query_same_type_twice_mutably()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    world.query_mut::<(Position, Position)>();
+}
+
+#[test]
+fn join_optional_components()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let mut entities = Vec::new();
+    for i in 0..4
+    {
+        entities.push(world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position { x: 0.0, y: 0.0 });
+            if i % 2 == 0
+            {
+                c.velocity.add(&e, Velocity { dx: i as f32, dy: 0.0 });
+            }
+            if i == 3
+            {
+                c.frozen.add(&e, Frozen);
+            }
+        }));
+    }
+
+    let mut seen = Vec::new();
+    join!(world, write: [position] maybe: [velocity, frozen], |entity| {
+        if let Some(velocity) = velocity
+        {
+            position.x += velocity.dx;
+        }
+        seen.push((*entity, velocity.is_some(), frozen.is_some()));
+    });
+    seen.sort_by(|a, b| a.0.get_index().cmp(&b.0.get_index()));
+    assert_eq!(vec![(entities[0], true, false), (entities[1], false, false),
+                    (entities[2], true, false), (entities[3], false, true)], seen);
+    assert_eq!(Some(&Position { x: 2.0, y: 0.0 }), world.get::<Position>(&entities[2]));
+
+    let mut count = 0;
+    join!(world, read: [frozen] write: [position] maybe: [velocity], |entity| {
+        assert!(velocity.is_none());
+        position.y = 1.0;
+        count += 1;
+    });
+    assert_eq!(1, count);
+    assert_eq!(Some(&Position { x: 0.0, y: 1.0 }), world.get::<Position>(&entities[3]));
+}
+
+#[test]
+fn default_components()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let plain = world.create_entity(defaults::<(Position, Velocity)>());
+    assert_eq!(Some(&Position { x: 0.0, y: 0.0 }), world.get::<Position>(&plain));
+    assert_eq!(Some(&Velocity { dx: 0.0, dy: 0.0 }), world.get::<Velocity>(&plain));
+    assert_eq!(None, world.get::<Frozen>(&plain));
+
+    let adjusted = world.create_entity(defaults::<(Position, Velocity, Frozen)>().then(
+        |e: BuildData, c: &mut TestComponents| {
+            c.velocity.add(&e, Velocity { dx: 1.0, dy: 0.0 });
+        }
+    ));
+    assert_eq!(Some(&Position { x: 0.0, y: 0.0 }), world.get::<Position>(&adjusted));
+    assert_eq!(Some(&Velocity { dx: 1.0, dy: 0.0 }), world.get::<Velocity>(&adjusted));
+    assert_eq!(Some(&Frozen), world.get::<Frozen>(&adjusted));
+
+    let single = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add_default(&e).y = 4.0;
+    });
+    assert_eq!(Some(&Position { x: 0.0, y: 4.0 }), world.get::<Position>(&single));
+    assert_eq!(None, world.get::<Velocity>(&single));
+}
+
+#[test]
+fn tuple_builders()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let moving = world.create_entity((Position { x: 1.0, y: 2.0 }, Velocity { dx: 3.0, dy: 0.0 }));
+    assert_eq!(Some(&Position { x: 1.0, y: 2.0 }), world.get::<Position>(&moving));
+    assert_eq!(Some(&Velocity { dx: 3.0, dy: 0.0 }), world.get::<Velocity>(&moving));
+    assert_eq!(None, world.get::<Frozen>(&moving));
+
+    world.data.create_entity((Frozen,));
+    world.flush();
+    assert_eq!(1, world.frozen.len());
+}
+
+#[test]
+fn combine_builders_and_modifiers()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let prefab = |e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 1.0, y: 1.0 });
+        c.velocity.add(&e, Velocity { dx: 1.0, dy: 0.0 });
+    };
+    let entity = world.create_entity(prefab.and((Velocity { dx: 5.0, dy: 0.0 },))
+        .map_after(|e, c| { c.position.add(&e, Position { x: 1.0, y: 3.0 }); }));
+    assert_eq!(Some(&Position { x: 1.0, y: 3.0 }), world.get::<Position>(&entity));
+    assert_eq!(Some(&Velocity { dx: 5.0, dy: 0.0 }), world.get::<Velocity>(&entity));
+
+    let stop = |e: ModifyData, c: &mut TestComponents| { c.velocity.remove(&e); };
+    world.modify_entity(entity, stop.and(|e: ModifyData, c: &mut TestComponents| { c.frozen.insert(&e, Frozen); })
+        .map_after(|e, c| { c.position[e].x = 0.0; }));
+    assert_eq!(Some(&Position { x: 0.0, y: 3.0 }), world.get::<Position>(&entity));
+    assert_eq!(None, world.get::<Velocity>(&entity));
+    assert_eq!(Some(&Frozen), world.get::<Frozen>(&entity));
+}
+
+#[test]
+fn modifiers_read_existing_components()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity((Position { x: 1.0, y: 0.0 }, Velocity { dx: 2.0, dy: 0.0 }));
+    world.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        assert_eq!(entity, e.entity());
+        let velocity = c.velocity.get(&e).unwrap();
+        c.position[e].x += velocity.dx;
+        if c.position.has(&e) && !c.frozen.has(&e)
+        {
+            c.velocity.remove(&e);
+        }
+    });
+    assert_eq!(Some(&Position { x: 3.0, y: 0.0 }), world.get::<Position>(&entity));
+    assert_eq!(None, world.get::<Velocity>(&entity));
+}
+
+#[test]
+fn read_components_added_earlier_in_build()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let place = |e: BuildData, c: &mut TestComponents| {
+        assert_eq!(None, c.position.get(&e));
+        c.position.add(&e, Position { x: 2.0, y: 3.0 });
+    };
+    let entity = world.create_entity(place.map_after(|e, c| {
+        assert!(!c.velocity.has(&e));
+        let position = c.position[e];
+        c.velocity.add(&e, Velocity { dx: position.x, dy: position.y });
+    }));
+    assert_eq!(Some(&Velocity { dx: 2.0, dy: 3.0 }), world.get::<Velocity>(&entity));
+}
+
+#[test]
+fn pooled_components()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    world.path.enable_pooling();
+
+    let spawn = |world: &mut World<TestComponents, TestSystems>| {
+        world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.path.add_pooled(&e).0.push(Position { x: 1.0, y: 1.0 });
+        })
+    };
+
+    let first = spawn(&mut world);
+    let second = spawn(&mut world);
+    assert_eq!(PoolStats { available: 0, reused: 0, created: 2 }, world.path.pool_stats());
+
+    world.remove_entity(first);
+    world.modify_entity(second, |e: ModifyData, c: &mut TestComponents| {
+        c.path.remove(&e);
+    });
+    // Only components of removed entities are pooled
+    assert_eq!(PoolStats { available: 1, reused: 0, created: 2 }, world.path.pool_stats());
+
+    let third = spawn(&mut world);
+    assert_eq!(PoolStats { available: 0, reused: 1, created: 2 }, world.path.pool_stats());
+    world.with_entity_data(&third, |e, c| {
+        // The pooled path was reset and kept its allocation
+        assert_eq!(vec![Position { x: 1.0, y: 1.0 }], c.path[e].0);
+        assert!(c.path[e].0.capacity() >= 1);
+    });
+
+    world.clear();
+    assert_eq!(1, world.path.pool_stats().available);
+    world.path.shrink_pool();
+    assert_eq!(0, world.path.pool_stats().available);
+}
+
+fn apply_velocities(components: &ComponentBorrows<TestComponents>)
+{
+    let mut positions = components.borrow_mut::<Position>();
+    let velocities = components.borrow::<Velocity>();
+    for (entity, position) in positions.iter_mut()
+    {
+        if let Some(velocity) = velocities.lookup(&entity)
+        {
+            position.x += velocity.dx;
+        }
+    }
+}
+
+#[test]
+fn borrow_several_lists()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 0.0, y: 0.0 });
+        c.velocity.add(&e, Velocity { dx: 2.0, dy: 0.0 });
+    });
+    {
+        let components = world.borrow_components();
+        apply_velocities(&components);
+        apply_velocities(&components);
+        // Borrows are released when dropped
+        let first = components.borrow::<Velocity>();
+        let second = components.borrow::<Velocity>();
+        assert_eq!(first.len(), second.len());
+        // Unchecked borrows don't count, so the caller has to keep them apart
+        let positions = unsafe { components.borrow_mut_unchecked::<Position>() };
+        assert_eq!(1, positions.len());
+    }
+    assert_eq!(Some(&Position { x: 4.0, y: 0.0 }), world.get::<Position>(&entity));
+}
+
+#[test]
+#[should_panic(expected = "Position")]
+fn borrowing_list_twice_panics()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let components = world.borrow_components();
+    let _first = components.borrow::<Position>();
+    let _second = components.borrow_mut::<Position>();
+}
+
+#[test]
+fn component_masks()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 0.0, y: 0.0 });
+        c.frozen.add(&e, Frozen);
+    });
+    let moving = world.position.mask() | world.velocity.mask();
+    assert_eq!(world.position.mask() | world.frozen.mask(), world.mask_of(&entity));
+    assert!(!world.mask_of(&entity).contains(moving));
+    assert!(world.mask_of(&entity).intersects(moving));
+
+    world.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.velocity.insert(&e, Velocity { dx: 1.0, dy: 0.0 });
+        c.frozen.remove(&e);
+    });
+    assert_eq!(moving, world.mask_of(&entity));
+
+    world.data.remove_component::<Velocity>(entity);
+    world.update();
+    assert_eq!(world.position.mask(), world.mask_of(&entity));
+
+    world.remove_entity(entity);
+    assert!(world.mask_of(&entity).is_empty());
+    assert_eq!(ComponentMask::empty(), world.mask_of(&entity));
+}
+
+#[test]
+fn component_mask_follows_direct_changes()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(());
+    world.with_entity_data(&entity, |e, c| {
+        c.velocity.set(&e, Velocity { dx: 1.0, dy: 0.0 });
+    });
+    assert_eq!(world.velocity.mask(), world.mask_of(&entity));
+
+    world.with_entity_data(&entity, |e, c| {
+        c.frozen.set(&e, Frozen);
+    });
+    assert_eq!(world.velocity.mask() | world.frozen.mask(), world.mask_of(&entity));
+
+    // Flushing stores the changes in the manager's masks, so the lists aren't looked in again
+    world.flush();
+    assert_eq!(world.velocity.mask() | world.frozen.mask(), world.__masks.get(&entity));
+    world.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.velocity.remove(&e);
+    });
+    assert_eq!(world.frozen.mask(), world.mask_of(&entity));
+    world.remove_entity(entity);
+    assert_eq!(ComponentMask::empty(), world.__masks.get(&entity));
+}
+
+#[test]
+fn shared_components()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let drops = Arc::new(AtomicUsize::new(0));
+    let stats = Shared::new(Stats { health: 10, drops: drops.clone() });
+
+    let entities: Vec<Entity> = (0..3).map(|_| world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.stats.add(&e, stats.clone());
+    })).collect();
+    drop(stats);
+    assert_eq!(vec![10, 10, 10], world.stats.iter().map(|(_, s)| s.health).collect::<Vec<_>>());
+
+    world.modify_entity(entities[0], |e: ModifyData, c: &mut TestComponents| {
+        c.stats.make_mut(&e).unwrap().health = 5;
+    });
+    world.with_entity_data(&entities[1], |e, c| {
+        assert_eq!(10, c.stats[e].health);
+        assert!(c.stats[e].ptr_eq(&c.stats.lookup(&entities[2]).unwrap()));
+        assert!(!c.stats[e].ptr_eq(&c.stats.lookup(&entities[0]).unwrap()));
+    });
+    assert_eq!(5, world.stats.lookup(&entities[0]).unwrap().health);
+
+    // Only the copy made by make_mut has been dropped
+    world.remove_entity(entities[0]);
+    world.remove_entity(entities[1]);
+    world.update();
+    assert_eq!(1, drops.load(Ordering::SeqCst));
+
+    world.remove_entity(entities[2]);
+    world.update();
+    assert_eq!(2, drops.load(Ordering::SeqCst));
+}
+
+#[test]
+fn dynamic_component_access()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let position = TypeId::of::<Position>();
+    let velocity = TypeId::of::<Velocity>();
+    assert!(world.components.component_types().contains(&("velocity", velocity)));
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: 1.0, y: 2.0 });
+    });
+    assert_eq!(Some(&Position { x: 1.0, y: 2.0 }),
+        world.get_dyn(&entity, position).and_then(|c| c.downcast_ref::<Position>()));
+    assert!(world.get_dyn(&entity, velocity).is_none());
+    assert!(world.get_dyn(&entity, TypeId::of::<String>()).is_none());
+
+    world.data.get_dyn_mut(&entity, position).unwrap().downcast_mut::<Position>().unwrap().x = 3.0;
+    assert_eq!(3.0, world.position.lookup(&entity).unwrap().x);
+
+    // Setting components keeps the entity's mask up to date
+    world.set_dyn(entity, Box::new(Velocity { dx: 1.0, dy: 0.0 })).unwrap();
+    assert_eq!(world.position.mask() | world.velocity.mask(), world.mask_of(&entity));
+    world.update();
+    assert_eq!(Some(&Position { x: 4.0, y: 2.0 }), world.get::<Position>(&entity));
+
+    world.data.set_dyn(entity, Box::new(Frozen)).unwrap();
+    assert!(!world.mask_of(&entity).contains(world.frozen.mask()));
+    world.update();
+    assert!(world.mask_of(&entity).contains(world.frozen.mask()));
+
+    let rejected = world.data.set_dyn(entity, Box::new(5u32)).unwrap_err();
+    assert_eq!(Some(&5), rejected.downcast_ref::<u32>());
+    assert!(world.set_dyn(entity, Box::new("not a component") as Box<Any>).is_err());
+}
+
+#[test]
+fn marker_components()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entities: Vec<Entity> = (0..150).map(|i| world.create_entity(move |e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: i as f32, y: 0.0 });
+        if i % 3 == 0
+        {
+            c.frozen.add(&e, Frozen);
+        }
+    })).collect();
+    let frozen: Vec<Entity> = entities.iter().cloned().filter(|e| e.get_index() % 3 == 0).collect();
+    assert_eq!(50, world.frozen.len());
+    assert_eq!(frozen, stored_entities(world.frozen.storage()));
+
+    world.data.modify_entity(entities[0], |e: ModifyData, c: &mut TestComponents| {
+        c.frozen.remove(&e);
+    });
+    world.data.add_component(entities[1], Frozen);
+    world.data.remove_entity(entities[99]);
+    world.update();
+    assert!(!world.frozen.lookup(&entities[0]).is_some());
+    assert!(world.frozen.lookup(&entities[1]).is_some());
+    assert!(!world.frozen.lookup(&entities[99]).is_some());
+    assert_eq!(49, world.frozen.len());
+
+    // The index of a removed entity can be reused without the new entity inheriting its marker
+    let replacement = world.create_entity(());
+    assert_eq!(entities[99].get_index(), replacement.get_index());
+    assert_eq!(None, world.get::<Frozen>(&replacement));
+    world.modify_entity(replacement, |e: ModifyData, c: &mut TestComponents| {
+        c.frozen.insert(&e, Frozen);
+    });
+    assert_eq!(None, world.frozen.lookup(&entities[99]));
+    assert_eq!(Some(&Frozen), world.frozen.lookup(&replacement));
+
+    let mut total = 0.0;
+    join!(world, read: [frozen, position], |entity| {
+        total += position.x;
+    });
+    let expected = (3..150).filter(|i| i % 3 == 0 && *i != 99).fold(1, |total, i| total + i);
+    assert_eq!(expected as f32, total);
+
+    world.clear();
+    assert_eq!(0, world.frozen.len());
+    assert_eq!(0, world.frozen.iter().count());
+}
+
+#[test]
+#[should_panic(expected = "while a null list is being iterated")]
+fn create_entity_while_iterating_markers()
+{
+    let mut entities = EntityManager::new();
+    let generations = entities.share_generations().share();
+    entities.create();
+
+    // Iterating a null list holds the generations, so it has to finish before entities change
+    let _iterating = generations.read();
+    entities.create();
+}
+
+#[test]
+fn component_statistics()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let count = |world: &World<TestComponents, TestSystems>, name: &str| {
+        world.components.stats().into_iter().find(|stats| stats.name == name).unwrap().count
+    };
+
+    let entities: Vec<Entity> = (0..10).map(|i| world.create_entity(move |e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position { x: i as f32, y: 0.0 });
+        if i % 2 == 0
+        {
+            c.velocity.add(&e, Velocity { dx: 1.0, dy: 0.0 });
+            c.frozen.add(&e, Frozen);
+        }
+    })).collect();
+
+    let stats = world.components.stats();
+    assert_eq!(vec!["position", "velocity", "frozen", "path", "stats"],
+        stats.iter().map(|stats| stats.name).collect::<Vec<_>>());
+    assert_eq!(vec![10, 5, 5, 0, 0], stats.iter().map(|stats| stats.count).collect::<Vec<_>>());
+    assert!(stats[0].type_name.ends_with("Position"));
+    assert!(stats[0].capacity >= 10);
+    assert!(stats[0].bytes >= 10 * std::mem::size_of::<Position>());
+
+    world.remove_entity(entities[0]);
+    world.data.remove_entity(entities[1]);
+    world.data.remove_component::<Velocity>(entities[2]);
+    assert_eq!(9, count(&world, "position"));
+    assert_eq!(4, count(&world, "velocity"));
+    world.update();
+    assert_eq!(8, count(&world, "position"));
+    assert_eq!(3, count(&world, "velocity"));
+    assert_eq!(4, count(&world, "frozen"));
+
+    world.clear();
+    assert!(world.components.stats().iter().all(|stats| stats.count == 0));
+}
@@ -0,0 +1,1774 @@
+
+#![feature(std_misc)]
+
+#[macro_use]
+extern crate ecs;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::rc::Rc;
+
+use ecs::{BuildData, ModifyData, EntityData};
+use ecs::{Entity, EntityIter, InvalidEntity, QueuedEvent, UnknownPrefab, WeakEntity, World, DataHelper};
+use ecs::{Process, SaveableComponent, System};
+use ecs::save::{Reader, Writer};
+use ecs::system::{EntityProcess, EntitySystem};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Health(i32);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Name(String);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Target(Entity);
+
+impl SaveableComponent for Health
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()> { writer.write(&self.0) }
+    fn load(reader: &mut Reader) -> io::Result<Health> { reader.read().map(Health) }
+}
+
+impl SaveableComponent for Target
+{
+    fn save(&self, writer: &mut Writer) -> io::Result<()> { writer.write(&self.0) }
+    fn load(reader: &mut Reader) -> io::Result<Target> { reader.read().map(Target) }
+}
+
+components! {
+    TestComponents {
+        #[hot] health: Health,
+        #[cold] name: Name,
+        #[cold] target: Target
+    }
+}
+
+saveable_components! {
+    TestComponents {
+        health,
+        target
+    }
+}
+
+cloneable_components! {
+    TestComponents {
+        health,
+        target
+    }
+}
+
+component_access! {
+    TestComponents {
+        health: Health,
+        name: Name,
+        target: Target
+    }
+}
+
+systems! {
+    TestSystems<TestComponents> {
+        counter: Counter = Counter { activated: 0, deactivated: Vec::new() },
+        order: EntitySystem<RecordOrder> = EntitySystem::new(RecordOrder(Vec::new()),
+                aspect!(<TestComponents> all: [health]))
+    }
+}
+
+systems! {
+    StrictSystems<TestComponents> {
+        strict: Strict = Strict { active: HashSet::new(), log: Vec::new() }
+    }
+}
+
+systems! {
+    ReaperSystems<TestComponents> {
+        damage: Reaper = Reaper(Vec::new()),
+        lifetime: Reaper = Reaper(Vec::new()),
+        strict: Strict = Strict { active: HashSet::new(), log: Vec::new() }
+    }
+}
+
+pub struct RecordOrder(pub Vec<Entity>);
+impl EntityProcess for RecordOrder
+{
+    fn process(&mut self, en: EntityIter<TestComponents>, _: &mut DataHelper<TestComponents>)
+    {
+        self.0 = en.map(|e| *e).collect();
+    }
+}
+impl System for RecordOrder { type Components = TestComponents; }
+
+pub struct Counter
+{
+    pub activated: usize,
+    pub deactivated: Vec<Entity>,
+}
+impl Process for Counter
+{
+    fn process(&mut self, _: &mut DataHelper<TestComponents>)
+    {
+
+    }
+}
+impl System for Counter
+{
+    type Components = TestComponents;
+    fn activated(&mut self, _: &EntityData, _: &TestComponents)
+    {
+        self.activated += 1;
+    }
+    fn deactivated(&mut self, e: &EntityData, _: &TestComponents)
+    {
+        self.deactivated.push(**e);
+    }
+}
+
+// Removes every entity that has run out of health, remembering the ones it was told about
+pub struct Reaper(pub Vec<Entity>);
+impl Process for Reaper
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        let dead: Vec<Entity> = data.components.health.iter()
+            .filter(|&(_, health)| health.0 <= 0).map(|(entity, _)| entity).collect();
+        for entity in dead.into_iter()
+        {
+            data.remove_entity(entity);
+        }
+    }
+}
+impl System for Reaper
+{
+    type Components = TestComponents;
+    fn deactivated(&mut self, e: &EntityData, _: &TestComponents)
+    {
+        self.0.push(**e);
+    }
+}
+
+// Panics if it is told about entities in an order that doesn't make sense.
+pub struct Strict
+{
+    pub active: HashSet<Entity>,
+    pub log: Vec<(&'static str, Entity)>,
+}
+impl Process for Strict
+{
+    fn process(&mut self, _: &mut DataHelper<TestComponents>)
+    {
+
+    }
+}
+impl System for Strict
+{
+    type Components = TestComponents;
+    fn activated(&mut self, e: &EntityData, _: &TestComponents)
+    {
+        assert!(self.active.insert(**e), "{:?} was activated twice", **e);
+        self.log.push(("activated", **e));
+    }
+    fn reactivated(&mut self, e: &EntityData, _: &TestComponents)
+    {
+        assert!(self.active.contains(&**e), "{:?} was reactivated before being activated", **e);
+        self.log.push(("reactivated", **e));
+    }
+    fn deactivated(&mut self, e: &EntityData, _: &TestComponents)
+    {
+        assert!(self.active.remove(&**e), "{:?} was deactivated without being activated", **e);
+        self.log.push(("deactivated", **e));
+    }
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_id_accessors()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let entity = world.create_entity(());
+    let id: ecs::entity::Id = entity.get_id();
+    assert_eq!(entity.get_generation(), id);
+}
+
+#[test]
+fn stale_handles_never_resolve()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let mut old = Vec::new();
+    for i in 0..20
+    {
+        let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.health.add(&e, Health(i));
+        });
+        world.remove_entity(entity);
+        old.push(entity);
+
+        let replacement = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.health.add(&e, Health(-1));
+        });
+        assert_eq!(entity.get_index(), replacement.get_index());
+        assert!(entity.get_generation() < replacement.get_generation());
+
+        for stale in old.iter()
+        {
+            assert!(world.with_entity_data(stale, |_, _| ()).is_none());
+        }
+        assert!(world.with_entity_data(&replacement, |_, _| ()).is_some());
+        world.remove_entity(replacement);
+    }
+}
+
+#[test]
+fn stale_queued_modify_is_dropped()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(10));
+    });
+    world.data.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.health.insert(&e, Health(0));
+    });
+    world.remove_entity(entity);
+
+    let replacement = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(20));
+    });
+    assert_eq!(entity.get_index(), replacement.get_index());
+
+    world.update();
+    world.with_entity_data(&replacement, |e, c| {
+        assert_eq!(Health(20), c.health[e]);
+    });
+}
+
+#[test]
+fn stale_immediate_modify_is_dropped()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(10));
+    });
+    world.remove_entity(entity);
+    let replacement = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(20));
+    });
+
+    world.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.health.insert(&e, Health(0));
+    });
+    world.with_entity_data(&replacement, |e, c| {
+        assert_eq!(Health(20), c.health[e]);
+    });
+}
+
+#[test]
+fn validity_and_pending_removal()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(());
+    assert!(world.is_valid(&entity));
+    assert!(!world.is_pending_removal(&entity));
+
+    world.data.remove_entity(entity);
+    assert!(world.is_valid(&entity));
+    assert!(world.is_pending_removal(&entity));
+
+    world.update();
+    assert!(!world.is_valid(&entity));
+    assert!(!world.is_pending_removal(&entity));
+    assert!(!world.is_valid(&Entity::nil()));
+}
+
+#[test]
+fn deferred_world_changes()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let entities: Vec<Entity> = (0..3).map(|i| world.create_entity(health(i))).collect();
+
+    for entity in entities.iter()
+    {
+        world.modify_entity_deferred(*entity, |e: ModifyData, c: &mut TestComponents| {
+            c.health[e].0 += 10;
+        });
+    }
+    world.remove_entity_deferred(entities[1]);
+    assert_eq!(Some(&Health(0)), world.get::<Health>(&entities[0]));
+    assert!(world.is_pending_removal(&entities[1]));
+    assert!(world.systems.counter.deactivated.is_empty());
+
+    world.flush();
+    assert_eq!(Some(&Health(10)), world.get::<Health>(&entities[0]));
+    assert_eq!(Some(&Health(12)), world.get::<Health>(&entities[2]));
+    assert!(!world.is_valid(&entities[1]));
+    assert_eq!(vec![entities[1]], world.systems.counter.deactivated);
+}
+
+#[test]
+fn clone_entity_copies_components()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let source = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(7));
+        c.name.add(&e, Name("grunt".to_string()));
+    });
+    let copy = world.clone_entity(source).unwrap();
+    assert!(copy != source);
+    assert_eq!(2, world.systems.counter.activated);
+
+    world.with_entity_data(&copy, |e, c| {
+        assert_eq!(Health(7), c.health[e]);
+        assert_eq!(Some(Name("grunt".to_string())), c.name.get(&e));
+        c.health[e] = Health(1);
+    });
+    world.with_entity_data(&source, |e, c| {
+        assert_eq!(Health(7), c.health[e]);
+    });
+
+    world.remove_entity(source);
+    assert!(world.clone_entity(source).is_none());
+}
+
+#[test]
+fn named_entities()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let player = world.create_entity(());
+    let camera = world.create_entity(());
+    world.set_name(player, "player");
+    world.set_name(camera, "camera");
+    assert_eq!(Some(player), world.entity_named("player"));
+    assert_eq!(Some("camera"), world.name_of(&camera));
+
+    // Renaming replaces the old mapping
+    world.set_name(player, "hero");
+    assert_eq!(None, world.entity_named("player"));
+    assert_eq!(Some(player), world.entity_named("hero"));
+
+    // Taking a name from another entity
+    world.set_name(player, "camera");
+    assert_eq!(Some(player), world.entity_named("camera"));
+    assert_eq!(None, world.name_of(&camera));
+
+    // Removal through the event queue clears the name, even if the index is reused
+    world.data.remove_entity(player);
+    world.update();
+    let reused = world.create_entity(());
+    assert_eq!(player.get_index(), reused.get_index());
+    assert_eq!(None, world.entity_named("camera"));
+    assert_eq!(None, world.name_of(&reused));
+}
+
+pub struct CameraTag;
+pub struct PlayerTag;
+
+#[test]
+fn singleton_tags()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let first = world.create_entity(());
+    let second = world.create_entity(());
+    assert_eq!(None, world.tagged::<CameraTag>());
+
+    world.set_tag::<CameraTag>(first);
+    world.set_tag::<PlayerTag>(first);
+    assert_eq!(Some(first), world.tagged::<CameraTag>());
+
+    // Tags move to the most recently assigned entity
+    world.set_tag::<CameraTag>(second);
+    assert_eq!(Some(second), world.tagged::<CameraTag>());
+    assert_eq!(Some(first), world.tagged::<PlayerTag>());
+
+    world.data.remove_entity(first);
+    world.update();
+    assert_eq!(None, world.tagged::<PlayerTag>());
+    assert_eq!(Some(second), world.tagged::<CameraTag>());
+
+    assert_eq!(Some(second), world.remove_tag::<CameraTag>());
+    assert_eq!(None, world.tagged::<CameraTag>());
+}
+
+#[test]
+fn hierarchy_cascading_removal()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let root = world.create_entity(());
+    let a = world.create_entity(());
+    let b = world.create_entity(());
+    let a1 = world.create_entity(());
+    world.set_parent(a, root);
+    world.set_parent(b, root);
+    world.set_parent(a1, a);
+    assert_eq!(vec![a, b], world.children_of(&root).collect::<Vec<_>>());
+    assert_eq!(Some(a), world.parent_of(&a1));
+
+    // Reparenting detaches from the old parent
+    world.set_parent(b, a);
+    world.set_parent(b, root);
+    assert_eq!(vec![a1], world.children_of(&a).collect::<Vec<_>>());
+
+    // A child removed individually in the same frame is only deactivated once
+    world.data.remove_entity(a1);
+    world.data.remove_entity_recursive(root);
+    world.update();
+    assert_eq!(vec![a1, a, b, root], world.systems.counter.deactivated);
+    for e in [root, a, b, a1].iter()
+    {
+        assert!(!world.is_valid(e));
+    }
+    assert_eq!(0, world.children_of(&root).count());
+}
+
+#[test]
+fn hierarchy_immediate_removal()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let root = world.create_entity(());
+    let child = world.create_entity(());
+    let grandchild = world.create_entity(());
+    world.set_parent(child, root);
+    world.set_parent(grandchild, child);
+
+    // Non-recursive removal orphans the children
+    world.remove_entity(child);
+    assert_eq!(None, world.parent_of(&grandchild));
+    assert!(world.is_valid(&grandchild));
+
+    world.set_parent(grandchild, root);
+    world.remove_entity_recursive(root);
+    assert_eq!(vec![child, grandchild, root], world.systems.counter.deactivated);
+}
+
+#[test]
+#[should_panic]
+fn hierarchy_rejects_cycles()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let parent = world.create_entity(());
+    let child = world.create_entity(());
+    world.set_parent(child, parent);
+    world.set_parent(parent, child);
+}
+
+#[test]
+fn entity_counts()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    assert_eq!(0, world.entity_count());
+
+    let a = world.create_entity(());
+    let b = world.data.create_entity(());
+    assert_eq!(2, world.entity_count());
+    assert_eq!(1, world.pending_events());
+
+    world.update();
+    assert_eq!(0, world.pending_events());
+
+    world.data.remove_entity(a);
+    assert_eq!(2, world.entity_count());
+    assert_eq!(1, world.pending_events());
+
+    world.update();
+    assert_eq!(1, world.entity_count());
+    assert_eq!(2, world.entity_capacity());
+
+    world.create_entity(());
+    assert_eq!(2, world.entity_count());
+    assert_eq!(2, world.entity_capacity());
+
+    world.remove_entity(b);
+    assert_eq!(1, world.entity_count());
+}
+
+#[test]
+fn flush_by_hand()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let entity = world.data.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(3));
+        c.name.add(&e, Name("scene".to_string()));
+    });
+    assert_eq!(None, world.data.get::<Health>(&entity));
+
+    // Flushing builds the scene straight away, without processing any systems
+    world.flush();
+    assert_eq!(0, world.pending_events());
+    assert_eq!(Some(&Health(3)), world.data.get::<Health>(&entity));
+    assert_eq!(Some(&Name("scene".to_string())), world.data.get::<Name>(&entity));
+    assert_eq!(1, world.systems.counter.activated);
+    assert!(world.systems.order.0.is_empty());
+
+    world.data.remove_entity(entity);
+    world.flush();
+    assert!(!world.is_valid(&entity));
+    assert_eq!(vec![entity], world.systems.counter.deactivated);
+
+    // Flushing an empty queue does nothing
+    world.flush();
+    assert_eq!(1, world.systems.counter.activated);
+}
+
+#[test]
+fn flush_applies_changes_queued_while_flushing()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    world.data.create_entity_then(health(1), |_, data: &mut DataHelper<TestComponents>| {
+        data.create_entity(health(2));
+    });
+    world.flush();
+
+    // The second builder was queued by the first one's callback, but still ran in the same flush
+    assert_eq!(0, world.pending_events());
+    assert_eq!(2, world.entity_count());
+    let mut healths = world.entities().filter_map(|e| world.get::<Health>(&e).map(|h| h.0)).collect::<Vec<_>>();
+    healths.sort();
+    assert_eq!(vec![1, 2], healths);
+    assert_eq!(2, world.systems.counter.activated);
+    assert!(world.systems.order.0.is_empty());
+}
+
+fn health(value: i32) -> Box<FnMut(BuildData, &mut TestComponents)>
+{
+    Box::new(move |e: BuildData, c: &mut TestComponents| { c.health.add(&e, Health(value)); })
+}
+
+#[test]
+fn queue_modify_before_build()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let entity = world.data.create_entity(health(1));
+    world.data.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.health.insert(&e, Health(2));
+        c.name.insert(&e, Name("built".to_string()));
+    });
+    world.data.add_component(entity, Target(entity));
+    world.flush();
+    assert_eq!(Some(&Health(2)), world.get::<Health>(&entity));
+    assert_eq!(Some(&Target(entity)), world.get::<Target>(&entity));
+    assert_eq!(vec![("activated", entity), ("reactivated", entity), ("reactivated", entity)],
+               world.systems.strict.log);
+}
+
+#[test]
+fn queue_modify_before_reserved_build()
+{
+    // Changes queued before a reserved entity has a builder wait for the build
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let reserved = world.reserve_ids(2);
+    world.data.modify_entity(reserved[0], |e: ModifyData, c: &mut TestComponents| {
+        c.health.insert(&e, Health(2));
+    });
+    world.data.add_component(reserved[0], Target(reserved[1]));
+    world.flush();
+    assert!(world.get::<Health>(&reserved[0]).is_none());
+    assert_eq!(Ok(()), world.data.build_reserved(reserved[0], health(1)));
+    world.flush();
+    assert_eq!(Some(&Health(2)), world.get::<Health>(&reserved[0]));
+    assert_eq!(Some(&Target(reserved[1])), world.get::<Target>(&reserved[0]));
+
+    // Removing a reserved entity drops the changes held back for it
+    world.data.add_component(reserved[1], Target(reserved[0]));
+    world.remove_entity(reserved[1]);
+    world.flush();
+    assert!(!world.is_valid(&reserved[1]));
+    assert_eq!(vec![("activated", reserved[0]), ("reactivated", reserved[0])], world.systems.strict.log);
+}
+
+#[test]
+fn queue_changes_after_removal()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let entity = world.create_entity(health(1));
+    world.data.remove_entity(entity);
+    world.data.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.name.insert(&e, Name("too late".to_string()));
+    });
+    world.data.add_component(entity, Target(entity));
+    world.data.disable_entity(entity);
+    world.data.remove_entity(entity);
+    world.flush();
+    assert!(!world.is_valid(&entity));
+    assert_eq!(vec![("activated", entity), ("deactivated", entity)], world.systems.strict.log);
+}
+
+#[test]
+fn remove_entity_from_several_systems()
+{
+    let mut world = World::<TestComponents, ReaperSystems>::new();
+    let alive = world.create_entity(health(1));
+    let dead = world.create_entity(health(0));
+    world.update();
+    assert!(world.is_valid(&alive) && !world.is_valid(&dead));
+    assert_eq!(vec![dead], world.systems.damage.0);
+    assert_eq!(vec![dead], world.systems.lifetime.0);
+    assert_eq!(vec![("activated", alive), ("activated", dead), ("deactivated", dead)],
+               world.systems.strict.log);
+
+    // Removing an entity straight away drops the removal already queued for it
+    let dead = world.create_entity(health(0));
+    world.data.remove_entity(dead);
+    world.remove_entity(dead);
+    world.update();
+    assert_eq!(2, world.systems.damage.0.len());
+    assert_eq!(2, world.systems.lifetime.0.len());
+    assert_eq!(5, world.systems.strict.log.len());
+}
+
+// Catches the panic of a builder, so the test can check the world it was unwound through
+fn panics<F>(world: &mut World<TestComponents, StrictSystems>, call: F) -> bool
+    where F: FnOnce(&mut World<TestComponents, StrictSystems>)
+{
+    unsafe { std::rt::unwind::try(move || call(world)) }.is_err()
+}
+
+fn malformed(e: BuildData, c: &mut TestComponents)
+{
+    c.health.add(&e, Health(1));
+    panic!("malformed entity data");
+}
+
+#[test]
+fn panicking_builder_leaves_nothing_behind()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    assert!(panics(&mut world, |world| { world.create_entity(malformed); }));
+    assert_eq!(0, world.entity_count());
+    assert_eq!(0, world.components.health.len());
+
+    // A queued build is undone too, and the rest of the queue is still there
+    let queued = world.data.create_entity(malformed);
+    let after = world.data.create_entity(health(2));
+    assert!(panics(&mut world, |world| world.flush()));
+    assert!(!world.is_valid(&queued));
+    world.flush();
+    assert_eq!(vec![after], world.entities().map(|e| *e).collect::<Vec<_>>());
+    assert_eq!(vec![&Health(2)], world.components.health.iter().map(|(_, h)| h).collect::<Vec<_>>());
+
+    // The freed index is handed out again, and the world carries on as normal
+    let entity = world.create_entity(health(3));
+    assert_eq!(queued.get_index(), entity.get_index());
+    assert_eq!(vec![("activated", after), ("activated", entity)], world.systems.strict.log);
+}
+
+#[test]
+fn panicking_builder_in_a_batch()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let created = Rc::new(RefCell::new(Vec::new()));
+    let log = created.clone();
+    world.on_entity_created(move |e, _| log.borrow_mut().push(*e));
+
+    // The entity built before the panicking one in the same batch is activated by the next flush
+    let built = world.data.create_entity(health(1));
+    world.data.create_entity(malformed);
+    assert!(panics(&mut world, |world| world.flush()));
+    assert!(world.systems.strict.log.is_empty());
+    world.flush();
+    assert_eq!(vec![("activated", built)], world.systems.strict.log);
+    assert_eq!(vec![built], *created.borrow());
+
+    // Removing one before then activates it first, so systems are never told about a removal
+    // they didn't see the entity for
+    let built = world.data.create_entity(health(2));
+    world.data.create_entity(malformed);
+    assert!(panics(&mut world, |world| world.flush()));
+    world.remove_entity(built);
+    assert_eq!(vec![("activated", built), ("deactivated", built)], world.systems.strict.log[1..].to_vec());
+
+    // Rolling back activates it before deactivating it along with the other entities that
+    // weren't in the snapshot, so it isn't left waiting to be activated once it's gone
+    let snapshot = world.snapshot();
+    let built = world.data.create_entity(health(3));
+    world.data.create_entity(malformed);
+    assert!(panics(&mut world, |world| world.flush()));
+    world.restore(&snapshot);
+    assert!(!world.is_valid(&built));
+    world.flush();
+    let first = world.systems.strict.log[0].1;
+    assert_eq!(vec![("activated", built), ("deactivated", built), ("reactivated", first)],
+               world.systems.strict.log[3..].to_vec());
+    assert_eq!(3, created.borrow().len());
+}
+
+#[test]
+fn panicking_observer_leaves_the_queue_intact()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let doomed = world.create_entity(health(1));
+    let other = world.create_entity(health(2));
+    world.on_entity_removed(|e, c| if c.health[e].0 == 1 { panic!("observer failed"); });
+
+    // The entity being removed when the observer panicked is still removed, and the changes
+    // queued after it are left for the next flush
+    world.data.remove_entity(doomed);
+    let queued = world.data.create_entity(health(3));
+    world.data.remove_entity(other);
+    assert!(panics(&mut world, |world| world.flush()));
+    assert!(!world.is_valid(&doomed));
+    assert_eq!(1, world.components.health.len());
+    assert_eq!(2, world.pending_events());
+
+    world.flush();
+    assert_eq!(vec![queued], world.entities().map(|e| *e).collect::<Vec<_>>());
+    assert_eq!(vec![("activated", doomed), ("activated", other), ("deactivated", doomed),
+                    ("activated", queued), ("deactivated", other)], world.systems.strict.log);
+}
+
+#[test]
+fn queue_remove_before_build()
+{
+    // The build was queued first, so the entity is built and then removed
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let entity = world.data.create_entity(health(1));
+    world.data.remove_entity(entity);
+    world.flush();
+    assert!(!world.is_valid(&entity));
+    assert_eq!(vec![("activated", entity), ("deactivated", entity)], world.systems.strict.log);
+
+    // Removing it straight away drops the queued build, so systems never see it
+    let entity = world.data.create_entity(health(1));
+    world.remove_entity(entity);
+    world.flush();
+    assert!(!world.is_valid(&entity));
+    assert_eq!(2, world.systems.strict.log.len());
+}
+
+#[test]
+fn queue_disable_before_build()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let queued = world.data.create_entity(health(1));
+    let immediate = world.data.create_entity(health(2));
+    world.data.disable_entity(queued);
+    world.disable_entity(immediate);
+    world.flush();
+    assert!(world.data.is_disabled(&queued) && world.data.is_disabled(&immediate));
+    // Only the entity whose build was queued before it was disabled was ever activated
+    assert_eq!(vec![("activated", queued), ("deactivated", queued)], world.systems.strict.log);
+
+    world.data.remove_entity(queued);
+    world.data.enable_entity(immediate);
+    world.flush();
+    assert!(!world.is_valid(&queued));
+    assert_eq!(vec![("activated", queued), ("deactivated", queued), ("activated", immediate)],
+               world.systems.strict.log);
+}
+
+#[test]
+fn queue_stale_handle_to_reused_index()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let old = world.create_entity(health(1));
+    world.data.remove_entity(old);
+    world.flush();
+    let new = world.data.create_entity(health(2));
+    assert_eq!(old.get_index(), new.get_index());
+    world.data.modify_entity(old, |e: ModifyData, c: &mut TestComponents| {
+        c.health.insert(&e, Health(-1));
+    });
+    world.data.remove_entity(old);
+    world.flush();
+    assert_eq!(Some(&Health(2)), world.get::<Health>(&new));
+    assert_eq!(vec![("activated", old), ("deactivated", old), ("activated", new)], world.systems.strict.log);
+}
+
+#[test]
+fn fallible_entity_operations()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let entity = world.create_entity(health(1));
+    assert_eq!(Ok(()), world.data.try_modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.health.insert(&e, Health(2));
+    }));
+    assert_eq!(Ok(()), world.data.try_remove_entity(entity));
+
+    // It can still be read until the queue is flushed, but not changed or removed again
+    assert_eq!(Ok(1), world.data.try_with_entity_data(&entity, |e, c| c.health[e].0));
+    assert_eq!(Err(InvalidEntity::PendingRemoval(entity)), world.data.try_remove_entity(entity));
+    assert_eq!(Err(InvalidEntity::PendingRemoval(entity)), world.data.try_modify_entity(entity, ()));
+
+    world.flush();
+    assert!(!world.is_valid(&entity));
+    assert_eq!(Err(InvalidEntity::Removed(entity)), world.data.try_remove_entity(entity));
+    assert_eq!(Err(InvalidEntity::Removed(entity)), world.data.try_modify_entity(entity, ()));
+    let error = world.data.try_with_entity_data(&entity, |_, _| ()).unwrap_err();
+    assert_eq!(entity, error.entity());
+    assert_eq!(format!("invalid entity {:?}", entity), error.to_string());
+}
+
+#[test]
+fn read_entity_data_through_shared_borrow()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let entities: Vec<Entity> = (1..4).map(|i| world.create_entity(health(i))).collect();
+
+    let mut total = 0;
+    for entity in world.entities()
+    {
+        total += world.with_entity_data_ref(&*entity, |e, c| c.health[e].0).unwrap();
+    }
+    assert_eq!(6, total);
+
+    // The closure is only called once, so it can move out of what it captures
+    let label = "health".to_string();
+    let described = world.data.with_entity_data_ref(&entities[2], move |e, c| format!("{} {}", label, c.health[e].0));
+    assert_eq!(Some("health 3".to_string()), described);
+
+    world.remove_entity(entities[0]);
+    assert_eq!(None, world.with_entity_data_ref(&entities[0], |_, _| ()));
+}
+
+#[test]
+fn queued_events()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let a = world.create_entity(health(1));
+    let b = world.data.create_entity(health(2));
+    world.data.add_component(a, Health(3));
+    world.data.remove_entity(b);
+    world.data.disable_entity(a);
+
+    assert_eq!(vec![QueuedEvent::Build(b), QueuedEvent::AddComponent(a), QueuedEvent::Remove(b),
+                    QueuedEvent::Disable(a)], world.data.queued_events().collect::<Vec<_>>());
+    assert!(world.data.is_pending_modification(&a));
+    assert!(!world.data.is_pending_modification(&b));
+    assert!(world.data.is_pending_removal(&b));
+    assert!(!world.data.is_pending_removal(&a));
+
+    world.flush();
+    assert_eq!(0, world.data.queued_events().count());
+    assert!(!world.data.is_pending_modification(&a));
+}
+
+#[test]
+fn world_stats()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let stats = world.stats();
+    assert_eq!((0, None, 0, 2), (stats.entities, stats.highest_index, stats.pending_events, stats.systems));
+
+    let entities: Vec<Entity> = (0..4).map(|i| world.create_entity(health(i))).collect();
+    world.remove_entity(entities[3]);
+    world.remove_entity(entities[1]);
+    world.disable_entity(entities[2]);
+    world.data.create_entity(());
+    let stats = world.stats();
+    assert_eq!(3, stats.entities);
+    assert_eq!(1, stats.disabled);
+    assert_eq!(Some(2), stats.highest_index);
+    assert_eq!(1, stats.free_indices);
+    assert_eq!(1, stats.pending_events);
+    assert_eq!(2, stats.components.iter().find(|list| list.name == "health").unwrap().count);
+}
+
+#[test]
+fn cascading_builds_settle_in_one_flush()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let parent = world.data.create_entity_then(health(1), |parent, data: &mut DataHelper<TestComponents>| {
+        let child = data.create_entity_then(health(2), |child, data: &mut DataHelper<TestComponents>| {
+            let grandchild = data.create_entity(health(3));
+            data.set_parent(grandchild, child);
+        });
+        data.set_parent(child, parent);
+    });
+    world.update();
+
+    let child = world.children_of(&parent).next().unwrap();
+    let grandchild = world.children_of(&child).next().unwrap();
+    for (entity, value) in vec![(parent, 1), (child, 2), (grandchild, 3)].into_iter()
+    {
+        assert_eq!(Some(&Health(value)), world.get::<Health>(&entity));
+    }
+    assert_eq!(3, world.systems.counter.activated);
+    assert_eq!(0, world.pending_events());
+}
+
+fn spawn_forever(_: Entity, data: &mut DataHelper<TestComponents>)
+{
+    data.create_entity_then((), spawn_forever);
+}
+
+#[test]
+#[should_panic(expected = "still being added to after 100 passes")]
+fn endless_flush_panics()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    world.data.create_entity_then((), spawn_forever);
+    world.flush();
+}
+
+#[test]
+fn deferred_builders_can_borrow()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let prefab = vec![Health(1), Health(2), Health(3)];
+    let entities = world.data.with_deferred(|queue| {
+        let entities: Vec<Entity> = prefab.iter().map(|health| queue.create_entity(move |e: BuildData, c: &mut TestComponents| {
+            c.health.add(&e, *health);
+        })).collect();
+        assert_eq!(3, queue.len());
+        entities
+    });
+
+    // The builders have run, but systems only hear about the entities at the flush
+    assert_eq!(Some(&Health(2)), world.get::<Health>(&entities[1]));
+    assert!(world.systems.strict.active.is_empty());
+    world.flush();
+    assert_eq!(3, world.systems.strict.active.len());
+    assert_eq!(3, prefab.len());
+}
+
+#[test]
+fn clear_world()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let mut old = Vec::new();
+    for i in 0..5
+    {
+        old.push(world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.health.add(&e, Health(i));
+        }));
+    }
+    world.set_name(old[0], "first");
+    world.data.create_entity(());
+    // Reserved entities were never activated, so they aren't deactivated either
+    world.reserve_ids(1);
+
+    world.clear();
+    assert_eq!(6, world.systems.counter.activated);
+    assert_eq!(6, world.systems.counter.deactivated.len());
+    assert_eq!(0, world.entity_count());
+    assert_eq!(None, world.entity_named("first"));
+
+    let fresh = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.name.add(&e, Name("fresh".to_string()));
+    });
+    assert_eq!(0, fresh.get_index());
+    for e in old.iter()
+    {
+        assert!(!world.is_valid(e));
+    }
+    world.with_entity_data(&fresh, |e, c| {
+        assert!(!c.health.has(&e));
+        assert!(c.name.has(&e));
+    });
+}
+
+#[test]
+fn reserve_capacity()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    world.reserve(100);
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(1));
+    });
+    world.reserve(0);
+    world.with_entity_data(&entity, |e, c| {
+        assert_eq!(Health(1), c.health[e]);
+    });
+    assert_eq!(1, world.entity_count());
+}
+
+#[test]
+fn queued_modify_runs_after_build()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.data.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(5));
+    });
+    assert!(world.is_valid(&entity));
+    assert!(world.is_pending_build(&entity));
+    world.data.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.health[e].0 *= 2;
+    });
+
+    world.update();
+    assert!(!world.is_pending_build(&entity));
+    world.with_entity_data(&entity, |e, c| {
+        assert_eq!(Health(10), c.health[e]);
+    });
+}
+
+#[test]
+fn removing_unbuilt_entity_skips_deactivation()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.data.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(5));
+    });
+    world.remove_entity(entity);
+    assert!(!world.is_valid(&entity));
+    assert!(!world.is_pending_build(&entity));
+
+    world.update();
+    assert_eq!(0, world.systems.counter.activated);
+    assert_eq!(0, world.systems.counter.deactivated.len());
+
+    // Queued removal after a queued build sees the build first
+    let entity = world.data.create_entity(());
+    world.data.remove_entity(entity);
+    world.update();
+    assert_eq!(1, world.systems.counter.activated);
+    assert_eq!(vec![entity], world.systems.counter.deactivated);
+}
+
+#[test]
+fn reserved_ids_are_not_reused()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let recycled: Vec<Entity> = (0..2).map(|_| world.create_entity(())).collect();
+    for &entity in recycled.iter()
+    {
+        world.remove_entity(entity);
+    }
+
+    // The recycled indices go to the reserved entities, so they can't go to anything else
+    let reserved = world.reserve_ids(4);
+    assert!(reserved.iter().all(|entity| world.is_valid(entity) && world.is_reserved(entity)));
+    let mut created = vec![world.create_entity(health(1)), world.data.create_entity(health(2))];
+    world.flush();
+    let removed = world.create_entity(());
+    world.remove_entity(removed);
+    created.push(world.data.create_entity(()));
+    created.push(world.create_entity(()));
+    world.update();
+    for entity in created.iter()
+    {
+        assert!(reserved.iter().all(|reserved| **reserved != **entity), "{:?} was reserved", entity);
+    }
+    assert!(world.systems.strict.log.iter().all(|&(_, entity)| !reserved.contains(&entity)));
+    assert!(world.health.lookup(&reserved[0]).is_none());
+
+    assert_eq!(Ok(()), world.build_reserved(reserved[0], health(3)));
+    assert_eq!(Ok(()), world.data.build_reserved(reserved[1], health(4)));
+    assert_eq!(Err(InvalidEntity::NotReserved(reserved[1])), world.data.build_reserved(reserved[1], health(5)));
+    assert_eq!(Err(InvalidEntity::NotReserved(created[0])), world.build_reserved(created[0], health(5)));
+    assert_eq!(Some(&Health(3)), world.get::<Health>(&reserved[0]));
+    assert!(world.is_pending_build(&reserved[1]));
+    world.flush();
+    assert_eq!(Some(&Health(4)), world.get::<Health>(&reserved[1]));
+    assert!(!world.is_reserved(&reserved[1]));
+    let log = &world.systems.strict.log;
+    assert_eq!(&[("activated", reserved[0]), ("activated", reserved[1])], &log[log.len() - 2..]);
+
+    // Reserved entities that are never built are removed like any other, and aren't deactivated
+    world.remove_entity(reserved[2]);
+    world.data.remove_entity(reserved[3]);
+    world.flush();
+    assert!(!world.is_valid(&reserved[2]) && !world.is_valid(&reserved[3]));
+    assert_eq!(Err(InvalidEntity::Removed(reserved[2])), world.build_reserved(reserved[2], ()));
+    let reused = world.create_entity(());
+    assert!(*reused == *reserved[2] || *reused == *reserved[3]);
+}
+
+#[test]
+fn batch_removal_deduplicates()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let a = world.create_entity(());
+    let b = world.create_entity(());
+    let c = world.create_entity(());
+    let d = world.create_entity(());
+
+    world.data.remove_entities(vec![a, b]);
+    world.data.remove_entities(vec![b, c, b]);
+    assert_eq!(3, world.pending_events());
+    world.update();
+    assert_eq!(vec![a, b, c], world.systems.counter.deactivated);
+
+    world.remove_entities(vec![d, d, a]);
+    assert_eq!(vec![a, b, c, d], world.systems.counter.deactivated);
+    assert_eq!(0, world.entity_count());
+}
+
+#[test]
+fn filtered_entity_iteration()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let mut healthy = Vec::new();
+    for i in 0..6
+    {
+        let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.health.add(&e, Health(i));
+        });
+        if i >= 3
+        {
+            healthy.push(entity);
+        }
+    }
+    world.data.remove_entity(healthy[0]);
+
+    let mut found: Vec<Entity> = world.entities_filtered(|e, c| c.health[*e].0 >= 3).map(|e| *e).collect();
+    found.sort_by(|a, b| a.get_index().cmp(&b.get_index()));
+    assert_eq!(&healthy[1..], &found[..]);
+
+    // The plain iterator skips the entity queued for removal too
+    let count = world.entities().filter_components(&world.data.components, |e, c| c.health[*e].0 >= 3).count();
+    assert_eq!(2, count);
+}
+
+#[test]
+fn disable_modify_enable()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(3));
+    });
+    world.disable_entity(entity);
+    assert!(world.is_valid(&entity));
+    assert!(world.is_disabled(&entity));
+    assert_eq!(vec![entity], world.systems.counter.deactivated);
+    assert_eq!(0, world.entities().count());
+    assert_eq!(1, world.entities_including_disabled().count());
+
+    // Modifying a disabled entity doesn't reactivate it
+    world.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.health[e] = Health(4);
+    });
+    assert_eq!(1, world.systems.counter.activated);
+
+    world.data.enable_entity(entity);
+    world.update();
+    assert!(!world.is_disabled(&entity));
+    assert_eq!(2, world.systems.counter.activated);
+    world.with_entity_data(&entity, |e, c| {
+        assert_eq!(Health(4), c.health[e]);
+    });
+}
+
+#[test]
+fn disable_remove()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(3));
+    });
+    world.data.disable_entity(entity);
+    world.data.remove_entity(entity);
+    world.update();
+
+    // Only deactivated once, when it was disabled
+    assert_eq!(vec![entity], world.systems.counter.deactivated);
+    assert!(!world.is_valid(&entity));
+    assert!(!world.is_disabled(&entity));
+    assert_eq!(0, world.entities_including_disabled().count());
+}
+
+#[test]
+fn deterministic_iteration_order()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let mut entities = Vec::new();
+    for i in 0..8
+    {
+        entities.push(world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.health.add(&e, Health(i));
+        }));
+    }
+    world.remove_entity(entities[5]);
+    world.remove_entity(entities[1]);
+    world.remove_entity(entities[6]);
+    // Reuses index 6, then index 1
+    let a = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(10));
+    });
+    let b = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(11));
+    });
+    world.remove_entity(entities[2]);
+
+    let expected = vec![entities[0], b, entities[3], entities[4], a, entities[7]];
+    let order: Vec<Entity> = world.entities().map(|e| *e).collect();
+    assert_eq!(expected, order);
+
+    world.update();
+    assert_eq!(expected, world.systems.order.0);
+}
+
+#[test]
+fn weak_entities()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let target = world.create_entity(());
+    let weak: WeakEntity = target.into();
+    assert_eq!(weak, target.downgrade());
+    assert_eq!(Some(target), weak.upgrade(&world));
+
+    let mut threat = HashMap::new();
+    threat.insert(weak, 10);
+    assert_eq!(Some(&10), threat.get(&target.downgrade()));
+
+    world.remove_entity(target);
+    let reused = world.create_entity(());
+    assert_eq!(target.get_index(), reused.get_index());
+    assert_eq!(None, weak.upgrade(&world));
+    assert_eq!(None, weak.upgrade(&world.data));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn debug_names()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let boss = world.create_entity(());
+    world.set_debug_name(boss, "boss_phase2");
+    assert_eq!(Some("boss_phase2"), world.debug_name(&boss));
+    assert_eq!(format!("{:?} \"boss_phase2\"", boss), format!("{:?}", world.debug_entity(&boss)));
+
+    world.remove_entity(boss);
+    assert_eq!(None, world.debug_name(&boss));
+    assert_eq!(format!("{:?}", boss), format!("{:?}", world.debug_entity(&boss)));
+
+    // Entity data handed out while the world is flushing or updating shows the name too
+    let printed = Rc::new(RefCell::new(Vec::new()));
+    let log = printed.clone();
+    world.on_entity_created(move |e: EntityData, _: &TestComponents| log.borrow_mut().push(format!("{:?}", e)));
+    let minion = world.data.create_entity(());
+    world.set_debug_name(minion, "minion");
+    world.flush();
+    assert_eq!(vec![format!("EntityData({:?} \"minion\")", minion)], *printed.borrow());
+}
+
+#[test]
+fn lifecycle_callbacks()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let log = Rc::new(RefCell::new(Vec::new()));
+
+    let created = log.clone();
+    world.on_entity_created(move |e, c| created.borrow_mut().push(("created", c.health[e])));
+    let modified = log.clone();
+    world.on_entity_modified(move |e, c| modified.borrow_mut().push(("modified", c.health[e])));
+    let removed = log.clone();
+    world.on_entity_removed(move |e, c| removed.borrow_mut().push(("removed", c.health[e])));
+
+    let entity = world.data.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(10));
+    });
+    world.data.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.health[e] = Health(5);
+    });
+    world.data.remove_entity(entity);
+    assert!(log.borrow().is_empty());
+
+    world.update();
+    assert_eq!(vec![("created", Health(10)), ("modified", Health(5)), ("removed", Health(5))],
+               *log.borrow());
+
+    log.borrow_mut().clear();
+    let immediate = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(1));
+    });
+    world.clear();
+    assert_eq!(vec![("created", Health(1)), ("removed", Health(1))], *log.borrow());
+    assert!(!world.is_valid(&immediate));
+}
+
+#[test]
+fn remove_observers()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let log = Rc::new(RefCell::new(Vec::new()));
+
+    let created = log.clone();
+    let on_created = world.on_entity_created(move |e, c| created.borrow_mut().push(("created", c.health[e])));
+    let removed = log.clone();
+    let on_removed = world.on_entity_removed(move |e, c| removed.borrow_mut().push(("removed", c.health[e])));
+    assert!(on_created != on_removed);
+
+    let first = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(3));
+    });
+    assert!(world.remove_observer(on_created));
+    assert!(!world.remove_observer(on_created));
+    let second = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(4));
+    });
+    world.remove_entity(first);
+    assert!(world.remove_observer(on_removed));
+    world.remove_entity(second);
+
+    assert_eq!(vec![("created", Health(3)), ("removed", Health(3))], *log.borrow());
+}
+
+#[test]
+fn build_entities_fluently()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.build_entity().with(Health(5)).with(Name("ship".to_string())).with(Health(8)).build();
+    assert_eq!(Some(&Health(8)), world.health.lookup(&entity));
+    assert_eq!(Some(&Name("ship".to_string())), world.name.lookup(&entity));
+    assert_eq!(1, world.systems.counter.activated);
+
+    // Dropping the handle without building means the entity never existed
+    drop(world.build_entity().with(Health(1)));
+    assert_eq!(1, world.systems.counter.activated);
+    assert_eq!(vec![entity], world.entities().map(|e| *e).collect::<Vec<_>>());
+    assert_eq!(1, world.health.len());
+}
+
+#[test]
+fn queue_reuses_its_space()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let mut sizes = Vec::new();
+    for _ in 0..3
+    {
+        for i in 0..100
+        {
+            let entity = world.data.create_entity(move |e: BuildData, c: &mut TestComponents| {
+                c.health.add(&e, Health(i));
+            });
+            world.data.modify_entity(entity, move |e: ModifyData, c: &mut TestComponents| {
+                c.health[e].0 += i;
+            });
+        }
+        world.flush();
+        sizes.push((world.stats().queue_bytes, world.data.queue_capacity()));
+    }
+    assert!(sizes[0].0 > 0 && sizes[0].1 >= 200);
+    assert_eq!(vec![sizes[0], sizes[0], sizes[0]], sizes);
+    assert_eq!(300, world.health.len());
+    assert_eq!(198, world.health.iter().map(|(_, h)| h.0).max().unwrap());
+}
+
+#[test]
+fn drop_queued_builders_once()
+{
+    let counted = Rc::new(());
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let removed = world.create_entity(());
+    world.remove_entity(removed);
+    let held = counted.clone();
+    world.data.modify_entity(removed, move |_: ModifyData, _: &mut TestComponents| { let _ = &held; });
+    let held = counted.clone();
+    world.data.create_entity(move |_: BuildData, _: &mut TestComponents| { let _ = &held; });
+    assert_eq!(3, Rc::strong_count(&counted));
+    world.flush();
+    assert_eq!(1, Rc::strong_count(&counted));
+
+    let held = counted.clone();
+    world.data.create_entity(move |_: BuildData, _: &mut TestComponents| { let _ = &held; });
+    drop(world);
+    assert_eq!(1, Rc::strong_count(&counted));
+}
+
+#[test]
+fn spawn_prefabs()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    world.register_prefab("grunt", |e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(10));
+        c.name.add(&e, Name("grunt".to_string()));
+    });
+    assert!(world.has_prefab("grunt"));
+
+    let grunt = world.spawn("grunt").unwrap();
+    assert_eq!(Some(&Health(10)), world.health.lookup(&grunt));
+    assert_eq!(1, world.systems.counter.activated);
+
+    let captain = world.spawn_with("grunt", |e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(25));
+    }).unwrap();
+    assert_eq!(Some(&Health(25)), world.health.lookup(&captain));
+    assert_eq!(Some(&Name("grunt".to_string())), world.name.lookup(&captain));
+
+    let queued = world.data.spawn_deferred("grunt").unwrap();
+    assert!(world.data.is_pending_build(&queued));
+    world.flush();
+    assert_eq!(Some(&Health(10)), world.health.lookup(&queued));
+    assert_eq!(3, world.systems.counter.activated);
+
+    let error = world.spawn("ogre").unwrap_err();
+    assert_eq!(UnknownPrefab("ogre".to_string()), error);
+    assert_eq!("unknown prefab \"ogre\"", error.to_string());
+    assert_eq!(Err(UnknownPrefab("ogre".to_string())), world.data.spawn_deferred("ogre"));
+    assert_eq!(3, world.entities().count());
+}
+
+#[test]
+fn reactivate_only_on_component_changes()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(10));
+    });
+    assert_eq!(1, world.systems.counter.activated);
+
+    world.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.health[e] = Health(5);
+    });
+    world.data.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.health[e] = Health(4);
+    });
+    world.update();
+    assert_eq!(1, world.systems.counter.activated);
+    assert!(world.systems.counter.deactivated.is_empty());
+
+    world.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.name.insert(&e, Name("Bob".to_string()));
+    });
+    assert_eq!(2, world.systems.counter.activated);
+
+    world.data.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.name.remove(&e);
+    });
+    world.update();
+    assert_eq!(3, world.systems.counter.activated);
+
+    world.data.force_reactivate(entity);
+    world.update();
+    assert_eq!(4, world.systems.counter.activated);
+    world.force_reactivate(entity);
+    assert_eq!(5, world.systems.counter.activated);
+    assert_eq!(vec![entity; 4], world.systems.counter.deactivated);
+}
+
+#[test]
+fn queued_component_changes()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(10));
+    });
+    assert_eq!(1, world.systems.counter.activated);
+    let modified = Rc::new(RefCell::new(0));
+    let counter = modified.clone();
+    world.on_entity_modified(move |_, _| *counter.borrow_mut() += 1);
+
+    world.add_component(entity, Name("Bob".to_string()));
+    world.remove_component::<Health>(entity);
+    world.add_component(entity, Health(5));
+    assert_eq!(None, world.get::<Name>(&entity));
+    world.update();
+    assert_eq!(Some(&Name("Bob".to_string())), world.get::<Name>(&entity));
+    assert_eq!(Some(&Health(5)), world.get::<Health>(&entity));
+    // Reactivated once for all three changes
+    assert_eq!(2, world.systems.counter.activated);
+    assert_eq!(vec![entity], world.systems.counter.deactivated);
+    assert_eq!(3, *modified.borrow());
+
+    // Replacing a component or removing a missing one doesn't change the entity's aspect
+    world.add_component(entity, Health(1));
+    world.remove_component::<Name>(entity);
+    world.remove_component::<Name>(entity);
+    world.update();
+    assert_eq!(Some(&Health(1)), world.get::<Health>(&entity));
+    assert_eq!(None, world.get::<Name>(&entity));
+    assert_eq!(3, world.systems.counter.activated);
+    // Only the removal changed the entity's components
+    assert_eq!(4, *modified.borrow());
+
+    world.add_component(entity, Health(2));
+    world.update();
+    assert_eq!(3, world.systems.counter.activated);
+
+    // Components queued for removed entities are dropped
+    world.data.remove_entity(entity);
+    world.add_component(entity, Name("Ghost".to_string()));
+    world.update();
+    let reused = world.create_entity(());
+    assert_eq!(entity.get_index(), reused.get_index());
+    assert_eq!(None, world.get::<Name>(&reused));
+    assert_eq!(4, world.systems.counter.activated);
+}
+
+#[test]
+fn transfer_between_worlds()
+{
+    let mut loading = World::<TestComponents, TestSystems>::new();
+    let mut gameplay = World::<TestComponents, TestSystems>::new();
+    let existing = gameplay.create_entity(());
+
+    let target = loading.create_entity(());
+    let entity = loading.create_entity(move |e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(4));
+        c.target.add(&e, Target(target));
+    });
+    let moved = loading.transfer_entity(entity, &mut gameplay).unwrap();
+    assert!(!loading.is_valid(&entity));
+    assert_eq!(None, loading.get::<Health>(&entity));
+    assert_eq!(vec![entity], loading.systems.counter.deactivated);
+    assert!(gameplay.is_valid(&moved) && moved != existing);
+    assert_eq!(Some(&Health(4)), gameplay.get::<Health>(&moved));
+    assert_eq!(2, gameplay.systems.counter.activated);
+    // Entity fields aren't remapped
+    assert_eq!(Some(&Target(target)), gameplay.get::<Target>(&moved));
+
+    // Unless the components are changed as they're moved
+    let remapped = loading.transfer_entity_with(target, &mut gameplay, move |e: ModifyData, c: &mut TestComponents| {
+        c.target.insert(&e, Target(existing));
+    }).unwrap();
+    assert_eq!(Some(&Target(existing)), gameplay.get::<Target>(&remapped));
+    assert_eq!(0, loading.entity_count());
+
+    gameplay.update();
+    assert_eq!(vec![moved], gameplay.systems.order.inner.0);
+
+    let disabled = loading.create_entity(());
+    loading.disable_entity(disabled);
+    let disabled = loading.transfer_entity(disabled, &mut gameplay).unwrap();
+    assert!(gameplay.data.is_disabled(&disabled));
+    assert_eq!(3, gameplay.systems.counter.activated);
+    assert_eq!(None, loading.transfer_entity(entity, &mut gameplay));
+}
+
+#[test]
+fn save_and_load()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let removed = world.create_entity(());
+    let first = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(10));
+        c.name.add(&e, Name("first".to_string()));
+    });
+    let second = world.create_entity(move |e: BuildData, c: &mut TestComponents| {
+        c.target.add(&e, Target(first));
+    });
+    let dangling = world.create_entity(move |e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(5));
+        c.target.add(&e, Target(removed));
+    });
+    world.data.disable_entity(dangling);
+    world.remove_entity(removed);
+
+    let mut saved = Vec::new();
+    world.save(&mut saved).unwrap();
+
+    let mut loaded = World::<TestComponents, TestSystems>::new();
+    loaded.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(-1));
+    });
+    let remap = loaded.load(&mut &saved[..]).unwrap();
+    assert_eq!(3, remap.len());
+    assert_eq!(3, loaded.entity_count());
+    // Only the entities that aren't disabled are activated
+    assert_eq!(3, loaded.systems.counter.activated);
+
+    let first = *remap.get(&first).unwrap();
+    assert_eq!(Some(&Health(10)), loaded.get::<Health>(&first));
+    // Names aren't listed in saveable_components!, so they aren't saved
+    assert_eq!(None, loaded.get::<Name>(&first));
+    assert_eq!(Some(&Target(first)), loaded.get::<Target>(remap.get(&second).unwrap()));
+
+    let dangling = *remap.get(&dangling).unwrap();
+    assert!(loaded.data.is_disabled(&dangling));
+    assert_eq!(Some(&Target(Entity::nil())), loaded.get::<Target>(&dangling));
+
+    loaded.update();
+    assert_eq!(vec![first], loaded.systems.order.inner.0);
+}
+
+#[test]
+fn snapshot_round_trip()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    let entities: Vec<Entity> = (0..6).map(|i| world.create_entity(move |e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(i));
+    })).collect();
+    world.remove_entity(entities[1]);
+    world.remove_entity(entities[4]);
+    world.modify_entity(entities[2], |e: ModifyData, c: &mut TestComponents| {
+        c.target.insert(&e, Target(entities[5]));
+    });
+    world.set_name(entities[3], "boss");
+    world.set_parent(entities[5], entities[3]);
+    world.set_parent(entities[0], entities[3]);
+    world.disable_entity(entities[2]);
+    world.update();
+
+    let mut saved = Vec::new();
+    world.save_snapshot(&mut saved).unwrap();
+    let mut loaded = World::<TestComponents, TestSystems>::load_snapshot(&mut &saved[..]).unwrap();
+
+    // Entities keep their handles, and the systems are told about them again
+    assert_eq!(world.entities().map(|e| *e).collect::<Vec<_>>(), loaded.entities().map(|e| *e).collect::<Vec<_>>());
+    assert!(!loaded.is_valid(&entities[1]));
+    assert!(loaded.data.is_disabled(&entities[2]));
+    assert_eq!(Some(&Target(entities[5])), loaded.get::<Target>(&entities[2]));
+    assert_eq!(Some(entities[3]), loaded.entity_named("boss"));
+    assert_eq!(vec![entities[5], entities[0]], loaded.children_of(&entities[3]).collect::<Vec<_>>());
+    assert_eq!(3, loaded.systems.counter.activated);
+
+    // Both worlds behave the same from here on
+    world.systems.counter.deactivated.clear();
+    for world in [&mut world, &mut loaded].iter_mut()
+    {
+        let created = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.health.add(&e, Health(7));
+        });
+        assert_eq!(entities[4].get_index(), created.get_index());
+        world.remove_entity_recursive(entities[3]);
+        world.update();
+    }
+    assert_eq!(world.entities().map(|e| *e).collect::<Vec<_>>(), loaded.entities().map(|e| *e).collect::<Vec<_>>());
+    assert_eq!(world.systems.order.inner.0, loaded.systems.order.inner.0);
+    assert_eq!(world.systems.counter.deactivated, loaded.systems.counter.deactivated);
+}
+
+#[test]
+fn restore_snapshot()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let kept = world.create_entity(health(10));
+    let removed = world.create_entity(health(20));
+    let disabled = world.create_entity(health(30));
+    world.set_name(kept, "hero");
+    let snapshot = world.snapshot();
+    assert_eq!(3, snapshot.entity_count());
+
+    world.modify_entity(kept, |e: ModifyData, c: &mut TestComponents| {
+        c.health.insert(&e, Health(5));
+        c.target.insert(&e, Target(removed));
+    });
+    world.remove_entity(removed);
+    world.disable_entity(disabled);
+    let created = world.create_entity(health(40));
+    assert_eq!(removed.get_index(), created.get_index());
+    let queued = world.data.create_entity(health(50));
+    world.systems.strict.log.clear();
+
+    world.restore(&snapshot);
+    assert_eq!(3, world.entity_count());
+    assert_eq!(0, world.pending_events());
+    assert_eq!(Some(&Health(10)), world.get::<Health>(&kept));
+    assert_eq!(None, world.get::<Target>(&kept));
+    assert_eq!(Some(&Health(20)), world.get::<Health>(&removed));
+    assert!(!world.data.is_disabled(&disabled));
+    assert!(!world.is_valid(&created));
+    assert!(!world.is_valid(&queued));
+    assert_eq!(Some(kept), world.entity_named("hero"));
+    assert_eq!(vec![("deactivated", created), ("reactivated", kept), ("activated", removed), ("activated", disabled)],
+               world.systems.strict.log);
+
+    // The snapshot can be restored again after the world moves on
+    world.remove_entity(kept);
+    world.update();
+    world.restore(&snapshot);
+    assert_eq!(Some(&Health(10)), world.get::<Health>(&kept));
+    assert_eq!(3, world.systems.strict.active.len());
+}
+
+#[test]
+fn reserved_entities_in_snapshots()
+{
+    let mut world = World::<TestComponents, StrictSystems>::new();
+    let built = world.create_entity(health(10));
+    let reserved = world.reserve_ids(2);
+    let snapshot = world.snapshot();
+
+    // Restoring makes the entities reserved again, rather than activating them unbuilt, and drops
+    // the changes held for them since
+    assert_eq!(Ok(()), world.build_reserved(reserved[0], health(20)));
+    world.data.modify_entity(reserved[1], |e: ModifyData, c: &mut TestComponents| {
+        c.health.insert(&e, Health(30));
+    });
+    world.restore(&snapshot);
+    assert!(world.is_reserved(&reserved[0]) && world.is_reserved(&reserved[1]));
+    assert_eq!(vec![built], world.systems.strict.active.iter().cloned().collect::<Vec<_>>());
+    assert_eq!(Ok(()), world.build_reserved(reserved[1], health(40)));
+    assert_eq!(Some(&Health(40)), world.get::<Health>(&reserved[1]));
+
+    // Saves skip them, and saved snapshots keep them reserved
+    let mut saved = Vec::new();
+    world.save(&mut saved).unwrap();
+    let mut loaded = World::<TestComponents, StrictSystems>::new();
+    assert_eq!(2, loaded.load(&mut &saved[..]).unwrap().len());
+    let mut saved = Vec::new();
+    world.save_snapshot(&mut saved).unwrap();
+    let mut loaded = World::<TestComponents, StrictSystems>::load_snapshot(&mut &saved[..]).unwrap();
+    assert!(loaded.is_reserved(&reserved[0]));
+    assert_eq!(2, loaded.systems.strict.active.len());
+    assert_eq!(Ok(()), loaded.build_reserved(reserved[0], health(50)));
+    assert_eq!(3, loaded.systems.strict.active.len());
+
+    // Changes held for a reserved entity can't be saved
+    world.data.modify_entity(reserved[0], |e: ModifyData, c: &mut TestComponents| {
+        c.health.insert(&e, Health(60));
+    });
+    assert!(world.save_snapshot(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn load_invalid_snapshot()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(10));
+    });
+
+    let mut saved = Vec::new();
+    world.save(&mut saved).unwrap();
+    assert!(World::<TestComponents, TestSystems>::load_snapshot(&mut &saved[..]).is_err());
+
+    let mut saved = Vec::new();
+    world.save_snapshot(&mut saved).unwrap();
+    assert!(world.load(&mut &saved[..]).is_err());
+    saved.pop();
+    assert!(World::<TestComponents, TestSystems>::load_snapshot(&mut &saved[..]).is_err());
+}
+
+#[test]
+fn load_invalid_save()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.health.add(&e, Health(10));
+    });
+
+    let mut saved = Vec::new();
+    world.save(&mut saved).unwrap();
+    saved.pop();
+    assert!(world.load(&mut &saved[..]).is_err());
+    assert_eq!(0, world.entity_count());
+    assert_eq!(1, world.systems.counter.activated);
+
+    assert!(world.load(&mut &b"not a saved world"[..]).is_err());
+}
+
+#[test]
+fn load_oversized_length()
+{
+    let mut saved = Vec::new();
+    Writer::new(&mut saved).write(&!0u64).unwrap();
+    saved.extend(b"short".iter().cloned());
+
+    let remap = HashMap::new();
+    let mut data = &saved[..];
+    let loaded = Reader::new(&mut data, &remap).read::<String>();
+    assert_eq!(io::ErrorKind::InvalidInput, loaded.unwrap_err().kind());
+
+    let mut saved = Vec::new();
+    {
+        let mut writer = Writer::new(&mut saved);
+        writer.write(&"health".to_string()).unwrap();
+        writer.write(&(1u64 << 40)).unwrap();
+    }
+    let mut data = &saved[..];
+    assert!(Reader::new(&mut data, &remap).read_component_data().is_err());
+}
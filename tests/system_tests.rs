@@ -0,0 +1,1942 @@
+#![feature(std_misc)]
+
+#[macro_use]
+extern crate ecs;
+
+use ecs::{Aspect, BuildData, DeactivationReason, FixedTimestep, ModifyData, EntityData};
+use ecs::{Entity, EntityIter, World, WorldBuilder, DataHelper};
+use ecs::{Phase, Process, System};
+
+use std::any::TypeId;
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::time::Duration;
+use ecs::system::{BatchedProcess, BatchedSystem, ContextSystem, EntityBatch, ProcessWithContext};
+use ecs::system::{Commands, ParallelProcess, ParallelSystem, ReactiveProcess, ReactiveSystem};
+use ecs::system::{EntityPairs, InteractOptions, InteractProcess, InteractSystem};
+use ecs::system::{EntityProcess, EntitySystem, IntervalSystem, LazySystem, LazyTrigger, StartupSystem, TimedSystem};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Position(f32);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Velocity(f32);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sprite;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Model;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frozen;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Selected;
+
+components! {
+    TestComponents {
+        #[hot] position: Position,
+        #[hot] velocity: Velocity,
+        #[cold] sprite: Sprite,
+        #[cold] model: Model,
+        #[cold] frozen: Frozen,
+        #[null] selected: Selected
+    }
+}
+
+component_access! {
+    TestComponents {
+        position: Position,
+        velocity: Velocity,
+        sprite: Sprite,
+        model: Model,
+        frozen: Frozen,
+        selected: Selected
+    }
+}
+
+systems! {
+    TestSystems<TestComponents> {
+        typed: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(),
+                Aspect::all().with::<(Position, Velocity)>().with_any::<(Sprite, Model)>().without::<(Frozen,)>()),
+        untyped: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(),
+                aspect!(<TestComponents> all: [position, velocity] any: [sprite, model] none: [frozen]))
+    }
+}
+
+systems! {
+    NarrowSystems<TestComponents> {
+        typed: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(), Aspect::all().with::<(Position,)>()),
+        untyped: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(), aspect!(<TestComponents> all: [position])),
+        custom: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(), unsafe {
+            Aspect::new(Box::new(|en: &EntityData, co: &TestComponents| co.position.lookup(&**en).is_some()))
+        })
+    }
+}
+
+systems! {
+    MarkerSystems<TestComponents> {
+        typed: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(), Aspect::all().with::<(Position, Selected)>()),
+        untyped: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(), aspect!(<TestComponents> all: [position, selected]))
+    }
+}
+
+systems! {
+    OrderedSystems<TestComponents> {
+        render: Recorder = Recorder { name: "render", priority: -10 },
+        physics: Recorder = Recorder { name: "physics", priority: 10 },
+        collision: Recorder = Recorder { name: "collision", priority: 5 },
+        damage: Recorder = Recorder { name: "damage", priority: 5 },
+        sound: Recorder = Recorder { name: "sound", priority: 0 }
+    }
+}
+
+systems! {
+    AnnotatedSystems<TestComponents> {
+        #[phase(PostUpdate)]
+        render: Recorder = Recorder { name: "render", priority: 100 },
+        #[priority(10)] #[phase(PreUpdate)]
+        input: Recorder = Recorder { name: "input", priority: 0 },
+        #[priority(20)]
+        physics: Recorder = Recorder { name: "physics", priority: 0 },
+        idle: Idle,
+    }
+}
+
+systems! {
+    DependentSystems<TestComponents> {
+        render: Stage<Render> = Stage::new("render").before::<Stage<Sound>>(),
+        sound: Stage<Sound> = Stage::new("sound"),
+        collision: Stage<Collision> = Stage::new("collision").after::<Stage<Movement>>(),
+        movement: Stage<Movement> = Stage::new("movement").after::<Stage<Input>>(),
+        input: Stage<Input> = Stage::new("input")
+    }
+}
+
+systems! {
+    CyclicSystems<TestComponents> {
+        input: Stage<Input> = Stage::new("input").after::<Stage<Collision>>(),
+        sound: Stage<Sound> = Stage::new("sound"),
+        movement: Stage<Movement> = Stage::new("movement").after::<Stage<Input>>(),
+        collision: Stage<Collision> = Stage::new("collision").after::<Stage<Movement>>()
+    }
+}
+
+systems! {
+    LazySystems<TestComponents> {
+        late: Triggerer = Triggerer { trigger: None, fire: false, priority: -1 },
+        grid: LazySystem<Rebuild> = LazySystem::new(Rebuild(0)),
+        early: Triggerer = Triggerer { trigger: None, fire: false, priority: 1 }
+    }
+}
+
+systems! {
+    TimedSystems<TestComponents> {
+        once: TimedSystem<Rebuild> = TimedSystem::new(Rebuild(0), Duration::milliseconds(250)),
+        catch_up: TimedSystem<Rebuild> = {
+            let mut system = TimedSystem::new(Rebuild(0), Duration::milliseconds(250));
+            system.set_max_catch_up(3);
+            system
+        }
+    }
+}
+
+systems! {
+    DeltaSystems<TestComponents> {
+        every: Deltas = Deltas(Vec::new()),
+        second: IntervalSystem<Deltas> = IntervalSystem::new(Deltas(Vec::new()), 2),
+        timed: TimedSystem<Deltas> = TimedSystem::new(Deltas(Vec::new()), Duration::milliseconds(500))
+    }
+}
+
+systems! {
+    PhaseSystems<TestComponents> {
+        post: Counter = Counter { phase: Phase::PostUpdate, spawn: false, counts: Vec::new() },
+        culler: EntitySystem<Culler> = EntitySystem::new(Culler { culled: Vec::new() },
+                aspect!(<TestComponents> all: [position] none: [])),
+        pre: Counter = Counter { phase: Phase::PreUpdate, spawn: true, counts: Vec::new() }
+    }
+}
+
+systems! {
+    LateSystems<TestComponents> {
+        #[phase(PostUpdate)]
+        culler: EntitySystem<Culler> = EntitySystem::new(Culler { culled: Vec::new() },
+                aspect!(<TestComponents> all: [position] none: [])),
+        spawner: Counter = Counter { phase: Phase::PostUpdate, spawn: true, counts: Vec::new() }
+    }
+}
+
+systems! {
+    StartupSystems<TestComponents> {
+        counter: Counter = Counter { phase: Phase::PreUpdate, spawn: false, counts: Vec::new() },
+        level: StartupSystem<Counter> = StartupSystem::new(Counter { phase: Phase::Update, spawn: true, counts: Vec::new() })
+    }
+}
+
+systems! {
+    ParallelSystems<TestComponents> {
+        workers: ParallelSystem<Worker, ParallelSystem<Worker, Worker>> = ParallelSystem::new(
+            Worker { sums: Vec::new(), cull: true, spawn: None },
+            ParallelSystem::new(
+                Worker { sums: Vec::new(), cull: false, spawn: Some(5.0) },
+                Worker { sums: Vec::new(), cull: false, spawn: Some(6.0) }))
+    }
+}
+
+systems! {
+    ReactiveSystems<TestComponents> {
+        moved: ReactiveSystem<Reactor> = ReactiveSystem::new(Reactor::new(),
+                aspect!(<TestComponents> all: [position] none: [])).watch::<Position>(),
+        accelerated: ReactiveSystem<Reactor> = ReactiveSystem::new(Reactor::new(),
+                aspect!(<TestComponents> all: [position] none: [])).watch::<Velocity>()
+    }
+}
+
+systems! {
+    SortedSystems<TestComponents> {
+        sorted: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(),
+                aspect!(<TestComponents> all: [position] none: [])).sorted_by(|e, c| c.position[e].0 as i32)
+    }
+}
+
+systems! {
+    ReplaySystems<TestComponents> {
+        watcher: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(),
+                aspect!(<TestComponents> all: [position] none: []))
+    }
+}
+
+systems! {
+    MourningSystems<TestComponents> {
+        mourner: EntitySystem<Mourner> = EntitySystem::new(Mourner { reasons: Vec::new() },
+                aspect!(<TestComponents> all: [position] none: []))
+    }
+}
+
+systems! {
+    ResourceSystems<TestComponents> {
+        scorer: Scorer = Scorer
+    }
+}
+
+systems! {
+    ContextSystems<TestComponents> {
+        reader: ContextSystem<InputReader, Keys> = ContextSystem::new(InputReader { read: Vec::new() }),
+        plain: Rebuild = Rebuild(0)
+    }
+}
+
+systems! {
+    MessageSystems<TestComponents> {
+        late: Listener = Listener { priority: -1, heard: Vec::new() },
+        emitter: Emitter = Emitter(0),
+        early: Listener = Listener { priority: 1, heard: Vec::new() }
+    }
+}
+
+systems! {
+    FilteredSystems<TestComponents> {
+        ahead: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(),
+                aspect!(<TestComponents> all: [position] none: [])).filtered_by(|e, c| c.position[e].0 > 0.0)
+    }
+}
+
+systems! {
+    InteractSystems<TestComponents> {
+        all: InteractSystem<PairRecorder> = InteractSystem::new(PairRecorder::new(),
+                aspect!(<TestComponents> all: [position] none: []), aspect!(<TestComponents> all: [velocity] none: [])),
+        filtered: InteractSystem<PairRecorder> = InteractSystem::with_options(PairRecorder::new(),
+                aspect!(<TestComponents> all: [position] none: []), aspect!(<TestComponents> all: [velocity] none: []),
+                InteractOptions { skip_self: true, unordered: true, skip_empty: true })
+    }
+}
+
+systems! {
+    ProfiledSystems<TestComponents> {
+        counter: Rebuild = Rebuild(0),
+        setup: StartupSystem<Rebuild> = StartupSystem::new(Rebuild(0)),
+        moving: TimedSystem<EntitySystem<Watcher>> = TimedSystem::new(
+            EntitySystem::new(Watcher::new(), aspect!(<TestComponents> all: [position])), Duration::milliseconds(500))
+    }
+}
+
+systems! {
+    BatchedSystems<TestComponents> {
+        echo: BatchedSystem<Echo> = BatchedSystem::new(Echo { batches: Vec::new() })
+    }
+}
+
+systems! {
+    IntervalSystems<TestComponents> {
+        plain: IntervalSystem<Rebuild> = IntervalSystem::new(Rebuild(0), 3),
+        first: IntervalSystem<Rebuild> = IntervalSystem::with_offset(Rebuild(0), 3, 0),
+        second: IntervalSystem<Rebuild> = IntervalSystem::with_offset(Rebuild(0), 3, 1),
+        spread: IntervalSystem<Rebuild> = IntervalSystem::spread(Rebuild(0), 4, 1, 2)
+    }
+}
+
+systems! {
+    ImmediateSystems<TestComponents> {
+        late: Positions = Positions { priority: -1, seen: Vec::new() },
+        watcher: EntitySystem<Watcher> = EntitySystem::new(Watcher::new(),
+                aspect!(<TestComponents> all: [position] none: [])),
+        flash: Flash = Flash { spawned: Vec::new() }
+    }
+}
+
+systems! {
+    SweepSystems<TestComponents> {
+        sweeper: Sweeper = Sweeper { kept: Vec::new() }
+    }
+}
+
+systems! {
+    StepSystems<TestComponents> {
+        spawner: Spawner = Spawner { steps: Vec::new() }
+    }
+}
+
+pub struct Mourner
+{
+    pub reasons: Vec<(Entity, DeactivationReason)>,
+}
+
+impl EntityProcess for Mourner
+{
+    fn process(&mut self, _: EntityIter<TestComponents>, _: &mut DataHelper<TestComponents>)
+    {
+
+    }
+}
+
+impl System for Mourner
+{
+    type Components = TestComponents;
+    fn deactivated_with_reason(&mut self, e: &EntityData, _: &TestComponents, reason: DeactivationReason)
+    {
+        self.reasons.push((**e, reason));
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Score(u32);
+
+// Adds the number of entities with a position to the score.
+pub struct Scorer;
+
+impl Process for Scorer
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        let points = data.position.len() as u32;
+        data.resource_mut::<Score>().0 += points;
+    }
+}
+
+impl System for Scorer
+{
+    type Components = TestComponents;
+}
+
+pub struct Keys
+{
+    pub keys: Vec<char>,
+}
+
+// Takes the keys pressed since the last update.
+pub struct InputReader
+{
+    pub read: Vec<char>,
+}
+
+impl ProcessWithContext<Keys> for InputReader
+{
+    fn process(&mut self, input: &mut Keys, data: &mut DataHelper<TestComponents>)
+    {
+        assert_eq!(0.0, data.delta());
+        self.read.extend(input.keys.drain());
+    }
+}
+
+impl System for InputReader
+{
+    type Components = TestComponents;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Damage(u32);
+
+// Deals more damage every update.
+pub struct Emitter(pub u32);
+
+impl Process for Emitter
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        self.0 += 1;
+        data.emit(Damage(self.0));
+    }
+}
+
+impl System for Emitter { type Components = TestComponents; }
+
+// Records the damage it reads in each update.
+pub struct Listener
+{
+    pub priority: i32,
+    pub heard: Vec<Vec<Damage>>,
+}
+
+impl Process for Listener
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        self.heard.push(data.messages::<Damage>().to_vec());
+    }
+}
+
+impl System for Listener
+{
+    type Components = TestComponents;
+    fn priority(&self) -> i32
+    {
+        self.priority
+    }
+}
+
+// Records how many entities have a position, and can spawn another one.
+pub struct Counter
+{
+    pub phase: Phase,
+    pub spawn: bool,
+    pub counts: Vec<usize>,
+}
+
+impl Process for Counter
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        self.counts.push(data.position.len());
+        if self.spawn
+        {
+            data.create_entity(|e: BuildData, c: &mut TestComponents| {
+                c.position.add(&e, Position(0.0));
+            });
+        }
+    }
+}
+
+impl System for Counter
+{
+    type Components = TestComponents;
+    fn phase(&self) -> Phase
+    {
+        self.phase
+    }
+}
+
+#[derive(Default)]
+pub struct Idle;
+
+impl Process for Idle
+{
+    fn process(&mut self, _: &mut DataHelper<TestComponents>)
+    {
+
+    }
+}
+
+impl System for Idle
+{
+    type Components = TestComponents;
+}
+
+// Removes every entity it processes, recording how many there were.
+pub struct Culler
+{
+    pub culled: Vec<usize>,
+}
+
+impl EntityProcess for Culler
+{
+    fn process(&mut self, en: EntityIter<TestComponents>, data: &mut DataHelper<TestComponents>)
+    {
+        let entities: Vec<Entity> = en.map(|e| *e).collect();
+        self.culled.push(entities.len());
+        for entity in entities.into_iter()
+        {
+            data.remove_entity(entity);
+        }
+    }
+}
+
+impl System for Culler { type Components = TestComponents; }
+
+// Sums up every position, and can remove the negative ones and spawn a new one.
+pub struct Worker
+{
+    pub sums: Vec<f32>,
+    pub cull: bool,
+    pub spawn: Option<f32>,
+}
+
+impl ParallelProcess for Worker
+{
+    fn process(&mut self, c: &TestComponents, commands: &mut Commands<TestComponents>)
+    {
+        let mut sum = 0.0;
+        for (entity, position) in c.position.iter()
+        {
+            sum += position.0;
+            if self.cull && position.0 < 0.0
+            {
+                commands.remove_entity(entity);
+            }
+        }
+        self.sums.push(sum);
+        if let Some(x) = self.spawn
+        {
+            commands.create_entity(move |e: BuildData, c: &mut TestComponents| {
+                c.position.add(&e, Position(x));
+            });
+        }
+    }
+
+    fn reads(&self) -> Vec<TypeId>
+    {
+        vec![TypeId::of::<Position>()]
+    }
+
+    fn writes(&self) -> Vec<TypeId>
+    {
+        Vec::new()
+    }
+}
+
+impl System for Worker { type Components = TestComponents; }
+
+// Moves every entity along by its velocity.
+pub struct Mover;
+
+impl ParallelProcess for Mover
+{
+    fn process(&mut self, c: &TestComponents, commands: &mut Commands<TestComponents>)
+    {
+        for (entity, velocity) in c.velocity.iter()
+        {
+            let dx = velocity.0;
+            commands.modify_entity(entity, move |e: ModifyData, c: &mut TestComponents| {
+                c.position[e].0 += dx;
+            });
+        }
+    }
+
+    fn reads(&self) -> Vec<TypeId>
+    {
+        vec![TypeId::of::<Position>(), TypeId::of::<Velocity>()]
+    }
+
+    fn writes(&self) -> Vec<TypeId>
+    {
+        vec![TypeId::of::<Position>()]
+    }
+}
+
+impl System for Mover { type Components = TestComponents; }
+
+// Records the changed and removed entities it is given each update.
+pub struct Reactor
+{
+    pub changed: Vec<Vec<Entity>>,
+    pub removed: Vec<Vec<Entity>>,
+}
+
+impl Reactor
+{
+    pub fn new() -> Reactor
+    {
+        Reactor { changed: Vec::new(), removed: Vec::new() }
+    }
+}
+
+impl ReactiveProcess for Reactor
+{
+    fn process(&mut self, changed: EntityIter<TestComponents>, removed: &[Entity], _: &mut DataHelper<TestComponents>)
+    {
+        self.changed.push(changed.map(|e| *e).collect());
+        self.removed.push(removed.to_vec());
+    }
+}
+
+impl System for Reactor { type Components = TestComponents; }
+
+// Records the pairs it is given, and how many times it was processed.
+pub struct PairRecorder
+{
+    pub pairs: RefCell<Vec<(Entity, Entity)>>,
+    pub calls: Cell<usize>,
+}
+
+impl PairRecorder
+{
+    pub fn new() -> PairRecorder
+    {
+        PairRecorder { pairs: RefCell::new(Vec::new()), calls: Cell::new(0) }
+    }
+}
+
+impl InteractProcess for PairRecorder
+{
+    fn process_pairs(&self, pairs: EntityPairs, _: &mut DataHelper<TestComponents>)
+    {
+        self.calls.set(self.calls.get() + 1);
+        *self.pairs.borrow_mut() = pairs.map(|(a, b)| (*a, *b)).collect();
+    }
+}
+
+impl System for PairRecorder { type Components = TestComponents; }
+
+// Records the batches it is given, and spawns an entity with a velocity for each new entity
+// with a position.
+pub struct Echo
+{
+    pub batches: Vec<EntityBatch>,
+}
+
+impl BatchedProcess for Echo
+{
+    fn process(&mut self, batch: &EntityBatch, data: &mut DataHelper<TestComponents>)
+    {
+        for entity in batch.activated.iter()
+        {
+            if data.position.lookup(entity).is_some()
+            {
+                data.create_entity(|e: BuildData, c: &mut TestComponents| {
+                    c.velocity.add(&e, Velocity(0.0));
+                });
+            }
+        }
+        self.batches.push(batch.clone());
+    }
+}
+
+impl System for Echo { type Components = TestComponents; }
+
+// Records how many entities had been built at each step, and spawns another one.
+pub struct Spawner
+{
+    pub steps: Vec<(f64, usize)>,
+}
+
+impl Process for Spawner
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        let built = data.position.len();
+        self.steps.push((data.delta(), built));
+        data.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position(0.0));
+        });
+    }
+}
+
+impl System for Spawner { type Components = TestComponents; }
+
+// Spawns an entity that later systems can see in the same update.
+pub struct Flash
+{
+    pub spawned: Vec<Entity>,
+}
+
+impl Process for Flash
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        let x = self.spawned.len() as f32;
+        self.spawned.push(data.create_entity_now(move |e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position(x));
+        }));
+    }
+}
+
+impl System for Flash
+{
+    type Components = TestComponents;
+    fn priority(&self) -> i32
+    {
+        1
+    }
+}
+
+// Records every position in each update.
+pub struct Positions
+{
+    pub priority: i32,
+    pub seen: Vec<Vec<Position>>,
+}
+
+impl Process for Positions
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        self.seen.push(data.position.iter().map(|(_, position)| *position).collect());
+    }
+}
+
+impl System for Positions
+{
+    type Components = TestComponents;
+    fn priority(&self) -> i32
+    {
+        self.priority
+    }
+}
+
+// Removes every entity that is behind the origin, and records the rest.
+pub struct Sweeper
+{
+    pub kept: Vec<Entity>,
+}
+
+impl Process for Sweeper
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        let behind: Vec<Entity> = data.entities()
+            .map(|e| *e)
+            .filter(|e| data.position.lookup(e).map_or(false, |p| p.0 < 0.0))
+            .collect();
+        for entity in behind.into_iter()
+        {
+            data.remove_entity(entity);
+        }
+        self.kept = data.entities().map(|e| *e).collect();
+    }
+}
+
+impl System for Sweeper { type Components = TestComponents; }
+
+// Records the delta of each update it runs in.
+pub struct Deltas(pub Vec<f64>);
+
+impl Process for Deltas
+{
+    fn process(&mut self, data: &mut DataHelper<TestComponents>)
+    {
+        self.0.push(data.delta());
+    }
+}
+
+impl System for Deltas { type Components = TestComponents; }
+
+pub struct Rebuild(pub usize);
+
+impl Process for Rebuild
+{
+    fn process(&mut self, _: &mut DataHelper<TestComponents>)
+    {
+        self.0 += 1;
+    }
+}
+
+impl System for Rebuild { type Components = TestComponents; }
+
+pub struct Input;
+pub struct Movement;
+pub struct Collision;
+pub struct Sound;
+pub struct Render;
+
+// Records when it runs, and runs before or after the given types of system.
+pub struct Stage<T>
+{
+    pub name: &'static str,
+    pub after: Vec<TypeId>,
+    pub before: Vec<TypeId>,
+    marker: PhantomData<T>,
+}
+
+impl<T> Stage<T>
+{
+    pub fn new(name: &'static str) -> Stage<T>
+    {
+        Stage { name: name, after: Vec::new(), before: Vec::new(), marker: PhantomData }
+    }
+
+    pub fn after<S: 'static>(mut self) -> Stage<T>
+    {
+        self.after.push(TypeId::of::<S>());
+        self
+    }
+
+    pub fn before<S: 'static>(mut self) -> Stage<T>
+    {
+        self.before.push(TypeId::of::<S>());
+        self
+    }
+}
+
+impl<T: 'static> Process for Stage<T>
+{
+    fn process(&mut self, _: &mut DataHelper<TestComponents>)
+    {
+        record(self.name.to_string());
+    }
+}
+
+impl<T: 'static> System for Stage<T>
+{
+    type Components = TestComponents;
+    fn run_after(&self) -> Vec<TypeId>
+    {
+        self.after.clone()
+    }
+    fn run_before(&self) -> Vec<TypeId>
+    {
+        self.before.clone()
+    }
+}
+
+pub struct Triggerer
+{
+    pub trigger: Option<LazyTrigger>,
+    pub fire: bool,
+    pub priority: i32,
+}
+
+impl Process for Triggerer
+{
+    fn process(&mut self, _: &mut DataHelper<TestComponents>)
+    {
+        if self.fire
+        {
+            self.trigger.as_ref().unwrap().trigger();
+            self.fire = false;
+        }
+    }
+}
+
+impl System for Triggerer
+{
+    type Components = TestComponents;
+    fn priority(&self) -> i32
+    {
+        self.priority
+    }
+}
+
+thread_local!(static CALLS: RefCell<Vec<String>> = RefCell::new(Vec::new()));
+
+fn record(call: String)
+{
+    CALLS.with(|calls| calls.borrow_mut().push(call));
+}
+
+fn take_calls() -> Vec<String>
+{
+    CALLS.with(|calls| calls.borrow_mut().drain().collect())
+}
+
+pub struct Recorder
+{
+    pub name: &'static str,
+    pub priority: i32,
+}
+
+impl Process for Recorder
+{
+    fn process(&mut self, _: &mut DataHelper<TestComponents>)
+    {
+        record(self.name.to_string());
+    }
+}
+
+impl System for Recorder
+{
+    type Components = TestComponents;
+    fn activated(&mut self, _: &EntityData, _: &TestComponents)
+    {
+        record(format!("{} activated", self.name));
+    }
+    fn reactivated(&mut self, _: &EntityData, _: &TestComponents)
+    {
+        record(format!("{} reactivated", self.name));
+    }
+    fn deactivated(&mut self, _: &EntityData, _: &TestComponents)
+    {
+        record(format!("{} deactivated", self.name));
+    }
+    fn priority(&self) -> i32
+    {
+        self.priority
+    }
+}
+
+pub struct Watcher
+{
+    pub seen: Vec<Entity>,
+    pub activated: Vec<Entity>,
+    pub deactivated: Vec<Entity>,
+}
+
+impl Watcher
+{
+    pub fn new() -> Watcher
+    {
+        Watcher { seen: Vec::new(), activated: Vec::new(), deactivated: Vec::new() }
+    }
+}
+
+impl EntityProcess for Watcher
+{
+    fn process(&mut self, en: EntityIter<TestComponents>, _: &mut DataHelper<TestComponents>)
+    {
+        self.seen = en.map(|e| *e).collect();
+    }
+}
+
+impl System for Watcher
+{
+    type Components = TestComponents;
+    fn activated(&mut self, e: &EntityData, _: &TestComponents)
+    {
+        self.activated.push(**e);
+    }
+    fn deactivated(&mut self, e: &EntityData, _: &TestComponents)
+    {
+        self.deactivated.push(**e);
+    }
+}
+
+#[test]
+fn aspect_membership()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let sprite = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+        c.velocity.add(&e, Velocity(0.0));
+        c.sprite.add(&e, Sprite);
+    });
+    let model = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+        c.velocity.add(&e, Velocity(0.0));
+        c.model.add(&e, Model);
+    });
+    let invisible = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+        c.velocity.add(&e, Velocity(0.0));
+    });
+    let frozen = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+        c.velocity.add(&e, Velocity(0.0));
+        c.sprite.add(&e, Sprite);
+        c.frozen.add(&e, Frozen);
+    });
+
+    world.update();
+    assert_eq!(vec![sprite, model], world.systems.typed.seen);
+    assert_eq!(vec![sprite, model], world.systems.untyped.seen);
+
+    // Gaining and losing components moves entities in and out of the system
+    world.modify_entity(frozen, |e: ModifyData, c: &mut TestComponents| {
+        c.frozen.remove(&e);
+    });
+    world.modify_entity(sprite, |e: ModifyData, c: &mut TestComponents| {
+        c.velocity.remove(&e);
+    });
+    world.modify_entity(invisible, |e: ModifyData, c: &mut TestComponents| {
+        c.model.insert(&e, Model);
+    });
+    world.update();
+    for watcher in vec![&world.systems.typed.inner, &world.systems.untyped.inner]
+    {
+        assert_eq!(vec![model, invisible, frozen], watcher.seen);
+        assert_eq!(vec![sprite, model, frozen, invisible], watcher.activated);
+        assert_eq!(vec![sprite], watcher.deactivated);
+    }
+}
+
+#[test]
+fn marker_aspect_membership()
+{
+    let mut world = World::<TestComponents, MarkerSystems>::new();
+
+    let selected = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+        c.selected.add(&e, Selected);
+    });
+    let other = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    world.update();
+    assert_eq!(vec![selected], world.systems.typed.seen);
+    assert_eq!(vec![selected], world.systems.untyped.seen);
+
+    world.modify_entity(other, |e: ModifyData, c: &mut TestComponents| {
+        c.selected.insert(&e, Selected);
+    });
+    world.remove_entity(selected);
+    world.update();
+    for watcher in vec![&world.systems.typed.inner, &world.systems.untyped.inner]
+    {
+        assert_eq!(vec![other], watcher.seen);
+        assert_eq!(vec![selected], watcher.deactivated);
+    }
+
+    // The marker of a reused index belongs to the new entity only
+    let reused = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.selected.add(&e, Selected);
+    });
+    assert_eq!(selected.get_index(), reused.get_index());
+    assert!(world.selected.lookup(&selected).is_none());
+    assert!(world.selected.lookup(&reused).is_some());
+    assert_eq!(vec![reused, other], world.selected.iter().map(|(e, _)| e).collect::<Vec<_>>());
+}
+
+#[test]
+fn ignore_changes_outside_aspect()
+{
+    let mut world = World::<TestComponents, NarrowSystems>::new();
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+
+    // Velocity isn't in the aspects, so only the system with a custom check is told about it
+    world.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.velocity.insert(&e, Velocity(0.0));
+    });
+    world.data.remove_component::<Velocity>(entity);
+    world.flush();
+    assert_eq!(vec![entity], world.systems.typed.activated);
+    assert_eq!(vec![entity], world.systems.untyped.activated);
+    assert!(world.systems.typed.deactivated.is_empty() && world.systems.untyped.deactivated.is_empty());
+    assert_eq!(vec![entity; 3], world.systems.custom.activated);
+    assert_eq!(vec![entity; 2], world.systems.custom.deactivated);
+
+    world.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.velocity.insert(&e, Velocity(0.0));
+        c.position.remove(&e);
+    });
+    for watcher in vec![&world.systems.typed, &world.systems.untyped]
+    {
+        assert!(!watcher.contains(&entity));
+        assert_eq!(vec![entity], watcher.deactivated);
+    }
+}
+
+#[test]
+fn system_priorities()
+{
+    let mut world = World::<TestComponents, OrderedSystems>::new();
+    let order = vec!["physics", "collision", "damage", "sound", "render"];
+    let with_suffix = |suffix: &str| order.iter().map(|name| format!("{}{}", name, suffix)).collect::<Vec<_>>();
+
+    world.update();
+    assert_eq!(with_suffix(""), take_calls());
+
+    let entity = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    assert_eq!(with_suffix(" activated"), take_calls());
+
+    world.modify_entity(entity, |e: ModifyData, c: &mut TestComponents| {
+        c.velocity.insert(&e, Velocity(0.0));
+    });
+    assert_eq!(with_suffix(" reactivated"), take_calls());
+
+    world.remove_entity(entity);
+    assert_eq!(with_suffix(" deactivated"), take_calls());
+}
+
+#[test]
+fn annotated_systems()
+{
+    let mut world = World::<TestComponents, AnnotatedSystems>::new();
+    assert_eq!(vec!["render", "physics", "input", "idle"], world.systems.system_order());
+
+    take_calls();
+    world.update();
+    assert_eq!(vec!["input", "physics", "render"], take_calls());
+}
+
+#[test]
+fn activate_built_entities_in_batches()
+{
+    let mut world = World::<TestComponents, OrderedSystems>::new();
+    let order = vec!["physics", "collision", "damage", "sound", "render"];
+    let twice = |suffix: &str| order.iter().flat_map(|name| vec![format!("{}{}", name, suffix); 2].into_iter())
+        .collect::<Vec<_>>();
+    let once = |suffix: &str| order.iter().map(|name| format!("{}{}", name, suffix)).collect::<Vec<_>>();
+
+    let first = world.data.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    world.data.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(1.0));
+    });
+    world.flush();
+    assert_eq!(twice(" activated"), take_calls());
+
+    // A change to another entity in between splits the builds into two batches
+    world.data.create_entity(());
+    world.data.remove_entity(first);
+    world.data.create_entity(());
+    world.flush();
+    let mut expected = once(" activated");
+    expected.extend(once(" deactivated").into_iter());
+    expected.extend(once(" activated").into_iter());
+    assert_eq!(expected, take_calls());
+
+    // So does the callback of each entity made with create_entity_then
+    world.data.create_entity_then((), |_, _: &mut DataHelper<TestComponents>| ());
+    world.data.create_entity_then((), |_, _: &mut DataHelper<TestComponents>| ());
+    world.flush();
+    let mut expected = once(" activated");
+    expected.extend(once(" activated").into_iter());
+    assert_eq!(expected, take_calls());
+}
+
+#[test]
+fn system_dependencies()
+{
+    let mut world = World::<TestComponents, DependentSystems>::new();
+    let order = vec!["render", "sound", "input", "movement", "collision"];
+    assert_eq!(order, world.systems.system_order());
+
+    take_calls();
+    world.update();
+    assert_eq!(order, take_calls());
+}
+
+#[test]
+#[should_panic(expected = "movement runs before collision runs before input runs before movement")]
+fn contradictory_system_dependencies()
+{
+    World::<TestComponents, CyclicSystems>::new();
+}
+
+#[test]
+fn inactive_systems_keep_tracking_entities()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+
+    let first = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+        c.velocity.add(&e, Velocity(0.0));
+        c.sprite.add(&e, Sprite);
+    });
+    world.update();
+    assert_eq!(vec![first], world.systems.typed.seen);
+
+    world.systems.set_active::<EntitySystem<Watcher>>(false);
+    assert!(!world.systems.is_system_active::<EntitySystem<Watcher>>());
+    let second = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+        c.velocity.add(&e, Velocity(0.0));
+        c.model.add(&e, Model);
+    });
+    world.remove_entity(first);
+    world.update();
+    for watcher in vec![&world.systems.typed.inner, &world.systems.untyped.inner]
+    {
+        assert_eq!(vec![first], watcher.seen);
+        assert_eq!(vec![first, second], watcher.activated);
+        assert_eq!(vec![first], watcher.deactivated);
+    }
+
+    world.systems.set_active::<EntitySystem<Watcher>>(true);
+    world.update();
+    assert_eq!(vec![second], world.systems.typed.seen);
+    assert_eq!(vec![second], world.systems.untyped.seen);
+}
+
+#[test]
+fn lazy_systems()
+{
+    let mut world = World::<TestComponents, LazySystems>::new();
+    world.update();
+    assert_eq!(0, world.systems.grid.0);
+
+    world.systems.grid.trigger();
+    world.systems.grid.trigger();
+    assert!(world.systems.grid.is_triggered());
+    world.update();
+    world.update();
+    assert_eq!(1, world.systems.grid.0);
+    assert!(!world.systems.grid.is_triggered());
+
+    world.systems.grid.trigger_n(2);
+    world.update();
+    world.update();
+    world.update();
+    assert_eq!(3, world.systems.grid.0);
+
+    // Systems that run first trigger it for the same update, later ones for the next update
+    world.systems.early.trigger = Some(world.systems.grid.trigger_handle());
+    world.systems.late.trigger = Some(world.systems.grid.trigger_handle());
+    world.systems.early.fire = true;
+    world.update();
+    assert_eq!(4, world.systems.grid.0);
+
+    world.systems.late.fire = true;
+    world.update();
+    assert_eq!(4, world.systems.grid.0);
+    world.update();
+    assert_eq!(5, world.systems.grid.0);
+}
+
+#[test]
+fn timed_systems()
+{
+    let mut world = World::<TestComponents, TimedSystems>::new();
+    let runs = |world: &World<TestComponents, TimedSystems>| (world.systems.once.0, world.systems.catch_up.0);
+
+    world.update_with_delta(0.1);
+    world.update_with_delta(0.1);
+    assert_eq!((0, 0), runs(&world));
+    world.update_with_delta(0.1);
+    assert_eq!((1, 1), runs(&world));
+    world.update();
+    assert_eq!((1, 1), runs(&world));
+
+    // A long frame only catches up as far as allowed, and the rest is skipped
+    world.update_with_delta(2.0);
+    assert_eq!((2, 4), runs(&world));
+    world.update_with_delta(0.1);
+    assert_eq!((2, 4), runs(&world));
+
+    // Shortening the interval doesn't run the built up time several times
+    world.systems.catch_up.set_interval(Duration::milliseconds(10));
+    world.update_with_delta(0.0);
+    assert_eq!((2, 5), runs(&world));
+    world.update_with_delta(0.02);
+    assert_eq!((2, 7), runs(&world));
+}
+
+#[test]
+fn update_deltas()
+{
+    let mut world = World::<TestComponents, DeltaSystems>::new();
+    world.update_with_delta(0.25);
+    world.update_with_delta(0.5);
+    world.update();
+    world.update_with_delta(0.75);
+    assert_eq!(vec![0.25, 0.5, 0.0, 0.75], world.systems.every.0);
+
+    // Wrapped systems see the time since they last ran
+    assert_eq!(vec![0.75, 0.75], world.systems.second.0);
+    assert_eq!(vec![0.5, 0.5], world.systems.timed.0);
+}
+
+#[test]
+fn parallel_systems()
+{
+    let mut world = World::<TestComponents, ParallelSystems>::new();
+    for x in [-1.0, 2.0, 3.0].iter()
+    {
+        let x = *x;
+        world.create_entity(move |e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position(x));
+        });
+    }
+
+    // Every worker sees the world as it was before any of their commands
+    world.update();
+    assert_eq!(vec![4.0], world.systems.workers.first.sums);
+    assert_eq!(vec![4.0], world.systems.workers.second.first.sums);
+    assert_eq!(vec![4.0], world.systems.workers.second.second.sums);
+
+    let mut positions: Vec<f32> = world.position.iter().map(|(_, p)| p.0).collect();
+    positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(vec![2.0, 3.0, 5.0, 6.0], positions);
+
+    world.update();
+    assert_eq!(vec![4.0, 16.0], world.systems.workers.second.second.sums);
+}
+
+#[test]
+#[should_panic(expected = "can't write components")]
+fn conflicting_parallel_processes()
+{
+    ParallelSystem::new(Worker { sums: Vec::new(), cull: false, spawn: None }, Mover);
+}
+
+#[test]
+fn update_phases()
+{
+    let mut world = World::<TestComponents, PhaseSystems>::new();
+    world.update();
+    world.update();
+
+    // Entities spawned before the update are processed by it, and removed before post-update
+    assert_eq!(vec![0, 0], world.systems.pre.counts);
+    assert_eq!(vec![1, 1], world.systems.culler.culled);
+    assert_eq!(vec![0, 0], world.systems.post.counts);
+}
+
+#[test]
+fn flush_after_last_phase()
+{
+    let mut world = World::<TestComponents, LateSystems>::new();
+    world.update();
+    let first: Vec<Entity> = world.entities().map(|e| *e).collect();
+    assert_eq!(1, first.len());
+
+    // Entities removed and created in the last phase are dealt with before the update returns
+    world.update();
+    assert!(!world.is_valid(&first[0]));
+    let second: Vec<Entity> = world.entities().map(|e| *e).collect();
+    assert_eq!(1, second.len());
+    assert!(second[0] != first[0]);
+    assert_eq!(vec![0, 1], world.systems.culler.culled);
+    assert_eq!(vec![0, 1], world.systems.spawner.counts);
+    assert!(world.queued_events().next().is_none());
+}
+
+#[test]
+fn startup_systems()
+{
+    let mut world = World::<TestComponents, StartupSystems>::new();
+    assert!(!world.systems.level.has_run());
+    world.update();
+    world.update();
+    world.update();
+
+    // The level is only spawned once, and is built before any other system runs
+    assert!(world.systems.level.has_run());
+    assert_eq!(vec![0], world.systems.level.counts);
+    assert_eq!(vec![1, 1, 1], world.systems.counter.counts);
+
+    world.systems.level.reset();
+    world.update();
+    world.update();
+    assert_eq!(vec![0, 1], world.systems.level.counts);
+    assert_eq!(vec![1, 1, 1, 2, 2], world.systems.counter.counts);
+}
+
+#[test]
+fn reactive_systems()
+{
+    let mut world = World::<TestComponents, ReactiveSystems>::new();
+    let a = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    let b = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+
+    // Every entity is new the first time, and nothing has changed the second
+    world.update();
+    world.update();
+
+    // Each system only sees changes to the components it watches
+    world.modify_entity(a, |e: ModifyData, c: &mut TestComponents| {
+        c.position.insert(&e, Position(1.0));
+    });
+    world.modify_entity(b, |e: ModifyData, c: &mut TestComponents| {
+        c.velocity.insert(&e, Velocity(1.0));
+    });
+    world.update();
+
+    // New entities count as changed, and removed ones are passed on
+    world.remove_entity(b);
+    let d = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    world.update();
+
+    assert_eq!(vec![vec![a, b], vec![], vec![a], vec![d]], world.systems.moved.changed);
+    assert_eq!(vec![vec![a, b], vec![], vec![b], vec![d]], world.systems.accelerated.changed);
+    let removed = vec![vec![], vec![], vec![], vec![b]];
+    assert_eq!(removed, world.systems.moved.removed);
+    assert_eq!(removed, world.systems.accelerated.removed);
+}
+
+#[test]
+fn sorted_entity_systems()
+{
+    let mut world = World::<TestComponents, SortedSystems>::new();
+    let spawn = |world: &mut World<TestComponents, SortedSystems>, x: f32| {
+        world.create_entity(move |e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position(x));
+        })
+    };
+    let a = spawn(&mut world, 3.0);
+    let b = spawn(&mut world, 1.0);
+    let c = spawn(&mut world, 2.0);
+    let d = spawn(&mut world, 1.0);
+    world.update();
+    assert_eq!(vec![b, d, c, a], world.systems.sorted.seen);
+
+    // Keys are worked out again, and ties stay in index order
+    world.remove_entity(c);
+    let e = spawn(&mut world, 0.0);
+    world.modify_entity(a, |e: ModifyData, c: &mut TestComponents| {
+        c.position.insert(&e, Position(1.0));
+    });
+    world.update();
+    assert_eq!(vec![e, a, b, d], world.systems.sorted.seen);
+}
+
+// Disables, enables and removes entities in the given orders, returning the indices the
+// system saw after each step.
+fn replay(disable: &[usize], enable: &[usize], remove: &[usize]) -> Vec<Vec<usize>>
+{
+    let mut world = World::<TestComponents, ReplaySystems>::new();
+    let entities: Vec<Entity> = (0..8).map(|i| world.create_entity(move |e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(i as f32));
+    })).collect();
+    let record = |world: &mut World<TestComponents, ReplaySystems>| -> Vec<usize> {
+        world.update();
+        world.systems.watcher.seen.iter().map(|e| e.get_index()).collect()
+    };
+    let mut seen = vec![record(&mut world)];
+    for &i in disable.iter()
+    {
+        world.disable_entity(entities[i]);
+    }
+    seen.push(record(&mut world));
+    for &i in enable.iter()
+    {
+        world.enable_entity(entities[i]);
+    }
+    seen.push(record(&mut world));
+    for &i in remove.iter()
+    {
+        world.remove_entity(entities[i]);
+    }
+    seen.push(record(&mut world));
+    seen
+}
+
+#[test]
+fn deterministic_entity_order()
+{
+    let first = replay(&[6, 1, 3, 0], &[3, 6], &[7, 2, 4]);
+    let second = replay(&[0, 3, 1, 6], &[6, 3], &[4, 7, 2]);
+    assert_eq!(first, second);
+    assert_eq!(vec![0, 1, 2, 3, 4, 5, 6, 7], first[0]);
+    assert_eq!(vec![2, 4, 5, 7], first[1]);
+    assert_eq!(vec![2, 3, 4, 5, 6, 7], first[2]);
+    assert_eq!(vec![3, 5, 6], first[3]);
+}
+
+#[test]
+fn deactivation_reasons()
+{
+    let mut world = World::<TestComponents, MourningSystems>::new();
+    let spawn = |world: &mut World<TestComponents, MourningSystems>| {
+        world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position(0.0));
+        })
+    };
+    let a = spawn(&mut world);
+    let b = spawn(&mut world);
+    let c = spawn(&mut world);
+    let d = spawn(&mut world);
+    world.remove_entity(a);
+    world.disable_entity(b);
+    world.modify_entity(c, |e: ModifyData, c: &mut TestComponents| {
+        c.position.remove(&e);
+    });
+    world.update();
+    world.clear();
+    assert_eq!(vec![(a, DeactivationReason::Removed), (b, DeactivationReason::Disabled),
+                    (c, DeactivationReason::Changed), (d, DeactivationReason::Removed)],
+               world.systems.mourner.reasons);
+}
+
+#[test]
+fn world_resources()
+{
+    let mut world = World::<TestComponents, ResourceSystems>::new();
+    assert!(!world.has_resource::<Score>());
+    assert_eq!(None, world.insert_resource(Score(0)));
+    world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    world.update();
+    world.update();
+    assert_eq!(&Score(2), world.resource::<Score>());
+
+    assert_eq!(Some(Score(2)), world.insert_resource(Score(10)));
+    world.update();
+    assert_eq!(Some(&Score(11)), world.get_resource::<Score>());
+
+    // Resources aren't per-entity, so clearing the world keeps them
+    world.clear();
+    world.resource_mut::<Score>().0 = 5;
+    assert_eq!(Some(Score(5)), world.remove_resource::<Score>());
+    assert_eq!(None, world.get_resource::<Score>());
+}
+
+#[test]
+fn build_world_in_stages()
+{
+    let mut world = WorldBuilder::<TestComponents, MessageSystems>::new()
+        .with_capacity(16)
+        .with_resource(Score(1))
+        .with_startup(|data| {
+            let points = data.resource::<Score>().0;
+            data.create_entity(move |e: BuildData, c: &mut TestComponents| {
+                c.position.add(&e, Position(points as f32));
+            });
+        })
+        .with_resource(Score(2))
+        .with_systems(|systems| systems.emitter.0 = 10)
+        .build();
+
+    // Everything queued by the startup closures has been applied already
+    assert_eq!(0, world.pending_events());
+    assert_eq!(vec![Position(1.0)], world.position.iter().map(|(_, p)| *p).collect::<Vec<_>>());
+    assert_eq!(&Score(2), world.resource::<Score>());
+
+    world.update();
+    world.update();
+    assert_eq!(vec![Damage(11)], world.messages::<Damage>());
+}
+
+#[test]
+#[should_panic(expected = "Score")]
+fn missing_resource()
+{
+    let mut world = World::<TestComponents, ResourceSystems>::new();
+    world.update();
+}
+
+#[test]
+fn update_with_context()
+{
+    let mut world = World::<TestComponents, ContextSystems>::new();
+    let mut input = Keys { keys: vec!['w', 'a'] };
+    world.update_ctx(&mut input);
+    assert_eq!(vec!['w', 'a'], world.systems.reader.read);
+    assert!(input.keys.is_empty());
+
+    // Updates without a context, or with one of another type, skip the system
+    input.keys.push('s');
+    world.update();
+    world.update_ctx(&mut 5u32);
+    assert_eq!(vec!['w', 'a'], world.systems.reader.read);
+    assert_eq!(3, world.systems.plain.0);
+
+    world.update_ctx(&mut input);
+    assert_eq!(vec!['w', 'a', 's'], world.systems.reader.read);
+}
+
+#[test]
+fn panicking_update_clears_context()
+{
+    let mut world = World::<TestComponents, ResourceSystems>::new();
+    let mut input = Keys { keys: vec!['w'] };
+    // The resource system panics, as there's no score
+    assert!(unsafe { std::rt::unwind::try(|| world.update_ctx(&mut input)) }.is_err());
+    assert_eq!(None, world.data.context_ptr(TypeId::of::<Keys>()));
+}
+
+#[test]
+fn system_messages()
+{
+    let mut world = World::<TestComponents, MessageSystems>::new();
+    world.update();
+    world.data.emit(Damage(10));
+    world.update();
+    world.update();
+
+    // Systems before and after the emitter see the same messages, one update later
+    let heard = vec![vec![], vec![Damage(1), Damage(10)], vec![Damage(2)]];
+    assert_eq!(heard, world.systems.early.heard);
+    assert_eq!(heard, world.systems.late.heard);
+    assert_eq!(vec![Damage(2)], world.messages::<Damage>());
+
+    assert_eq!(vec![Damage(2)], world.drain_messages::<Damage>().collect::<Vec<_>>());
+    assert!(world.messages::<Damage>().is_empty());
+    assert!(world.messages::<u32>().is_empty());
+}
+
+#[test]
+fn filtered_entity_systems()
+{
+    let mut world = World::<TestComponents, FilteredSystems>::new();
+    let a = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(1.0));
+    });
+    let b = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(-1.0));
+    });
+    world.update();
+    assert_eq!(vec![a], world.systems.ahead.seen);
+
+    // Changing a value doesn't update membership until the system is refreshed
+    world.with_entity_data(&b, |e, c| c.position[e] = Position(2.0));
+    world.update();
+    assert_eq!(vec![a], world.systems.ahead.seen);
+    world.systems.ahead.refresh(&b, &world.data);
+    world.update();
+    assert_eq!(vec![a, b], world.systems.ahead.seen);
+
+    world.with_entity_data(&a, |e, c| c.position[e] = Position(-2.0));
+    world.systems.ahead.refresh_all(&world.data);
+    world.update();
+    assert_eq!(vec![b], world.systems.ahead.seen);
+    assert_eq!(vec![a, b], world.systems.ahead.activated);
+    assert_eq!(vec![a], world.systems.ahead.deactivated);
+
+    // Reactivation checks the filter again
+    world.modify_entity(a, |e: ModifyData, c: &mut TestComponents| {
+        c.position.insert(&e, Position(3.0));
+        c.velocity.insert(&e, Velocity(0.0));
+    });
+    world.update();
+    assert_eq!(vec![a, b], world.systems.ahead.seen);
+
+    // Removed entities are dropped when refreshed
+    world.remove_entity(a);
+    world.systems.ahead.refresh(&a, &world.data);
+    assert!(!world.systems.ahead.contains(&a));
+}
+
+#[test]
+fn refreshing_drops_stale_entities()
+{
+    let mut world = World::<TestComponents, TestSystems>::new();
+    // A system kept outside of the world only hears about entities when it's refreshed
+    let mut system = EntitySystem::new(Watcher::new(), aspect!(<TestComponents> all: [position] none: []));
+    let a = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    let b = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    system.refresh_all(&world.data);
+    assert!(system.contains(&a) && system.contains(&b));
+
+    world.remove_entity(a);
+    world.disable_entity(b);
+    world.flush();
+    system.refresh_all(&world.data);
+    assert!(!system.contains(&a) && !system.contains(&b));
+    assert_eq!(vec![a, b], system.deactivated);
+}
+
+#[test]
+fn interact_pairs()
+{
+    let mut world = World::<TestComponents, InteractSystems>::new();
+    let a = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    let mut both = Vec::new();
+    for _ in 0..2
+    {
+        both.push(world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position(0.0));
+            c.velocity.add(&e, Velocity(0.0));
+        }));
+    }
+    let (b, c) = (both[0], both[1]);
+    let d = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.velocity.add(&e, Velocity(0.0));
+    });
+    world.update();
+
+    assert_eq!(vec![(a, b), (a, c), (a, d), (b, b), (b, c), (b, d), (c, b), (c, c), (c, d)],
+               *world.systems.all.pairs.borrow());
+    // Self-pairs are skipped, and (c, b) is the same pair as (b, c)
+    assert_eq!(vec![(a, b), (a, c), (a, d), (b, c), (b, d), (c, d)],
+               *world.systems.filtered.pairs.borrow());
+
+    // Once nothing has a velocity, the filtered system isn't processed at all
+    world.remove_entities(vec![b, c, d]);
+    world.update();
+    assert_eq!(2, world.systems.all.calls.get());
+    assert!(world.systems.all.pairs.borrow().is_empty());
+    assert_eq!(1, world.systems.filtered.calls.get());
+}
+
+#[test]
+fn system_profiling()
+{
+    let mut world = World::<TestComponents, ProfiledSystems>::new();
+    let calls = |world: &World<TestComponents, ProfiledSystems>| {
+        world.systems.profile().iter().map(|timing| timing.calls).collect::<Vec<_>>()
+    };
+    for _ in 0..2
+    {
+        world.create_entity(|e: BuildData, c: &mut TestComponents| { c.position.add(&e, Position(0.0)); });
+    }
+    world.update();
+    assert_eq!(vec![0, 0, 0], calls(&world));
+
+    // Only systems that are actually processed are counted
+    world.systems.set_profiling(true);
+    world.update_with_delta(0.5);
+    world.update_with_delta(0.25);
+    assert_eq!(vec![2, 0, 2], calls(&world));
+    assert_eq!(vec!["counter", "setup", "moving"], world.systems.profile().iter().map(|timing| timing.name).collect::<Vec<_>>());
+    assert!(world.systems.profile()[0].type_name.contains("Rebuild"));
+    // The timed system only passed its entities on once
+    assert_eq!(vec![0, 0, 2], world.systems.profile().iter().map(|timing| timing.entities).collect::<Vec<_>>());
+
+    world.systems.reset_profile();
+    world.systems.set_profiling(false);
+    world.update();
+    assert_eq!(vec![0, 0, 0], calls(&world));
+
+    let dynamic = world.add_dynamic_system(Box::new(EntitySystem::new(Watcher::new(),
+        aspect!(<TestComponents> all: [position]))));
+    world.update();
+    world.set_dynamic_profiling(true);
+    world.update();
+    let profile = world.dynamic_profile();
+    assert_eq!(1, profile.len());
+    assert_eq!(dynamic, profile[0].0);
+    assert_eq!(("dynamic", 1, 2), (profile[0].1.name, profile[0].1.calls, profile[0].1.entities));
+
+    world.reset_dynamic_profile();
+    assert_eq!(0, world.dynamic_profile()[0].1.calls);
+}
+
+#[test]
+fn dynamic_systems()
+{
+    let mut world = World::<TestComponents, ProfiledSystems>::new();
+    let a = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    take_calls();
+
+    // New systems find out about the entities that already exist
+    let late = world.add_dynamic_system(Box::new(Recorder { name: "late", priority: 0 }));
+    assert_eq!(vec!["late activated"], take_calls());
+    world.add_dynamic_system(Box::new(Recorder { name: "early", priority: 1 }));
+    assert_eq!(vec!["early activated"], take_calls());
+    assert_eq!(2, world.dynamic_system_count());
+
+    world.update();
+    assert_eq!(vec!["early", "late"], take_calls());
+    world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    assert_eq!(vec!["early activated", "late activated"], take_calls());
+
+    assert!(world.remove_dynamic_system(late).is_some());
+    assert!(world.remove_dynamic_system(late).is_none());
+    world.update();
+    world.remove_entity(a);
+    assert_eq!(vec!["early", "early deactivated"], take_calls());
+}
+
+#[test]
+fn system_entity_lists()
+{
+    let mut world = World::<TestComponents, InteractSystems>::new();
+    let a = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    let b = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+        c.velocity.add(&e, Velocity(0.0));
+    });
+    assert_eq!(vec![a, b], world.systems.all.entities_a().collect::<Vec<_>>());
+    assert_eq!(vec![b], world.systems.all.entities_b().collect::<Vec<_>>());
+    assert!(world.systems.all.contains_a(&a) && !world.systems.all.contains_b(&a));
+
+    let mut world = World::<TestComponents, SortedSystems>::new();
+    let a = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(0.0));
+    });
+    let b = world.create_entity(|e: BuildData, c: &mut TestComponents| {
+        c.velocity.add(&e, Velocity(0.0));
+    });
+    assert_eq!(vec![a], world.systems.sorted.entities().collect::<Vec<_>>());
+    assert!(world.systems.sorted.contains(&a));
+    assert!(!world.systems.sorted.contains(&b));
+    world.remove_entity(a);
+    assert_eq!(0, world.systems.sorted.entities().count());
+}
+
+#[test]
+fn batched_notifications()
+{
+    let mut world = World::<TestComponents, BatchedSystems>::new();
+    let spawn = |world: &mut World<TestComponents, BatchedSystems>| {
+        world.create_entity(|e: BuildData, c: &mut TestComponents| {
+            c.position.add(&e, Position(0.0));
+        })
+    };
+    let a = spawn(&mut world);
+    let b = spawn(&mut world);
+    world.update();
+    assert_eq!(vec![a, b], world.systems.echo.batches[0].activated);
+
+    // The entities spawned in response are in the next batch
+    world.update();
+    assert_eq!(2, world.systems.echo.batches[1].activated.len());
+    assert!(world.systems.echo.batches[1].activated.iter().all(|e| world.velocity.lookup(e).is_some()));
+
+    let c = spawn(&mut world);
+    world.remove_entity(c);
+    world.remove_entity(a);
+    world.disable_entity(b);
+    world.enable_entity(b);
+    assert_eq!(vec![b], world.systems.echo.pending().reactivated);
+    world.update();
+    let batch = EntityBatch { activated: vec![], reactivated: vec![b], deactivated: vec![a] };
+    assert_eq!(batch, world.systems.echo.batches[2]);
+    assert!(world.systems.echo.pending().is_empty());
+}
+
+#[test]
+fn interval_offsets()
+{
+    let mut world = World::<TestComponents, IntervalSystems>::new();
+    let counts = |world: &World<TestComponents, IntervalSystems>| {
+        [world.systems.plain.0, world.systems.first.0, world.systems.second.0, world.systems.spread.0]
+    };
+    let mut fired: Vec<Vec<usize>> = (0..4).map(|_| Vec::new()).collect();
+    for update in 0..10
+    {
+        let before = counts(&world);
+        world.update();
+        let after = counts(&world);
+        for i in 0..4
+        {
+            if after[i] != before[i]
+            {
+                fired[i].push(update);
+            }
+        }
+    }
+    assert_eq!(vec![2, 5, 8], fired[0]);
+    assert_eq!(vec![0, 3, 6, 9], fired[1]);
+    assert_eq!(vec![1, 4, 7], fired[2]);
+    assert_eq!(vec![2, 6], fired[3]);
+}
+
+#[test]
+#[should_panic(expected = "offset")]
+fn interval_offset_out_of_range()
+{
+    IntervalSystem::with_offset(Rebuild(0), 3, 3);
+}
+
+#[test]
+#[should_panic(expected = "must be positive")]
+fn zero_interval()
+{
+    IntervalSystem::new(Rebuild(0), 0);
+}
+
+#[test]
+fn paused_world()
+{
+    let mut world = World::<TestComponents, DeltaSystems>::new();
+    world.update_with_delta(0.5);
+    world.set_paused(true);
+    assert!(world.is_paused());
+
+    // Paused updates still flush the queue, but don't run or tick any systems
+    world.data.create_entity(());
+    world.update_with_delta(0.5);
+    world.update_with_delta(0.5);
+    assert_eq!(0, world.pending_events());
+    assert_eq!(1, world.data.entity_count());
+    assert_eq!(vec![0.5], world.systems.every.0);
+    assert!(world.systems.second.0.is_empty());
+    assert_eq!(vec![0.5], world.systems.timed.0);
+
+    world.step_with_delta(0.25);
+    assert!(world.is_paused());
+    assert_eq!(vec![0.5, 0.25], world.systems.every.0);
+    assert_eq!(vec![0.75], world.systems.second.0);
+    assert_eq!(vec![0.5], world.systems.timed.0);
+
+    world.set_paused(false);
+    world.update_with_delta(0.25);
+    assert_eq!(vec![0.5, 0.25, 0.25], world.systems.every.0);
+    assert_eq!(vec![0.5, 0.5], world.systems.timed.0);
+}
+
+#[test]
+fn fixed_timestep()
+{
+    let mut world = World::<TestComponents, StepSystems>::new();
+    let mut timestep = FixedTimestep::new(0.1);
+    timestep.set_max_steps(4);
+
+    let frames = [0.05, 0.07, 0.25, 0.01, 0.0, 1.0, 0.03];
+    let mut steps = Vec::new();
+    let mut leftover = Vec::new();
+    for frame in frames.iter()
+    {
+        let before = world.systems.spawner.steps.len();
+        leftover.push(timestep.update(&mut world, *frame));
+        steps.push(world.systems.spawner.steps.len() - before);
+    }
+    // The long frame only runs as many steps as allowed, and the rest of it is dropped
+    assert_eq!(vec![0, 1, 2, 0, 0, 4, 1], steps);
+    let expected = [0.5, 0.2, 0.7, 0.8, 0.8, 0.8, 0.1];
+    for (leftover, expected) in leftover.iter().zip(expected.iter())
+    {
+        assert!((leftover - expected).abs() < 1e-6, "leftover {} should be {}", leftover, expected);
+    }
+
+    // Entities created in one step have been built by the next one
+    let spawned: Vec<(f64, usize)> = (0..8).map(|i| (0.1, i)).collect();
+    assert_eq!(spawned, world.systems.spawner.steps);
+}
+
+#[test]
+fn create_entities_now()
+{
+    let mut world = World::<TestComponents, ImmediateSystems>::new();
+    world.update();
+
+    // Later systems can read the entity straight away, but it is only activated at the flush
+    let first = world.systems.flash.spawned[0];
+    assert_eq!(vec![vec![Position(0.0)]], world.systems.late.seen);
+    assert_eq!(Vec::<Entity>::new(), world.systems.watcher.seen);
+    assert_eq!(vec![first], world.systems.watcher.activated);
+    assert!(!world.data.is_pending_build(&first));
+
+    world.update();
+    assert_eq!(vec![Position(0.0), Position(1.0)], world.systems.late.seen[1]);
+    assert_eq!(vec![first], world.systems.watcher.seen);
+
+    // Removing it before the flush never activates it
+    let entity = world.data.create_entity_now(|e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(5.0));
+    });
+    assert!(world.data.is_pending_build(&entity));
+    assert_eq!(Some(&Position(5.0)), world.get::<Position>(&entity));
+    world.remove_entity(entity);
+    world.flush();
+    assert_eq!(2, world.systems.watcher.activated.len());
+    assert!(world.systems.watcher.deactivated.is_empty());
+}
+
+#[test]
+fn walk_entities_from_a_system()
+{
+    let mut world = World::<TestComponents, SweepSystems>::new();
+    let entities: Vec<Entity> = vec![1.0, -1.0, 2.0, -2.0].into_iter().map(|x| world.create_entity(move |e: BuildData, c: &mut TestComponents| {
+        c.position.add(&e, Position(x));
+    })).collect();
+    let disabled = world.create_entity(());
+    world.disable_entity(disabled);
+    world.update();
+
+    // Entities queued for removal during the process are already skipped
+    assert_eq!(vec![entities[0], entities[2]], world.systems.sweeper.kept);
+    assert!(!world.is_valid(&entities[1]));
+    assert!(!world.is_valid(&entities[3]));
+
+    // The world skips them as well
+    world.data.remove_entity(entities[2]);
+    assert_eq!(vec![entities[0]], world.entities().map(|e| *e).collect::<Vec<_>>());
+    assert_eq!(3, world.entities_including_disabled().count());
+}